@@ -0,0 +1,369 @@
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::process::ChildStdout;
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+use tokio::time::{Duration, Instant};
+use tracing::{error, info, warn};
+
+use crate::capture::{graceful_stop_ffmpeg, start_screen_recording_process, FFMPEG_EXIT_TIMEOUT};
+use crate::backpressure::unuploaded_bytes;
+use crate::journal::{self, JournalEvent};
+use crate::logging::ffmpeg_log_path;
+use crate::manifest::{segment_number, update_manifest, StopReason};
+use crate::metrics::encoder_speed;
+use crate::network::{upload_backlog, uploads_allowed};
+use crate::progress::spawn_stats_task;
+use crate::session::{stop_recording, RecordingState};
+use crate::utils::monitor_and_log_recording_start;
+use crate::zoom::restore_zoom;
+
+/// Emitted with a `CaptureChange` when the screen capture settings are adapted.
+pub const CAPTURE_CHANGED_EVENT: &str = "recording://capture-changed";
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// ffmpeg `speed=` below which the encoder is falling behind real time.
+const SLOW_SPEED: f64 = 0.9;
+/// How long the encoder has to stay slow before a step down, so a short spike in
+/// load doesn't cost quality for the rest of the recording.
+const SLOW_FOR: Duration = Duration::from_secs(10);
+
+/// Cheaper encoder settings tried in turn while the encoder keeps falling behind:
+/// first a higher CRF, then lower frame rates.
+const QUALITY_STEPS: [QualityStep; 3] = [
+    QualityStep { crf_increase: 5, framerate: None },
+    QualityStep { crf_increase: 5, framerate: Some(24) },
+    QualityStep { crf_increase: 5, framerate: Some(15) },
+];
+
+/// Upload backlog, in seconds to drain at the measured throughput, past which uploads
+/// count as falling behind. Never less than this many segments' worth.
+const BEHIND_SECS: f64 = 30.0;
+const BEHIND_SEGMENTS: f64 = 4.0;
+/// How long uploads have to stay behind before segments are lengthened, and caught
+/// up before they are shortened again. Each change restarts capture, which loses
+/// about a second, so these are deliberately slow.
+const BEHIND_FOR: Duration = Duration::from_secs(20);
+const CAUGHT_UP_FOR: Duration = Duration::from_secs(60);
+/// Longest segments uploads falling behind can stretch to.
+const MAX_ADAPTIVE_SEGMENT_SECS: u32 = 30;
+/// CRF added while the upload backlog is over its limit, which about halves the
+/// bitrate.
+const BACKLOG_CRF_INCREASE: u32 = 6;
+/// How long the backlog has to stay over its limit before quality is lowered, and
+/// under `BACKLOG_CLEARED_SHARE` of it before quality is restored.
+const BACKLOG_FULL_FOR: Duration = Duration::from_secs(10);
+const BACKLOG_CLEARED_FOR: Duration = Duration::from_secs(60);
+const BACKLOG_CLEARED_SHARE: f64 = 0.5;
+
+#[derive(Debug, Clone, Copy)]
+struct QualityStep {
+    crf_increase: u32,
+    framerate: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CaptureChangeReason {
+    /// The encoder couldn't keep up with real time, so quality was stepped down.
+    EncoderBehind,
+    /// Uploads couldn't keep up, so segments were lengthened.
+    UploadsBehind,
+    /// The upload backlog cleared, so segments were shortened again.
+    UploadsCaughtUp,
+    /// Too much was waiting for upload, so quality was lowered to save disk space.
+    UploadBacklogFull,
+    /// The upload backlog went down again, so quality was restored.
+    UploadBacklogCleared,
+}
+
+/// A change to the screen capture settings, kept in the manifest.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CaptureChange {
+    pub video_id: String,
+    pub reason: CaptureChangeReason,
+    /// First segment recorded with the new settings.
+    pub from_segment: u64,
+    pub crf: u32,
+    pub framerate: u32,
+    pub segment_time_secs: u32,
+}
+
+/// The screen ffmpeg settings that can be adapted while recording.
+#[derive(Debug, Clone, Copy)]
+struct CaptureSettings {
+    crf: u32,
+    framerate: u32,
+    segment_time_secs: u32,
+}
+
+/// What it takes to start the screen ffmpeg of a recording again, and what may be
+/// adapted.
+pub struct ScreenCapture {
+    pub app: Option<AppHandle>,
+    pub video_id: String,
+    pub recording_dir: PathBuf,
+    pub data_dir: PathBuf,
+    pub ffmpeg_binary: PathBuf,
+    pub args: Vec<OsString>,
+    pub adapt_quality: bool,
+    pub adapt_segment_time: bool,
+    /// Raises the CRF while more than this many bytes wait for upload.
+    pub backlog_limit_bytes: Option<u64>,
+}
+
+/// Restarts the screen ffmpeg with new settings when the encoder can't keep up with
+/// real time (cheaper encoding) or uploads can't keep up with capture (longer
+/// segments, so fewer requests), and shortens segments again once uploads catch up.
+/// With a backlog limit, quality is also lowered while too much waits for upload.
+/// Segment numbering carries on from where the previous process stopped.
+pub fn spawn_adaptive_capture(state: Arc<Mutex<RecordingState>>, capture: ScreenCapture) {
+    tokio::spawn(async move {
+        let initial = CaptureSettings {
+            crf: arg_value(&capture.args, "-crf").and_then(|crf| crf.parse().ok()).unwrap_or(28),
+            framerate: arg_value(&capture.args, "-r").and_then(|fps| fps.parse().ok()).unwrap_or(30),
+            segment_time_secs: arg_value(&capture.args, "-segment_time").and_then(|secs| secs.parse().ok()).unwrap_or(3),
+        };
+        let mut current = initial;
+        let mut steps = QUALITY_STEPS.iter();
+        let mut slow_since: Option<Instant> = None;
+        let mut behind_since: Option<Instant> = None;
+        let mut caught_up_since: Option<Instant> = None;
+        let mut backlog_full_since: Option<Instant> = None;
+        let mut backlog_cleared_since: Option<Instant> = None;
+        let mut reduced_for_backlog = false;
+
+        loop {
+            tokio::time::sleep(POLL_INTERVAL).await;
+
+            let slow = capture.adapt_quality && encoder_speed("screen").map_or(false, |speed| speed < SLOW_SPEED);
+            let slow_for = since(&mut slow_since, slow);
+
+            let backlog = upload_backlog();
+            let behind_limit = BEHIND_SECS.max(BEHIND_SEGMENTS * current.segment_time_secs as f64);
+            // Offline or paused uploads aren't helped by longer segments.
+            let uploading = backlog.online && uploads_allowed();
+            let behind = capture.adapt_segment_time && uploading
+                && backlog.estimated_secs.map_or(false, |secs| secs > behind_limit);
+            let caught_up = capture.adapt_segment_time && uploading && backlog.pending_uploads <= 1;
+            let behind_for = since(&mut behind_since, behind);
+            let caught_up_for = since(&mut caught_up_since, caught_up);
+
+            let unuploaded = match capture.backlog_limit_bytes {
+                Some(_) => {
+                    let recording_dir = capture.recording_dir.clone();
+                    tokio::task::spawn_blocking(move || unuploaded_bytes(&recording_dir)).await.unwrap_or(0)
+                },
+                None => 0,
+            };
+            let backlog_full = capture.backlog_limit_bytes.map_or(false, |limit| unuploaded > limit);
+            let backlog_cleared = capture.backlog_limit_bytes
+                .map_or(false, |limit| (unuploaded as f64) < limit as f64 * BACKLOG_CLEARED_SHARE);
+            let backlog_full_for = since(&mut backlog_full_since, backlog_full);
+            let backlog_cleared_for = since(&mut backlog_cleared_since, backlog_cleared);
+            let backlog_crf = if reduced_for_backlog { BACKLOG_CRF_INCREASE } else { 0 };
+
+            let mut next = current;
+            let reason = if slow_for >= SLOW_FOR {
+                match steps.next() {
+                    Some(step) => {
+                        next.crf = initial.crf + step.crf_increase + backlog_crf;
+                        next.framerate = step.framerate.map_or(initial.framerate, |framerate| framerate.min(initial.framerate));
+                        CaptureChangeReason::EncoderBehind
+                    },
+                    None => continue,
+                }
+            } else if backlog_full_for >= BACKLOG_FULL_FOR && !reduced_for_backlog {
+                next.crf = current.crf + BACKLOG_CRF_INCREASE;
+                CaptureChangeReason::UploadBacklogFull
+            } else if backlog_cleared_for >= BACKLOG_CLEARED_FOR && reduced_for_backlog {
+                next.crf = current.crf - BACKLOG_CRF_INCREASE;
+                CaptureChangeReason::UploadBacklogCleared
+            } else if behind_for >= BEHIND_FOR && current.segment_time_secs < MAX_ADAPTIVE_SEGMENT_SECS {
+                next.segment_time_secs = (current.segment_time_secs * 2).min(MAX_ADAPTIVE_SEGMENT_SECS);
+                CaptureChangeReason::UploadsBehind
+            } else if caught_up_for >= CAUGHT_UP_FOR && current.segment_time_secs > initial.segment_time_secs {
+                next.segment_time_secs = (current.segment_time_secs / 2).max(initial.segment_time_secs);
+                CaptureChangeReason::UploadsCaughtUp
+            } else {
+                continue;
+            };
+
+            let mut guard = state.lock().await;
+            if guard.recording_options.as_ref().map(|options| &options.video_id) != Some(&capture.video_id)
+                || guard.screen_process.is_none() {
+                return;
+            }
+            // ffmpeg reports no progress while suspended, so the last speed is stale.
+            if guard.is_paused() {
+                continue;
+            }
+
+            let from_segment = restart_capture(&mut guard, &capture, next).await;
+            drop(guard);
+            slow_since = None;
+            behind_since = None;
+            caught_up_since = None;
+            backlog_full_since = None;
+            backlog_cleared_since = None;
+            match from_segment {
+                Ok(from_segment) => {
+                    current = next;
+                    match reason {
+                        CaptureChangeReason::UploadBacklogFull => reduced_for_backlog = true,
+                        CaptureChangeReason::UploadBacklogCleared => reduced_for_backlog = false,
+                        _ => {},
+                    }
+                    let change = CaptureChange {
+                        video_id: capture.video_id.clone(),
+                        reason,
+                        from_segment,
+                        crf: next.crf,
+                        framerate: next.framerate,
+                        segment_time_secs: next.segment_time_secs,
+                    };
+                    info!("Adapted screen capture of {}: {:?}", capture.video_id, change);
+                    if let Err(e) = update_manifest(&capture.recording_dir, |manifest| manifest.capture_changes.push(change.clone())) {
+                        warn!("Failed to record the capture change in the manifest: {}", e);
+                    }
+                    if let Some(ref app) = capture.app {
+                        let _ = app.emit_all(CAPTURE_CHANGED_EVENT, change);
+                    }
+                },
+                Err(e) => {
+                    error!("Failed to restart screen capture of {}: {}", capture.video_id, e);
+                    stop_recording(&state, StopReason::FfmpegCrash).await;
+                    return;
+                },
+            }
+        }
+    });
+}
+
+/// How long `condition` has held, tracked in `started`.
+fn since(started: &mut Option<Instant>, condition: bool) -> Duration {
+    if condition {
+        started.get_or_insert_with(Instant::now).elapsed()
+    } else {
+        *started = None;
+        Duration::ZERO
+    }
+}
+
+/// Stops the screen ffmpeg and starts it again with `settings`, returning the number
+/// of the first segment the new process records.
+async fn restart_capture(
+    guard: &mut RecordingState,
+    capture: &ScreenCapture,
+    settings: CaptureSettings,
+) -> Result<u64, String> {
+    if let Some(stdin) = guard.screen_process_stdin.take() {
+        graceful_stop_ffmpeg(stdin).await.map_err(|e| e.to_string())?;
+    }
+    if let Some(mut process) = guard.screen_process.take() {
+        if tokio::time::timeout(FFMPEG_EXIT_TIMEOUT, process.wait()).await.is_err() {
+            warn!("Screen FFmpeg did not exit in time, killing it");
+            let _ = process.kill().await;
+        }
+    }
+    if let Some(relay) = guard.segment_list_relay.take() {
+        let _ = relay.await;
+    }
+
+    let chunks_dir = capture.recording_dir.join("screen");
+    let from_segment = next_segment_number(&chunks_dir);
+    let args = restart_args(&capture.args, settings, from_segment);
+
+    let (mut child, stderr, stdin) = start_screen_recording_process(&capture.ffmpeg_binary, &args)
+        .await
+        .map_err(|e| e.to_string())?;
+    let stdout = child.stdout.take().ok_or("FFmpeg stdout is not available".to_string())?;
+    if let Some(pid) = child.id() {
+        journal::append(&capture.recording_dir, JournalEvent::ProcessStarted { track: "screen".to_string(), pid });
+    }
+    guard.segment_list_relay = Some(relay_segment_list(stdout, chunks_dir));
+
+    let video_id = &capture.video_id;
+    let (line_tx, line_rx) = tokio::sync::mpsc::unbounded_channel();
+    spawn_stats_task(capture.app.clone(), capture.recording_dir.clone(), video_id.clone(), "screen".to_string(), settings.framerate as f64, line_rx);
+    let log_path = ffmpeg_log_path(&capture.data_dir, video_id, "screen");
+    monitor_and_log_recording_start(stderr, video_id, "video", log_path, Some(line_tx), false).await.map_err(String::from)?;
+
+    guard.screen_process = Some(child);
+    guard.screen_process_stdin = Some(stdin);
+    if let (Some(zoom), Some(stdin)) = (guard.zoom, guard.screen_process_stdin.as_mut()) {
+        if let Err(e) = restore_zoom(stdin, zoom).await {
+            warn!("Failed to restore the zoom after restarting capture: {}", e);
+        }
+    }
+
+    Ok(from_segment)
+}
+
+/// The capture args with the new settings. The segment list goes to stdout, since
+/// ffmpeg would truncate the list of the segments recorded so far, and numbering
+/// continues at `start_number`.
+fn restart_args(args: &[OsString], settings: CaptureSettings, start_number: u64) -> Vec<OsString> {
+    let mut args = args.to_vec();
+    set_arg(&mut args, "-crf", settings.crf.to_string());
+    set_arg(&mut args, "-r", settings.framerate.to_string());
+    set_arg(&mut args, "-segment_time", settings.segment_time_secs.to_string());
+    set_arg(&mut args, "-segment_list", "pipe:1".to_string());
+    if let Some(at) = args.iter().position(|arg| arg == "-segment_list") {
+        args.splice(at..at, ["-segment_start_number".into(), OsString::from(start_number.to_string())]);
+    }
+    args
+}
+
+fn arg_value<'a>(args: &'a [OsString], flag: &str) -> Option<&'a str> {
+    let at = args.iter().position(|arg| arg == flag)?;
+    args.get(at + 1).and_then(|value| value.to_str())
+}
+
+fn set_arg(args: &mut [OsString], flag: &str, value: String) {
+    if let Some(at) = args.iter().position(|arg| arg == flag) {
+        if let Some(current) = args.get_mut(at + 1) {
+            *current = value.into();
+        }
+    }
+}
+
+/// One past the highest numbered segment in `chunks_dir`.
+fn next_segment_number(chunks_dir: &Path) -> u64 {
+    std::fs::read_dir(chunks_dir)
+        .map(|entries| {
+            entries
+                .flatten()
+                .filter_map(|entry| segment_number(&entry.file_name().to_string_lossy()))
+                .max()
+                .map_or(0, |number| number + 1)
+        })
+        .unwrap_or(0)
+}
+
+/// Appends the segments a restarted ffmpeg lists on stdout to `segment_list.txt`.
+fn relay_segment_list(stdout: ChildStdout, chunks_dir: PathBuf) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut lines = BufReader::new(stdout).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            if line.is_empty() {
+                continue;
+            }
+            let appended = async {
+                let mut segment_list = tokio::fs::OpenOptions::new()
+                    .append(true)
+                    .create(true)
+                    .open(chunks_dir.join("segment_list.txt"))
+                    .await?;
+                segment_list.write_all(format!("{}\n", line).as_bytes()).await
+            };
+            if let Err(e) = appended.await {
+                error!("Failed to add {} to the segment list: {}", line, e);
+            }
+        }
+    })
+}
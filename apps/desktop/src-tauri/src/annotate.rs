@@ -0,0 +1,261 @@
+use ab_glyph::{point, Font, FontVec, PxScale, ScaleFont};
+use serde::Deserialize;
+use xcap::image::{imageops, Rgba, RgbaImage};
+
+const DEFAULT_COLOR: Rgba<u8> = Rgba([255, 59, 48, 255]);
+const DEFAULT_THICKNESS: u32 = 4;
+const DEFAULT_TEXT_SIZE: f32 = 32.0;
+const DEFAULT_BLUR_SIGMA: f32 = 12.0;
+/// Translucent yellow, like a highlighter pen.
+const DEFAULT_HIGHLIGHT_COLOR: Rgba<u8> = Rgba([255, 214, 10, 96]);
+const DEFAULT_HIGHLIGHT_THICKNESS: u32 = 24;
+
+/// System fonts tried in order for text annotations; the first that exists is used.
+#[cfg(target_os = "macos")]
+const FONT_PATHS: &[&str] = &["/System/Library/Fonts/Helvetica.ttc", "/System/Library/Fonts/Supplemental/Arial.ttf"];
+#[cfg(target_os = "windows")]
+const FONT_PATHS: &[&str] = &["C:\\Windows\\Fonts\\segoeui.ttf", "C:\\Windows\\Fonts\\arial.ttf"];
+#[cfg(target_os = "linux")]
+const FONT_PATHS: &[&str] = &[
+    "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf",
+    "/usr/share/fonts/TTF/DejaVuSans.ttf",
+    "/usr/share/fonts/dejavu/DejaVuSans.ttf",
+    "/usr/share/fonts/truetype/liberation/LiberationSans-Regular.ttf",
+    "/usr/share/fonts/noto/NotoSans-Regular.ttf",
+];
+
+/// One edit to a screenshot. Coordinates are in pixels of the image as it is when
+/// the operation runs, so operations after a crop are relative to the cropped area.
+/// Colors are hex strings like "#ff3b30" or "#ff3b3080".
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum AnnotationOp {
+    Crop { x: u32, y: u32, width: u32, height: u32 },
+    Arrow {
+        from_x: i32,
+        from_y: i32,
+        to_x: i32,
+        to_y: i32,
+        color: Option<String>,
+        thickness: Option<u32>,
+    },
+    Rectangle {
+        x: i32,
+        y: i32,
+        width: u32,
+        height: u32,
+        color: Option<String>,
+        thickness: Option<u32>,
+    },
+    /// Blurs a region beyond reading, e.g. to hide a password or an email address.
+    Blur { x: u32, y: u32, width: u32, height: u32, sigma: Option<f32> },
+    /// `x` and `y` are the top left corner of the first line.
+    Text { x: i32, y: i32, text: String, color: Option<String>, size: Option<f32> },
+    /// A freehand stroke through `points`, as `[x, y]` pairs.
+    Pen { points: Vec<(i32, i32)>, color: Option<String>, thickness: Option<u32> },
+    /// A wide, translucent freehand stroke. Each stroke is laid down at one opacity,
+    /// but where two highlights cross, the colors add up.
+    Highlight { points: Vec<(i32, i32)>, color: Option<String>, thickness: Option<u32> },
+}
+
+/// Applies `ops` to `image` in order.
+pub fn apply(mut image: RgbaImage, ops: &[AnnotationOp]) -> Result<RgbaImage, String> {
+    for op in ops {
+        match op {
+            AnnotationOp::Crop { x, y, width, height } => {
+                let (x, y, width, height) = clamp_region(&image, *x, *y, *width, *height)?;
+                image = imageops::crop_imm(&image, x, y, width, height).to_image();
+            },
+            AnnotationOp::Arrow { from_x, from_y, to_x, to_y, color, thickness } => {
+                let color = parse_color(color.as_deref())?;
+                let thickness = thickness.unwrap_or(DEFAULT_THICKNESS).max(1);
+                draw_arrow(&mut image, (*from_x as f32, *from_y as f32), (*to_x as f32, *to_y as f32), color, thickness);
+            },
+            AnnotationOp::Rectangle { x, y, width, height, color, thickness } => {
+                let color = parse_color(color.as_deref())?;
+                let thickness = thickness.unwrap_or(DEFAULT_THICKNESS).max(1);
+                let (left, top) = (*x as f32, *y as f32);
+                let (right, bottom) = (left + *width as f32, top + *height as f32);
+                for (from, to) in [
+                    ((left, top), (right, top)),
+                    ((right, top), (right, bottom)),
+                    ((right, bottom), (left, bottom)),
+                    ((left, bottom), (left, top)),
+                ] {
+                    draw_line(&mut image, from, to, color, thickness);
+                }
+            },
+            AnnotationOp::Blur { x, y, width, height, sigma } => {
+                let (x, y, width, height) = clamp_region(&image, *x, *y, *width, *height)?;
+                let region = imageops::crop_imm(&image, x, y, width, height).to_image();
+                let blurred = imageops::blur(&region, sigma.unwrap_or(DEFAULT_BLUR_SIGMA));
+                imageops::replace(&mut image, &blurred, x as i64, y as i64);
+            },
+            AnnotationOp::Text { x, y, text, color, size } => {
+                let color = parse_color(color.as_deref())?;
+                draw_text(&mut image, *x, *y, text, color, size.unwrap_or(DEFAULT_TEXT_SIZE))?;
+            },
+            AnnotationOp::Pen { points, color, thickness } => {
+                let color = parse_color(color.as_deref())?;
+                draw_polyline(&mut image, points, color, thickness.unwrap_or(DEFAULT_THICKNESS).max(1));
+            },
+            AnnotationOp::Highlight { points, color, thickness } => {
+                let color = match color {
+                    Some(_) => parse_color(color.as_deref())?,
+                    None => DEFAULT_HIGHLIGHT_COLOR,
+                };
+                draw_polyline(&mut image, points, color, thickness.unwrap_or(DEFAULT_HIGHLIGHT_THICKNESS).max(1));
+            },
+        }
+    }
+    Ok(image)
+}
+
+/// Limits a region to the image, failing when nothing of it is inside.
+fn clamp_region(image: &RgbaImage, x: u32, y: u32, width: u32, height: u32) -> Result<(u32, u32, u32, u32), String> {
+    let width = width.min(image.width().saturating_sub(x));
+    let height = height.min(image.height().saturating_sub(y));
+    if width == 0 || height == 0 {
+        return Err(format!("Region at {},{} is outside the {}x{} image", x, y, image.width(), image.height()));
+    }
+    Ok((x, y, width, height))
+}
+
+fn parse_color(color: Option<&str>) -> Result<Rgba<u8>, String> {
+    let hex = match color {
+        Some(color) => color.trim_start_matches('#'),
+        None => return Ok(DEFAULT_COLOR),
+    };
+    let channel = |at: usize| u8::from_str_radix(&hex[at..at + 2], 16).map_err(|_| format!("Invalid color: {}", hex));
+    match hex.len() {
+        _ if !hex.is_ascii() => Err(format!("Invalid color: {}", hex)),
+        6 => Ok(Rgba([channel(0)?, channel(2)?, channel(4)?, 255])),
+        8 => Ok(Rgba([channel(0)?, channel(2)?, channel(4)?, channel(6)?])),
+        _ => Err(format!("Invalid color: {}", hex)),
+    }
+}
+
+/// Lays `color` over the pixel at `x`, `y` with `coverage` times its alpha. Works on
+/// transparent images too, such as the live drawing canvas.
+fn blend(image: &mut RgbaImage, x: i32, y: i32, color: Rgba<u8>, coverage: f32) {
+    if x < 0 || y < 0 || x >= image.width() as i32 || y >= image.height() as i32 {
+        return;
+    }
+    let alpha = coverage.clamp(0.0, 1.0) * color[3] as f32 / 255.0;
+    let pixel = image.get_pixel_mut(x as u32, y as u32);
+    let below = pixel[3] as f32 / 255.0 * (1.0 - alpha);
+    let out_alpha = alpha + below;
+    if out_alpha <= 0.0 {
+        return;
+    }
+    for (channel, value) in pixel.0.iter_mut().zip(color.0).take(3) {
+        *channel = ((value as f32 * alpha + *channel as f32 * below) / out_alpha).round() as u8;
+    }
+    pixel[3] = (out_alpha * 255.0).round() as u8;
+}
+
+/// Draws a line with round ends, covering every pixel within `thickness / 2` of it.
+fn draw_line(image: &mut RgbaImage, from: (f32, f32), to: (f32, f32), color: Rgba<u8>, thickness: u32) {
+    let radius = thickness as f32 / 2.0;
+    let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+    let length = (dx * dx + dy * dy).sqrt();
+    // Only the part of the line's bounding box inside the image is visited.
+    let (width, height) = (image.width() as f32, image.height() as f32);
+    let (min_x, max_x) = ((from.0.min(to.0) - radius).max(0.0), (from.0.max(to.0) + radius).min(width));
+    let (min_y, max_y) = ((from.1.min(to.1) - radius).max(0.0), (from.1.max(to.1) + radius).min(height));
+
+    for y in min_y.floor() as i32..=max_y.ceil() as i32 {
+        for x in min_x.floor() as i32..=max_x.ceil() as i32 {
+            let (px, py) = (x as f32 - from.0, y as f32 - from.1);
+            // Distance from the pixel to the closest point of the segment.
+            let t = if length > 0.0 { ((px * dx + py * dy) / (length * length)).clamp(0.0, 1.0) } else { 0.0 };
+            let (cx, cy) = (px - t * dx, py - t * dy);
+            let distance = (cx * cx + cy * cy).sqrt();
+            // One pixel of falloff keeps the edges smooth.
+            let coverage = radius + 0.5 - distance;
+            if coverage > 0.0 {
+                blend(image, x, y, color, coverage);
+            }
+        }
+    }
+}
+
+/// Joins `points` with round-ended lines; a single point draws a dot. The stroke is
+/// drawn opaque on a layer covering its bounds and then blended in once, so the
+/// overlapping joints of a translucent stroke don't show.
+fn draw_polyline(image: &mut RgbaImage, points: &[(i32, i32)], color: Rgba<u8>, thickness: u32) {
+    let margin = thickness as i32 / 2 + 1;
+    let left = points.iter().map(|point| point.0).min().unwrap_or(0).saturating_sub(margin).max(0);
+    let top = points.iter().map(|point| point.1).min().unwrap_or(0).saturating_sub(margin).max(0);
+    let right = points.iter().map(|point| point.0).max().unwrap_or(0).saturating_add(margin).min(image.width() as i32);
+    let bottom = points.iter().map(|point| point.1).max().unwrap_or(0).saturating_add(margin).min(image.height() as i32);
+    if points.is_empty() || right <= left || bottom <= top {
+        return;
+    }
+
+    let mut layer = RgbaImage::new((right - left) as u32, (bottom - top) as u32);
+    let opaque = Rgba([color[0], color[1], color[2], 255]);
+    let points: Vec<(f32, f32)> = points.iter().map(|&(x, y)| ((x - left) as f32, (y - top) as f32)).collect();
+    match points.as_slice() {
+        [point] => draw_line(&mut layer, *point, *point, opaque, thickness),
+        _ => {
+            for pair in points.windows(2) {
+                draw_line(&mut layer, pair[0], pair[1], opaque, thickness);
+            }
+        },
+    }
+
+    for (x, y, pixel) in layer.enumerate_pixels() {
+        if pixel[3] > 0 {
+            blend(image, left + x as i32, top + y as i32, color, pixel[3] as f32 / 255.0);
+        }
+    }
+}
+
+fn draw_arrow(image: &mut RgbaImage, from: (f32, f32), to: (f32, f32), color: Rgba<u8>, thickness: u32) {
+    draw_line(image, from, to, color, thickness);
+
+    let angle = (to.1 - from.1).atan2(to.0 - from.0);
+    let head_length = (thickness as f32 * 4.0).max(12.0);
+    for side in [-1.0, 1.0] {
+        let head_angle = angle + std::f32::consts::PI + side * std::f32::consts::FRAC_PI_6;
+        let end = (to.0 + head_length * head_angle.cos(), to.1 + head_length * head_angle.sin());
+        draw_line(image, to, end, color, thickness);
+    }
+}
+
+fn load_font() -> Result<FontVec, String> {
+    FONT_PATHS.iter()
+        .filter_map(|path| std::fs::read(path).ok())
+        .find_map(|data| FontVec::try_from_vec_and_index(data, 0).ok())
+        .ok_or("No font found for text annotations".to_string())
+}
+
+fn draw_text(image: &mut RgbaImage, x: i32, y: i32, text: &str, color: Rgba<u8>, size: f32) -> Result<(), String> {
+    let font = load_font()?;
+    let scaled = font.as_scaled(PxScale::from(size));
+    let line_height = scaled.height() + scaled.line_gap();
+
+    for (line_index, line) in text.lines().enumerate() {
+        let baseline = y as f32 + scaled.ascent() + line_index as f32 * line_height;
+        let mut caret = x as f32;
+        let mut previous = None;
+        for c in line.chars() {
+            let glyph_id = scaled.glyph_id(c);
+            if let Some(previous) = previous {
+                caret += scaled.kern(previous, glyph_id);
+            }
+            let glyph = glyph_id.with_scale_and_position(scaled.scale(), point(caret, baseline));
+            caret += scaled.h_advance(glyph_id);
+            previous = Some(glyph_id);
+
+            if let Some(outline) = font.outline_glyph(glyph) {
+                let bounds = outline.px_bounds();
+                outline.draw(|gx, gy, coverage| {
+                    blend(image, bounds.min.x as i32 + gx as i32, bounds.min.y as i32 + gy as i32, color, coverage);
+                });
+            }
+        }
+    }
+    Ok(())
+}
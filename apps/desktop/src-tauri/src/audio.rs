@@ -7,16 +7,29 @@ use std::sync::{Arc};
 use tokio::sync::Mutex;
 use tokio::io::{AsyncWriteExt, BufReader};
 use tokio::process::{Command, ChildStderr, ChildStdin};
-
+use tracing::{debug, error, info, warn};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use serde::Serialize;
+use tauri::AppHandle;
+
+use crate::audio_levels::LevelReporter;
+use crate::journal::{self, JournalEvent};
+use crate::manifest::segment_pattern;
 use crate::recording::RecordingOptions;
-use crate::utils::{ffmpeg_path_as_str, monitor_and_log_recording_start};
+use crate::utils::{ffmpeg_path, monitor_and_log_recording_start};
+
+/// Automatic gain control: `dynaudnorm` raises quiet stretches by up to 20 dB over a
+/// window of a couple of seconds, and the limiter catches peaks it doesn't react to
+/// in time, such as a sudden shout right at the mic.
+pub const AUTO_GAIN_FILTER: &str = "dynaudnorm=f=150:g=15:p=0.9:m=10,alimiter=limit=0.95:level=disabled";
 
 pub struct AudioRecorder {
     pub options: Option<RecordingOptions>,
     ffmpeg_process: Option<tokio::process::Child>,
     ffmpeg_stdin: Option<Arc<Mutex<ChildStdin>>>,
     device_name: Option<String>,
-    stream: Option<cpal::Stream>,
+    stream: Option<InputStreamHandle>,
 }
 
 impl AudioRecorder {
@@ -31,39 +44,27 @@ impl AudioRecorder {
         }
     }
 
-    pub async fn start_audio_recording(&mut self, options: RecordingOptions, audio_file_path: &str, custom_device: Option<&str>) -> Result<(), String> {
+    /// `speech_track`, when set, is an extra 16 kHz mono WAV of the whole recording
+    /// kept locally for transcription. With an `app`, input levels are emitted while
+    /// recording.
+    pub async fn start_audio_recording(
+        &mut self,
+        options: RecordingOptions,
+        audio_chunks_dir: &Path,
+        custom_device: Option<&str>,
+        log_path: PathBuf,
+        speech_track: Option<PathBuf>,
+        app: Option<AppHandle>,
+    ) -> Result<(), String> {
         self.options = Some(options);
         
-        let host = cpal::default_host();
-        let devices = host.devices().expect("Failed to get devices");
         let (tx, mut rx) = tokio::sync::mpsc::channel::<Vec<u8>>(32);
-        
-        let mut input_devices = devices.filter_map(|device| {
-            let supported_input_configs = device.supported_input_configs();
-            if supported_input_configs.is_ok() && supported_input_configs.unwrap().count() > 0 {
-                Some(device)
-            } else {
-                None
-            }
-        });
 
-        let device = if let Some(custom_device_name) = custom_device {
-            input_devices
-                .find(|d| d.name().map(|name| name == custom_device_name).unwrap_or(false))
-                .unwrap_or_else(|| host.default_input_device().expect("No default input device available"))
-        } else {
-            host.default_input_device().expect("No default input device available")
-        };
+        let device = input_device(custom_device)?;
 
-        println!("Using audio device: {}", device.name().expect("Failed to get device name"));
+        info!("Using audio device: {}", device.name().expect("Failed to get device name"));
 
-        let config = device.supported_input_configs()
-            .expect("Failed to get supported input configs")
-            .find(|c| c.sample_format() == SampleFormat::F32 || c.sample_format() == SampleFormat::I16 || c.sample_format() == SampleFormat::I8 || c.sample_format() == SampleFormat::I32)
-            .unwrap_or_else(||
-                device.supported_input_configs().expect("Failed to get supported input configs").next().expect("No supported input config")
-            )
-            .with_max_sample_rate();
+        let config = input_config(&device)?;
 
         let sample_rate = config.sample_rate().0;
         let channels = config.channels();
@@ -75,49 +76,90 @@ impl AudioRecorder {
             _ => panic!("Unsupported sample format."),
         };
 
-        println!("Sample rate: {}", sample_rate);
-        println!("Channels: {}", channels);
-        println!("Sample format: {}", sample_format);
-        println!("Audio file path: {}", audio_file_path);
+        debug!("Sample rate: {}", sample_rate);
+        debug!("Channels: {}", channels);
+        debug!("Sample format: {}", sample_format);
+        debug!("Audio chunks directory: {}", audio_chunks_dir.display());
         
-        let ffmpeg_binary_path_str = ffmpeg_path_as_str().unwrap().to_owned();
-        let audio_file_path_owned = audio_file_path.to_owned();
+        let ffmpeg_binary = ffmpeg_path()?;
         let sample_rate_str = sample_rate.to_string();
+        let segment_time_str = self.options.as_ref().map_or(3, |options| options.segment_time_secs).to_string();
         let channels_str = channels.to_string();
 
-        println!("Starting audio recording and processing...");
-        let output_chunk_pattern = format!("{}/audio_recording_%03d.aac", audio_file_path_owned);
-        let segment_list_filename = format!("{}/segment_list.txt", audio_file_path_owned);
+        info!("Starting audio recording and processing...");
+        let options = self.options.as_ref().ok_or("Recording options are not set".to_string())?;
+        let (codec, extension, segment_format) = match options.audio_codec.as_str() {
+            "opus" => ("libopus", "webm", "webm"),
+            _ => ("aac", "aac", "adts"),
+        };
+        let output_sample_rate = match (options.audio_codec.as_str(), options.audio_sample_rate) {
+            ("opus", 0) => 48000,
+            (_, rate) => rate,
+        };
+        let bitrate = if options.audio_bitrate.is_empty() { "128k".to_string() } else { options.audio_bitrate.clone() };
+        let output_chunk_pattern = audio_chunks_dir.join(segment_pattern(&options.segment_prefix("audio"), extension));
+        let segment_list_filename = audio_chunks_dir.join("segment_list.txt");
       
         let mut audio_filters = Vec::new();
 
-        if channels > 2 {
-            audio_filters.push("pan=stereo|FL=FL+0.5*FC|FR=FR+0.5*FC");
+        if channels > 2 && options.audio_channels == 0 {
+            audio_filters.push("pan=stereo|FL=FL+0.5*FC|FR=FR+0.5*FC".to_string());
         }
 
-        audio_filters.push("loudnorm");
+        // A late mic is pulled forward by dropping its first samples; an early one
+        // is pushed back with silence.
+        if options.audio_offset_ms > 0 {
+            audio_filters.push(format!("atrim=start={:.3},asetpts=PTS-STARTPTS", options.audio_offset_ms as f64 / 1000.0));
+        } else if options.audio_offset_ms < 0 {
+            audio_filters.push(format!("adelay={}:all=1", -options.audio_offset_ms));
+        }
+
+        if options.auto_gain.unwrap_or_default() {
+            audio_filters.push(AUTO_GAIN_FILTER.to_string());
+        }
+        audio_filters.push("loudnorm".to_string());
 
         let audio_filters_str = audio_filters.join(",");
 
-        let ffmpeg_command: Vec<String> = vec![
+        let mut ffmpeg_command: Vec<OsString> = vec![
             "-f", sample_format,
             "-ar", &sample_rate_str,
             "-ac", &channels_str,
             "-i", "-",
-            "-c:a", "aac",
-            "-b:a", "128k",
+            "-c:a", codec,
+            "-b:a", &bitrate,
+        ].into_iter().map(OsString::from).collect();
+
+        if output_sample_rate != 0 {
+            ffmpeg_command.extend(["-ar".into(), output_sample_rate.to_string().into()]);
+        }
+        if options.audio_channels != 0 {
+            ffmpeg_command.extend(["-ac".into(), options.audio_channels.to_string().into()]);
+        }
+
+        ffmpeg_command.extend([
             "-af", &audio_filters_str,
             "-f", "segment",
-            "-segment_time", "3",
-            "-segment_list", &segment_list_filename,
-            &output_chunk_pattern,
-        ].into_iter().map(|s| s.to_string()).collect();
+            "-segment_time", &segment_time_str,
+            "-segment_format", segment_format,
+            "-segment_list",
+        ].map(OsString::from));
+        ffmpeg_command.push(segment_list_filename.into_os_string());
+        ffmpeg_command.push(output_chunk_pattern.into_os_string());
+
+        if let Some(speech_track) = speech_track {
+            ffmpeg_command.extend(["-ac", "1", "-ar", "16000", "-c:a", "pcm_s16le"].map(OsString::from));
+            ffmpeg_command.push(speech_track.into_os_string());
+        }
 
         let video_id = self.options.as_ref().unwrap().video_id.clone();
 
-        let mut child = start_audio_recording_process(&ffmpeg_binary_path_str, &video_id, &ffmpeg_command)
+        let mut child = start_audio_recording_process(&ffmpeg_binary, &video_id, &ffmpeg_command, log_path)
             .await
             .map_err(|e| e.to_string())?;
+        if let (Some(pid), Some(recording_dir)) = (child.id(), audio_chunks_dir.parent()) {
+            journal::append(recording_dir, JournalEvent::ProcessStarted { track: "audio".to_string(), pid });
+        }
 
         let mut stdin = child.stdin.take().expect("failed to take child stdin");
         let stdin = Arc::new(Mutex::new(stdin));
@@ -127,103 +169,53 @@ impl AudioRecorder {
             while let Some(bytes) = rx.recv().await {
                 let mut stdin_guard = stdin_clone.lock().await;
                 if stdin_guard.write_all(&bytes).await.is_err() {
-                    eprintln!("Failed to write to FFmpeg stdin");
+                    error!("Failed to write to FFmpeg stdin");
                     break;
                 }
             }
         });
 
-        let err_fn = move |err| {
-            eprintln!("an error occurred on stream: {}", err);
-        };
-        
-        let stream_result: Result<cpal::Stream, cpal::BuildStreamError> = match config.sample_format() {
-          SampleFormat::I8 => device.build_input_stream(
-              &config.into(),
-              move |data: &[i8], _: &_| {
-                  let bytes = data.iter().map(|&sample| sample as u8).collect::<Vec<u8>>();
-                  if tx.try_send(bytes).is_err() {
-                      eprintln!("Channel send error. Dropping data.");
-                  }
-              },
-              err_fn,
-              None,
-          ),
-          SampleFormat::I16 => device.build_input_stream(
-              &config.into(),
-              move |data: &[i16], _: &_| {
-                  let mut bytes = vec![0; data.len() * 2];
-                  LittleEndian::write_i16_into(data, &mut bytes);
-                  if tx.try_send(bytes).is_err() {
-                      eprintln!("Channel send error. Dropping data.");
-                  }
-              },
-              err_fn,
-              None,
-          ),
-          SampleFormat::I32 => device.build_input_stream(
-              &config.into(),
-              move |data: &[i32], _: &_| {
-                  let mut bytes = vec![0; data.len() * 4];
-                  LittleEndian::write_i32_into(data, &mut bytes);
-                  if tx.try_send(bytes).is_err() {
-                      eprintln!("Channel send error. Dropping data.");
-                  }
-              },
-              err_fn,
-              None, 
-          ),
-          SampleFormat::F32 => device.build_input_stream(
-              &config.into(),
-              move |data: &[f32], _: &_| {
-                  let bytes = bytemuck::cast_slice::<f32, u8>(data).to_vec();
-                  if tx.try_send(bytes).is_err() {
-                      eprintln!("Channel send error. Dropping data.");
-                  }
-              },
-              err_fn,
-              None,
-          ),
-          _sample_format => Err(cpal::BuildStreamError::DeviceNotAvailable),
-        };
-
-        let stream = stream_result.map_err(|_| "Failed to build input stream")?;
+        let level_reporter = app.map(|app| LevelReporter::new(app, sample_rate, channels));
+        let device_name = device.name().expect("Failed to get device name");
+        let stream = tokio::task::spawn_blocking(move || spawn_input_stream(device, config, tx, level_reporter))
+            .await
+            .map_err(|e| e.to_string())??;
 
         self.stream = Some(stream);
         self.ffmpeg_process = Some(child);
         self.ffmpeg_stdin = Some(stdin);
-        self.device_name = Some(device.name().expect("Failed to get device name"));
+        self.device_name = Some(device_name);
         
         self.trigger_play()?;
 
         Ok(())
     }
 
-    pub fn trigger_play (&mut self) -> Result<(), &'static str> {
-        if let Some(ref mut stream) = self.stream {
-            stream.play().map_err(|_| "Failed to play stream")?;
-            println!("Audio recording playing.");
-        } else {
-            return Err("Recording was not started");
+    /// Stops feeding ffmpeg until `trigger_play` is called again, so the paused time
+    /// is left out of the audio segments.
+    pub fn pause(&mut self) -> Result<(), &'static str> {
+        match self.stream {
+            Some(ref stream) => stream.send(StreamControl::Pause),
+            None => Err("Recording was not started"),
         }
+    }
 
-        Ok(())
+    pub fn trigger_play (&mut self) -> Result<(), &'static str> {
+        match self.stream {
+            Some(ref stream) => stream.send(StreamControl::Play),
+            None => Err("Recording was not started"),
+        }
     }
 
     pub async fn stop_audio_recording(&mut self) -> Result<(), String> {
-        if let Some(ref mut stream) = self.stream {
-            stream.pause().map_err(|_| "Failed to pause stream")?;
-            println!("Audio recording paused.");
-        } else {
-            return Err("Recording was not started".to_string());
+        match self.stream.take() {
+            Some(stream) => tokio::task::spawn_blocking(move || stream.stop()).await.map_err(|e| e.to_string())?,
+            None => return Err("Recording was not started".to_string()),
         }
 
-        if let Some(stdin_arc) = &self.ffmpeg_stdin {
-            let mut stdin = stdin_arc.lock().await;
-            if stdin.write_all(b"q").await.is_err() {
-                return Err("Failed to write to FFmpeg stdin".to_string());
-            }
-        }
+        // ffmpeg reads the samples from stdin, so closing it is what ends the recording.
+        // The writer task lets go of its end once the stream's sender is gone.
+        self.ffmpeg_stdin = None;
 
         if let Some(ref mut child) = self.ffmpeg_process {
             if let Err(e) = child.wait().await {
@@ -237,32 +229,330 @@ impl AudioRecorder {
     }
 }
 
-#[tauri::command]
-pub fn enumerate_audio_devices() -> Vec<String> {
-    let host = cpal::default_host();
-    let default_device = host.default_input_device().expect("No default input device available");
-    let default_device_name = default_device.name().expect("Failed to get default device name");
-
-    let devices = host.devices().expect("Failed to get devices");
-    let mut input_device_names: Vec<String> = devices
-        .filter_map(|device| {
-            let supported_input_configs = device.supported_input_configs();
-            if supported_input_configs.is_ok() && supported_input_configs.unwrap().count() > 0 {
-                device.name().ok()
-            } else {
-                None
+enum StreamControl {
+    Play,
+    Pause,
+    Stop,
+}
+
+/// Handle to the thread that owns the input stream. cpal streams can't be moved
+/// between threads on every platform, so the stream stays on its thread and is driven
+/// over a channel, like the native encoder.
+struct InputStreamHandle {
+    control: std::sync::mpsc::Sender<StreamControl>,
+    thread: Option<std::thread::JoinHandle<()>>,
+}
+
+impl InputStreamHandle {
+    fn send(&self, control: StreamControl) -> Result<(), &'static str> {
+        self.control.send(control).map_err(|_| "Audio stream is not running")
+    }
+
+    /// Stops and drops the stream, which closes the sample channel. Blocks the caller.
+    fn stop(mut self) {
+        let _ = self.control.send(StreamControl::Stop);
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("Audio stream thread panicked");
+            }
+        }
+    }
+}
+
+/// Builds the input stream on its own thread, paused. Blocks until it is built.
+fn spawn_input_stream(
+    device: cpal::Device,
+    config: cpal::SupportedStreamConfig,
+    tx: tokio::sync::mpsc::Sender<Vec<u8>>,
+    level_reporter: Option<LevelReporter>,
+) -> Result<InputStreamHandle, String> {
+    let (control_tx, control_rx) = std::sync::mpsc::channel();
+    let (ready_tx, ready_rx) = std::sync::mpsc::channel();
+
+    let thread = std::thread::Builder::new()
+        .name("audio-input-stream".to_string())
+        .spawn(move || {
+            let stream = match build_input_stream(&device, config, tx, level_reporter) {
+                Ok(stream) => {
+                    let _ = ready_tx.send(Ok(()));
+                    stream
+                },
+                Err(e) => {
+                    let _ = ready_tx.send(Err(e));
+                    return;
+                },
+            };
+
+            for control in control_rx {
+                let result = match control {
+                    StreamControl::Play => stream.play().map(|_| info!("Audio recording playing.")).map_err(|e| e.to_string()),
+                    StreamControl::Pause => stream.pause().map(|_| info!("Audio recording paused.")).map_err(|e| e.to_string()),
+                    StreamControl::Stop => break,
+                };
+                if let Err(e) = result {
+                    error!("Failed to control the audio stream: {}", e);
+                }
+            }
+
+            if let Err(e) = stream.pause() {
+                warn!("Failed to pause the audio stream before stopping: {}", e);
             }
         })
+        .map_err(|e| e.to_string())?;
+
+    ready_rx.recv().map_err(|_| "Audio stream thread exited during startup".to_string())??;
+
+    Ok(InputStreamHandle { control: control_tx, thread: Some(thread) })
+}
+
+fn build_input_stream(
+    device: &cpal::Device,
+    config: cpal::SupportedStreamConfig,
+    tx: tokio::sync::mpsc::Sender<Vec<u8>>,
+    mut level_reporter: Option<LevelReporter>,
+) -> Result<cpal::Stream, String> {
+    let err_fn = move |err| {
+        error!("an error occurred on stream: {}", err);
+    };
+
+    let stream_result: Result<cpal::Stream, cpal::BuildStreamError> = match config.sample_format() {
+      SampleFormat::I8 => device.build_input_stream(
+          &config.into(),
+          move |data: &[i8], _: &_| {
+              if let Some(ref mut reporter) = level_reporter {
+                  reporter.push(data.iter().map(|&s| s as f32 / i8::MAX as f32));
+              }
+              let bytes = data.iter().map(|&sample| sample as u8).collect::<Vec<u8>>();
+              if tx.try_send(bytes).is_err() {
+                  warn!("Channel send error. Dropping data.");
+              }
+          },
+          err_fn,
+          None,
+      ),
+      SampleFormat::I16 => device.build_input_stream(
+          &config.into(),
+          move |data: &[i16], _: &_| {
+              if let Some(ref mut reporter) = level_reporter {
+                  reporter.push(data.iter().map(|&s| s as f32 / i16::MAX as f32));
+              }
+              let mut bytes = vec![0; data.len() * 2];
+              LittleEndian::write_i16_into(data, &mut bytes);
+              if tx.try_send(bytes).is_err() {
+                  warn!("Channel send error. Dropping data.");
+              }
+          },
+          err_fn,
+          None,
+      ),
+      SampleFormat::I32 => device.build_input_stream(
+          &config.into(),
+          move |data: &[i32], _: &_| {
+              if let Some(ref mut reporter) = level_reporter {
+                  reporter.push(data.iter().map(|&s| s as f32 / i32::MAX as f32));
+              }
+              let mut bytes = vec![0; data.len() * 4];
+              LittleEndian::write_i32_into(data, &mut bytes);
+              if tx.try_send(bytes).is_err() {
+                  warn!("Channel send error. Dropping data.");
+              }
+          },
+          err_fn,
+          None, 
+      ),
+      SampleFormat::F32 => device.build_input_stream(
+          &config.into(),
+          move |data: &[f32], _: &_| {
+              if let Some(ref mut reporter) = level_reporter {
+                  reporter.push(data.iter().copied());
+              }
+              let bytes = bytemuck::cast_slice::<f32, u8>(data).to_vec();
+              if tx.try_send(bytes).is_err() {
+                  warn!("Channel send error. Dropping data.");
+              }
+          },
+          err_fn,
+          None,
+      ),
+      _sample_format => Err(cpal::BuildStreamError::DeviceNotAvailable),
+    };
+
+    stream_result.map_err(|_| "Failed to build input stream".to_string())
+}
+
+/// Checks the audio encoding fields of `RecordingOptions`; zero sample rates and
+/// channel counts keep the input's.
+pub fn validate_audio_format(codec: &str, sample_rate: u32, channels: u16, bitrate: &str) -> Result<(), String> {
+    match codec {
+        "" | "aac" => {},
+        "opus" if sample_rate == 44100 => return Err("Opus only supports 48 kHz audio".to_string()),
+        "opus" => {},
+        _ => return Err(format!("Unsupported audio codec: {}", codec)),
+    }
+    if ![0, 44100, 48000].contains(&sample_rate) {
+        return Err(format!("Unsupported audio sample rate: {}", sample_rate));
+    }
+    if channels > 2 {
+        return Err("Audio can be recorded in mono or stereo".to_string());
+    }
+    let valid_bitrate = bitrate.strip_suffix('k')
+        .and_then(|kbps| kbps.parse::<u32>().ok())
+        .map_or(false, |kbps| (16..=320).contains(&kbps));
+    if !bitrate.is_empty() && !valid_bitrate {
+        return Err(format!("Invalid audio bitrate: {} (expected e.g. 96k)", bitrate));
+    }
+    Ok(())
+}
+
+/// The input device named `custom_device`, falling back to the default input when
+/// it isn't given or can't be found.
+/// Shared-mode WASAPI on Windows, the platform default elsewhere.
+fn audio_host() -> cpal::Host {
+    #[cfg(target_os = "windows")]
+    {
+        if let Ok(host) = cpal::host_from_id(cpal::HostId::Wasapi) {
+            return host;
+        }
+    }
+    cpal::default_host()
+}
+
+/// Input devices with unique labels: when several devices share a name (common with
+/// identical USB mics or localized endpoint names on Windows), later ones get a
+/// ` (2)`, ` (3)` ... suffix in enumeration order.
+fn labelled_input_devices(host: &cpal::Host) -> Result<Vec<(String, cpal::Device)>, String> {
+    let devices = host.input_devices().map_err(|e| format!("Failed to get devices: {}", e))?;
+    let mut labelled: Vec<(String, cpal::Device)> = vec![];
+    for device in devices {
+        let has_config = device.supported_input_configs().map_or(false, |mut configs| configs.next().is_some());
+        let name = match device.name() {
+            Ok(name) if has_config => name,
+            _ => continue,
+        };
+        let duplicates = labelled.iter().filter(|(_, other)| other.name().ok().as_deref() == Some(name.as_str())).count();
+        let label = if duplicates == 0 { name } else { format!("{} ({})", name, duplicates + 1) };
+        labelled.push((label, device));
+    }
+    Ok(labelled)
+}
+
+/// Prefix of `audio_name`s that pick a PulseAudio/PipeWire source on Linux, e.g.
+/// `pulse:alsa_output.pci-0000_00_1f.3.analog-stereo.monitor` for system audio.
+pub const PULSE_SOURCE_PREFIX: &str = "pulse:";
+
+/// A PulseAudio source, which PipeWire also exposes through pipewire-pulse.
+#[derive(Debug, Clone, Serialize)]
+pub struct PulseSource {
+    /// Pass as `audio_name`, prefixed with `PULSE_SOURCE_PREFIX`.
+    pub name: String,
+    /// Monitor sources capture what an output device plays, i.e. system audio.
+    pub is_monitor: bool,
+}
+
+/// Lists the PulseAudio/PipeWire sources, or nothing where `pactl` isn't available.
+#[tauri::command]
+pub fn list_pulse_sources() -> Vec<PulseSource> {
+    if std::env::consts::OS != "linux" {
+        return vec![];
+    }
+
+    // Not `run_command`, which panics when the tool isn't installed.
+    let output = match std::process::Command::new("pactl").args(["list", "short", "sources"]).output() {
+        Ok(output) if output.status.success() => String::from_utf8_lossy(&output.stdout).into_owned(),
+        Ok(output) => {
+            debug!("pactl exited with {}", output.status);
+            return vec![];
+        },
+        Err(e) => {
+            debug!("Could not list PulseAudio sources: {}", e);
+            return vec![];
+        },
+    };
+
+    // Tab-separated: index, name, driver, sample spec, state.
+    output.lines()
+        .filter_map(|line| line.split('\t').nth(1))
+        .map(|name| PulseSource { name: name.to_string(), is_monitor: name.ends_with(".monitor") })
+        .collect()
+}
+
+/// Opens a PulseAudio/PipeWire source through the ALSA plugin devices, which read the
+/// source to capture from the environment when the stream is opened.
+fn pulse_source_device(host: &cpal::Host, source: &str) -> Result<cpal::Device, String> {
+    std::env::set_var("PULSE_SOURCE", source);
+    std::env::set_var("PIPEWIRE_NODE", source);
+
+    let mut devices = labelled_input_devices(host)?;
+    let index = ["pulse", "pipewire"].iter()
+        .find_map(|plugin| devices.iter().position(|(label, _)| label == plugin))
+        .ok_or("Selecting a PulseAudio source requires the ALSA pulse or pipewire plugin".to_string())?;
+    Ok(devices.swap_remove(index).1)
+}
+
+pub fn input_device(custom_device: Option<&str>) -> Result<cpal::Device, String> {
+    let host = audio_host();
+
+    if let Some(source) = custom_device.and_then(|name| name.strip_prefix(PULSE_SOURCE_PREFIX)) {
+        return pulse_source_device(&host, source);
+    }
+
+    if let Some(custom_device_name) = custom_device {
+        let device = labelled_input_devices(&host)?
+            .into_iter()
+            .find(|(label, _)| label == custom_device_name)
+            .map(|(_, device)| device);
+        match device {
+            Some(device) => return Ok(device),
+            None => warn!("Audio device {} not found, using the default input", custom_device_name),
+        }
+    }
+
+    host.default_input_device().ok_or("No default input device available".to_string())
+}
+
+/// Picks the capture format. WASAPI in shared mode only opens the endpoint's mix
+/// format, so on Windows that is what the device reports as its default; elsewhere the
+/// highest sample rate of a supported sample format is used.
+fn input_config(device: &cpal::Device) -> Result<cpal::SupportedStreamConfig, String> {
+    if cfg!(target_os = "windows") {
+        return device.default_input_config().map_err(|e| format!("Failed to get input config: {}", e));
+    }
+
+    let supported = |format: SampleFormat| matches!(format, SampleFormat::F32 | SampleFormat::I16 | SampleFormat::I8 | SampleFormat::I32);
+    let mut configs: Vec<_> = device.supported_input_configs()
+        .map_err(|e| format!("Failed to get supported input configs: {}", e))?
         .collect();
+    if configs.is_empty() {
+        return Err("No supported input config".to_string());
+    }
+    let index = configs.iter().position(|config| supported(config.sample_format())).unwrap_or(0);
+    Ok(configs.swap_remove(index).with_max_sample_rate())
+}
 
-    input_device_names.retain(|name| name != &default_device_name);
-    input_device_names.insert(0, default_device_name);
+#[tauri::command]
+pub fn enumerate_audio_devices() -> Vec<String> {
+    let host = audio_host();
+    let mut input_device_names: Vec<String> = match labelled_input_devices(&host) {
+        Ok(devices) => devices.into_iter().map(|(label, _)| label).collect(),
+        Err(e) => {
+            error!("{}", e);
+            return vec![];
+        },
+    };
+
+    // The default device's own name is its first label, so it can be moved to the front.
+    if let Some(default_device_name) = host.default_input_device().and_then(|device| device.name().ok()) {
+        if let Some(index) = input_device_names.iter().position(|name| name == &default_device_name) {
+            let default_device_name = input_device_names.remove(index);
+            input_device_names.insert(0, default_device_name);
+        }
+    }
 
     input_device_names
 }
 
-async fn start_audio_recording_process(ffmpeg_binary_path_str: &str, video_id: &str, audio_args: &[String]) -> Result<(tokio::process::Child), std::io::Error> {
-    let mut child = Command::new(ffmpeg_binary_path_str)
+async fn start_audio_recording_process(ffmpeg_binary: &Path, video_id: &str, audio_args: &[OsString], log_path: PathBuf) -> Result<(tokio::process::Child), std::io::Error> {
+    let mut child = Command::new(ffmpeg_binary)
         .args(audio_args)
         .stdin(Stdio::piped())
         .stdout(Stdio::piped())
@@ -274,8 +564,8 @@ async fn start_audio_recording_process(ffmpeg_binary_path_str: &str, video_id: &
     let video_id_owned = video_id.to_owned();
 
     tokio::spawn(async move {
-        if let Err(e) = monitor_and_log_recording_start(stderr, &video_id_owned, "audio").await {
-            eprintln!("Failed to monitor and log audio recording start: {}", e);
+        if let Err(e) = monitor_and_log_recording_start(stderr, &video_id_owned, "audio", log_path, None, true).await {
+            error!("Failed to monitor and log audio recording start: {}", e);
         }
     });
 
@@ -0,0 +1,226 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use cpal::traits::{DeviceTrait, StreamTrait};
+use cpal::SampleFormat;
+use serde::Serialize;
+use tauri::{AppHandle, Manager};
+use tracing::{error, info, warn};
+
+use crate::audio::input_device;
+use crate::device_arbitration::{acquire_now, mic_device, DeviceUser};
+
+/// Emitted with an `AudioLevel` about ten times a second while the mic preview runs.
+pub const MIC_PREVIEW_EVENT: &str = "audio://preview-level";
+
+/// Emitted with an `AudioLevel` about ten times a second while recording.
+pub const RECORDING_LEVEL_EVENT: &str = "audio://recording-level";
+/// Emitted with `true` once the mic has been silent for `SILENCE_SECS` while
+/// recording, and with `false` when sound comes back.
+pub const MIC_SILENCE_EVENT: &str = "audio://silence";
+
+/// How much audio each level covers.
+const LEVEL_WINDOW_SECS: f32 = 0.1;
+/// Peaks below this (about -60 dBFS) count as silence.
+const SILENCE_PEAK: f32 = 0.001;
+const SILENCE_SECS: f32 = 5.0;
+
+static PREVIEW_STOP: Mutex<Option<std::sync::mpsc::Sender<()>>> = Mutex::new(None);
+
+/// Linear levels from 0 to 1 over the last window of samples.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct AudioLevel {
+    pub rms: f32,
+    pub peak: f32,
+}
+
+/// Accumulates samples and produces a level once a full window has been seen.
+pub struct LevelMeter {
+    window_samples: usize,
+    count: usize,
+    sum_squares: f32,
+    peak: f32,
+}
+
+impl LevelMeter {
+    /// `sample_rate` and `channels` describe the interleaved samples passed to `push`.
+    pub fn new(sample_rate: u32, channels: u16) -> Self {
+        LevelMeter {
+            window_samples: ((sample_rate as f32 * LEVEL_WINDOW_SECS) as usize * channels as usize).max(1),
+            count: 0,
+            sum_squares: 0.0,
+            peak: 0.0,
+        }
+    }
+
+    pub fn push(&mut self, samples: impl Iterator<Item = f32>) -> Option<AudioLevel> {
+        let mut level = None;
+        for sample in samples {
+            self.sum_squares += sample * sample;
+            self.peak = self.peak.max(sample.abs());
+            self.count += 1;
+
+            if self.count == self.window_samples {
+                level = Some(AudioLevel {
+                    rms: (self.sum_squares / self.count as f32).sqrt().min(1.0),
+                    peak: self.peak.min(1.0),
+                });
+                self.count = 0;
+                self.sum_squares = 0.0;
+                self.peak = 0.0;
+            }
+        }
+        level
+    }
+}
+
+/// Emits the levels of a recording's audio and warns when the mic stays silent, so a
+/// muted or wrong input is noticed within seconds.
+pub struct LevelReporter {
+    app: AppHandle,
+    meter: LevelMeter,
+    silent_windows: u32,
+}
+
+impl LevelReporter {
+    pub fn new(app: AppHandle, sample_rate: u32, channels: u16) -> Self {
+        LevelReporter { app, meter: LevelMeter::new(sample_rate, channels), silent_windows: 0 }
+    }
+
+    pub fn push(&mut self, samples: impl Iterator<Item = f32>) {
+        let level = match self.meter.push(samples) {
+            Some(level) => level,
+            None => return,
+        };
+        let _ = self.app.emit_all(RECORDING_LEVEL_EVENT, level);
+
+        let silence_windows = (SILENCE_SECS / LEVEL_WINDOW_SECS) as u32;
+        if level.peak < SILENCE_PEAK {
+            self.silent_windows += 1;
+            if self.silent_windows == silence_windows {
+                warn!("Microphone has been silent for {} seconds", SILENCE_SECS);
+                let _ = self.app.emit_all(MIC_SILENCE_EVENT, true);
+            }
+        } else {
+            if self.silent_windows >= silence_windows {
+                let _ = self.app.emit_all(MIC_SILENCE_EVENT, false);
+            }
+            self.silent_windows = 0;
+        }
+    }
+}
+
+/// Opens `device` (or the default input) without recording anything and emits its
+/// levels as `MIC_PREVIEW_EVENT` until `stop_mic_preview` is called. Starting a new
+/// preview replaces the running one. Fails while a recording or test uses the mic.
+#[tauri::command]
+pub fn start_mic_preview(app: AppHandle, device: Option<String>) -> Result<(), String> {
+    stop_preview();
+    let lease = acquire_now(DeviceUser::MicPreview, vec![mic_device(device.as_deref())])?;
+
+    let (stop_tx, stop_rx) = std::sync::mpsc::channel::<()>();
+    let (started_tx, started_rx) = std::sync::mpsc::channel::<Result<(), String>>();
+
+    // cpal streams can't move between threads on every platform, so the stream lives
+    // on its own thread until the stop channel fires or is dropped.
+    std::thread::spawn(move || {
+        let stream = match build_level_stream(device.as_deref(), move |level| {
+            let _ = app.emit_all(MIC_PREVIEW_EVENT, level);
+        }) {
+            Ok(stream) => stream,
+            Err(e) => {
+                let _ = started_tx.send(Err(e));
+                return;
+            },
+        };
+        let _ = started_tx.send(Ok(()));
+        let _ = stop_rx.recv();
+        drop(stream);
+        drop(lease);
+        info!("Mic preview stopped");
+    });
+
+    started_rx.recv().map_err(|e| e.to_string())??;
+    *PREVIEW_STOP.lock().map_err(|e| e.to_string())? = Some(stop_tx);
+
+    Ok(())
+}
+
+#[tauri::command]
+pub fn stop_mic_preview() {
+    stop_preview();
+}
+
+/// Also used when a recording starts, so the preview never competes for the device.
+pub fn stop_preview() {
+    if let Ok(mut stop) = PREVIEW_STOP.lock() {
+        if let Some(stop) = stop.take() {
+            let _ = stop.send(());
+        }
+    }
+}
+
+/// Opens `device` (or the default input) and records for `duration` without keeping
+/// anything, returning the loudest levels seen. Blocks the calling thread.
+pub fn measure_input_level(device: Option<&str>, duration: Duration) -> Result<AudioLevel, String> {
+    let loudest = Arc::new(Mutex::new(AudioLevel { rms: 0.0, peak: 0.0 }));
+    let loudest_clone = loudest.clone();
+    let stream = build_level_stream(device, move |level| {
+        if let Ok(mut loudest) = loudest_clone.lock() {
+            loudest.rms = loudest.rms.max(level.rms);
+            loudest.peak = loudest.peak.max(level.peak);
+        }
+    })?;
+    std::thread::sleep(duration);
+    drop(stream);
+
+    let level = *loudest.lock().map_err(|e| e.to_string())?;
+    Ok(level)
+}
+
+/// Opens `device` (or the default input) and calls `on_level` for every window of
+/// samples until the returned stream is dropped.
+fn build_level_stream(device: Option<&str>, mut on_level: impl FnMut(AudioLevel) + Send + 'static) -> Result<cpal::Stream, String> {
+    let device = input_device(device)?;
+    let config = device.default_input_config().map_err(|e| format!("Failed to get input config: {}", e))?;
+    let mut meter = LevelMeter::new(config.sample_rate().0, config.channels());
+    info!("Opening {} to measure levels", device.name().unwrap_or_default());
+
+    let mut emit = move |samples: &mut dyn Iterator<Item = f32>| {
+        if let Some(level) = meter.push(samples) {
+            on_level(level);
+        }
+    };
+    let err_fn = |err| error!("an error occurred on the level stream: {}", err);
+
+    let stream = match config.sample_format() {
+        SampleFormat::I8 => device.build_input_stream(
+            &config.into(),
+            move |data: &[i8], _: &_| emit(&mut data.iter().map(|&s| s as f32 / i8::MAX as f32)),
+            err_fn,
+            None,
+        ),
+        SampleFormat::I16 => device.build_input_stream(
+            &config.into(),
+            move |data: &[i16], _: &_| emit(&mut data.iter().map(|&s| s as f32 / i16::MAX as f32)),
+            err_fn,
+            None,
+        ),
+        SampleFormat::I32 => device.build_input_stream(
+            &config.into(),
+            move |data: &[i32], _: &_| emit(&mut data.iter().map(|&s| s as f32 / i32::MAX as f32)),
+            err_fn,
+            None,
+        ),
+        SampleFormat::F32 => device.build_input_stream(
+            &config.into(),
+            move |data: &[f32], _: &_| emit(&mut data.iter().copied()),
+            err_fn,
+            None,
+        ),
+        sample_format => return Err(format!("Unsupported sample format: {}", sample_format)),
+    }.map_err(|e| format!("Failed to build level stream: {}", e))?;
+
+    stream.play().map_err(|e| format!("Failed to start level stream: {}", e))?;
+
+    Ok(stream)
+}
@@ -0,0 +1,164 @@
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+use sysinfo::Disks;
+use tauri::{AppHandle, Manager};
+use tokio::sync::Mutex;
+use tracing::{debug, warn};
+
+use crate::manifest::{pending_segments, update_manifest};
+use crate::session::RecordingState;
+
+/// Emitted with a `BacklogWarning` when the segments waiting for upload pile up.
+pub const UPLOAD_BACKLOG_WARNING_EVENT: &str = "recording://upload-backlog-warning";
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+/// The backlog's growth is measured over this long, so that a single new segment
+/// doesn't count as a trend.
+const GROWTH_WINDOW: Duration = Duration::from_secs(60);
+/// Warns when the disk would fill up this soon at the rate the backlog grows.
+const DISK_FULL_WARNING: Duration = Duration::from_secs(30 * 60);
+/// A warning is raised again only after the backlog has dropped below this share of
+/// what raised it.
+const REARM_SHARE: f64 = 0.5;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BacklogWarningKind {
+    /// More than `max_upload_backlog_mb` is waiting for upload.
+    LimitExceeded,
+    /// The backlog grows fast enough to fill the disk within half an hour.
+    DiskFilling,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BacklogWarning {
+    pub video_id: String,
+    pub kind: BacklogWarningKind,
+    pub unuploaded_bytes: u64,
+    pub limit_bytes: u64,
+    pub available_disk_bytes: Option<u64>,
+    /// At the backlog's current growth; `None` while it isn't growing.
+    pub secs_until_disk_full: Option<f64>,
+    /// Recorded time when the warning was raised.
+    pub offset_secs: f64,
+    pub message: String,
+}
+
+/// Bytes of this recording's segments on disk that are neither uploaded nor skipped.
+pub fn unuploaded_bytes(recording_dir: &Path) -> u64 {
+    ["screen", "audio"]
+        .iter()
+        .flat_map(|video_type| {
+            let chunks_dir = recording_dir.join(video_type);
+            pending_segments(recording_dir, video_type)
+                .unwrap_or_default()
+                .into_iter()
+                .map(move |segment| chunks_dir.join(segment))
+        })
+        .filter_map(|path| path.metadata().ok())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+/// Free space on the disk holding `path`.
+fn available_disk_bytes(path: &Path) -> Option<u64> {
+    Disks::new_with_refreshed_list()
+        .list()
+        .iter()
+        .filter(|disk| path.starts_with(disk.mount_point()))
+        .max_by_key(|disk| disk.mount_point().as_os_str().len())
+        .map(|disk| disk.available_space())
+}
+
+/// Watches how much of the recording is waiting for upload while it runs. Warns
+/// through `UPLOAD_BACKLOG_WARNING_EVENT` when that passes `limit_bytes` (`0` never
+/// does) or would fill the disk within half an hour, so an hours-long offline
+/// recording can be stopped before it runs out of space. Warnings are kept in the
+/// manifest.
+pub fn spawn_backpressure_task(
+    app: Option<AppHandle>,
+    state: Arc<Mutex<RecordingState>>,
+    recording_dir: PathBuf,
+    video_id: String,
+    limit_bytes: u64,
+) {
+    tauri::async_runtime::spawn(async move {
+        let mut samples: VecDeque<(Instant, u64)> = VecDeque::new();
+        let mut limit_warned_at: Option<u64> = None;
+        let mut disk_warned_at: Option<u64> = None;
+
+        loop {
+            tokio::time::sleep(CHECK_INTERVAL).await;
+            let guard = state.lock().await;
+            if !guard.is_recording() || guard.recording_dir.as_ref() != Some(&recording_dir) {
+                break;
+            }
+            let offset_secs = guard.elapsed().as_secs_f64();
+            drop(guard);
+
+            let dir = recording_dir.clone();
+            let Ok((unuploaded, available)) = tokio::task::spawn_blocking(move || (unuploaded_bytes(&dir), available_disk_bytes(&dir))).await else {
+                break;
+            };
+
+            let now = Instant::now();
+            samples.push_back((now, unuploaded));
+            while samples.front().map_or(false, |(at, _)| now.duration_since(*at) > GROWTH_WINDOW) {
+                samples.pop_front();
+            }
+            let growth = samples.front()
+                .filter(|(at, _)| now.duration_since(*at) >= GROWTH_WINDOW / 2)
+                .map(|(at, bytes)| (unuploaded as f64 - *bytes as f64) / now.duration_since(*at).as_secs_f64())
+                .filter(|growth| *growth > 0.0);
+            let secs_until_disk_full = available.zip(growth).map(|(available, growth)| available as f64 / growth);
+            debug!("Upload backlog of {}: {} bytes, {:?} bytes free, growing {:?} bytes/s", video_id, unuploaded, available, growth);
+
+            let mut raised = vec![];
+            if limit_bytes > 0 && unuploaded >= limit_bytes && limit_warned_at.is_none() {
+                limit_warned_at = Some(unuploaded);
+                raised.push(BacklogWarningKind::LimitExceeded);
+            }
+            if secs_until_disk_full.map_or(false, |secs| secs < DISK_FULL_WARNING.as_secs_f64()) && disk_warned_at.is_none() {
+                disk_warned_at = Some(unuploaded);
+                raised.push(BacklogWarningKind::DiskFilling);
+            }
+            for warned_at in [&mut limit_warned_at, &mut disk_warned_at] {
+                if warned_at.map_or(false, |bytes| (unuploaded as f64) < bytes as f64 * REARM_SHARE) {
+                    *warned_at = None;
+                }
+            }
+
+            for kind in raised {
+                let warning = BacklogWarning {
+                    video_id: video_id.clone(),
+                    kind,
+                    unuploaded_bytes: unuploaded,
+                    limit_bytes,
+                    available_disk_bytes: available,
+                    secs_until_disk_full,
+                    offset_secs,
+                    message: warning_message(kind, unuploaded, secs_until_disk_full),
+                };
+                warn!("{} for {}", warning.message, video_id);
+                if let Some(ref app) = app {
+                    let _ = app.emit_all(UPLOAD_BACKLOG_WARNING_EVENT, &warning);
+                }
+                if let Err(e) = update_manifest(&recording_dir, |manifest| manifest.backlog_warnings.push(warning)) {
+                    warn!("Failed to save the backlog warning of {}: {}", video_id, e);
+                }
+            }
+        }
+    });
+}
+
+fn warning_message(kind: BacklogWarningKind, unuploaded_bytes: u64, secs_until_disk_full: Option<f64>) -> String {
+    let unuploaded_mb = unuploaded_bytes / (1024 * 1024);
+    match (kind, secs_until_disk_full) {
+        (BacklogWarningKind::DiskFilling, Some(secs)) => {
+            format!("Disk full in about {} minutes — {} MB waiting for upload", (secs / 60.0).ceil(), unuploaded_mb)
+        },
+        _ => format!("{} MB waiting for upload — check the connection or stop the recording", unuploaded_mb),
+    }
+}
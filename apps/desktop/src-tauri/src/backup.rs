@@ -0,0 +1,179 @@
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use std::time::Duration;
+use reqwest::{Method, StatusCode, Url};
+use serde::{Serialize, Deserialize};
+use tracing::{debug, info, warn};
+
+use crate::recording::RecordingOptions;
+use crate::upload::file_body;
+
+/// Segments waiting to be copied to the backup destination, mirrored per video type.
+/// They are hard links (or copies) of the chunks, so the primary upload can delete
+/// its file without waiting for the backup.
+const BACKUP_DIR: &str = "backup";
+const MAX_BACKUP_ATTEMPTS: u32 = 5;
+
+static BACKUP_DESTINATION: RwLock<Option<BackupDestination>> = RwLock::new(None);
+
+/// A second place every segment is copied to, next to the primary upload.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BackupDestination {
+    /// A local or mounted network directory.
+    Directory { path: String },
+    /// A WebDAV collection; credentials are optional.
+    #[serde(rename = "webdav")]
+    WebDav {
+        url: String,
+        #[serde(default)]
+        username: String,
+        #[serde(default)]
+        password: String,
+    },
+}
+
+impl BackupDestination {
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            BackupDestination::Directory { path } => {
+                if !Path::new(path).is_dir() {
+                    return Err(format!("Backup directory does not exist: {}", path));
+                }
+            },
+            BackupDestination::WebDav { url, .. } => {
+                let parsed = Url::parse(url).map_err(|e| format!("Invalid WebDAV URL {}: {}", url, e))?;
+                if parsed.scheme() != "http" && parsed.scheme() != "https" {
+                    return Err(format!("WebDAV URL must use http or https: {}", url));
+                }
+            },
+        }
+        Ok(())
+    }
+}
+
+pub fn set_backup_destination(destination: Option<BackupDestination>) {
+    match BACKUP_DESTINATION.write() {
+        Ok(mut guard) => *guard = destination,
+        Err(e) => warn!("Failed to update the backup destination: {}", e),
+    }
+}
+
+fn backup_destination() -> Option<BackupDestination> {
+    BACKUP_DESTINATION.read().ok().and_then(|guard| guard.clone())
+}
+
+/// Queues a segment for backup before its primary upload starts. Does nothing when no
+/// backup destination is configured.
+pub fn stage_segment(recording_dir: &Path, video_type: &str, segment_path: &Path) -> Option<PathBuf> {
+    backup_destination()?;
+
+    let staged_dir = recording_dir.join(BACKUP_DIR).join(video_type);
+    let staged_path = staged_dir.join(segment_path.file_name()?);
+    let result = std::fs::create_dir_all(&staged_dir)
+        .and_then(|_| std::fs::hard_link(segment_path, &staged_path).or_else(|_| std::fs::copy(segment_path, &staged_path).map(|_| ())));
+
+    match result {
+        Ok(_) => Some(staged_path),
+        Err(e) => {
+            warn!("Failed to stage {} for backup: {}", segment_path.display(), e);
+            None
+        },
+    }
+}
+
+/// Copies a staged segment to the backup destination, retrying on its own schedule
+/// so a slow or unreachable backup never holds up the primary upload. The staged file
+/// is removed once the copy succeeds and kept for `retry_backups` otherwise.
+pub async fn backup_segment(options: RecordingOptions, video_type: String, staged_path: PathBuf) -> Result<(), String> {
+    let destination = match backup_destination() {
+        Some(destination) => destination,
+        None => return Ok(()),
+    };
+    let file_name = staged_path.file_name().and_then(|name| name.to_str()).ok_or("Invalid file path")?.to_string();
+    let relative_path = [options.user_id.as_str(), options.video_id.as_str(), video_type.as_str(), file_name.as_str()];
+
+    let mut attempt = 1;
+    loop {
+        match copy_to_destination(&destination, &relative_path, &staged_path).await {
+            Ok(_) => break,
+            Err(e) if attempt < MAX_BACKUP_ATTEMPTS => {
+                let backoff = Duration::from_secs(2u64.pow(attempt));
+                warn!("Backup of {} failed (attempt {}), retrying in {:?}: {}", file_name, attempt, backoff, e);
+                tokio::time::sleep(backoff).await;
+                attempt += 1;
+            },
+            Err(e) => return Err(format!("Failed to back up {}: {}", file_name, e)),
+        }
+    }
+
+    debug!("Backed up {}", file_name);
+    tokio::fs::remove_file(&staged_path).await
+        .map_err(|e| format!("Failed to remove backed up segment {}: {}", file_name, e))
+}
+
+/// Backs up the segments left staged by earlier failed attempts.
+pub async fn retry_backups(recording_dir: &Path, options: &RecordingOptions) -> Result<(), String> {
+    let mut failed = 0;
+    for video_type in ["screen", "audio"] {
+        let entries = match std::fs::read_dir(recording_dir.join(BACKUP_DIR).join(video_type)) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for entry in entries.flatten() {
+            if let Err(e) = backup_segment(options.clone(), video_type.to_string(), entry.path()).await {
+                warn!("{}", e);
+                failed += 1;
+            }
+        }
+    }
+
+    if failed > 0 {
+        return Err(format!("{} segments could not be backed up", failed));
+    }
+    info!("Backups for {} are complete", options.video_id);
+    Ok(())
+}
+
+async fn copy_to_destination(destination: &BackupDestination, relative_path: &[&str], source: &Path) -> Result<(), String> {
+    match destination {
+        BackupDestination::Directory { path } => {
+            let target = relative_path.iter().fold(PathBuf::from(path), |target, part| target.join(part));
+            if let Some(parent) = target.parent() {
+                tokio::fs::create_dir_all(parent).await.map_err(|e| e.to_string())?;
+            }
+            tokio::fs::copy(source, &target).await.map_err(|e| e.to_string())?;
+        },
+        BackupDestination::WebDav { url, username, password } => {
+            let client = reqwest::Client::new();
+            let auth = |request: reqwest::RequestBuilder| {
+                if username.is_empty() {
+                    request
+                } else {
+                    request.basic_auth(username, Some(password))
+                }
+            };
+
+            // WebDAV won't create missing parent collections on PUT.
+            let mut collection = url.trim_end_matches('/').to_string();
+            for part in &relative_path[..relative_path.len() - 1] {
+                collection = format!("{}/{}", collection, part);
+                let mkcol = Method::from_bytes(b"MKCOL").map_err(|e| e.to_string())?;
+                let response = auth(client.request(mkcol, format!("{}/", collection))).send().await.map_err(|e| e.to_string())?;
+                // 405 means the collection already exists.
+                if !response.status().is_success() && response.status() != StatusCode::METHOD_NOT_ALLOWED {
+                    return Err(format!("Failed to create WebDAV collection {}: {}", collection, response.status()));
+                }
+            }
+
+            let (body, len) = file_body(source).await?;
+            let file_url = format!("{}/{}", collection, relative_path[relative_path.len() - 1]);
+            let request = client.put(&file_url).header(reqwest::header::CONTENT_LENGTH, len).body(body);
+            let response = auth(request).send().await.map_err(|e| e.to_string())?;
+            if !response.status().is_success() {
+                return Err(format!("WebDAV upload failed with {}", response.status()));
+            }
+        },
+    }
+    Ok(())
+}
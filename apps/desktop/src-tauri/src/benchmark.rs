@@ -0,0 +1,137 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use serde::Serialize;
+use tauri::State;
+use tokio::process::Command;
+use tokio::sync::Mutex;
+use tracing::{debug, info};
+
+use crate::capture::resolve_device_input;
+use crate::encoders::{get_encoder_capabilities, hardware_upload};
+use crate::recording::{RecordingOptions, VideoQuality};
+use crate::screenshot::{capture_screen, parse_screen_index};
+use crate::session::RecordingState;
+use crate::utils::ffmpeg_path;
+
+/// Seconds of video each candidate encodes.
+const BENCHMARK_SECS: f64 = 3.0;
+/// Encoding has to run this much faster than real time to keep up while capture and
+/// uploads take their share of the machine.
+const REALTIME_HEADROOM: f64 = 1.2;
+/// A candidate still running after this has no chance of keeping up.
+const RUN_TIMEOUT: Duration = Duration::from_secs(10);
+const FRAMERATES: [u32; 2] = [30, 60];
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BenchmarkRun {
+    pub encoder: String,
+    /// The capture quality this run matches; `None` for hardware encoders, which
+    /// recording doesn't use yet.
+    pub quality: Option<VideoQuality>,
+    pub fps: u32,
+    /// Seconds of video encoded per second; `0` when the run failed.
+    pub speed: f64,
+    pub realtime: bool,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BenchmarkResult {
+    pub width: u32,
+    pub height: u32,
+    pub runs: Vec<BenchmarkRun>,
+    /// The best quality whose 30 fps run kept up; `None` when even `standard` didn't.
+    pub recommended_quality: Option<VideoQuality>,
+}
+
+/// Encodes a few seconds of moving test video at the size of the screen in `options`
+/// with each capture quality and each usable hardware encoder, at 30 and 60 fps, and
+/// reports which keep up in real time. Nothing is written to disk. Takes up to a
+/// minute, and refuses to run during a recording, which it would slow down.
+#[tauri::command]
+pub async fn benchmark_capture(
+    state: State<'_, Arc<Mutex<RecordingState>>>,
+    options: RecordingOptions,
+) -> Result<BenchmarkResult, String> {
+    if state.lock().await.is_recording() {
+        return Err("Stop the current recording before running a benchmark".to_string());
+    }
+
+    let screen_index = parse_screen_index(&resolve_device_input(options.screen_index.clone()).await?);
+    let (width, height) = tokio::task::spawn_blocking(move || capture_screen(screen_index).map(|image| image.dimensions()))
+        .await
+        .map_err(|e| e.to_string())??;
+    info!("Benchmarking capture encoders at {}x{}", width, height);
+
+    let mut candidates: Vec<(String, Option<VideoQuality>)> = [VideoQuality::Standard, VideoQuality::Text, VideoQuality::Text444]
+        .into_iter()
+        .map(|quality| ("libx264".to_string(), Some(quality)))
+        .collect();
+    let capabilities = get_encoder_capabilities().await?;
+    candidates.extend(capabilities.encoders.iter()
+        .filter(|encoder| encoder.hardware && encoder.name.starts_with("h264"))
+        .map(|encoder| (encoder.name.clone(), None)));
+
+    let mut runs = vec![];
+    for (encoder, quality) in candidates {
+        for fps in FRAMERATES {
+            let run = run_candidate(&encoder, quality, fps, width, height).await;
+            debug!("Benchmark run: {:?}", run);
+            runs.push(run);
+        }
+    }
+
+    let recommended_quality = [VideoQuality::Text444, VideoQuality::Text, VideoQuality::Standard]
+        .into_iter()
+        .find(|quality| runs.iter().any(|run| run.quality == Some(*quality) && run.fps == 30 && run.realtime));
+
+    Ok(BenchmarkResult { width, height, runs, recommended_quality })
+}
+
+async fn run_candidate(encoder: &str, quality: Option<VideoQuality>, fps: u32, width: u32, height: u32) -> BenchmarkRun {
+    let result = encode_test_video(encoder, quality, fps, width, height).await;
+    let (speed, error) = match result {
+        Ok(speed) => (speed, None),
+        Err(e) => (0.0, Some(e)),
+    };
+    BenchmarkRun {
+        encoder: encoder.to_string(),
+        quality,
+        fps,
+        speed,
+        realtime: speed >= REALTIME_HEADROOM,
+        error,
+    }
+}
+
+/// Encodes `BENCHMARK_SECS` of `testsrc2`, whose motion and detail cost about as much
+/// as a busy screen, and returns how many times faster than real time it went.
+async fn encode_test_video(encoder: &str, quality: Option<VideoQuality>, fps: u32, width: u32, height: u32) -> Result<f64, String> {
+    let source = format!("testsrc2=s={}x{}:r={}:d={}", width, height, fps, BENCHMARK_SECS);
+    let mut command = Command::new(ffmpeg_path()?);
+    command.args(["-hide_banner", "-loglevel", "error"]);
+    let (device_args, upload_filter) = hardware_upload(encoder);
+    command.args(device_args);
+    command.args(["-f", "lavfi", "-i", &source]);
+    if let Some(upload_filter) = upload_filter {
+        command.args(["-vf", upload_filter]);
+    }
+    command.args(["-c:v", encoder, "-g", "30"]);
+    if let Some(quality) = quality {
+        let (preset, crf, pix_fmt) = quality.encoder_settings();
+        command.args(["-preset", preset, "-crf", crf, "-pix_fmt", pix_fmt]);
+    }
+    command.args(["-f", "null", "-"]).kill_on_drop(true);
+
+    let started_at = Instant::now();
+    let output = tokio::time::timeout(RUN_TIMEOUT, command.output())
+        .await
+        .map_err(|_| format!("Took longer than {} seconds", RUN_TIMEOUT.as_secs()))?
+        .map_err(|e| e.to_string())?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(stderr.lines().last().unwrap_or("Encoding failed").to_string());
+    }
+
+    Ok(BENCHMARK_SECS / started_at.elapsed().as_secs_f64().max(0.001))
+}
@@ -0,0 +1,612 @@
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use serde::{Serialize, Deserialize};
+use tokio::io::AsyncWriteExt;
+use tokio::process::{Command, ChildStderr, ChildStdin};
+use tokio::time::Duration;
+
+use crate::audio::AUTO_GAIN_FILTER;
+use crate::devices::avfoundation_index;
+use crate::drawing::{add_drawing_input, create_canvas};
+use crate::manifest::segment_pattern;
+use crate::options::DeviceId;
+use crate::recording::{CameraBackground, RecordingOptions};
+use crate::zoom::{ZOOM_CROP_FILTER, ZOOM_SCALE_FILTER};
+
+/// How long ffmpeg gets to write its last segment after "q" before it is killed.
+pub const FFMPEG_EXIT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Options allowed before `-i`, each followed by its value. Anything that could name
+/// another file, and so read or write outside the recording, is left out.
+const INPUT_FFMPEG_FLAGS: &[&str] = &[
+    "-probesize", "-analyzeduration", "-thread_queue_size", "-rtbufsize", "-fflags",
+    "-use_wallclock_as_timestamps", "-capture_cursor", "-capture_mouse_clicks", "-draw_mouse",
+    "-show_region",
+];
+/// Encoder options allowed after the encoder, each followed by its value. Stream
+/// specifiers are ignored, so `-b` covers `-b:v`.
+const OUTPUT_FFMPEG_FLAGS: &[&str] = &[
+    "-tune", "-profile", "-level", "-b", "-maxrate", "-minrate", "-bufsize", "-threads", "-bf",
+    "-refs", "-sc_threshold", "-keyint_min", "-qmin", "-qmax", "-rc-lookahead", "-aq-mode",
+    "-aq-strength", "-color_primaries", "-color_trc", "-colorspace", "-color_range",
+    "-max_muxing_queue_size",
+];
+
+/// Extra ffmpeg arguments for the video capture, for encoder and input options the
+/// app doesn't expose.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct ExtraFfmpegArgs {
+    /// Added just before `-i`, e.g. `-probesize 10M`.
+    pub input: Vec<String>,
+    /// Added after the encoder options, e.g. `-tune zerolatency`.
+    pub output: Vec<String>,
+}
+
+impl ExtraFfmpegArgs {
+    pub fn is_empty(&self) -> bool {
+        self.input.is_empty() && self.output.is_empty()
+    }
+
+    /// Only accepts `<flag> <value>` pairs of allowed flags; a bare value would be
+    /// taken by ffmpeg as another input or output file.
+    pub fn validate(&self) -> Result<(), String> {
+        validate_flag_pairs(&self.input, INPUT_FFMPEG_FLAGS)?;
+        validate_flag_pairs(&self.output, OUTPUT_FFMPEG_FLAGS)
+    }
+}
+
+fn validate_flag_pairs(args: &[String], allowed: &[&str]) -> Result<(), String> {
+    for pair in args.chunks(2) {
+        let flag = &pair[0];
+        if !allowed.contains(&flag.split(':').next().unwrap_or(flag)) {
+            return Err(format!("FFmpeg argument {} is not allowed", flag));
+        }
+        match pair.get(1) {
+            Some(value) if value.contains("://") => return Err(format!("FFmpeg argument {} can't be a URL", value)),
+            Some(_) => {},
+            None => return Err(format!("FFmpeg argument {} needs a value", flag)),
+        }
+    }
+    Ok(())
+}
+
+pub fn clean_and_create_dir(dir: &Path) -> Result<(), String> {
+    if dir.exists() {
+        // Instead of just reading the directory, this will also handle subdirectories.
+        std::fs::remove_dir_all(dir).map_err(|e| e.to_string())?;
+    }
+    std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+
+    let segment_list_path = dir.join("segment_list.txt");
+    match File::open(&segment_list_path) {
+        Ok(_) => Ok(()),
+        Err(ref e) if e.kind() == ErrorKind::NotFound => {
+            File::create(&segment_list_path).map_err(|e| e.to_string())?;
+            Ok(())
+        },
+        Err(e) => Err(e.to_string()), 
+    }
+}
+
+pub async fn construct_recording_args(
+    options: &RecordingOptions,
+    chunks_dir: &Path, 
+    video_type: &str,
+    input_index: &str, 
+) -> Result<Vec<OsString>, String> {
+    let output_filename_pattern = chunks_dir.join(segment_pattern(&options.segment_prefix(video_type), "ts"));
+    let segment_list_path = chunks_dir.join("segment_list.txt");
+    
+    ensure_segment_list_exists(segment_list_path.clone())
+        .map_err(|e| format!("Failed to ensure segment list file exists: {}", e))?;
+      
+    let fps = if video_type == "screen" { 30 } else { options.framerate.unwrap_or(30) }.to_string();
+    let (preset, crf, pix_fmt) = options.video_quality.unwrap_or_default().encoder_settings();
+    let (preset, crf, pix_fmt) = (preset.to_string(), crf.to_string(), pix_fmt.to_string());
+    let codec = "libx264".to_string();
+    let gop = "30".to_string();
+
+    let mut args = if video_type == "camera" {
+        let mut args = camera_input_args(&fps, input_index)?;
+        args.extend([
+            "-c:v".to_string(), codec,
+            "-crf".to_string(), crf,
+            "-preset".to_string(), preset,
+            "-pix_fmt".to_string(), pix_fmt,
+            "-g".to_string(), gop,
+            "-r".to_string(), fps.to_string(),
+            "-an".to_string(),
+        ]);
+        args
+    } else {
+        match std::env::consts::OS {
+            "macos" => {
+                vec![
+                    "-f".to_string(), "avfoundation".to_string(),
+                    "-framerate".to_string(), fps.to_string(),
+                    "-capture_cursor".to_string(), "1".to_string(),
+                    "-thread_queue_size".to_string(), "512".to_string(),
+                    "-i".to_string(), format!("{}", input_index),
+                    "-c:v".to_string(), codec,
+                    "-crf".to_string(), crf,
+                    "-preset".to_string(), preset,
+                    "-pix_fmt".to_string(), pix_fmt,
+                    "-g".to_string(), gop,
+                    "-r".to_string(), fps.to_string(),
+                    "-an".to_string(),
+                ]
+            },
+            "linux" => {
+                vec![
+                    "-f".to_string(), "x11grab".to_string(),
+                    "-i".to_string(), format!("{}+0,0", input_index),
+                    "-draw_mouse".to_string(), "1".to_string(),
+                    "-pix_fmt".to_string(), pix_fmt,
+                    "-c:v".to_string(), codec,
+                    "-crf".to_string(), crf,
+                    "-preset".to_string(), preset,
+                    "-g".to_string(), gop,
+                    "-r".to_string(), fps.to_string(),
+                    "-an".to_string(),
+                ]
+            },
+            "windows" => {
+                vec![
+                    "-f".to_string(), "gdigrab".to_string(),
+                    "-i".to_string(), "desktop".to_string(),
+                    "-pixel_format".to_string(), pix_fmt,
+                    "-c:v".to_string(), codec,
+                    "-crf".to_string(), crf,
+                    "-preset".to_string(), preset,
+                    "-g".to_string(), gop,
+                    "-r".to_string(), fps.to_string(),
+                    "-an".to_string(), // This is the argument to skip audio recording.
+                ]
+            },
+            _ => return Err("Unsupported OS".to_string()),
+        }
+    };
+
+    // Mark the colors explicitly; untagged output is read as limited-range BT.601 by
+    // most players, which washes out screen content converted from RGB.
+    let color_args_at = args.iter().position(|arg| arg == "-an").unwrap_or(args.len());
+    args.splice(color_args_at..color_args_at, color_args(options.force_full_range.unwrap_or_default()));
+
+    if video_type == "camera" && options.camera_background == Some(CameraBackground::Blur) {
+        if let Some(filter) = args.iter().position(|arg| arg == "-vf").and_then(|at| args.get_mut(at + 1)) {
+            *filter = format!("{},{}", BACKGROUND_BLUR_FILTER, filter);
+        }
+    }
+
+    let input_at = args.iter().position(|arg| arg == "-i").unwrap_or(0);
+    args.splice(input_at..input_at, options.extra_ffmpeg_args.input.iter().cloned());
+    let output_at = args.iter().position(|arg| arg == "-an").unwrap_or(args.len());
+    args.splice(output_at..output_at, options.extra_ffmpeg_args.output.iter().cloned());
+
+    if options.muxed_audio.unwrap_or_default() && video_type == "screen" && !options.audio_name.is_empty() {
+        add_muxed_audio(&mut args, options)?;
+    }
+
+    let mut args: Vec<OsString> = args.into_iter().map(OsString::from).collect();
+    args.extend([
+        "-f".into(), "segment".into(),
+        "-segment_time".into(), options.segment_time_secs.to_string().into(),
+        "-segment_format".into(), "mpegts".into(),
+        "-segment_list".into(), segment_list_path.into_os_string(),
+        "-segment_list_type".into(), "flat".into(),
+        "-reset_timestamps".into(), "1".into(),
+        output_filename_pattern.into_os_string(),
+    ]);
+
+    if options.live_drawing.unwrap_or_default() && video_type == "screen" {
+        let recording_dir = chunks_dir.parent().ok_or("Invalid chunks directory".to_string())?;
+        let canvas_path = create_canvas(recording_dir, input_index)?;
+        add_drawing_input(&mut args, &canvas_path)?;
+    }
+
+    Ok(args)
+}
+
+/// Records the mic into the screen segments: adds it as an input and encodes it in
+/// place of `-an`.
+fn add_muxed_audio(args: &mut Vec<String>, options: &RecordingOptions) -> Result<(), String> {
+    let input_at = args.iter().position(|arg| arg == "-i").ok_or("Screen input is missing".to_string())? + 1;
+    match std::env::consts::OS {
+        // avfoundation takes the screen and the mic as one "<video>:<audio>" input.
+        "macos" => args[input_at] = format!("{}:{}", args[input_at], options.audio_name),
+        "linux" => {
+            let mic_input = ["-f", "pulse", "-i", &options.audio_name].map(String::from);
+            args.splice(input_at + 1..input_at + 1, mic_input);
+        },
+        "windows" => {
+            let mic_input = ["-f".to_string(), "dshow".to_string(), "-i".to_string(), format!("audio={}", options.audio_name)];
+            args.splice(input_at + 1..input_at + 1, mic_input);
+        },
+        _ => return Err("Unsupported OS".to_string()),
+    }
+
+    let bitrate = if options.audio_bitrate.is_empty() { "128k".to_string() } else { options.audio_bitrate.clone() };
+    let audio_at = args.iter().position(|arg| arg == "-an").ok_or("Audio options are missing".to_string())?;
+    args.splice(audio_at..audio_at + 1, ["-c:a".to_string(), "aac".to_string(), "-b:a".to_string(), bitrate]);
+    if options.auto_gain.unwrap_or_default() {
+        args.splice(audio_at..audio_at, ["-af".to_string(), AUTO_GAIN_FILTER.to_string()]);
+    }
+    Ok(())
+}
+
+/// Lays a sharp copy of the frame, faded out from an ellipse around the centre, over
+/// a blurred copy. `geq` works per pixel, so this costs noticeably more CPU than the
+/// plain camera capture.
+const BACKGROUND_BLUR_FILTER: &str = "split[sharp][background];\
+    [background]boxblur=20:2[blurred];\
+    [sharp]format=yuva420p,geq=lum='lum(X,Y)':cb='cb(X,Y)':cr='cr(X,Y)':\
+    a='255*clip(4*(1-pow((X-W/2)/(W*0.3),2)-pow((Y-H/2)/(H*0.45),2)),0,1)'[subject];\
+    [blurred][subject]overlay=format=auto";
+
+/// Converts to BT.709 in limited (TV) range, or full (PC) range when `full_range` is
+/// set, and tags the stream to match. The zoom crop runs first, and the conversion
+/// doubles as the scale that brings a zoomed region back to the full frame size.
+fn color_args(full_range: bool) -> Vec<String> {
+    let range = if full_range { "pc" } else { "tv" };
+    vec![
+        "-vf".to_string(), format!("{},{}=out_color_matrix=bt709:out_range={}", ZOOM_CROP_FILTER, ZOOM_SCALE_FILTER, range),
+        "-color_range".to_string(), range.to_string(),
+        "-colorspace".to_string(), "bt709".to_string(),
+        "-color_primaries".to_string(), "bt709".to_string(),
+        "-color_trc".to_string(), "bt709".to_string(),
+    ]
+}
+
+/// Webcam input for camera-only recordings. `camera` is an avfoundation index on
+/// macOS, a v4l2 device index or path on Linux and a DirectShow device name on Windows.
+fn camera_input_args(fps: &str, camera: &str) -> Result<Vec<String>, String> {
+    match std::env::consts::OS {
+        "macos" => Ok(vec![
+            "-f".to_string(), "avfoundation".to_string(),
+            "-framerate".to_string(), fps.to_string(),
+            "-thread_queue_size".to_string(), "512".to_string(),
+            "-i".to_string(), format!("{}:none", camera),
+        ]),
+        "linux" => {
+            let device = if camera.chars().all(|c| c.is_ascii_digit()) {
+                format!("/dev/video{}", camera)
+            } else {
+                camera.to_string()
+            };
+            Ok(vec![
+                "-f".to_string(), "v4l2".to_string(),
+                "-framerate".to_string(), fps.to_string(),
+                "-i".to_string(), device,
+            ])
+        },
+        "windows" => Ok(vec![
+            "-f".to_string(), "dshow".to_string(),
+            "-framerate".to_string(), fps.to_string(),
+            "-i".to_string(), format!("video={}", camera),
+        ]),
+        _ => Err("Unsupported OS".to_string()),
+    }
+}
+
+/// The ffmpeg input for a selected device; empty when none was selected. avfoundation
+/// indices shift as cameras and virtual devices come and go, so on macOS devices
+/// picked by name are looked up at start time.
+pub async fn resolve_device_input(device: Option<DeviceId>) -> Result<String, String> {
+    match device {
+        Some(DeviceId::Name(name)) if std::env::consts::OS == "macos" => {
+            tokio::task::spawn_blocking(move || avfoundation_index(&name))
+                .await
+                .map_err(|e| e.to_string())?
+                .map(|index| index.to_string())
+        },
+        Some(device) => Ok(device.to_string()),
+        None => Ok(String::new()),
+    }
+}
+
+fn ensure_segment_list_exists(file_path: PathBuf) -> io::Result<()> {
+    match File::open(&file_path) {
+        Ok(_) => (), 
+        Err(ref e) if e.kind() == ErrorKind::NotFound => {
+            File::create(&file_path)?;
+        },
+        Err(e) => {
+            return Err(e);
+        },
+    }
+    Ok(())
+}
+
+pub async fn start_screen_recording_process(ffmpeg_binary: &Path, ffmpeg_screen_args: &[OsString]) -> Result<(tokio::process::Child, ChildStderr, ChildStdin), io::Error> {
+    let mut child = Command::new(ffmpeg_binary)
+        .args(ffmpeg_screen_args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    
+    let stderr = child.stderr.take().expect("failed to take child stdout");
+    let stdin = child.stdin.take().expect("failed to take child stdin");
+    
+    Ok((child, stderr, stdin))
+}
+
+pub async fn graceful_stop_ffmpeg(mut stdin: tokio::process::ChildStdin) -> Result<(), std::io::Error> {
+    stdin.write_all(b"q\n").await?;
+    Ok(())
+}
+
+/// Suspends or resumes the screen ffmpeg process so it stops grabbing frames.
+#[cfg(unix)]
+pub fn signal_ffmpeg(process: &tokio::process::Child, pause: bool) -> Result<(), String> {
+    let pid = process.id().ok_or("FFmpeg process has already exited".to_string())?;
+    let signal = if pause { "-STOP" } else { "-CONT" };
+    let status = std::process::Command::new("kill")
+        .args([signal, &pid.to_string()])
+        .status()
+        .map_err(|e| format!("Failed to signal FFmpeg: {}", e))?;
+
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("Failed to signal FFmpeg: kill exited with {}", status))
+    }
+}
+
+/// Windows has no signal to suspend a process; recordings there pause at the frame
+/// gate instead.
+#[cfg(windows)]
+pub fn signal_ffmpeg(_process: &tokio::process::Child, _pause: bool) -> Result<(), String> {
+    Err("FFmpeg can't be suspended on Windows".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_options(extra: serde_json::Value) -> RecordingOptions {
+        let mut options = serde_json::json!({
+            "user_id": "user",
+            "video_id": "video",
+            "aws_region": "us-east-1",
+            "aws_bucket": "bucket",
+            "segment_time_secs": 3,
+        });
+        if let (Some(options), Some(extra)) = (options.as_object_mut(), extra.as_object()) {
+            options.extend(extra.clone());
+        }
+        serde_json::from_value(options).unwrap()
+    }
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("cap-capture-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn value_after<'a>(args: &'a [OsString], flag: &str) -> Option<&'a str> {
+        args.iter().position(|arg| arg == flag).and_then(|at| args.get(at + 1)).and_then(|arg| arg.to_str())
+    }
+
+    fn position(args: &[OsString], arg: &str) -> usize {
+        args.iter().position(|candidate| candidate == arg).unwrap()
+    }
+
+    /// Every capture ends in the same segment output the upload loop reads.
+    fn assert_segment_output(args: &[OsString], chunks_dir: &Path, prefix: &str) {
+        assert_eq!(value_after(args, "-segment_format"), Some("mpegts"));
+        assert_eq!(value_after(args, "-segment_list_type"), Some("flat"));
+        assert_eq!(value_after(args, "-segment_time"), Some("3"));
+        let segment_list_at = position(args, "-segment_list");
+        assert_eq!(args[segment_list_at + 1], chunks_dir.join("segment_list.txt").into_os_string());
+        assert_eq!(args.last(), Some(&chunks_dir.join(segment_pattern(prefix, "ts")).into_os_string()));
+        assert!(chunks_dir.join("segment_list.txt").is_file());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn linux_screen_capture_uses_x11grab() {
+        let chunks_dir = test_dir("linux-screen");
+        let args = construct_recording_args(&test_options(serde_json::json!({})), &chunks_dir, "screen", ":0.0").await.unwrap();
+
+        assert_eq!(value_after(&args, "-f"), Some("x11grab"));
+        assert_eq!(value_after(&args, "-i"), Some(":0.0+0,0"));
+        assert_eq!(value_after(&args, "-draw_mouse"), Some("1"));
+        assert_eq!(value_after(&args, "-c:v"), Some("libx264"));
+        assert_eq!(value_after(&args, "-r"), Some("30"));
+        assert_segment_output(&args, &chunks_dir, "recording_chunk");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn linux_camera_index_becomes_a_video_device() {
+        let chunks_dir = test_dir("linux-camera");
+        let options = test_options(serde_json::json!({ "framerate": 24 }));
+        let args = construct_recording_args(&options, &chunks_dir, "camera", "2").await.unwrap();
+
+        assert_eq!(value_after(&args, "-f"), Some("v4l2"));
+        assert_eq!(value_after(&args, "-i"), Some("/dev/video2"));
+        assert_eq!(value_after(&args, "-framerate"), Some("24"));
+        assert_segment_output(&args, &chunks_dir, "recording_chunk");
+
+        let by_path = construct_recording_args(&options, &chunks_dir, "camera", "/dev/v4l/by-id/cam").await.unwrap();
+        assert_eq!(value_after(&by_path, "-i"), Some("/dev/v4l/by-id/cam"));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn linux_muxed_audio_adds_a_pulse_input() {
+        let chunks_dir = test_dir("linux-muxed");
+        let options = test_options(serde_json::json!({ "muxed_audio": true, "audio_name": "default" }));
+        let args = construct_recording_args(&options, &chunks_dir, "screen", ":0.0").await.unwrap();
+
+        let pulse_at = position(&args, "pulse");
+        assert!(position(&args, ":0.0+0,0") < pulse_at);
+        assert_eq!(args.get(pulse_at + 2).and_then(|arg| arg.to_str()), Some("default"));
+        assert_eq!(value_after(&args, "-c:a"), Some("aac"));
+        assert_eq!(value_after(&args, "-b:a"), Some("128k"));
+        assert!(!args.iter().any(|arg| arg == "-an"));
+    }
+
+    #[cfg(target_os = "macos")]
+    #[tokio::test]
+    async fn macos_screen_capture_uses_avfoundation() {
+        let chunks_dir = test_dir("macos-screen");
+        let args = construct_recording_args(&test_options(serde_json::json!({})), &chunks_dir, "screen", "1").await.unwrap();
+
+        assert_eq!(value_after(&args, "-f"), Some("avfoundation"));
+        assert_eq!(value_after(&args, "-i"), Some("1"));
+        assert_eq!(value_after(&args, "-capture_cursor"), Some("1"));
+        assert_segment_output(&args, &chunks_dir, "recording_chunk");
+    }
+
+    #[cfg(target_os = "macos")]
+    #[tokio::test]
+    async fn macos_camera_has_no_audio_input() {
+        let chunks_dir = test_dir("macos-camera");
+        let args = construct_recording_args(&test_options(serde_json::json!({})), &chunks_dir, "camera", "0").await.unwrap();
+
+        assert_eq!(value_after(&args, "-i"), Some("0:none"));
+        assert_segment_output(&args, &chunks_dir, "recording_chunk");
+    }
+
+    #[cfg(target_os = "macos")]
+    #[tokio::test]
+    async fn macos_muxed_audio_joins_the_screen_input() {
+        let chunks_dir = test_dir("macos-muxed");
+        let options = test_options(serde_json::json!({ "muxed_audio": true, "audio_name": "0" }));
+        let args = construct_recording_args(&options, &chunks_dir, "screen", "1").await.unwrap();
+
+        assert_eq!(value_after(&args, "-i"), Some("1:0"));
+        assert_eq!(value_after(&args, "-c:a"), Some("aac"));
+    }
+
+    #[cfg(target_os = "windows")]
+    #[tokio::test]
+    async fn windows_screen_capture_uses_gdigrab() {
+        let chunks_dir = test_dir("windows-screen");
+        let args = construct_recording_args(&test_options(serde_json::json!({})), &chunks_dir, "screen", "").await.unwrap();
+
+        assert_eq!(value_after(&args, "-f"), Some("gdigrab"));
+        assert_eq!(value_after(&args, "-i"), Some("desktop"));
+        assert!(args.iter().any(|arg| arg == "-pixel_format"));
+        assert_segment_output(&args, &chunks_dir, "recording_chunk");
+    }
+
+    #[cfg(target_os = "windows")]
+    #[tokio::test]
+    async fn windows_camera_and_mic_use_dshow() {
+        let chunks_dir = test_dir("windows-camera");
+        let camera = construct_recording_args(&test_options(serde_json::json!({})), &chunks_dir, "camera", "Integrated Camera").await.unwrap();
+        assert_eq!(value_after(&camera, "-f"), Some("dshow"));
+        assert_eq!(value_after(&camera, "-i"), Some("video=Integrated Camera"));
+
+        let options = test_options(serde_json::json!({ "muxed_audio": true, "audio_name": "Microphone" }));
+        let screen = construct_recording_args(&options, &chunks_dir, "screen", "").await.unwrap();
+        assert!(screen.iter().any(|arg| arg == "audio=Microphone"));
+    }
+
+    #[tokio::test]
+    async fn color_and_extra_args_are_placed_around_the_encoder() {
+        let chunks_dir = test_dir("placement");
+        let options = test_options(serde_json::json!({
+            "force_full_range": true,
+            "extra_ffmpeg_args": { "input": ["-probesize", "10M"], "output": ["-tune", "zerolatency"] },
+        }));
+        let args = construct_recording_args(&options, &chunks_dir, "screen", ":0.0").await.unwrap();
+
+        assert_eq!(position(&args, "-probesize") + 2, position(&args, "-i"));
+        assert_eq!(value_after(&args, "-color_range"), Some("pc"));
+        assert!(position(&args, "-c:v") < position(&args, "-tune"));
+        assert_eq!(position(&args, "-tune") + 2, position(&args, "-an"));
+        assert!(position(&args, "-color_trc") < position(&args, "-an"));
+    }
+
+    #[tokio::test]
+    async fn video_id_naming_prefixes_segments_with_the_video_id() {
+        let chunks_dir = test_dir("naming");
+        let options = test_options(serde_json::json!({ "chunk_naming": "video_id" }));
+        let args = construct_recording_args(&options, &chunks_dir, "screen", ":0.0").await.unwrap();
+
+        assert_eq!(args.last(), Some(&chunks_dir.join(segment_pattern("video", "ts")).into_os_string()));
+    }
+
+    #[tokio::test]
+    async fn segment_output_keeps_spaces_and_non_ascii_characters() {
+        let chunks_dir = test_dir("unicode").join("Ünïcode Recordings 录屏").join("screen");
+        std::fs::create_dir_all(&chunks_dir).unwrap();
+        let args = construct_recording_args(&test_options(serde_json::json!({})), &chunks_dir, "screen", ":0.0").await.unwrap();
+
+        assert_segment_output(&args, &chunks_dir, "recording_chunk");
+        assert!(args.last().and_then(|arg| arg.to_str()).unwrap().contains("Ünïcode Recordings 录屏"));
+    }
+
+    /// Paths go to ffmpeg as they are, so a directory name that isn't valid Unicode
+    /// still records into itself.
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn segment_output_keeps_paths_that_are_not_unicode() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let chunks_dir = test_dir("not-unicode").join(std::ffi::OsStr::from_bytes(b"recording\xff")).join("screen");
+        std::fs::create_dir_all(&chunks_dir).unwrap();
+        let args = construct_recording_args(&test_options(serde_json::json!({})), &chunks_dir, "screen", ":0.0").await.unwrap();
+
+        assert_segment_output(&args, &chunks_dir, "recording_chunk");
+        assert!(args.last().unwrap().to_str().is_none());
+    }
+
+    /// std adds the `\\?\` prefix to the app's own file operations, but ffmpeg gets the
+    /// path as an argument, so a long recording directory has to keep the prefix itself.
+    #[cfg(target_os = "windows")]
+    #[tokio::test]
+    async fn segment_output_keeps_the_long_path_prefix() {
+        let mut chunks_dir = std::fs::canonicalize(test_dir("long-path")).unwrap();
+        for _ in 0..20 {
+            chunks_dir.push("nested recording folder");
+        }
+        std::fs::create_dir_all(&chunks_dir).unwrap();
+        assert!(chunks_dir.as_os_str().len() > 260);
+
+        let args = construct_recording_args(&test_options(serde_json::json!({})), &chunks_dir, "screen", "").await.unwrap();
+        assert_segment_output(&args, &chunks_dir, "recording_chunk");
+        assert!(args.last().and_then(|arg| arg.to_str()).unwrap().starts_with("\\\\?\\"));
+    }
+
+    #[test]
+    fn extra_args_that_touch_other_files_are_refused() {
+        let allowed = ExtraFfmpegArgs {
+            input: vec!["-probesize".to_string(), "10M".to_string()],
+            output: vec!["-tune".to_string(), "zerolatency".to_string(), "-profile:v".to_string(), "high".to_string()],
+        };
+        assert!(allowed.validate().is_ok());
+
+        let denied: &[&[&str]] = &[
+            &["-i", "input.mp4"],
+            &["-map:0", "0"],
+            &["-segment_time", "1"],
+            &["-sdp_file", "out.sdp"],
+            &["-vstats_file", "stats.log"],
+            &["-passlogfile", "pass"],
+            &["-fpre", "preset.ffpreset"],
+            &["/home/user/.bashrc"],
+            &["C:/x.mkv"],
+            &["-tune", "zerolatency", "out.mkv"],
+            &["-tune"],
+            &["-b:v", "http://example.com"],
+        ];
+        for args in denied {
+            let output = args.iter().map(|arg| arg.to_string()).collect();
+            let extra = ExtraFfmpegArgs { input: vec![], output };
+            assert!(extra.validate().is_err(), "{:?} was allowed", args);
+        }
+
+        let input_flag_in_output = ExtraFfmpegArgs { input: vec![], output: vec!["-probesize".to_string(), "10M".to_string()] };
+        assert!(input_flag_in_output.validate().is_err());
+    }
+}
@@ -0,0 +1,46 @@
+use std::collections::BTreeSet;
+use std::sync::Mutex;
+use tauri::{AppHandle, Manager, Window};
+use tracing::{info, warn};
+
+/// Labels of the app's windows kept out of recordings, such as a teleprompter or notes.
+static EXCLUDED_WINDOWS: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+
+/// Keeps the window `label` visible to the presenter but out of screen captures, or
+/// lets it be captured again when `excluded` is false. The setting sticks to the
+/// label, so a window closed and opened again is excluded as well. Relies on the OS's
+/// content protection: `sharingType` on macOS and display affinity on Windows. Linux
+/// has no equivalent.
+#[tauri::command]
+pub fn set_window_capture_excluded(app: AppHandle, label: String, excluded: bool) -> Result<(), String> {
+    if cfg!(target_os = "linux") {
+        return Err("Windows can't be hidden from screen capture on Linux".to_string());
+    }
+    let window = app.get_window(&label).ok_or(format!("Window {} not found", label))?;
+    window.set_content_protected(excluded)
+        .map_err(|e| format!("Failed to change the capture exclusion of {}: {}", label, e))?;
+
+    let mut excluded_windows = EXCLUDED_WINDOWS.lock().map_err(|e| e.to_string())?;
+    if excluded {
+        excluded_windows.insert(label.clone());
+    } else {
+        excluded_windows.remove(&label);
+    }
+    info!("Window {} is {} screen capture", label, if excluded { "excluded from" } else { "included in" });
+    Ok(())
+}
+
+#[tauri::command]
+pub fn list_capture_excluded_windows() -> Vec<String> {
+    EXCLUDED_WINDOWS.lock().map(|windows| windows.iter().cloned().collect()).unwrap_or_default()
+}
+
+/// Excludes a newly loaded window from capture if its label was registered before.
+pub fn apply_to_window(window: &Window) {
+    let is_excluded = EXCLUDED_WINDOWS.lock().map_or(false, |windows| windows.contains(window.label()));
+    if is_excluded {
+        if let Err(e) = window.set_content_protected(true) {
+            warn!("Failed to exclude window {} from capture: {}", window.label(), e);
+        }
+    }
+}
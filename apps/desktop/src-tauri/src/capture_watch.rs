@@ -0,0 +1,197 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager};
+use tokio::sync::Mutex;
+use tracing::{error, warn};
+
+use crate::ffmpeg_error::{classify_stderr_line, FfmpegErrorKind};
+use crate::journal::{self, JournalEvent};
+use crate::manifest::{update_manifest, StopReason};
+use crate::session::{stop_recording, RecordingState};
+
+/// Emitted with a `CaptureFailure` when screen capture ends on its own.
+pub const CAPTURE_FAILED_EVENT: &str = "recording://capture-failed";
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// Time without any segment data before the capture counts as stalled. A display
+/// that went to sleep leaves ffmpeg running without producing frames.
+const STALL_TIMEOUT: Duration = Duration::from_secs(30);
+/// How much of the end of the ffmpeg log is searched for the cause of an exit.
+const LOG_TAIL_LINES: usize = 50;
+/// A poll that took this much longer by the wall clock than by the monotonic clock,
+/// which stops while the system sleeps, means it slept in between.
+const SLEEP_DETECTION_GAP: Duration = Duration::from_secs(5);
+/// A failure this soon after waking up is put down to the sleep. Covers a stall,
+/// which takes `STALL_TIMEOUT` to notice.
+const SLEEP_FAILURE_WINDOW: Duration = Duration::from_secs(60);
+
+/// Output ffmpeg gives when the captured display is unplugged or changes resolution.
+const DISPLAY_CHANGE_MARKERS: &[&str] = &[
+    "frame changed from size",
+    "input picture width",
+    "cannot open display",
+    "xio:",
+    "bad window",
+    "failed to capture image",
+    "invalid device index",
+];
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CaptureFailureKind {
+    /// The display was unplugged or changed resolution.
+    DisplayChanged,
+    /// No frames arrived for `STALL_TIMEOUT`, as when the display sleeps.
+    Stalled,
+    /// ffmpeg couldn't write the next segment.
+    DiskFull,
+    /// Capture stopped or stalled right after the system woke up from sleep.
+    SystemSleep,
+    /// ffmpeg exited for another reason.
+    Crashed,
+}
+
+impl From<CaptureFailureKind> for StopReason {
+    fn from(kind: CaptureFailureKind) -> Self {
+        match kind {
+            CaptureFailureKind::DisplayChanged => StopReason::DisplayChanged,
+            CaptureFailureKind::Stalled => StopReason::CaptureStalled,
+            CaptureFailureKind::DiskFull => StopReason::DiskFull,
+            CaptureFailureKind::SystemSleep => StopReason::SystemSleep,
+            CaptureFailureKind::Crashed => StopReason::FfmpegCrash,
+        }
+    }
+}
+
+/// Why a recording stopped without being asked to; kept in the manifest.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CaptureFailure {
+    pub video_id: String,
+    pub kind: CaptureFailureKind,
+    pub message: String,
+}
+
+/// Watches the screen ffmpeg of a recording. If it exits or stalls, the recording
+/// is stopped like `stop_recording` would, so the segments recorded so far are
+/// uploaded, and the failure is recorded and emitted instead of leaving the
+/// recording stuck.
+pub fn spawn_capture_watch(
+    app: Option<AppHandle>,
+    state: Arc<Mutex<RecordingState>>,
+    video_id: String,
+    recording_dir: PathBuf,
+    log_path: PathBuf,
+) {
+    tokio::spawn(async move {
+        let chunks_dir = recording_dir.join("screen");
+        let mut last_written = None;
+        let mut last_activity = Instant::now();
+        let mut woke_at: Option<Instant> = None;
+
+        let mut failure = loop {
+            let (polled_at, polled_at_wall) = (Instant::now(), SystemTime::now());
+            tokio::time::sleep(POLL_INTERVAL).await;
+            let wall_elapsed = polled_at_wall.elapsed().unwrap_or_default();
+            if wall_elapsed > polled_at.elapsed() + SLEEP_DETECTION_GAP {
+                warn!("System slept for about {} seconds while recording {}", wall_elapsed.as_secs(), video_id);
+                woke_at = Some(Instant::now());
+                // Time asleep doesn't count towards a stall.
+                last_activity = Instant::now();
+            }
+            let mut guard = state.lock().await;
+            if guard.recording_options.as_ref().map(|options| &options.video_id) != Some(&video_id) {
+                return;
+            }
+            let paused = guard.paused_at.is_some();
+            let process = match guard.screen_process.as_mut() {
+                Some(process) => process,
+                // Taken by `stop_capture`; the recording is stopping normally.
+                None => return,
+            };
+
+            match process.try_wait() {
+                Ok(Some(status)) => break exit_failure(&video_id, &log_path, &status.to_string()),
+                Ok(None) => {},
+                Err(e) => {
+                    warn!("Failed to check the screen FFmpeg of {}: {}", video_id, e);
+                    continue;
+                },
+            }
+
+            let written = newest_write(&chunks_dir);
+            if paused || written != last_written {
+                last_written = written;
+                last_activity = Instant::now();
+            } else if last_activity.elapsed() >= STALL_TIMEOUT {
+                let _ = process.start_kill();
+                break CaptureFailure {
+                    video_id: video_id.clone(),
+                    kind: CaptureFailureKind::Stalled,
+                    message: format!("Screen capture stopped producing frames for {} seconds", STALL_TIMEOUT.as_secs()),
+                };
+            }
+        };
+
+        let after_sleep = woke_at.map_or(false, |woke_at| woke_at.elapsed() <= SLEEP_FAILURE_WINDOW);
+        if after_sleep && matches!(failure.kind, CaptureFailureKind::Stalled | CaptureFailureKind::Crashed) {
+            failure.kind = CaptureFailureKind::SystemSleep;
+            failure.message = format!("Screen capture broke after the system went to sleep: {}", failure.message);
+        }
+        error!("Screen capture of {} failed ({:?}): {}", video_id, failure.kind, failure.message);
+        journal::append(&recording_dir, JournalEvent::CaptureFailed { message: failure.message.clone() });
+        if let Err(e) = update_manifest(&recording_dir, |manifest| manifest.capture_failure = Some(failure.clone())) {
+            warn!("Failed to record the capture failure in the manifest: {}", e);
+        }
+        if let Some(ref app) = app {
+            let _ = app.emit_all(CAPTURE_FAILED_EVENT, &failure);
+        }
+        stop_recording(&state, failure.kind.into()).await;
+    });
+}
+
+fn exit_failure(video_id: &str, log_path: &Path, status: &str) -> CaptureFailure {
+    let contents = std::fs::read_to_string(log_path).unwrap_or_default();
+    let tail: Vec<&str> = contents.lines().rev().take(LOG_TAIL_LINES).collect();
+
+    let display_line = tail.iter().find(|line| {
+        let line = line.to_lowercase();
+        DISPLAY_CHANGE_MARKERS.iter().any(|marker| line.contains(marker))
+    });
+    if let Some(line) = display_line {
+        return CaptureFailure {
+            video_id: video_id.to_string(),
+            kind: CaptureFailureKind::DisplayChanged,
+            message: format!("The display being recorded was disconnected or changed resolution: {}", line),
+        };
+    }
+
+    if let Some(line) = tail.iter().find(|line| classify_stderr_line(line) == Some(FfmpegErrorKind::DiskFull)) {
+        return CaptureFailure {
+            video_id: video_id.to_string(),
+            kind: CaptureFailureKind::DiskFull,
+            message: format!("The disk is full: {}", line),
+        };
+    }
+
+    let error_line = tail.iter().find(|line| classify_stderr_line(line).is_some()).or(tail.first());
+    CaptureFailure {
+        video_id: video_id.to_string(),
+        kind: CaptureFailureKind::Crashed,
+        message: match error_line {
+            Some(line) => format!("FFmpeg exited with {}: {}", status, line),
+            None => format!("FFmpeg exited with {}", status),
+        },
+    }
+}
+
+/// The latest modification time of the files in `dir`; ffmpeg writes to the open
+/// segment continuously while frames arrive.
+fn newest_write(dir: &Path) -> Option<SystemTime> {
+    std::fs::read_dir(dir)
+        .ok()?
+        .flatten()
+        .filter_map(|entry| entry.metadata().ok()?.modified().ok())
+        .max()
+}
@@ -0,0 +1,120 @@
+use std::path::Path;
+use serde::{Serialize, Deserialize};
+use tracing::{debug, info};
+
+use crate::markers::{Marker, MARKERS_FILE};
+use crate::recording::RecordingOptions;
+use crate::timeline::{TimelineEntry, TIMELINE_FILE};
+use crate::transcription::vtt_timestamp;
+use crate::upload::upload_sidecar_file;
+
+const CHAPTERS_JSON_FILE: &str = "chapters.json";
+const CHAPTERS_VTT_FILE: &str = "chapters.vtt";
+/// Time in one app before switching to it starts a chapter; quick switches to look
+/// something up stay part of the chapter around them.
+const MIN_APP_CHAPTER_SECS: f64 = 30.0;
+/// An app switch this close to a marker is taken to be what the marker labels.
+const MARKER_SNAP_SECS: f64 = 10.0;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Chapter {
+    pub start_secs: f64,
+    pub end_secs: f64,
+    pub title: String,
+}
+
+/// Builds chapters from the recording's markers and window timeline, and uploads them
+/// under the `chapters` type as `chapters.json` and as WebVTT chapters. Does nothing
+/// when the recording has neither.
+pub async fn upload_chapters(recording_dir: &Path, options: &RecordingOptions, duration_secs: Option<f64>) -> Result<(), String> {
+    let markers: Vec<Marker> = read_json(&recording_dir.join(MARKERS_FILE)).await;
+    let timeline: Vec<TimelineEntry> = read_json(&recording_dir.join(TIMELINE_FILE)).await;
+
+    let chapters = build_chapters(&markers, &timeline, duration_secs);
+    if chapters.is_empty() {
+        debug!("No markers or window changes to make chapters of for {}", options.video_id);
+        return Ok(());
+    }
+
+    let json_path = recording_dir.join(CHAPTERS_JSON_FILE);
+    let contents = serde_json::to_string(&chapters).map_err(|e| e.to_string())?;
+    tokio::fs::write(&json_path, contents).await
+        .map_err(|e| format!("Failed to write chapters: {}", e))?;
+    let vtt_path = recording_dir.join(CHAPTERS_VTT_FILE);
+    tokio::fs::write(&vtt_path, to_vtt(&chapters)).await
+        .map_err(|e| format!("Failed to write chapters: {}", e))?;
+
+    upload_sidecar_file(options, &json_path, "chapters").await?;
+    upload_sidecar_file(options, &vtt_path, "chapters").await?;
+    info!("Uploaded {} chapters for {}", chapters.len(), options.video_id);
+
+    Ok(())
+}
+
+/// A missing or unreadable file reads as empty, as when the feature was off.
+async fn read_json<T: for<'de> Deserialize<'de>>(path: &Path) -> Vec<T> {
+    tokio::fs::read_to_string(path).await
+        .ok()
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default()
+}
+
+/// Markers start chapters with their labels. Stretches of at least
+/// `MIN_APP_CHAPTER_SECS` in one app start chapters named after it, unless a marker
+/// is close by. The first chapter is stretched back to the start of the recording
+/// and each one ends where the next begins.
+fn build_chapters(markers: &[Marker], timeline: &[TimelineEntry], duration_secs: Option<f64>) -> Vec<Chapter> {
+    let mut starts: Vec<(f64, String)> = markers.iter()
+        .map(|marker| (marker.offset_secs, marker.label.clone()))
+        .collect();
+
+    for (at, entry) in timeline.iter().enumerate() {
+        let switched = at == 0 || timeline[at - 1].app_name != entry.app_name;
+        if !switched {
+            continue;
+        }
+        // The stay lasts until the next entry in another app.
+        let left_at = timeline[at + 1..].iter()
+            .find(|later| later.app_name != entry.app_name)
+            .map(|later| later.offset_secs)
+            .or(duration_secs)
+            .unwrap_or(entry.offset_secs);
+        let near_marker = markers.iter().any(|marker| (marker.offset_secs - entry.offset_secs).abs() < MARKER_SNAP_SECS);
+        if left_at - entry.offset_secs >= MIN_APP_CHAPTER_SECS && !near_marker {
+            starts.push((entry.offset_secs, entry.app_name.clone()));
+        }
+    }
+
+    starts.sort_by(|a, b| a.0.total_cmp(&b.0));
+    // Two long stays in the same app with only short switches between them are one chapter.
+    starts.dedup_by(|later, earlier| later.1 == earlier.1 && !markers.iter().any(|marker| marker.offset_secs == later.0));
+    if let Some(first) = starts.first_mut() {
+        first.0 = 0.0;
+    }
+
+    let end_of_recording = duration_secs.unwrap_or_else(|| starts.last().map_or(0.0, |last| last.0));
+    let mut chapters: Vec<Chapter> = vec![];
+    for (at, (start_secs, title)) in starts.iter().enumerate() {
+        let end_secs = starts.get(at + 1).map_or(end_of_recording, |next| next.0);
+        if end_secs <= *start_secs {
+            continue;
+        }
+        let title = if title.is_empty() { format!("Chapter {}", chapters.len() + 1) } else { title.clone() };
+        chapters.push(Chapter { start_secs: *start_secs, end_secs, title });
+    }
+    chapters
+}
+
+fn to_vtt(chapters: &[Chapter]) -> String {
+    let mut vtt = String::from("WEBVTT\n\n");
+    for (index, chapter) in chapters.iter().enumerate() {
+        vtt.push_str(&format!(
+            "{}\n{} --> {}\n{}\n\n",
+            index + 1,
+            vtt_timestamp((chapter.start_secs * 1000.0).round() as i64),
+            vtt_timestamp((chapter.end_secs * 1000.0).round() as i64),
+            chapter.title.replace('\n', " "),
+        ));
+    }
+    vtt
+}
@@ -0,0 +1,177 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tracing::error;
+
+use crate::capture::ExtraFfmpegArgs;
+use crate::manifest::{recording_dir, validate_video_id, RecordingManifest, RecordingStatus, StopReason};
+use crate::recording::{CaptureMode, RecordingOptions};
+use crate::session::{spawn_recording, stop_recording, RecordingState};
+use crate::options::parse_optional;
+use crate::settings::Settings;
+use crate::upload::StorageOptions;
+
+const USAGE: &str = "Usage: cap record --user-id <id> --video-id <id> --aws-region <region> --aws-bucket <bucket>
+                  [--display <index>] [--camera <index>] [--mic <name>]
+                  [--framerate <fps>] [--resolution <WxH>] [--duration <seconds>]
+                  [--mode screen|camera] [--delay <seconds>]
+
+Waits --delay seconds, then records until --duration elapses or Ctrl+C is
+pressed and waits for the upload to finish. Options that are left out use the
+app's saved settings. With --mode camera only the camera (and mic) is recorded.";
+
+/// `cap record ...` runs without the Tauri UI.
+pub fn is_cli_invocation(args: &[String]) -> bool {
+    args.get(1).map_or(false, |command| command == "record")
+}
+
+struct RecordArgs {
+    options: RecordingOptions,
+    duration: Option<Duration>,
+}
+
+/// Runs the CLI and returns the process exit code.
+pub fn run(args: &[String], data_dir: PathBuf, settings: Settings) -> i32 {
+    let flags = &args[2..];
+    if flags.iter().any(|flag| flag == "--help" || flag == "-h") {
+        println!("{}", USAGE);
+        return 0;
+    }
+
+    let record_args = match parse_record_args(flags) {
+        Ok(record_args) => record_args,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            return 2;
+        },
+    };
+
+    tauri::async_runtime::block_on(record(record_args, data_dir, settings))
+}
+
+fn parse_record_args(flags: &[String]) -> Result<RecordArgs, String> {
+    let mut options = RecordingOptions {
+        user_id: String::new(),
+        video_id: String::new(),
+        screen_index: None,
+        video_index: None,
+        audio_name: String::new(),
+        aws_region: String::new(),
+        aws_bucket: String::new(),
+        framerate: None,
+        resolution: None,
+        segment_time_secs: 0,
+        capture_mode: CaptureMode::Screen,
+        audio_codec: String::new(),
+        audio_sample_rate: 0,
+        audio_channels: 0,
+        audio_bitrate: String::new(),
+        force_full_range: None,
+        video_quality: None,
+        storage: StorageOptions::default(),
+        early_segment_upload: None,
+        upload_order: None,
+        audio_offset_ms: 0,
+        muxed_audio: None,
+        auto_gain: None,
+        live_drawing: None,
+        camera_background: None,
+        extra_ffmpeg_args: ExtraFfmpegArgs::default(),
+        start_delay_secs: 0,
+        chunk_naming: None,
+    };
+    let mut duration = None;
+
+    let mut flags = flags.iter();
+    while let Some(flag) = flags.next() {
+        let value = flags.next().ok_or(format!("Missing value for {}", flag))?.clone();
+        match flag.as_str() {
+            "--user-id" => options.user_id = value,
+            "--video-id" => options.video_id = value,
+            "--aws-region" => options.aws_region = value,
+            "--aws-bucket" => options.aws_bucket = value,
+            "--display" => options.screen_index = parse_optional(&value)?,
+            "--camera" => options.video_index = parse_optional(&value)?,
+            "--mic" => options.audio_name = value,
+            "--framerate" => options.framerate = parse_optional(&value).map_err(|_| format!("Invalid framerate: {}", value))?,
+            "--resolution" => options.resolution = parse_optional(&value)?,
+            "--mode" => options.capture_mode = value.parse()?,
+            "--delay" => options.start_delay_secs = value.parse().map_err(|_| format!("Invalid delay: {}", value))?,
+            "--duration" => {
+                let secs: u64 = value.parse().map_err(|_| format!("Invalid duration: {}", value))?;
+                duration = Some(Duration::from_secs(secs));
+            },
+            _ => return Err(format!("Unknown option: {}", flag)),
+        }
+    }
+
+    for (flag, value) in [
+        ("--user-id", &options.user_id),
+        ("--video-id", &options.video_id),
+        ("--aws-region", &options.aws_region),
+        ("--aws-bucket", &options.aws_bucket),
+    ] {
+        if value.is_empty() {
+            return Err(format!("Missing required option {}", flag));
+        }
+    }
+    validate_video_id(&options.video_id)?;
+
+    Ok(RecordArgs { options, duration })
+}
+
+async fn record(args: RecordArgs, data_dir: PathBuf, settings: Settings) -> i32 {
+    let state = Arc::new(Mutex::new(RecordingState::new(data_dir.clone())));
+    let settings = Arc::new(Mutex::new(settings));
+    let video_id = args.options.video_id.clone();
+    if args.options.start_delay_secs > 0 {
+        println!("Starting {} in {} seconds...", video_id, args.options.start_delay_secs);
+    }
+
+    let recording = match spawn_recording(None, state.clone(), settings, args.options).await {
+        Ok(recording) => recording,
+        Err(e) => {
+            eprintln!("Failed to start recording: {}", e);
+            return 1;
+        },
+    };
+
+    match args.duration {
+        Some(duration) => println!("Recording {} for {} seconds, press Ctrl+C to stop early...", video_id, duration.as_secs()),
+        None => println!("Recording {}, press Ctrl+C to stop...", video_id),
+    }
+
+    let stop_after = async {
+        match args.duration {
+            Some(duration) => tokio::time::sleep(duration).await,
+            None => std::future::pending().await,
+        }
+    };
+    let reason = tokio::select! {
+        _ = stop_after => StopReason::MaxDuration,
+        _ = tokio::signal::ctrl_c() => StopReason::UserStop,
+    };
+
+    println!("Stopping recording and finishing uploads...");
+    stop_recording(&state, reason).await;
+
+    if let Ok(Err(e)) = recording.await {
+        error!("Recording {} failed: {}", video_id, e);
+    }
+
+    let status = recording_dir(&data_dir, &video_id)
+        .and_then(|dir| RecordingManifest::load(&dir))
+        .map(|manifest| manifest.status);
+
+    match status {
+        Ok(RecordingStatus::Complete) => {
+            println!("Recording {} uploaded.", video_id);
+            0
+        },
+        _ => {
+            eprintln!("Some segments of {} failed to upload. Retry the upload from the app.", video_id);
+            1
+        },
+    }
+}
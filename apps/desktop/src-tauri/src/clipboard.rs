@@ -0,0 +1,34 @@
+use std::borrow::Cow;
+use std::path::Path;
+use std::sync::Mutex;
+use arboard::{Clipboard, ImageData};
+
+/// Kept open after copying: on Linux the clipboard contents are served by the app
+/// that set them, and disappear once its `Clipboard` is dropped.
+static CLIPBOARD: Mutex<Option<Clipboard>> = Mutex::new(None);
+
+fn with_clipboard(copy: impl FnOnce(&mut Clipboard) -> Result<(), arboard::Error>) -> Result<(), String> {
+    let mut clipboard = CLIPBOARD.lock().map_err(|e| e.to_string())?;
+    if clipboard.is_none() {
+        *clipboard = Some(Clipboard::new().map_err(|e| format!("Failed to open the clipboard: {}", e))?);
+    }
+    let clipboard = clipboard.as_mut().ok_or("Clipboard is not available".to_string())?;
+    copy(clipboard).map_err(|e| format!("Failed to copy to the clipboard: {}", e))
+}
+
+pub fn copy_text(text: &str) -> Result<(), String> {
+    with_clipboard(|clipboard| clipboard.set_text(text))
+}
+
+/// Puts the image at `path` on the clipboard.
+pub fn copy_image(path: &Path) -> Result<(), String> {
+    let image = xcap::image::open(path)
+        .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?
+        .to_rgba8();
+    let image = ImageData {
+        width: image.width() as usize,
+        height: image.height() as usize,
+        bytes: Cow::Owned(image.into_raw()),
+    };
+    with_clipboard(|clipboard| clipboard.set_image(image))
+}
@@ -0,0 +1,183 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{header, Body, Method, Request, Response, Server, StatusCode};
+use serde::{Serialize, Deserialize};
+use serde_json::json;
+use subtle::ConstantTimeEq;
+use tauri::{AppHandle, Manager};
+use tokio::sync::Mutex;
+use tracing::{error, info};
+
+use crate::manifest::StopReason;
+use crate::recording::RecordingOptions;
+use crate::session::{
+    discard_recording, set_paused, spawn_recording, stop_recording, RecordingPhase, RecordingState,
+};
+use crate::settings::Settings;
+
+/// Emitted after the control API changes the recording, so the UI can follow along.
+pub const CONTROL_API_EVENT: &str = "control-api://action";
+
+static SERVER: std::sync::Mutex<Option<tauri::async_runtime::JoinHandle<()>>> = std::sync::Mutex::new(None);
+
+/// Localhost API for external tools (Stream Deck, OBS scripts, test harnesses).
+/// Every request needs an `Authorization: Bearer <token>` header.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct ControlApiSettings {
+    pub enabled: bool,
+    pub port: u16,
+    pub token: String,
+}
+
+impl Default for ControlApiSettings {
+    fn default() -> Self {
+        ControlApiSettings {
+            enabled: false,
+            port: 47800,
+            token: String::new(),
+        }
+    }
+}
+
+/// A random 32 character hex token from the OS's secure random number generator.
+pub fn generate_token() -> Result<String, String> {
+    let mut bytes = [0u8; 16];
+    getrandom::getrandom(&mut bytes).map_err(|e| format!("Failed to generate a token: {}", e))?;
+    Ok(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+/// Compares in constant time, so how long a request takes to be refused doesn't tell
+/// how much of a guessed token was right.
+pub fn tokens_match(provided: &str, token: &str) -> bool {
+    provided.as_bytes().ct_eq(token.as_bytes()).into()
+}
+
+#[derive(Serialize)]
+struct StatusResponse {
+    status: RecordingPhase,
+    video_id: Option<String>,
+    elapsed_secs: u64,
+}
+
+/// Stops the running server, if any, and starts a new one when enabled. The server
+/// only listens on 127.0.0.1.
+pub async fn restart_control_api(app: &AppHandle, settings: &ControlApiSettings) -> Result<(), String> {
+    if let Some(server) = SERVER.lock().map_err(|e| e.to_string())?.take() {
+        server.abort();
+        info!("Control API stopped");
+    }
+
+    if !settings.enabled {
+        return Ok(());
+    }
+    if settings.token.is_empty() {
+        return Err("The control API requires a token".to_string());
+    }
+
+    let address = SocketAddr::from(([127, 0, 0, 1], settings.port));
+    let app = app.clone();
+    let token = Arc::new(settings.token.clone());
+    let make_service = make_service_fn(move |_connection| {
+        let app = app.clone();
+        let token = token.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| handle_request(app.clone(), token.clone(), request)))
+        }
+    });
+
+    let server = Server::try_bind(&address)
+        .map_err(|e| format!("Failed to start the control API on port {}: {}", settings.port, e))?
+        .serve(make_service);
+    info!("Control API listening on {}", address);
+
+    let handle = tauri::async_runtime::spawn(async move {
+        if let Err(e) = server.await {
+            error!("Control API server failed: {}", e);
+        }
+    });
+    *SERVER.lock().map_err(|e| e.to_string())? = Some(handle);
+
+    Ok(())
+}
+
+fn json_response(status: StatusCode, body: serde_json::Value) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap_or_default()
+}
+
+fn is_authorized(request: &Request<Body>, token: &str) -> bool {
+    request.headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map_or(false, |provided| tokens_match(provided, token))
+}
+
+async fn handle_request(app: AppHandle, token: Arc<String>, request: Request<Body>) -> Result<Response<Body>, Infallible> {
+    if !is_authorized(&request, &token) {
+        return Ok(json_response(StatusCode::UNAUTHORIZED, json!({ "error": "Missing or invalid token" })));
+    }
+
+    let state = app.state::<Arc<Mutex<RecordingState>>>().inner().clone();
+    let path = request.uri().path().to_string();
+
+    let result = match (request.method().clone(), path.as_str()) {
+        (Method::GET, "/status") => Ok(status(&state).await),
+        (Method::POST, "/start") => start(&app, state.clone(), request).await,
+        (Method::POST, "/stop") => stop(state.clone()).await,
+        (Method::POST, "/pause") => set_paused(&mut *state.lock().await, true).map(|_| json!({})),
+        (Method::POST, "/resume") => set_paused(&mut *state.lock().await, false).map(|_| json!({})),
+        (Method::POST, "/cancel") => discard_recording(&state).await.map(|_| json!({})),
+        _ => return Ok(json_response(StatusCode::NOT_FOUND, json!({ "error": "Not found" }))),
+    };
+
+    Ok(match result {
+        Ok(body) => {
+            if path != "/status" {
+                let _ = app.emit_all(CONTROL_API_EVENT, path.trim_start_matches('/'));
+            }
+            json_response(StatusCode::OK, body)
+        },
+        Err(e) => json_response(StatusCode::CONFLICT, json!({ "error": e })),
+    })
+}
+
+async fn status(state: &Mutex<RecordingState>) -> serde_json::Value {
+    let guard = state.lock().await;
+    let phase = guard.phase();
+    let video_id = match phase {
+        RecordingPhase::Idle => None,
+        _ => guard.recording_options.as_ref().map(|options| options.video_id.clone()),
+    };
+
+    json!(StatusResponse { status: phase, video_id, elapsed_secs: guard.elapsed().as_secs() })
+}
+
+/// Expects the same JSON body as the `start_dual_recording` command.
+async fn start(app: &AppHandle, state: Arc<Mutex<RecordingState>>, request: Request<Body>) -> Result<serde_json::Value, String> {
+    let body = hyper::body::to_bytes(request.into_body()).await.map_err(|e| e.to_string())?;
+    let options: RecordingOptions = serde_json::from_slice(&body).map_err(|e| format!("Invalid recording options: {}", e))?;
+    let video_id = options.video_id.clone();
+
+    let settings = app.state::<Arc<Mutex<Settings>>>().inner().clone();
+    spawn_recording(Some(app.clone()), state, settings, options).await?;
+
+    Ok(json!({ "video_id": video_id }))
+}
+
+/// Uploads can take a while, so this returns once stopping has begun.
+async fn stop(state: Arc<Mutex<RecordingState>>) -> Result<serde_json::Value, String> {
+    if !state.lock().await.is_recording() {
+        return Err("No recording is in progress".to_string());
+    }
+
+    tauri::async_runtime::spawn(async move { stop_recording(&state, StopReason::UserStop).await });
+
+    Ok(json!({}))
+}
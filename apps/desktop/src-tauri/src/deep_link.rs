@@ -0,0 +1,128 @@
+use std::collections::HashMap;
+use reqwest::Url;
+use tauri::{AppHandle, Manager};
+use tracing::{error, info, warn};
+
+use crate::capture::ExtraFfmpegArgs;
+use crate::manifest::validate_video_id;
+use crate::recording::{CaptureMode, RecordingOptions};
+use crate::options::parse_optional;
+use crate::upload::StorageOptions;
+use crate::tray::show_main_window;
+
+pub const DEEP_LINK_SCHEME: &str = "cap";
+/// Emitted with the `RecordingOptions` of a `cap://record` link. Nothing is recorded
+/// until the user confirms; the frontend then fills in the user and bucket of the
+/// logged-in session and calls `start_dual_recording`.
+pub const DEEP_LINK_EVENT: &str = "deep-link://record";
+
+const RECORD_PARAMS: [&str; 9] = [
+    "video_id", "screen_index", "video_index", "audio_name", "framerate", "resolution",
+    "segment_time_secs", "capture_mode", "start_delay_secs",
+];
+
+/// Registers the `cap://` scheme. URLs opened while the app runs arrive through the
+/// plugin; on Windows and Linux a cold start passes the URL as the first argument.
+pub fn register(app: &AppHandle) {
+    let handle = app.clone();
+    if let Err(e) = tauri_plugin_deep_link::register(DEEP_LINK_SCHEME, move |url| handle_deep_link(&handle, url)) {
+        error!("Failed to register the {}:// URL scheme: {}", DEEP_LINK_SCHEME, e);
+    }
+
+    #[cfg(not(target_os = "macos"))]
+    if let Some(url) = std::env::args().nth(1).filter(|arg| arg.starts_with(&format!("{}://", DEEP_LINK_SCHEME))) {
+        handle_deep_link(app, url);
+    }
+}
+
+fn handle_deep_link(app: &AppHandle, url: String) {
+    info!("Received deep link: {}", url);
+
+    let options = match parse_record_url(&url) {
+        Ok(options) => options,
+        Err(e) => {
+            warn!("Ignoring deep link {}: {}", url, e);
+            return;
+        },
+    };
+
+    show_main_window(app);
+    if let Err(e) = app.emit_all(DEEP_LINK_EVENT, options) {
+        error!("Failed to emit deep link event: {}", e);
+    }
+}
+
+/// Parses `cap://record?video_id=...`. Device and quality parameters are optional and
+/// fall back to the saved settings. The user and bucket are left empty: any page can
+/// open a link, so they are never taken from it.
+pub fn parse_record_url(url: &str) -> Result<RecordingOptions, String> {
+    let url = Url::parse(url).map_err(|e| format!("Invalid URL: {}", e))?;
+    if url.scheme() != DEEP_LINK_SCHEME || url.host_str() != Some("record") {
+        return Err("Unsupported deep link".to_string());
+    }
+
+    let mut params: HashMap<String, String> = HashMap::new();
+    for (key, value) in url.query_pairs() {
+        if !RECORD_PARAMS.contains(&key.as_ref()) {
+            return Err(format!("Unknown parameter: {}", key));
+        }
+        params.insert(key.into_owned(), value.into_owned());
+    }
+
+    let mut required = |key: &str| -> Result<String, String> {
+        params.remove(key)
+            .filter(|value| !value.is_empty())
+            .ok_or(format!("Missing parameter: {}", key))
+    };
+    let video_id = required("video_id")?;
+    validate_video_id(&video_id)?;
+
+    let framerate = params.remove("framerate").unwrap_or_default();
+    let framerate = parse_optional(&framerate).map_err(|_| format!("Invalid framerate: {}", framerate))?;
+
+    let segment_time_secs = match params.remove("segment_time_secs") {
+        Some(value) => value.parse().map_err(|_| format!("Invalid segment_time_secs: {}", value))?,
+        None => 0,
+    };
+
+    let start_delay_secs = match params.remove("start_delay_secs") {
+        Some(value) => value.parse().map_err(|_| format!("Invalid start_delay_secs: {}", value))?,
+        None => 0,
+    };
+
+    let capture_mode = match params.remove("capture_mode") {
+        Some(value) => value.parse()?,
+        None => CaptureMode::default(),
+    };
+
+    Ok(RecordingOptions {
+        user_id: String::new(),
+        video_id,
+        screen_index: parse_optional(&params.remove("screen_index").unwrap_or_default())?,
+        video_index: parse_optional(&params.remove("video_index").unwrap_or_default())?,
+        audio_name: params.remove("audio_name").unwrap_or_default(),
+        aws_region: String::new(),
+        aws_bucket: String::new(),
+        framerate,
+        resolution: parse_optional(&params.remove("resolution").unwrap_or_default())?,
+        segment_time_secs,
+        capture_mode,
+        audio_codec: String::new(),
+        audio_sample_rate: 0,
+        audio_channels: 0,
+        audio_bitrate: String::new(),
+        force_full_range: None,
+        video_quality: None,
+        storage: StorageOptions::default(),
+        early_segment_upload: None,
+        upload_order: None,
+        audio_offset_ms: 0,
+        muxed_audio: None,
+        auto_gain: None,
+        live_drawing: None,
+        camera_background: None,
+        extra_ffmpeg_args: ExtraFfmpegArgs::default(),
+        start_delay_secs,
+        chunk_naming: None,
+    })
+}
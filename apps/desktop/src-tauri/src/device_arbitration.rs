@@ -0,0 +1,178 @@
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager};
+use tracing::{debug, info, warn};
+
+use crate::audio_levels::stop_preview;
+use crate::capture::resolve_device_input;
+use crate::ffmpeg_error::{FfmpegError, FfmpegErrorKind};
+use crate::options::DeviceId;
+use crate::recording::{CaptureMode, RecordingOptions};
+
+/// Emitted with a `ReleaseRequest` when a recording needs a device the frontend's
+/// camera preview holds. The preview should close and call `release_camera_preview`.
+pub const DEVICE_RELEASE_EVENT: &str = "devices://release-requested";
+/// How long a recording waits for a device to be let go before failing like ffmpeg
+/// would on a busy device. A test recording holds its devices for about five seconds.
+const ACQUIRE_TIMEOUT: Duration = Duration::from_secs(15);
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+static HELD_DEVICES: Mutex<Vec<HeldDevice>> = Mutex::new(Vec::new());
+static NEXT_LEASE_ID: AtomicU64 = AtomicU64::new(1);
+/// Held from `acquire_camera_preview` until `release_camera_preview`.
+static CAMERA_PREVIEW: Mutex<Option<DeviceLease>> = Mutex::new(None);
+
+/// What has a capture device open.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceUser {
+    Recording,
+    TestRecording,
+    MicPreview,
+    CameraPreview,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReleaseRequest {
+    pub device: String,
+    pub requested_by: DeviceUser,
+}
+
+struct HeldDevice {
+    lease_id: u64,
+    device: String,
+    user: DeviceUser,
+    /// Set by `DeviceLease::set_shared`.
+    shared: bool,
+}
+
+/// Devices registered to one user; they are released when it is dropped.
+#[derive(Debug)]
+pub struct DeviceLease {
+    id: u64,
+}
+
+impl DeviceLease {
+    /// Lets the next recording open these devices while this lease still holds them.
+    /// `split_recording` overlaps the two captures on purpose.
+    pub fn set_shared(&self, shared: bool) {
+        if let Ok(mut held) = HELD_DEVICES.lock() {
+            for device in held.iter_mut().filter(|device| device.lease_id == self.id) {
+                device.shared = shared;
+            }
+        }
+    }
+}
+
+impl Drop for DeviceLease {
+    fn drop(&mut self) {
+        if let Ok(mut held) = HELD_DEVICES.lock() {
+            held.retain(|device| device.lease_id != self.id);
+        }
+    }
+}
+
+/// The key of a screen or camera input, as `resolve_device_input` gives it. Screens
+/// and cameras share avfoundation's indices on macOS.
+pub fn video_device(input: &str) -> String {
+    format!("video:{}", if input.is_empty() { "default" } else { input })
+}
+
+/// The key of a mic; `None` is the default input.
+pub fn mic_device(name: Option<&str>) -> String {
+    format!("mic:{}", name.filter(|name| !name.is_empty()).unwrap_or("default"))
+}
+
+/// The devices capturing with `options` opens.
+pub async fn recording_devices(options: &RecordingOptions) -> Result<Vec<String>, String> {
+    let video_input = match options.capture_mode {
+        CaptureMode::Screen => resolve_device_input(options.screen_index.clone()).await?,
+        CaptureMode::Camera => resolve_device_input(options.video_index.clone()).await?,
+    };
+    Ok(vec![video_device(&video_input), mic_device(Some(&options.audio_name))])
+}
+
+/// Registers `devices` for `user`, so no one else opens them at the same time. A
+/// recording or test recording stops the mic preview, asks the camera preview to
+/// close, and waits in line behind any other test or recording. A preview fails right
+/// away when a device is taken. Only a lease marked with `DeviceLease::set_shared`
+/// lets a recording in alongside it.
+pub async fn acquire(app: Option<&AppHandle>, user: DeviceUser, devices: Vec<String>) -> Result<DeviceLease, String> {
+    let started = Instant::now();
+    let mut release_requested = false;
+
+    loop {
+        let conflicts = match try_register(user, &devices)? {
+            Ok(lease) => return Ok(lease),
+            Err(conflicts) => conflicts,
+        };
+
+        for (device, holder) in &conflicts {
+            match holder {
+                DeviceUser::MicPreview => stop_preview(),
+                DeviceUser::CameraPreview if !release_requested => {
+                    info!("Asking the camera preview to release {} for the {:?}", device, user);
+                    if let Some(app) = app {
+                        let _ = app.emit_all(DEVICE_RELEASE_EVENT, ReleaseRequest { device: device.clone(), requested_by: user });
+                    }
+                },
+                _ => {},
+            }
+        }
+        release_requested = true;
+
+        if started.elapsed() >= ACQUIRE_TIMEOUT {
+            let (device, holder) = &conflicts[0];
+            warn!("Gave up waiting for {} after {} seconds", device, ACQUIRE_TIMEOUT.as_secs());
+            return Err(FfmpegError::new(FfmpegErrorKind::DeviceBusy, format!("{} is still in use by the {:?}", device, holder)).into());
+        }
+        debug!("Waiting for {:?} to be released", conflicts);
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+/// `acquire` for previews, which don't wait.
+pub fn acquire_now(user: DeviceUser, devices: Vec<String>) -> Result<DeviceLease, String> {
+    try_register(user, &devices)?.map_err(|conflicts| {
+        let (device, holder) = &conflicts[0];
+        FfmpegError::new(FfmpegErrorKind::DeviceBusy, format!("{} is in use by the {:?}", device, holder)).into()
+    })
+}
+
+/// Registers the devices if no other lease holds them, or returns who holds them.
+fn try_register(user: DeviceUser, devices: &[String]) -> Result<Result<DeviceLease, Vec<(String, DeviceUser)>>, String> {
+    let mut held = HELD_DEVICES.lock().map_err(|e| e.to_string())?;
+    let conflicts: Vec<(String, DeviceUser)> = held.iter()
+        .filter(|device| devices.contains(&device.device))
+        .filter(|device| !(device.shared && user == DeviceUser::Recording))
+        .map(|device| (device.device.clone(), device.user))
+        .collect();
+    if !conflicts.is_empty() {
+        return Ok(Err(conflicts));
+    }
+
+    let lease = DeviceLease { id: NEXT_LEASE_ID.fetch_add(1, Ordering::SeqCst) };
+    held.extend(devices.iter().map(|device| HeldDevice { lease_id: lease.id, device: device.clone(), user, shared: false }));
+    Ok(Ok(lease))
+}
+
+/// Registers the frontend's camera preview of `camera`, so a recording asks it to close
+/// before opening the camera. Fails while a recording or test uses the camera.
+#[tauri::command]
+pub async fn acquire_camera_preview(camera: DeviceId) -> Result<(), String> {
+    let input = resolve_device_input(Some(camera)).await?;
+    let mut preview = CAMERA_PREVIEW.lock().map_err(|e| e.to_string())?;
+    // Switching cameras releases the previous one.
+    *preview = None;
+    *preview = Some(acquire_now(DeviceUser::CameraPreview, vec![video_device(&input)])?);
+    Ok(())
+}
+
+#[tauri::command]
+pub fn release_camera_preview() {
+    if let Ok(mut preview) = CAMERA_PREVIEW.lock() {
+        *preview = None;
+    }
+}
@@ -1,30 +1,31 @@
 use serde::{Serialize, Deserialize};
+use tracing::{debug, info};
 
 use crate::utils::run_command;
-use crate::utils::ffmpeg_path_as_str;
+use crate::utils::ffmpeg_path;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct DeviceList {
-    video_devices: Vec<String>,
-    audio_devices: Vec<String>,
+    pub video_devices: Vec<String>,
+    pub audio_devices: Vec<String>,
 }
 
 #[tauri::command]
 pub fn list_devices() -> Result<DeviceList, String> {
     let os_type = std::env::consts::OS;
-    let ffmpeg_binary_path_str = ffmpeg_path_as_str()?;
+    let ffmpeg_binary = ffmpeg_path()?;
 
-    println!("OS: {}", os_type);
-    println!("FFmpeg binary path: {}", ffmpeg_binary_path_str);
+    debug!("OS: {}", os_type);
+    debug!("FFmpeg binary path: {}", ffmpeg_binary.display());
 
     match os_type {
         "macos" => {
-            let (output, stderr) = run_command(&ffmpeg_binary_path_str, vec!["-f", "avfoundation", "-list_devices", "true", "-i", ""])?;
+            let (output, stderr) = run_command(&ffmpeg_binary, vec!["-f", "avfoundation", "-list_devices", "true", "-i", ""])?;
             let raw_output = if !stderr.trim().is_empty() { stderr } else { output };
             let (video_devices, audio_devices) = parse_devices_macos(&raw_output);
 
-            println!("Video devices: {:?}", video_devices);
-            println!("Audio devices: {:?}", audio_devices);
+            info!("Video devices: {:?}", video_devices);
+            info!("Audio devices: {:?}", audio_devices);
 
             Ok(DeviceList { video_devices, audio_devices })
         }
@@ -37,7 +38,7 @@ pub fn list_devices() -> Result<DeviceList, String> {
             Ok(DeviceList { video_devices, audio_devices })
         }
         "windows" => {
-            let (raw_output, _) = run_command(&ffmpeg_binary_path_str, vec!["-f", "dshow", "-list_devices", "true", "-i", ""])?;
+            let (raw_output, _) = run_command(&ffmpeg_binary, vec!["-f", "dshow", "-list_devices", "true", "-i", ""])?;
             let (video_devices, audio_devices) = parse_devices_windows(&raw_output);
             Ok(DeviceList { video_devices, audio_devices })
         }
@@ -45,6 +46,14 @@ pub fn list_devices() -> Result<DeviceList, String> {
     }
 }
 
+/// The current avfoundation index of the video device (camera or screen) called `name`.
+pub fn avfoundation_index(name: &str) -> Result<usize, String> {
+    let video_devices = list_devices()?.video_devices;
+    video_devices.iter()
+        .position(|device| device == name)
+        .ok_or(format!("Video device {} was not found", name))
+}
+
 fn parse_devices_macos(raw_output: &str) -> (Vec<String>, Vec<String>) {
     let lines: Vec<&str> = raw_output.lines().collect();
     let video_start_index = lines.iter().position(|&x| x.contains("AVFoundation video devices:")).unwrap_or(0) + 1;
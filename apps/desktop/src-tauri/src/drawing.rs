@@ -0,0 +1,101 @@
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tauri::State;
+use tokio::sync::Mutex;
+use tracing::debug;
+use xcap::image::{ImageFormat, RgbaImage};
+use xcap::Monitor;
+
+use crate::annotate::{self, AnnotationOp};
+use crate::screenshot::parse_screen_index;
+use crate::session::RecordingState;
+
+/// The strokes drawn so far, in the recording directory. ffmpeg reads it again for
+/// every overlay frame, so replacing it shows up in the capture.
+pub const DRAWING_FILE: &str = "drawing.png";
+/// Overlay frames per second; strokes show up in the recording this often.
+const DRAWING_FRAMERATE: &str = "10";
+
+/// Creates a transparent canvas the size of the display in `input_index`, in the
+/// display's logical points, which is also what the frontend's overlay window uses.
+pub fn create_canvas(recording_dir: &Path, input_index: &str) -> Result<PathBuf, String> {
+    let screen_index = parse_screen_index(input_index);
+    let monitor = Monitor::all()
+        .map_err(|e| format!("Failed to list screens: {}", e))?
+        .into_iter()
+        .nth(screen_index)
+        .ok_or(format!("Screen {} not found", screen_index))?;
+
+    let canvas_path = recording_dir.join(DRAWING_FILE);
+    save_canvas(&RgbaImage::new(monitor.width().max(1), monitor.height().max(1)), &canvas_path)?;
+    Ok(canvas_path)
+}
+
+/// Adds the canvas as the last input of the screen capture and lays it over the
+/// captured frames, stretched to their size, ahead of the `-vf` chain. The chain
+/// becomes a `-filter_complex`, so the drawing is zoomed along with the screen.
+pub fn add_drawing_input(args: &mut Vec<OsString>, canvas_path: &Path) -> Result<(), String> {
+    let input_count = args.iter().filter(|arg| *arg == "-i").count();
+    let last_input_at = args.iter().rposition(|arg| arg == "-i").ok_or("Screen input is missing".to_string())?;
+    let canvas_input = [
+        "-re".into(),
+        "-loop".into(), "1".into(),
+        "-framerate".into(), DRAWING_FRAMERATE.into(),
+        "-i".into(), canvas_path.as_os_str().to_owned(),
+    ];
+    args.splice(last_input_at + 2..last_input_at + 2, canvas_input);
+
+    let filter_at = args.iter().position(|arg| arg == "-vf").ok_or("Video filter is missing".to_string())?;
+    let filter = args.get(filter_at + 1).cloned().unwrap_or_default();
+    let mut filter_complex = OsString::from(format!(
+        "[{}:v]format=rgba[ink];[ink][0:v]scale2ref[sized_ink][screen];[screen][sized_ink]overlay=format=auto:eof_action=repeat,",
+        input_count,
+    ));
+    filter_complex.push(filter);
+    args[filter_at] = "-filter_complex".into();
+    args[filter_at + 1] = filter_complex;
+    Ok(())
+}
+
+/// Replaces what is drawn over the active recording with `ops`, in the canvas's
+/// coordinates; an empty list clears it. The frontend sends all strokes every time.
+/// Crops and blurs are not supported on the canvas.
+#[tauri::command]
+pub async fn set_drawing(state: State<'_, Arc<Mutex<RecordingState>>>, ops: Vec<AnnotationOp>) -> Result<(), String> {
+    let guard = state.lock().await;
+    if !guard.is_recording() {
+        return Err("No recording is in progress".to_string());
+    }
+    if !guard.recording_options.as_ref().map_or(false, |options| options.live_drawing.unwrap_or_default()) {
+        return Err("Live drawing is off for this recording".to_string());
+    }
+    let recording_dir = guard.recording_dir.clone().ok_or("Recording directory is not set".to_string())?;
+    drop(guard);
+
+    if ops.iter().any(|op| matches!(op, AnnotationOp::Crop { .. } | AnnotationOp::Blur { .. })) {
+        return Err("Crops and blurs can't be drawn live".to_string());
+    }
+
+    tokio::task::spawn_blocking(move || {
+        let canvas_path = recording_dir.join(DRAWING_FILE);
+        let (width, height) = xcap::image::image_dimensions(&canvas_path)
+            .map_err(|e| format!("Failed to read the drawing canvas: {}", e))?;
+        let canvas = annotate::apply(RgbaImage::new(width, height), &ops)?;
+        save_canvas(&canvas, &canvas_path)?;
+        debug!("Drew {} strokes over the recording", ops.len());
+        Ok(())
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// Writes the canvas next to its final path and renames it over, so ffmpeg never
+/// reads a half-written image.
+fn save_canvas(canvas: &RgbaImage, canvas_path: &Path) -> Result<(), String> {
+    let temp_path = canvas_path.with_extension("png.tmp");
+    canvas.save_with_format(&temp_path, ImageFormat::Png)
+        .map_err(|e| format!("Failed to save the drawing canvas: {}", e))?;
+    std::fs::rename(&temp_path, canvas_path)
+        .map_err(|e| format!("Failed to replace the drawing canvas: {}", e))
+}
@@ -0,0 +1,133 @@
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+use serde::Serialize;
+use tracing::{debug, info};
+
+use crate::utils::ffmpeg_path;
+
+/// Probe results for the ffmpeg they were taken with; probing takes a few seconds.
+static CAPABILITIES: Mutex<Option<(PathBuf, EncoderCapabilities)>> = Mutex::new(None);
+
+/// Hardware encoders worth probing on each OS. A listed encoder often fails to open
+/// without the matching GPU or driver, so each one is tried on a test frame.
+#[cfg(target_os = "macos")]
+const HARDWARE_ENCODERS: &[&str] = &["h264_videotoolbox", "hevc_videotoolbox"];
+#[cfg(target_os = "windows")]
+const HARDWARE_ENCODERS: &[&str] = &["h264_nvenc", "hevc_nvenc", "h264_qsv", "hevc_qsv", "h264_amf", "hevc_amf"];
+#[cfg(target_os = "linux")]
+const HARDWARE_ENCODERS: &[&str] = &["h264_nvenc", "hevc_nvenc", "h264_vaapi", "hevc_vaapi", "h264_qsv", "hevc_qsv"];
+
+const SOFTWARE_ENCODERS: &[&str] = &["libx264"];
+
+/// Sizes tried from the largest down; the first that encodes is reported as the max.
+const PROBE_RESOLUTIONS: [(u32, u32); 5] = [(7680, 4320), (5120, 2880), (3840, 2160), (2560, 1440), (1920, 1080)];
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EncoderCapabilities {
+    /// Encoders that opened and encoded a test frame.
+    pub encoders: Vec<EncoderCapability>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EncoderCapability {
+    pub name: String,
+    pub hardware: bool,
+    pub pixel_formats: Vec<String>,
+    /// Largest probed size the encoder accepted; `None` when even 1080p failed.
+    pub max_width: Option<u32>,
+    pub max_height: Option<u32>,
+}
+
+/// Reports which video encoders the active ffmpeg can actually use here. The probes
+/// run once per ffmpeg binary and are cached.
+#[tauri::command]
+pub async fn get_encoder_capabilities() -> Result<EncoderCapabilities, String> {
+    let ffmpeg = ffmpeg_path()?;
+    if let Some((path, capabilities)) = CAPABILITIES.lock().map_err(|e| e.to_string())?.as_ref() {
+        if *path == ffmpeg {
+            return Ok(capabilities.clone());
+        }
+    }
+
+    let path = ffmpeg.clone();
+    let capabilities = tokio::task::spawn_blocking(move || probe_capabilities(&path))
+        .await
+        .map_err(|e| e.to_string())??;
+    info!("Encoder capabilities: {:?}", capabilities);
+    *CAPABILITIES.lock().map_err(|e| e.to_string())? = Some((ffmpeg, capabilities.clone()));
+    Ok(capabilities)
+}
+
+fn probe_capabilities(ffmpeg: &Path) -> Result<EncoderCapabilities, String> {
+    let output = Command::new(ffmpeg)
+        .args(["-hide_banner", "-encoders"])
+        .output()
+        .map_err(|e| format!("Failed to run {}: {}", ffmpeg.display(), e))?;
+    let listing = String::from_utf8_lossy(&output.stdout);
+    let listed = |name: &str| listing.lines().any(|line| line.split_whitespace().nth(1) == Some(name));
+
+    let candidates = SOFTWARE_ENCODERS.iter().map(|name| (*name, false))
+        .chain(HARDWARE_ENCODERS.iter().map(|name| (*name, true)));
+
+    let mut encoders = vec![];
+    for (name, hardware) in candidates.filter(|(name, _)| listed(name)) {
+        let max_resolution = PROBE_RESOLUTIONS.iter().find(|(width, height)| encodes(ffmpeg, name, *width, *height));
+        let opens = max_resolution.is_some() || encodes(ffmpeg, name, 640, 360);
+        if !opens {
+            debug!("Encoder {} is listed but could not encode a test frame", name);
+            continue;
+        }
+        encoders.push(EncoderCapability {
+            name: name.to_string(),
+            hardware,
+            pixel_formats: pixel_formats(ffmpeg, name),
+            max_width: max_resolution.map(|(width, _)| *width),
+            max_height: max_resolution.map(|(_, height)| *height),
+        });
+    }
+
+    Ok(EncoderCapabilities { encoders })
+}
+
+/// Encodes one black frame of the given size with `encoder`, discarding the output.
+fn encodes(ffmpeg: &Path, encoder: &str, width: u32, height: u32) -> bool {
+    let source = format!("color=black:s={}x{}:d=0.1", width, height);
+    let mut command = Command::new(ffmpeg);
+    command.args(["-hide_banner", "-loglevel", "error"]);
+    let (device_args, upload_filter) = hardware_upload(encoder);
+    command.args(device_args);
+    command.args(["-f", "lavfi", "-i", &source]);
+    if let Some(upload_filter) = upload_filter {
+        command.args(["-vf", upload_filter]);
+    }
+    command
+        .args(["-frames:v", "1", "-c:v", encoder, "-f", "null", "-"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .map_or(false, |status| status.success())
+}
+
+/// Arguments to put before the inputs and the filter to run on the frames before
+/// `encoder` gets them. VAAPI encoders take frames already uploaded to the GPU.
+pub fn hardware_upload(encoder: &str) -> (&'static [&'static str], Option<&'static str>) {
+    if encoder.ends_with("_vaapi") {
+        (&["-vaapi_device", "/dev/dri/renderD128"], Some("format=nv12,hwupload"))
+    } else {
+        (&[], None)
+    }
+}
+
+/// Reads the `Supported pixel formats:` line of `ffmpeg -h encoder=<name>`.
+fn pixel_formats(ffmpeg: &Path, encoder: &str) -> Vec<String> {
+    let output = match Command::new(ffmpeg).args(["-hide_banner", "-h", &format!("encoder={}", encoder)]).output() {
+        Ok(output) => output,
+        Err(_) => return vec![],
+    };
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("Supported pixel formats:"))
+        .map(|formats| formats.split_whitespace().map(str::to_string).collect())
+        .unwrap_or_default()
+}
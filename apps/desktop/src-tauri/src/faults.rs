@@ -0,0 +1,166 @@
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use futures::future::{BoxFuture, FutureExt};
+use tokio::sync::Mutex;
+use tracing::warn;
+
+use crate::recording::RecordingOptions;
+use crate::session::RecordingState;
+use crate::upload::Uploader;
+
+/// Hidden developer setting, e.g. `ffmpeg_crash_after=20,fail_every_nth_upload=3`,
+/// for reproducing the failures the recovery paths are meant to handle.
+const FAULTS_ENV: &str = "CAP_INJECT_FAULTS";
+
+static FAULTS: RwLock<FaultInjection> = RwLock::new(FaultInjection {
+    ffmpeg_crash_after: None,
+    fail_every_nth_upload: None,
+    disk_full_after: None,
+});
+static RECORDING_STARTED: RwLock<Option<Instant>> = RwLock::new(None);
+
+/// Simulated failures for QA. Times count from the start of each recording.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FaultInjection {
+    /// Kills the screen ffmpeg this long into the recording.
+    pub ffmpeg_crash_after: Option<Duration>,
+    /// Fails every nth segment upload before it reaches the uploader.
+    pub fail_every_nth_upload: Option<usize>,
+    /// From this long into the recording, manifest writes fail as if the disk were
+    /// full, and the screen ffmpeg is killed as it would be on a failed write.
+    pub disk_full_after: Option<Duration>,
+}
+
+impl FromStr for FaultInjection {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut faults = FaultInjection::default();
+        for fault in value.split(',').map(str::trim).filter(|fault| !fault.is_empty()) {
+            let (name, amount) = fault.split_once('=').ok_or(format!("Missing value for fault: {}", fault))?;
+            let amount: u64 = amount.trim().parse().map_err(|_| format!("Invalid value for fault: {}", fault))?;
+            match name.trim() {
+                "ffmpeg_crash_after" => faults.ffmpeg_crash_after = Some(Duration::from_secs(amount)),
+                "fail_every_nth_upload" if amount > 0 => faults.fail_every_nth_upload = Some(amount as usize),
+                "disk_full_after" => faults.disk_full_after = Some(Duration::from_secs(amount)),
+                _ => return Err(format!("Invalid fault: {}", fault)),
+            }
+        }
+        Ok(faults)
+    }
+}
+
+/// Reads the faults to inject from `CAP_INJECT_FAULTS`, if set.
+pub fn init_from_env() {
+    let value = match std::env::var(FAULTS_ENV) {
+        Ok(value) => value,
+        Err(_) => return,
+    };
+    match value.parse::<FaultInjection>() {
+        Ok(faults) => {
+            warn!("Injecting faults: {:?}", faults);
+            if let Ok(mut current) = FAULTS.write() {
+                *current = faults;
+            }
+        },
+        Err(e) => warn!("Ignoring {}: {}", FAULTS_ENV, e),
+    }
+}
+
+fn faults() -> FaultInjection {
+    FAULTS.read().map(|faults| *faults).unwrap_or_default()
+}
+
+/// Starts the clock for the timed faults of a new recording, and schedules the
+/// screen ffmpeg to be killed if a crash or full disk is due.
+pub fn arm(state: Arc<Mutex<RecordingState>>, video_id: String) {
+    let faults = faults();
+    if let Ok(mut started) = RECORDING_STARTED.write() {
+        *started = Some(Instant::now());
+    }
+
+    let kill_after = [faults.ffmpeg_crash_after, faults.disk_full_after].into_iter().flatten().min();
+    if let Some(kill_after) = kill_after {
+        tokio::spawn(async move {
+            tokio::time::sleep(kill_after).await;
+            let mut guard = state.lock().await;
+            if guard.recording_options.as_ref().map(|options| &options.video_id) != Some(&video_id) {
+                return;
+            }
+            if let Some(process) = guard.screen_process.as_mut() {
+                warn!("Injected fault: killing the screen FFmpeg of {}", video_id);
+                let _ = process.start_kill();
+            }
+        });
+    }
+}
+
+/// Fails like a write to a full disk once the injected `disk_full_after` has passed.
+pub fn check_disk_space() -> Result<(), String> {
+    let disk_full_after = match faults().disk_full_after {
+        Some(disk_full_after) => disk_full_after,
+        None => return Ok(()),
+    };
+    let started = RECORDING_STARTED.read().ok().and_then(|started| *started);
+    match started {
+        Some(started) if started.elapsed() >= disk_full_after => {
+            Err("No space left on device (injected fault)".to_string())
+        },
+        _ => Ok(()),
+    }
+}
+
+/// Wraps `uploader` so every nth upload fails, when that fault is injected.
+pub fn wrap_uploader(uploader: Arc<dyn Uploader>) -> Arc<dyn Uploader> {
+    match faults().fail_every_nth_upload {
+        Some(every) => Arc::new(FailingUploader { inner: uploader, every, attempts: AtomicUsize::new(0) }),
+        None => uploader,
+    }
+}
+
+struct FailingUploader {
+    inner: Arc<dyn Uploader>,
+    every: usize,
+    attempts: AtomicUsize,
+}
+
+impl Uploader for FailingUploader {
+    fn upload_segment<'a>(
+        &'a self,
+        options: &'a RecordingOptions,
+        file_path: &'a Path,
+        file_type: &'a str,
+        duration_secs: f64,
+    ) -> BoxFuture<'a, Result<String, String>> {
+        let attempt = self.attempts.fetch_add(1, Ordering::SeqCst) + 1;
+        if attempt % self.every == 0 {
+            warn!("Injected fault: failing the upload of {}", file_path.display());
+            return async move { Err(format!("Injected upload failure for {}", file_path.display())) }.boxed();
+        }
+        self.inner.upload_segment(options, file_path, file_type, duration_secs)
+    }
+
+    fn missing_uploads<'a>(
+        &'a self,
+        options: &'a RecordingOptions,
+        file_keys: &'a [String],
+    ) -> BoxFuture<'a, Result<Vec<String>, String>> {
+        self.inner.missing_uploads(options, file_keys)
+    }
+
+    fn upload_file<'a>(
+        &'a self,
+        options: &'a RecordingOptions,
+        file_path: &'a Path,
+        file_type: &'a str,
+    ) -> BoxFuture<'a, Result<String, String>> {
+        self.inner.upload_file(options, file_path, file_type)
+    }
+
+    fn object_url(&self, options: &RecordingOptions, file_key: &str) -> Option<String> {
+        self.inner.object_url(options, file_key)
+    }
+}
@@ -0,0 +1,92 @@
+use serde::Serialize;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FfmpegErrorKind {
+    PermissionDenied,
+    DeviceBusy,
+    DeviceNotFound,
+    UnknownInputFormat,
+    EncoderUnavailable,
+    DiskFull,
+    InvalidOptions,
+    Unknown,
+}
+
+/// A failure reported by an ffmpeg process, classified from its stderr so the
+/// frontend can show the user what to do about it.
+#[derive(Debug, Clone, Serialize)]
+pub struct FfmpegError {
+    pub kind: FfmpegErrorKind,
+    pub message: String,
+    pub hint: String,
+}
+
+impl FfmpegError {
+    pub fn new(kind: FfmpegErrorKind, message: impl Into<String>) -> Self {
+        FfmpegError {
+            kind,
+            message: message.into(),
+            hint: remediation_hint(kind).to_string(),
+        }
+    }
+}
+
+impl fmt::Display for FfmpegError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.message, self.hint)
+    }
+}
+
+/// Commands report errors as strings, so typed errors are serialized as JSON that
+/// the frontend can parse to read `kind` and `hint`.
+impl From<FfmpegError> for String {
+    fn from(error: FfmpegError) -> Self {
+        serde_json::to_string(&error).unwrap_or_else(|_| error.to_string())
+    }
+}
+
+/// Maps a known ffmpeg stderr line to an error kind, or `None` for ordinary output.
+pub fn classify_stderr_line(line: &str) -> Option<FfmpegErrorKind> {
+    let line = line.to_lowercase();
+
+    let kind = if line.contains("operation not permitted")
+        || line.contains("permission denied")
+        || line.contains("not authorized") {
+        FfmpegErrorKind::PermissionDenied
+    } else if line.contains("device or resource busy") || line.contains("resource busy") {
+        FfmpegErrorKind::DeviceBusy
+    } else if line.contains("unknown input format") {
+        FfmpegErrorKind::UnknownInputFormat
+    } else if line.contains("invalid device index")
+        || line.contains("device not found")
+        || line.contains("could not find video device")
+        || line.contains("could not find audio only device")
+        || line.contains("cannot open display") {
+        FfmpegErrorKind::DeviceNotFound
+    } else if line.contains("unknown encoder") || line.contains("encoder not found") {
+        FfmpegErrorKind::EncoderUnavailable
+    } else if line.contains("no space left on device") {
+        FfmpegErrorKind::DiskFull
+    } else if line.contains("invalid argument") || line.contains("selected framerate") {
+        FfmpegErrorKind::InvalidOptions
+    } else {
+        return None;
+    };
+
+    Some(kind)
+}
+
+fn remediation_hint(kind: FfmpegErrorKind) -> &'static str {
+    match kind {
+        FfmpegErrorKind::PermissionDenied => "Grant Cap screen recording, camera and microphone access in your system privacy settings, then restart Cap.",
+        FfmpegErrorKind::DeviceBusy => "Another application is using this device. Close it (or its preview) and try again.",
+        FfmpegErrorKind::DeviceNotFound => "The selected device is no longer available. Reconnect it or pick a different one.",
+        FfmpegErrorKind::UnknownInputFormat => "The FFmpeg build in use does not support capturing on this system. Reinstall Cap or configure a different FFmpeg binary.",
+        FfmpegErrorKind::EncoderUnavailable => "The FFmpeg build in use is missing a required encoder. Configure a different FFmpeg binary or choose another quality setting.",
+        FfmpegErrorKind::DiskFull => "Your disk is full. Free up some space and try again.",
+        FfmpegErrorKind::InvalidOptions => "The device rejected the requested settings. Try a different framerate or resolution.",
+        FfmpegErrorKind::Unknown => "Check the exported logs for details.",
+    }
+}
@@ -0,0 +1,92 @@
+use std::process::Command;
+use std::sync::Mutex;
+use tracing::{debug, info, warn};
+
+/// Shortcuts run on macOS, where Focus modes can't be switched directly. Users create
+/// them in the Shortcuts app with the "Set Focus" action.
+const MACOS_FOCUS_ON_SHORTCUT: &str = "Cap Focus On";
+const MACOS_FOCUS_OFF_SHORTCUT: &str = "Cap Focus Off";
+
+/// How to undo enabling Do Not Disturb, and how many recordings want it on.
+static FOCUS: Mutex<(usize, Option<Restore>)> = Mutex::new((0, None));
+
+enum Restore {
+    /// GNOME's previous `show-banners` value.
+    Gnome(String),
+    MacShortcut,
+}
+
+/// Keeps notifications from popping up in the recording while alive, restoring the
+/// previous setting once the last recording stops. Supported on GNOME and, with the
+/// shortcuts above, on macOS.
+pub struct DoNotDisturb(());
+
+impl DoNotDisturb {
+    pub fn enable() -> DoNotDisturb {
+        if let Ok(mut focus) = FOCUS.lock() {
+            focus.0 += 1;
+            if focus.0 == 1 {
+                focus.1 = turn_on();
+            }
+        }
+        DoNotDisturb(())
+    }
+}
+
+impl Drop for DoNotDisturb {
+    fn drop(&mut self) {
+        if let Ok(mut focus) = FOCUS.lock() {
+            focus.0 = focus.0.saturating_sub(1);
+            if focus.0 == 0 {
+                if let Some(restore) = focus.1.take() {
+                    turn_off(restore);
+                }
+            }
+        }
+    }
+}
+
+fn turn_on() -> Option<Restore> {
+    let restore = match std::env::consts::OS {
+        "linux" => {
+            let previous = run("gsettings", &["get", "org.gnome.desktop.notifications", "show-banners"])?;
+            run("gsettings", &["set", "org.gnome.desktop.notifications", "show-banners", "false"])?;
+            Restore::Gnome(previous.trim().to_string())
+        },
+        "macos" => {
+            run("shortcuts", &["run", MACOS_FOCUS_ON_SHORTCUT])?;
+            Restore::MacShortcut
+        },
+        os => {
+            warn!("Do Not Disturb can't be enabled on {}", os);
+            return None;
+        },
+    };
+    info!("Enabled Do Not Disturb for the recording");
+    Some(restore)
+}
+
+fn turn_off(restore: Restore) {
+    let restored = match restore {
+        Restore::Gnome(previous) => run("gsettings", &["set", "org.gnome.desktop.notifications", "show-banners", &previous]),
+        Restore::MacShortcut => run("shortcuts", &["run", MACOS_FOCUS_OFF_SHORTCUT]),
+    };
+    if restored.is_some() {
+        info!("Restored notifications after the recording");
+    }
+}
+
+/// Not `run_command`, which panics when the tool isn't installed.
+fn run(program: &str, args: &[&str]) -> Option<String> {
+    match Command::new(program).args(args).output() {
+        Ok(output) if output.status.success() => Some(String::from_utf8_lossy(&output.stdout).into_owned()),
+        Ok(output) => {
+            warn!("{} {} exited with {}", program, args.join(" "), output.status);
+            None
+        },
+        Err(e) => {
+            debug!("Could not run {}: {}", program, e);
+            None
+        },
+    }
+}
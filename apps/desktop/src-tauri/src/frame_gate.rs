@@ -0,0 +1,167 @@
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::process::{ChildStdin, ChildStdout, Command};
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info, warn};
+
+use crate::capture::FFMPEG_EXIT_TIMEOUT;
+use crate::utils::monitor_and_log_recording_start;
+
+/// A screen capture split in two ffmpeg processes: the capture process grabs raw
+/// frames and writes them to a pipe, and the encoder process encodes whatever comes
+/// through. Frames are dropped in between while paused. The encoder numbers frames
+/// by count, so the output stays one continuous stream across a pause instead of
+/// jumping by the paused time.
+pub struct FrameGate {
+    paused: Arc<AtomicBool>,
+    relay: JoinHandle<()>,
+    pub encoder_pid: Option<u32>,
+}
+
+impl FrameGate {
+    pub fn set_paused(&self, paused: bool) {
+        self.paused.store(paused, Ordering::SeqCst);
+    }
+
+    /// Waits for the encoder to write its last segment. The capture process has to be
+    /// stopped first, which ends the stream of frames.
+    pub async fn finish(self) {
+        if let Err(e) = self.relay.await {
+            error!("Failed to join the frame relay: {}", e);
+        }
+    }
+}
+
+/// Splits screen capture arguments from `construct_recording_args` into the capture
+/// stage, which ends in raw frames on stdout, and the encoder stage, which reads them
+/// from stdin. The `-vf` conversion runs in the capture stage, straight from the
+/// captured pixels, at the output frame rate and pixel format.
+pub fn split_capture_args(args: &[OsString]) -> Result<(Vec<OsString>, Vec<OsString>), String> {
+    let value_of = |flags: &[&str]| args.iter()
+        .position(|arg| flags.iter().any(|flag| arg == *flag))
+        .and_then(|at| args.get(at + 1))
+        .and_then(|value| value.to_str());
+    let fps = value_of(&["-r"]).unwrap_or("30");
+    let pix_fmt = value_of(&["-pix_fmt", "-pixel_format"]).unwrap_or("yuv420p");
+
+    let input_at = args.iter().position(|arg| arg == "-i").ok_or("Screen input is missing".to_string())?;
+    let (input, output) = args.split_at((input_at + 2).min(args.len()));
+
+    let mut capture_args = input.to_vec();
+    let mut encoder_args = ["-f", "yuv4mpegpipe", "-i", "-"].map(OsString::from).to_vec();
+    let mut output = output.iter();
+    while let Some(arg) = output.next() {
+        if arg == "-vf" {
+            capture_args.push(arg.clone());
+            capture_args.extend(output.next().cloned());
+        } else {
+            encoder_args.push(arg.clone());
+        }
+    }
+    capture_args.extend(["-r", fps, "-pix_fmt", pix_fmt, "-f", "yuv4mpegpipe", "-"].map(OsString::from));
+
+    Ok((capture_args, encoder_args))
+}
+
+/// Starts the encoder stage and relays frames into it from `frames`, the capture
+/// process's stdout. The encoder's output is logged to `log_path`.
+pub fn spawn_frame_gate(
+    ffmpeg_binary: &Path,
+    encoder_args: &[OsString],
+    frames: ChildStdout,
+    video_id: &str,
+    log_path: PathBuf,
+) -> Result<FrameGate, String> {
+    let mut encoder = Command::new(ffmpeg_binary)
+        .args(encoder_args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Failed to start the screen encoder: {}", e))?;
+    let encoder_pid = encoder.id();
+    let stdin = encoder.stdin.take().ok_or("Screen encoder stdin is not available".to_string())?;
+    let stderr = encoder.stderr.take().ok_or("Screen encoder stderr is not available".to_string())?;
+
+    let video_id_owned = video_id.to_string();
+    tokio::spawn(async move {
+        if let Err(e) = monitor_and_log_recording_start(stderr, &video_id_owned, "video encoder", log_path, None, false).await {
+            error!("Screen encoder failed: {}", e);
+        }
+    });
+
+    let paused = Arc::new(AtomicBool::new(false));
+    let relay_paused = paused.clone();
+    let relay = tokio::spawn(async move {
+        match relay_frames(frames, stdin, &relay_paused).await {
+            Ok(frames) => info!("Relayed {} frames to the screen encoder", frames),
+            // The encoder has exited, or capture ended mid-frame; dropping the pipes
+            // stops the other process too.
+            Err(e) => warn!("Frame relay stopped: {}", e),
+        }
+        match tokio::time::timeout(FFMPEG_EXIT_TIMEOUT, encoder.wait()).await {
+            Ok(Ok(status)) => debug!("Screen encoder exited with {}", status),
+            Ok(Err(e)) => error!("Failed to wait for the screen encoder: {}", e),
+            Err(_) => {
+                warn!("Screen encoder did not exit in time, killing it");
+                let _ = encoder.kill().await;
+            },
+        }
+    });
+
+    Ok(FrameGate { paused, relay, encoder_pid })
+}
+
+/// Copies the yuv4mpeg stream in `frames` to `encoder`, leaving out the frames that
+/// arrive while `paused` is set. Closes the encoder's stdin when capture ends, which
+/// makes it finish the last segment. Returns the number of frames passed on.
+async fn relay_frames(frames: ChildStdout, mut encoder: ChildStdin, paused: &AtomicBool) -> Result<u64, String> {
+    let mut reader = BufReader::new(frames);
+    let mut header = Vec::new();
+    reader.read_until(b'\n', &mut header).await.map_err(|e| e.to_string())?;
+    if header.is_empty() {
+        return Ok(0);
+    }
+    let frame_size = y4m_frame_size(&String::from_utf8_lossy(&header))?;
+    encoder.write_all(&header).await.map_err(|e| e.to_string())?;
+
+    let mut frame_header = Vec::new();
+    let mut frame = vec![0u8; frame_size];
+    let mut relayed = 0;
+    loop {
+        frame_header.clear();
+        if reader.read_until(b'\n', &mut frame_header).await.map_err(|e| e.to_string())? == 0 {
+            break;
+        }
+        reader.read_exact(&mut frame).await.map_err(|e| e.to_string())?;
+        if paused.load(Ordering::SeqCst) {
+            continue;
+        }
+        encoder.write_all(&frame_header).await.map_err(|e| e.to_string())?;
+        encoder.write_all(&frame).await.map_err(|e| e.to_string())?;
+        relayed += 1;
+    }
+    encoder.shutdown().await.map_err(|e| e.to_string())?;
+
+    Ok(relayed)
+}
+
+/// Bytes per frame for a `YUV4MPEG2 W<width> H<height> ... C<colorspace>` header.
+fn y4m_frame_size(header: &str) -> Result<usize, String> {
+    let param = |tag: char| header.split_whitespace().find_map(|field| field.strip_prefix(tag));
+    let width: usize = param('W').and_then(|width| width.parse().ok()).ok_or("Frame header has no width".to_string())?;
+    let height: usize = param('H').and_then(|height| height.parse().ok()).ok_or("Frame header has no height".to_string())?;
+    let (chroma_width, chroma_height) = ((width + 1) / 2, (height + 1) / 2);
+
+    match param('C').unwrap_or("420") {
+        colorspace if colorspace.starts_with("444") => Ok(3 * width * height),
+        colorspace if colorspace.starts_with("422") => Ok(width * height + 2 * chroma_width * height),
+        colorspace if colorspace.starts_with("420") => Ok(width * height + 2 * chroma_width * chroma_height),
+        colorspace if colorspace.starts_with("mono") => Ok(width * height),
+        colorspace => Err(format!("Unsupported frame colorspace: {}", colorspace)),
+    }
+}
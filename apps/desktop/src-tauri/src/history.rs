@@ -0,0 +1,131 @@
+use std::path::Path;
+use std::sync::Arc;
+use serde::Serialize;
+use tauri::State;
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+use crate::manifest::{recording_dir, recordings_root, RecordingManifest, RecordingStatus};
+use crate::session::RecordingState;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RecordingSummary {
+    pub video_id: String,
+    pub created_at: String,
+    pub updated_at: String,
+    pub status: RecordingStatus,
+    pub screen_segments: usize,
+    pub audio_segments: usize,
+    pub size_bytes: u64,
+    pub path: String,
+}
+
+impl RecordingSummary {
+    fn from_dir(dir: &Path) -> Result<Self, String> {
+        let manifest = RecordingManifest::load(dir)?;
+
+        Ok(RecordingSummary {
+            video_id: manifest.video_id,
+            created_at: manifest.created_at,
+            updated_at: manifest.updated_at,
+            status: manifest.status,
+            screen_segments: count_segments(&dir.join("screen")),
+            audio_segments: count_segments(&dir.join("audio")),
+            size_bytes: dir_size(dir),
+            path: dir.to_string_lossy().into_owned(),
+        })
+    }
+}
+
+fn count_segments(chunks_dir: &Path) -> usize {
+    std::fs::read_to_string(chunks_dir.join("segment_list.txt"))
+        .map(|contents| contents.lines().filter(|line| !line.is_empty()).count())
+        .unwrap_or(0)
+}
+
+fn dir_size(dir: &Path) -> u64 {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return 0,
+    };
+
+    entries.flatten()
+        .map(|entry| {
+            let path = entry.path();
+            if path.is_dir() {
+                dir_size(&path)
+            } else {
+                entry.metadata().map(|metadata| metadata.len()).unwrap_or(0)
+            }
+        })
+        .sum()
+}
+
+async fn data_dir(state: &State<'_, Arc<Mutex<RecordingState>>>) -> Result<std::path::PathBuf, String> {
+    state.lock().await.data_dir.clone()
+        .ok_or("Data directory is not set in the recording state".to_string())
+}
+
+/// Lists local recordings, newest first.
+#[tauri::command]
+pub async fn list_recordings(state: State<'_, Arc<Mutex<RecordingState>>>) -> Result<Vec<RecordingSummary>, String> {
+    let root = recordings_root(&data_dir(&state).await?);
+
+    let entries = match std::fs::read_dir(&root) {
+        Ok(entries) => entries,
+        Err(_) => return Ok(vec![]),
+    };
+
+    let mut recordings: Vec<RecordingSummary> = entries.flatten()
+        .filter(|entry| entry.path().is_dir())
+        .filter_map(|entry| match RecordingSummary::from_dir(&entry.path()) {
+            Ok(summary) => Some(summary),
+            Err(e) => {
+                warn!("Skipping recording directory {}: {}", entry.path().display(), e);
+                None
+            },
+        })
+        .collect();
+
+    recordings.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+
+    Ok(recordings)
+}
+
+#[tauri::command]
+pub async fn get_recording(
+    state: State<'_, Arc<Mutex<RecordingState>>>,
+    video_id: String,
+) -> Result<RecordingSummary, String> {
+    let dir = recording_dir(&data_dir(&state).await?, &video_id)?;
+    RecordingSummary::from_dir(&dir)
+}
+
+/// Deletes a recording's local files. The recording in progress can't be deleted.
+#[tauri::command]
+pub async fn delete_recording(
+    state: State<'_, Arc<Mutex<RecordingState>>>,
+    video_id: String,
+) -> Result<(), String> {
+    let guard = state.lock().await;
+    let data_dir = guard.data_dir.clone()
+        .ok_or("Data directory is not set in the recording state".to_string())?;
+    let dir = recording_dir(&data_dir, &video_id)?;
+
+    if guard.recording_dir.as_ref() == Some(&dir) {
+        let status = RecordingManifest::load(&dir).map(|manifest| manifest.status);
+        if matches!(status, Ok(RecordingStatus::Recording) | Ok(RecordingStatus::Uploading)) {
+            return Err("Cannot delete a recording that is still in progress".to_string());
+        }
+    }
+    drop(guard);
+
+    if !dir.exists() {
+        return Err(format!("Recording {} not found", video_id));
+    }
+
+    tokio::fs::remove_dir_all(&dir).await.map_err(|e| format!("Failed to delete recording: {}", e))?;
+    info!("Deleted recording {}", video_id);
+
+    Ok(())
+}
@@ -0,0 +1,133 @@
+use std::collections::HashMap;
+use serde::{Serialize, Deserialize};
+use tauri::{AppHandle, GlobalShortcutManager, Manager};
+use tracing::{error, info};
+
+pub const HOTKEY_EVENT: &str = "hotkey://pressed";
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum HotkeyAction {
+    StartRecording,
+    StopRecording,
+    /// Toggles between pausing and resuming.
+    PauseRecording,
+    CancelRecording,
+    TakeScreenshot,
+}
+
+/// Accelerators in Tauri's format, e.g. "CmdOrControl+Shift+R". An empty string
+/// disables the shortcut.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct HotkeySettings {
+    pub start_recording: String,
+    pub stop_recording: String,
+    pub pause_recording: String,
+    pub cancel_recording: String,
+    pub take_screenshot: String,
+}
+
+impl Default for HotkeySettings {
+    fn default() -> Self {
+        HotkeySettings {
+            start_recording: "CmdOrControl+Alt+Shift+R".to_string(),
+            stop_recording: "CmdOrControl+Alt+Shift+S".to_string(),
+            pause_recording: "CmdOrControl+Alt+Shift+P".to_string(),
+            cancel_recording: "CmdOrControl+Alt+Shift+X".to_string(),
+            take_screenshot: "CmdOrControl+Alt+Shift+I".to_string(),
+        }
+    }
+}
+
+impl HotkeySettings {
+    fn bindings(&self) -> Vec<(HotkeyAction, &str)> {
+        vec![
+            (HotkeyAction::StartRecording, self.start_recording.as_str()),
+            (HotkeyAction::StopRecording, self.stop_recording.as_str()),
+            (HotkeyAction::PauseRecording, self.pause_recording.as_str()),
+            (HotkeyAction::CancelRecording, self.cancel_recording.as_str()),
+            (HotkeyAction::TakeScreenshot, self.take_screenshot.as_str()),
+        ]
+        .into_iter()
+        .filter(|(_, accelerator)| !accelerator.trim().is_empty())
+        .collect()
+    }
+
+    /// Rejects two actions bound to the same key combination.
+    pub fn validate(&self) -> Result<(), String> {
+        let mut seen: HashMap<String, HotkeyAction> = HashMap::new();
+        for (action, accelerator) in self.bindings() {
+            if let Some(other) = seen.insert(normalize_accelerator(accelerator), action) {
+                return Err(format!("Shortcut {} is assigned to both {:?} and {:?}", accelerator, other, action));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Lower-cases an accelerator, folds modifier aliases and sorts the modifiers so
+/// "Shift+Ctrl+R" and "control+shift+r" compare equal. `CmdOrCtrl` becomes the key it
+/// stands for on this platform, so it also conflicts with an explicit Ctrl, or Cmd on
+/// macOS.
+fn normalize_accelerator(accelerator: &str) -> String {
+    let mut parts: Vec<String> = accelerator.split('+')
+        .map(|part| part.trim().to_lowercase())
+        .collect();
+    let key = parts.pop().unwrap_or_default();
+
+    let mut modifiers: Vec<&str> = parts.iter()
+        .map(|modifier| match modifier.as_str() {
+            "cmdorctrl" | "cmdorcontrol" | "commandorcontrol" | "commandorctrl" => {
+                if cfg!(target_os = "macos") { "super" } else { "ctrl" }
+            },
+            "ctrl" | "control" => "ctrl",
+            "cmd" | "command" | "super" | "meta" => "super",
+            "alt" | "option" => "alt",
+            other => other,
+        })
+        .collect();
+    modifiers.sort_unstable();
+    modifiers.dedup();
+    modifiers.push(&key);
+
+    modifiers.join("+")
+}
+
+/// Asks the frontend to perform `action`; it owns the recording options and UI state.
+pub fn emit_action(app: &AppHandle, action: HotkeyAction) {
+    if let Err(e) = app.emit_all(HOTKEY_EVENT, action) {
+        error!("Failed to emit hotkey event: {}", e);
+    }
+}
+
+/// Replaces all registered global shortcuts with `hotkeys`. Pressing one emits
+/// `HOTKEY_EVENT` with the action, which the frontend handles even while its window
+/// is hidden. Shortcuts the OS refuses (usually because another app owns them) are
+/// reported together; the others stay registered.
+pub fn register_hotkeys(app: &AppHandle, hotkeys: &HotkeySettings) -> Result<(), String> {
+    hotkeys.validate()?;
+
+    let mut manager = app.global_shortcut_manager();
+    manager.unregister_all().map_err(|e| e.to_string())?;
+
+    let mut failed = vec![];
+    for (action, accelerator) in hotkeys.bindings() {
+        let handle = app.clone();
+        let result = manager.register(accelerator, move || {
+            info!("Hotkey pressed: {:?}", action);
+            emit_action(&handle, action);
+        });
+
+        if let Err(e) = result {
+            error!("Failed to register shortcut {} for {:?}: {}", accelerator, action, e);
+            failed.push(accelerator.to_string());
+        }
+    }
+
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("These shortcuts are invalid or already in use by another application: {}", failed.join(", ")))
+    }
+}
@@ -0,0 +1,484 @@
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, RwLock};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager};
+use tokio::process::Command;
+use tracing::{error, info, warn};
+
+use crate::chapters::upload_chapters;
+use crate::manifest::{recording_dir, remove_uploaded_chunks, sort_segments, RecordingManifest, MANIFEST_FILE};
+use crate::recording::RecordingOptions;
+use crate::transcription::transcribe_and_upload;
+use crate::upload::{upload_segment, upload_sidecar_file};
+use crate::upload_loop::load_segment_list;
+use crate::utils::ffmpeg_path;
+use crate::waveform::upload_waveform;
+
+/// Emitted with a `Job` whenever one is queued, starts, finishes or fails.
+pub const JOB_EVENT: &str = "jobs://updated";
+const JOBS_FILE: &str = "jobs.json";
+/// Written into the recording directory by `JobKind::RemuxMp4`.
+pub const MP4_EXPORT_FILE: &str = "recording.mp4";
+/// Written into the recording directory by `JobKind::ExportWebm`.
+pub const WEBM_EXPORT_FILE: &str = "recording.webm";
+/// Holds the segments of `JobKind::Renditions`, one directory per height.
+const RENDITIONS_DIR: &str = "renditions";
+
+static QUEUE: Mutex<JobQueue> = Mutex::new(JobQueue { jobs: vec![], next_id: 1, data_dir: None, running: 0 });
+static MAX_CONCURRENT_JOBS: AtomicUsize = AtomicUsize::new(1);
+/// Set once the app is running; jobs queued before that wait for `start`.
+static APP: RwLock<Option<AppHandle>> = RwLock::new(None);
+
+/// Work done on a recording after it stops. Runs in the background, one job per kind
+/// and recording, so it never holds up the next recording.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum JobKind {
+    /// Joins the audio segments' peaks and uploads them.
+    Waveform,
+    Transcribe { model_path: String },
+    /// Joins the local segments into `recording.mp4`; needs the segments kept on disk.
+    RemuxMp4,
+    /// Encodes the local segments into a VP9 and Opus `recording.webm`, for places that
+    /// only take royalty-free formats. Two passes give a steadier quality per byte.
+    ExportWebm { two_pass: bool },
+    /// Transcodes each screen segment to every height in `heights` and uploads the
+    /// copies as `screen_<height>p`, so slow connections can play a smaller stream.
+    /// The segments have to stay on disk until this runs; with `remove_segments` set,
+    /// the uploaded ones are removed afterwards, as `KeepUntilConfirmed` would have.
+    Renditions { heights: Vec<u32>, remove_segments: bool },
+    /// Builds chapters from the markers and the window timeline and uploads them.
+    Chapters,
+    /// Uploads the recording's manifest, for debugging on the server side.
+    UploadManifest,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Done,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Job {
+    pub id: u64,
+    pub video_id: String,
+    #[serde(flatten)]
+    pub kind: JobKind,
+    pub status: JobStatus,
+    pub attempts: u32,
+    pub error: Option<String>,
+    pub created_at: String,
+}
+
+struct JobQueue {
+    jobs: Vec<Job>,
+    next_id: u64,
+    data_dir: Option<PathBuf>,
+    running: usize,
+}
+
+impl JobQueue {
+    /// Writes the unfinished jobs to `jobs.json`, so they run again after a restart.
+    fn save(&self) {
+        let data_dir = match self.data_dir {
+            Some(ref data_dir) => data_dir,
+            None => return,
+        };
+        let pending: Vec<&Job> = self.jobs.iter().filter(|job| job.status != JobStatus::Done).collect();
+        let result = serde_json::to_string_pretty(&pending)
+            .map_err(|e| e.to_string())
+            .and_then(|contents| {
+                let temp_path = data_dir.join(format!("{}.tmp", JOBS_FILE));
+                std::fs::write(&temp_path, contents).map_err(|e| e.to_string())?;
+                std::fs::rename(&temp_path, data_dir.join(JOBS_FILE)).map_err(|e| e.to_string())
+            });
+        if let Err(e) = result {
+            warn!("Failed to save the job queue: {}", e);
+        }
+    }
+}
+
+/// Loads the jobs left from the last run. Jobs that were running when the app quit
+/// are queued again.
+pub fn load(data_dir: &Path) {
+    let mut jobs: Vec<Job> = std::fs::read_to_string(data_dir.join(JOBS_FILE))
+        .ok()
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default();
+    for job in jobs.iter_mut().filter(|job| job.status == JobStatus::Running) {
+        job.status = JobStatus::Queued;
+    }
+    if !jobs.is_empty() {
+        info!("Loaded {} unfinished post-processing jobs", jobs.len());
+    }
+
+    if let Ok(mut queue) = QUEUE.lock() {
+        queue.next_id = jobs.iter().map(|job| job.id + 1).max().unwrap_or(1);
+        queue.jobs = jobs;
+        queue.data_dir = Some(data_dir.to_path_buf());
+    }
+}
+
+/// Starts running queued jobs, emitting their updates to `app`.
+pub fn start(app: AppHandle) {
+    if let Ok(mut current) = APP.write() {
+        *current = Some(app);
+    }
+    schedule();
+}
+
+pub fn set_max_concurrent_jobs(max_concurrent_jobs: usize) {
+    MAX_CONCURRENT_JOBS.store(max_concurrent_jobs.max(1), Ordering::SeqCst);
+    schedule();
+}
+
+/// Queues `kind` for the recording `video_id`, unless the same job is already waiting.
+pub fn enqueue(video_id: &str, kind: JobKind) {
+    let job = {
+        let mut queue = match QUEUE.lock() {
+            Ok(queue) => queue,
+            Err(e) => {
+                error!("Failed to queue {:?} for {}: {}", kind, video_id, e);
+                return;
+            },
+        };
+        let duplicate = queue.jobs.iter().any(|job| {
+            job.video_id == video_id && job.kind == kind && job.status == JobStatus::Queued
+        });
+        if duplicate {
+            return;
+        }
+        let job = Job {
+            id: queue.next_id,
+            video_id: video_id.to_string(),
+            kind,
+            status: JobStatus::Queued,
+            attempts: 0,
+            error: None,
+            created_at: Utc::now().to_rfc3339(),
+        };
+        queue.next_id += 1;
+        queue.jobs.push(job.clone());
+        queue.save();
+        job
+    };
+    info!("Queued job {} ({:?}) for {}", job.id, job.kind, job.video_id);
+    emit(&job);
+    schedule();
+}
+
+fn emit(job: &Job) {
+    if let Some(app) = APP.read().ok().and_then(|app| app.clone()) {
+        let _ = app.emit_all(JOB_EVENT, job);
+    }
+}
+
+/// Starts queued jobs, oldest first, until `MAX_CONCURRENT_JOBS` are running.
+fn schedule() {
+    if APP.read().map_or(true, |app| app.is_none()) {
+        return;
+    }
+    let mut started = vec![];
+    if let Ok(mut queue) = QUEUE.lock() {
+        while queue.running < MAX_CONCURRENT_JOBS.load(Ordering::SeqCst) {
+            let job = match queue.jobs.iter_mut().find(|job| job.status == JobStatus::Queued) {
+                Some(job) => job,
+                None => break,
+            };
+            job.status = JobStatus::Running;
+            job.attempts += 1;
+            job.error = None;
+            started.push(job.clone());
+            queue.running += 1;
+        }
+        if !started.is_empty() {
+            queue.save();
+        }
+    }
+
+    for job in started {
+        emit(&job);
+        tauri::async_runtime::spawn(run(job));
+    }
+}
+
+async fn run(job: Job) {
+    let data_dir = QUEUE.lock().ok().and_then(|queue| queue.data_dir.clone());
+    info!("Running job {} ({:?}) for {}", job.id, job.kind, job.video_id);
+    let result = match data_dir {
+        Some(data_dir) => execute(&data_dir, &job.video_id, &job.kind).await,
+        None => Err("Data directory is not set".to_string()),
+    };
+    if let Err(ref e) = result {
+        warn!("Job {} ({:?}) for {} failed: {}", job.id, job.kind, job.video_id, e);
+    }
+
+    let finished = QUEUE.lock().ok().and_then(|mut queue| {
+        queue.running = queue.running.saturating_sub(1);
+        let finished = queue.jobs.iter_mut().find(|queued| queued.id == job.id).map(|finished| {
+            match result {
+                Ok(()) => finished.status = JobStatus::Done,
+                Err(e) => {
+                    finished.status = JobStatus::Failed;
+                    finished.error = Some(e);
+                },
+            }
+            finished.clone()
+        });
+        queue.save();
+        finished
+    });
+    if let Some(ref finished) = finished {
+        emit(finished);
+    }
+    schedule();
+}
+
+async fn execute(data_dir: &Path, video_id: &str, kind: &JobKind) -> Result<(), String> {
+    let recording_dir = recording_dir(data_dir, video_id)?;
+    let manifest = RecordingManifest::load(&recording_dir)?;
+    match kind {
+        JobKind::Waveform => upload_waveform(&recording_dir, &manifest.options).await,
+        JobKind::Transcribe { model_path } => {
+            transcribe_and_upload(&recording_dir, &manifest.options, model_path).await.map(|_| ())
+        },
+        JobKind::RemuxMp4 => remux_to_mp4(&recording_dir).await,
+        JobKind::ExportWebm { two_pass } => export_webm(&recording_dir, *two_pass).await,
+        JobKind::Renditions { heights, remove_segments } => {
+            for height in heights {
+                upload_rendition(&recording_dir, &manifest.options, *height).await?;
+            }
+            if *remove_segments {
+                remove_uploaded_chunks(&recording_dir, "screen");
+            }
+            Ok(())
+        },
+        JobKind::Chapters => upload_chapters(&recording_dir, &manifest.options, manifest.duration_secs).await,
+        JobKind::UploadManifest => {
+            upload_sidecar_file(&manifest.options, &recording_dir.join(MANIFEST_FILE), "manifest").await.map(|_| ())
+        },
+    }
+}
+
+/// Joins the screen segments and, when there is one, the separate audio track into
+/// one MP4 without re-encoding the video.
+async fn remux_to_mp4(recording_dir: &Path) -> Result<(), String> {
+    let mut command = Command::new(ffmpeg_path()?);
+    command.arg("-y");
+    add_concat_inputs(&mut command, recording_dir)?;
+    let output = command
+        .args(["-c:v", "copy", "-c:a", "aac", "-movflags", "+faststart"])
+        .arg(recording_dir.join(MP4_EXPORT_FILE))
+        .output()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("Failed to remux to MP4: {}", stderr.lines().last().unwrap_or_default()));
+    }
+    info!("Wrote {}", recording_dir.join(MP4_EXPORT_FILE).display());
+    Ok(())
+}
+
+/// Re-encodes the recording to VP9 and Opus in a WebM. With `two_pass`, a first pass
+/// only analyses the video, and the second spends bits where that found them needed.
+async fn export_webm(recording_dir: &Path, two_pass: bool) -> Result<(), String> {
+    let ffmpeg_binary = ffmpeg_path()?;
+    let passlog = recording_dir.join("webm_pass");
+    let vp9_args = ["-c:v", "libvpx-vp9", "-b:v", "0", "-crf", "33", "-row-mt", "1", "-deadline", "good", "-cpu-used", "4"];
+
+    let passes: &[Option<&str>] = if two_pass { &[Some("1"), Some("2")] } else { &[None] };
+    for pass in passes {
+        let mut command = Command::new(&ffmpeg_binary);
+        command.arg("-y");
+        add_concat_inputs(&mut command, recording_dir)?;
+        command.args(vp9_args);
+        if let Some(pass) = pass {
+            command.args(["-pass", *pass, "-passlogfile"]).arg(&passlog);
+        }
+        if *pass == Some("1") {
+            command.args(["-an", "-f", "null", "-"]);
+        } else {
+            command.args(["-c:a", "libopus", "-b:a", "128k"]).arg(recording_dir.join(WEBM_EXPORT_FILE));
+        }
+
+        let output = command.output().await.map_err(|e| e.to_string())?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(format!("Failed to export WebM: {}", stderr.lines().last().unwrap_or_default()));
+        }
+    }
+
+    if two_pass {
+        // libvpx names the log after the prefix and the stream index.
+        let _ = std::fs::remove_file(recording_dir.join("webm_pass-0.log"));
+    }
+    info!("Wrote {}", recording_dir.join(WEBM_EXPORT_FILE).display());
+    Ok(())
+}
+
+/// Adds the screen segments and, when there is one, the separate audio track as
+/// concat inputs, mapping the video and whichever audio the recording has.
+fn add_concat_inputs(command: &mut Command, recording_dir: &Path) -> Result<(), String> {
+    let screen_list = concat_list(&recording_dir.join("screen"))?
+        .ok_or("The recording has no screen segments".to_string())?;
+    let audio_list = concat_list(&recording_dir.join("audio"))?;
+
+    command.args(["-f", "concat", "-safe", "0", "-i"]).arg(&screen_list);
+    match audio_list {
+        Some(ref audio_list) => {
+            command.args(["-f", "concat", "-safe", "0", "-i"]).arg(audio_list);
+            command.args(["-map", "0:v", "-map", "1:a"]);
+        },
+        None => {
+            command.args(["-map", "0:v", "-map", "0:a?"]);
+        },
+    }
+    Ok(())
+}
+
+/// Transcodes the screen segments to `height` one by one, keeping their names and
+/// timestamps so the rendition lines up with the original, and uploads each copy.
+/// Segments that are already that small are scaled to their own height.
+async fn upload_rendition(recording_dir: &Path, options: &RecordingOptions, height: u32) -> Result<(), String> {
+    let chunks_dir = recording_dir.join("screen");
+    let segments = load_segment_list(&chunks_dir.join("segment_list.txt")).unwrap_or_default();
+    let rendition_dir = recording_dir.join(RENDITIONS_DIR).join(format!("{}p", height));
+    std::fs::create_dir_all(&rendition_dir).map_err(|e| e.to_string())?;
+    let file_type = format!("screen_{}p", height);
+    let ffmpeg_binary = ffmpeg_path()?;
+
+    for segment in &segments {
+        let segment_path = chunks_dir.join(segment);
+        if !segment_path.is_file() {
+            return Err(format!("Segment {} is no longer on disk; keep segments with chunk_retention to make renditions", segment));
+        }
+        let rendition_path = rendition_dir.join(segment);
+        let output = Command::new(&ffmpeg_binary)
+            .args(["-y", "-copyts", "-i"])
+            .arg(&segment_path)
+            .args(["-map", "0:v", "-map", "0:a?"])
+            .args(["-vf", &format!("scale=-2:'min({},ih)'", height)])
+            .args(["-c:v", "libx264", "-preset", "veryfast", "-crf", "26", "-pix_fmt", "yuv420p"])
+            .args(["-c:a", "copy", "-muxdelay", "0", "-f", "mpegts"])
+            .arg(&rendition_path)
+            .output()
+            .await
+            .map_err(|e| e.to_string())?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(format!("Failed to transcode {} to {}p: {}", segment, height, stderr.lines().last().unwrap_or_default()));
+        }
+
+        upload_segment(options, &rendition_path, &file_type).await?;
+        if let Err(e) = std::fs::remove_file(&rendition_path) {
+            warn!("Failed to remove {} after upload: {}", rendition_path.display(), e);
+        }
+    }
+    info!("Uploaded the {}p rendition of {}", height, options.video_id);
+    Ok(())
+}
+
+/// Writes an ffmpeg concat list of the segments in `chunks_dir`. Returns `None` when
+/// the track has no segments, and fails when some were already removed after upload.
+/// Segments are listed by file name, which ffmpeg resolves next to the list, so the
+/// recording directory's path never has to be written out as text.
+fn concat_list(chunks_dir: &Path) -> Result<Option<PathBuf>, String> {
+    let mut segments: Vec<String> = load_segment_list(&chunks_dir.join("segment_list.txt")).unwrap_or_default().into_iter().collect();
+    sort_segments(&mut segments);
+    if segments.is_empty() {
+        return Ok(None);
+    }
+
+    let mut list = String::new();
+    for segment in &segments {
+        let path = chunks_dir.join(segment);
+        if !path.is_file() {
+            return Err(format!("Segment {} is no longer on disk; keep segments with chunk_retention to export the recording", segment));
+        }
+        list.push_str(&format!("file '{}'\n", segment.replace('\'', "'\\''")));
+    }
+    let list_path = chunks_dir.join("concat_list.txt");
+    std::fs::write(&list_path, list).map_err(|e| e.to_string())?;
+    Ok(Some(list_path))
+}
+
+#[tauri::command]
+pub fn list_jobs() -> Vec<Job> {
+    QUEUE.lock().map(|queue| queue.jobs.clone()).unwrap_or_default()
+}
+
+/// Queues a failed job to run again.
+#[tauri::command]
+pub fn retry_job(id: u64) -> Result<(), String> {
+    let job = {
+        let mut queue = QUEUE.lock().map_err(|e| e.to_string())?;
+        let job = queue.jobs.iter_mut()
+            .find(|job| job.id == id)
+            .ok_or(format!("Job {} not found", id))?;
+        if job.status != JobStatus::Failed {
+            return Err(format!("Job {} has not failed", id));
+        }
+        job.status = JobStatus::Queued;
+        let job = job.clone();
+        queue.save();
+        job
+    };
+    emit(&job);
+    schedule();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("cap-jobs-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn concat_list_names_segments_next_to_the_list() {
+        let chunks_dir = test_dir("concat").join("Ana's Rëcordings 录屏").join("screen");
+        std::fs::create_dir_all(&chunks_dir).unwrap();
+        std::fs::write(chunks_dir.join("Ana's_chunk_00000.ts"), b"ts").unwrap();
+        std::fs::write(chunks_dir.join("segment_list.txt"), "Ana's_chunk_00000.ts\n").unwrap();
+
+        let list_path = concat_list(&chunks_dir).unwrap().unwrap();
+        assert_eq!(list_path.parent(), Some(chunks_dir.as_path()));
+        assert_eq!(std::fs::read_to_string(list_path).unwrap(), "file 'Ana'\\''s_chunk_00000.ts'\n");
+    }
+
+    #[test]
+    fn concat_list_keeps_the_recording_order() {
+        let chunks_dir = test_dir("concat-order");
+        let segments = ["recording_chunk_00010.ts", "recording_chunk_00002.ts", "recording_chunk_00001.ts"];
+        for segment in segments {
+            std::fs::write(chunks_dir.join(segment), b"ts").unwrap();
+        }
+        std::fs::write(chunks_dir.join("segment_list.txt"), segments.join("\n")).unwrap();
+
+        let list_path = concat_list(&chunks_dir).unwrap().unwrap();
+        assert_eq!(
+            std::fs::read_to_string(list_path).unwrap(),
+            "file 'recording_chunk_00001.ts'\nfile 'recording_chunk_00002.ts'\nfile 'recording_chunk_00010.ts'\n",
+        );
+    }
+
+    #[test]
+    fn concat_list_is_empty_without_segments() {
+        let chunks_dir = test_dir("concat-empty");
+        std::fs::write(chunks_dir.join("segment_list.txt"), "").unwrap();
+        assert_eq!(concat_list(&chunks_dir).unwrap(), None);
+    }
+}
@@ -0,0 +1,176 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use tauri::AppHandle;
+use tracing::{info, warn};
+use zip::write::{FileOptions, ZipWriter};
+use zip::CompressionMethod;
+
+use crate::logging::{ffmpeg_log_path, LOGS_DIR};
+use crate::manifest::{recording_dir, recordings_root, MANIFEST_FILE};
+use crate::recording::RecordingOptions;
+
+pub const JOURNAL_FILE: &str = "journal.jsonl";
+
+/// Sessions found without a `Stopped` entry at launch, kept for the frontend to offer
+/// recovery.
+static UNCLEAN_SESSIONS: RwLock<Vec<UncleanSession>> = RwLock::new(vec![]);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum JournalEvent {
+    Started { options: RecordingOptions, app_pid: u32 },
+    /// An ffmpeg child of the session; `track` is "screen" or "audio".
+    ProcessStarted { track: String, pid: u32 },
+    SegmentUploaded { track: String, segment: String },
+    SegmentFailed { track: String, segment: String, error: String },
+    SegmentSkipped { track: String, segment: String },
+    CaptureFailed { message: String },
+    Stopped { reason: String },
+}
+
+/// One line of a session's journal.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub at: String,
+    #[serde(flatten)]
+    pub event: JournalEvent,
+}
+
+/// A session the app didn't get to stop, found at launch.
+#[derive(Debug, Clone, Serialize)]
+pub struct UncleanSession {
+    pub video_id: String,
+    pub started_at: Option<String>,
+    /// Time of the last journal entry, roughly when the app went down.
+    pub last_entry_at: String,
+    pub segments_uploaded: usize,
+    /// ffmpeg processes the session started, which may have outlived the app.
+    #[serde(skip)]
+    pub ffmpeg_pids: Vec<u32>,
+}
+
+/// Appends `event` to the journal in `recording_dir`. The journal is append-only and
+/// written line by line, so whatever made it to disk before a crash can be read back.
+pub fn append(recording_dir: &Path, event: JournalEvent) {
+    let entry = JournalEntry { at: Utc::now().to_rfc3339(), event };
+    let result = serde_json::to_string(&entry)
+        .map_err(io::Error::from)
+        .and_then(|line| {
+            let mut journal = OpenOptions::new().create(true).append(true).open(recording_dir.join(JOURNAL_FILE))?;
+            writeln!(journal, "{}", line)?;
+            journal.sync_data()
+        });
+    if let Err(e) = result {
+        warn!("Failed to write to the journal of {}: {}", recording_dir.display(), e);
+    }
+}
+
+/// Reads a session's journal, ignoring a last line cut short by a crash.
+pub fn read(recording_dir: &Path) -> Vec<JournalEntry> {
+    fs::read_to_string(recording_dir.join(JOURNAL_FILE))
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect()
+}
+
+/// Finds the sessions whose journal never got a `Stopped` entry, i.e. the app or the
+/// machine went down mid-recording. Each gets a `Stopped` entry so it is only reported
+/// once, and the list is kept for `get_unclean_sessions`. Call before anything else
+/// touches the recordings at launch.
+pub fn detect_unclean_sessions(data_dir: &Path) -> Vec<UncleanSession> {
+    let entries = match fs::read_dir(recordings_root(data_dir)) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+
+    let mut sessions = vec![];
+    for entry in entries.flatten() {
+        let dir = entry.path();
+        let journal = read(&dir);
+        // Retried uploads add entries after `Stopped`, so only look at the last session.
+        let started = match journal.iter().rposition(|entry| matches!(entry.event, JournalEvent::Started { .. })) {
+            Some(started) => started,
+            None => continue,
+        };
+        if journal[started..].iter().any(|entry| matches!(entry.event, JournalEvent::Stopped { .. })) {
+            continue;
+        }
+        let last = &journal[journal.len() - 1];
+
+        let session = UncleanSession {
+            video_id: dir.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default(),
+            started_at: Some(journal[started].at.clone()),
+            last_entry_at: last.at.clone(),
+            segments_uploaded: journal.iter()
+                .filter(|entry| matches!(entry.event, JournalEvent::SegmentUploaded { .. }))
+                .count(),
+            ffmpeg_pids: journal[started..].iter()
+                .filter_map(|entry| match entry.event {
+                    JournalEvent::ProcessStarted { pid, .. } => Some(pid),
+                    _ => None,
+                })
+                .collect(),
+        };
+        info!("Recording {} was not stopped cleanly, last journal entry at {}", session.video_id, session.last_entry_at);
+        append(&dir, JournalEvent::Stopped { reason: "unclean_shutdown".to_string() });
+        sessions.push(session);
+    }
+
+    if let Ok(mut unclean) = UNCLEAN_SESSIONS.write() {
+        *unclean = sessions.clone();
+    }
+    sessions
+}
+
+/// Recordings interrupted by a crash in the previous run. `retry_upload` recovers
+/// what was recorded; `export_diagnostics` bundles what is needed to investigate.
+#[tauri::command]
+pub fn get_unclean_sessions() -> Vec<UncleanSession> {
+    UNCLEAN_SESSIONS.read().map(|sessions| sessions.clone()).unwrap_or_default()
+}
+
+/// Zips the journal, manifest and ffmpeg logs of a recording for a bug report,
+/// returning the archive path.
+#[tauri::command]
+pub async fn export_diagnostics(app: AppHandle, video_id: String) -> Result<String, String> {
+    let data_dir = app.path_resolver().app_data_dir()
+        .ok_or("Data directory is not available".to_string())?;
+    let recording_dir = recording_dir(&data_dir, &video_id)?;
+    let export_path = data_dir.join(format!("cap-diagnostics-{}-{}.zip", video_id, Utc::now().format("%Y%m%d-%H%M%S")));
+
+    let files: Vec<(String, PathBuf)> = vec![
+        (JOURNAL_FILE.to_string(), recording_dir.join(JOURNAL_FILE)),
+        (MANIFEST_FILE.to_string(), recording_dir.join(MANIFEST_FILE)),
+        (format!("{}/ffmpeg/screen.log", LOGS_DIR), ffmpeg_log_path(&data_dir, &video_id, "screen")),
+        (format!("{}/ffmpeg/audio.log", LOGS_DIR), ffmpeg_log_path(&data_dir, &video_id, "audio")),
+    ];
+    let archive_path = export_path.clone();
+    tokio::task::spawn_blocking(move || write_diagnostics_archive(&files, &archive_path))
+        .await
+        .map_err(|e| e.to_string())??;
+
+    info!("Exported diagnostics for {} to {}", video_id, export_path.display());
+
+    Ok(export_path.to_string_lossy().into_owned())
+}
+
+fn write_diagnostics_archive(files: &[(String, PathBuf)], export_path: &Path) -> Result<(), String> {
+    let file = File::create(export_path).map_err(|e| format!("Failed to create diagnostics archive: {}", e))?;
+    let mut zip = ZipWriter::new(file);
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    for (entry_name, path) in files.iter().filter(|(_, path)| path.is_file()) {
+        zip.start_file(entry_name.as_str(), options).map_err(|e| e.to_string())?;
+        let mut source = File::open(path).map_err(|e| e.to_string())?;
+        io::copy(&mut source, &mut zip).map_err(|e| e.to_string())?;
+    }
+
+    zip.finish().map_err(|e| e.to_string())?;
+
+    Ok(())
+}
@@ -0,0 +1,134 @@
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use chrono::Utc;
+use std::sync::Arc;
+use tauri::{AppHandle, State};
+use tokio::sync::Mutex;
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::{fmt, prelude::*, EnvFilter};
+use zip::write::{FileOptions, ZipWriter};
+use zip::CompressionMethod;
+
+use crate::session::RecordingState;
+
+pub const LOGS_DIR: &str = "logs";
+
+const MAX_LOG_FILES: usize = 7;
+const RECENT_LOG_DAYS: u64 = 3;
+
+/// Installs the global tracing subscriber, writing to stdout and to a daily rotating
+/// file under `logs_dir`. The returned guard must be kept alive to flush the file writer.
+pub fn init(logs_dir: &Path) -> Option<WorkerGuard> {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+
+    let file_appender = RollingFileAppender::builder()
+        .rotation(Rotation::DAILY)
+        .filename_prefix("cap")
+        .filename_suffix("log")
+        .max_log_files(MAX_LOG_FILES)
+        .build(logs_dir);
+
+    let (file_layer, guard) = match file_appender {
+        Ok(appender) => {
+            let (writer, guard) = tracing_appender::non_blocking(appender);
+            (Some(fmt::layer().with_ansi(false).with_writer(writer)), Some(guard))
+        },
+        Err(e) => {
+            eprintln!("Failed to create log file appender, logging to stdout only: {}", e);
+            (None, None)
+        },
+    };
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt::layer().with_writer(io::stdout))
+        .with(file_layer)
+        .init();
+
+    guard
+}
+
+/// Location of the captured ffmpeg stderr for one process of a recording session.
+/// `data_dir` is the recordings directory, which the `data_dir` setting can move
+/// away from the app's own logs; `export_logs` collects both.
+pub fn ffmpeg_log_path(data_dir: &Path, video_id: &str, log_type: &str) -> PathBuf {
+    data_dir.join(LOGS_DIR).join("ffmpeg").join(format!("{}-{}.log", video_id, log_type))
+}
+
+/// Zips the recent app logs and ffmpeg logs. The ffmpeg logs of a moved recordings
+/// directory go under `recordings/` in the archive.
+#[tauri::command]
+pub async fn export_logs(app: AppHandle, state: State<'_, Arc<Mutex<RecordingState>>>) -> Result<String, String> {
+    let data_dir = app.path_resolver().app_data_dir()
+        .ok_or("Data directory is not available".to_string())?;
+
+    let mut log_dirs = vec![(data_dir.join(LOGS_DIR), String::new())];
+    if let Some(recordings_dir) = state.lock().await.data_dir.clone().filter(|dir| *dir != data_dir) {
+        log_dirs.push((recordings_dir.join(LOGS_DIR), "recordings/".to_string()));
+    }
+    let export_path = data_dir.join(format!("cap-logs-{}.zip", Utc::now().format("%Y%m%d-%H%M%S")));
+
+    let archive_path = export_path.clone();
+    tokio::task::spawn_blocking(move || write_logs_archive(&log_dirs, &archive_path))
+        .await
+        .map_err(|e| e.to_string())??;
+
+    tracing::info!("Exported logs to {}", export_path.display());
+
+    Ok(export_path.to_string_lossy().into_owned())
+}
+
+/// Adds the files under each directory, their names relative to it behind its prefix.
+fn write_logs_archive(log_dirs: &[(PathBuf, String)], export_path: &Path) -> Result<(), String> {
+    let cutoff = SystemTime::now() - Duration::from_secs(RECENT_LOG_DAYS * 24 * 60 * 60);
+
+    let file = File::create(export_path).map_err(|e| format!("Failed to create log archive: {}", e))?;
+    let mut zip = ZipWriter::new(file);
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    for (logs_dir, prefix) in log_dirs {
+        for path in collect_log_files(logs_dir).map_err(|e| format!("Failed to read logs directory: {}", e))? {
+            let is_recent = fs::metadata(&path)
+                .and_then(|metadata| metadata.modified())
+                .map(|modified| modified >= cutoff)
+                .unwrap_or(false);
+            if !is_recent {
+                continue;
+            }
+
+            let entry_name = path.strip_prefix(logs_dir)
+                .map_err(|e| e.to_string())?
+                .to_string_lossy()
+                .replace('\\', "/");
+
+            zip.start_file(format!("{}{}", prefix, entry_name), options).map_err(|e| e.to_string())?;
+            let mut log_file = File::open(&path).map_err(|e| e.to_string())?;
+            io::copy(&mut log_file, &mut zip).map_err(|e| e.to_string())?;
+        }
+    }
+
+    zip.finish().map_err(|e| e.to_string())?;
+
+    Ok(())
+}
+
+fn collect_log_files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    if !dir.exists() {
+        return Ok(files);
+    }
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            files.extend(collect_log_files(&path)?);
+        } else if path.is_file() {
+            files.push(path);
+        }
+    }
+
+    Ok(files)
+}
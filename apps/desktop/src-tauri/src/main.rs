@@ -1,78 +1,169 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
 use std::sync::{Arc};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use tokio::sync::Mutex;
-use std::sync::atomic::{AtomicBool};
 use std::env;
-use tauri::{Manager};
+use tauri::{Manager, RunEvent, WindowEvent};
 use window_vibrancy::{apply_blur, apply_vibrancy, NSVisualEffectMaterial};
 use window_shadows::set_shadow;
 use tauri_plugin_positioner::{WindowExt, Position};
+use tracing::{error, warn};
 
 mod recording;
+mod capture;
+mod capture_watch;
+mod session;
+mod upload_loop;
+mod options;
 mod upload;
+mod upload_stats;
 mod devices;
 mod utils;
 mod audio;
+mod audio_levels;
+mod backup;
+mod logging;
+mod ffmpeg_error;
+mod progress;
+mod settings;
+mod manifest;
+mod history;
+mod hotkeys;
+mod tray;
+mod deep_link;
+mod cli;
+mod control_api;
+mod webhooks;
+mod notifications;
+mod trim;
+mod transcription;
+mod waveform;
+mod timeline;
+mod mouse_events;
+mod markers;
+mod network;
+mod power;
+mod focus;
+mod verify;
+mod sts;
+mod test_recording;
+mod faults;
+mod metrics;
+mod journal;
+mod orphans;
+mod adaptive;
+mod encoders;
+mod frame_gate;
+mod screenshot;
+mod annotate;
+mod clipboard;
+mod jobs;
+mod poster;
+mod chapters;
+mod zoom;
+mod drawing;
+mod capture_exclusion;
+mod benchmark;
+mod telemetry;
+mod device_arbitration;
+mod multipart;
+mod backpressure;
+mod preview_server;
+#[cfg(feature = "native-encoder")]
+mod native_encoder;
 
-use recording::{RecordingState, start_dual_recording, stop_all_recordings};
-use upload::upload_file;
-use audio::{enumerate_audio_devices};
-
-use ffmpeg_sidecar::{
-    command::ffmpeg_is_installed,
-    download::{check_latest_version, download_ffmpeg_package, ffmpeg_download_url, unpack_ffmpeg},
-    error::Result as FfmpegResult,
-    paths::sidecar_dir,
-    version::ffmpeg_version,
+use session::{
+    RecordingState, start_dual_recording, stop_all_recordings, pause_recording, resume_recording, cancel_recording,
+    retry_upload, shutdown_recording, split_recording,
 };
+use upload::upload_file;
+use audio::{enumerate_audio_devices, list_pulse_sources};
+use audio_levels::{start_mic_preview, stop_mic_preview};
+use logging::export_logs;
+use utils::{get_ffmpeg_version, detect_system_ffmpeg, set_ffmpeg_path};
+use settings::{Settings, get_settings, set_settings};
+use history::{list_recordings, get_recording, delete_recording};
+use trim::trim_recording;
+use transcription::transcribe_recording;
+use markers::add_marker;
+use upload_stats::get_upload_stats;
+use options::validate_recording_options;
+use test_recording::test_recording;
+use metrics::get_runtime_metrics;
+use encoders::get_encoder_capabilities;
+use journal::{get_unclean_sessions, export_diagnostics};
+use screenshot::{take_screenshot, annotate_screenshot, capture_frame_now};
+use jobs::{list_jobs, retry_job};
+use poster::set_poster_frame;
+use zoom::set_zoom;
+use drawing::set_drawing;
+use capture_exclusion::{list_capture_excluded_windows, set_window_capture_excluded};
+use benchmark::benchmark_capture;
+use device_arbitration::{acquire_camera_preview, release_camera_preview};
+use preview_server::get_preview_stream;
+use network::{pause_uploads, resume_uploads, get_upload_pause_state, get_upload_backlog};
 
 fn main() {    
+    let context = tauri::generate_context!();
+    let data_directory = tauri::api::path::app_data_dir(context.config()).unwrap_or_else(|| PathBuf::new());
+    let _log_guard = logging::init(&data_directory.join(logging::LOGS_DIR));
+    faults::init_from_env();
+
     std::panic::set_hook(Box::new(|info| {
-        eprintln!("Thread panicked: {:?}", info);
+        error!("Thread panicked: {:?}", info);
     }));
 
-    if which::which("ffmpeg").is_err() {
-        if let Err(e) = handle_ffmpeg_installation() {
-            eprintln!("Failed to handle FFmpeg installation: {}", e);
-        }
-    }
-
-    fn handle_ffmpeg_installation() -> FfmpegResult<()> {
-        if ffmpeg_is_installed() {
-            println!("FFmpeg is already installed! 🎉");
-            return Ok(());
-        }
+    let config_directory = tauri::api::path::app_config_dir(context.config()).unwrap_or_else(|| PathBuf::new());
+    let settings = Settings::load(&config_directory);
 
-        match check_latest_version() {
-            Ok(version) => println!("Latest available version: {}", version),
-            Err(_) => println!("Skipping version check on this platform."),
-        }
+    let recordings_directory = settings.resolve_data_dir(data_directory.clone());
+    webhooks::set_webhooks(settings.webhooks.clone());
+    network::set_pause_on_metered(settings.pause_uploads_on_metered);
+    sts::set_use_sts(settings.use_sts_credentials);
+    multipart::set_multipart_settings(settings.multipart_upload);
+    backup::set_backup_destination(settings.backup_destination.clone());
+    jobs::set_max_concurrent_jobs(settings.max_concurrent_jobs);
+    jobs::load(&recordings_directory);
 
-        let download_url = ffmpeg_download_url()?;
-        let destination = sidecar_dir()?;
-
-        println!("Downloading from: {:?}", download_url);
-        let archive_path = download_ffmpeg_package(download_url, &destination)?;
-        println!("Downloaded package: {:?}", archive_path);
+    if let Err(e) = utils::ensure_ffmpeg(&data_directory, settings.ffmpeg_path.as_deref().map(Path::new)) {
+        error!("Failed to handle FFmpeg installation: {}", e);
+    }
 
-        println!("Extracting...");
-        unpack_ffmpeg(&archive_path, &destination)?;
+    let args: Vec<String> = env::args().collect();
+    if cli::is_cli_invocation(&args) {
+        let exit_code = cli::run(&args, recordings_directory, settings);
+        // Flush buffered log lines; `exit` skips destructors.
+        drop(_log_guard);
+        std::process::exit(exit_code);
+    }
 
-        let version = ffmpeg_version()?;
-        println!("FFmpeg version: {}", version);
+    tauri_plugin_deep_link::prepare("so.cap.desktop");
 
-        println!("Done! 🏁");
-        Ok(())
-    }
+    let unclean_sessions = journal::detect_unclean_sessions(&recordings_directory);
+    orphans::kill_orphaned_ffmpeg(&unclean_sessions);
+    manifest::mark_interrupted_recordings(&recordings_directory);
+    manifest::cleanup_recordings(&recordings_directory, settings.retention_days);
+    manifest::cleanup_chunks(&recordings_directory, settings.chunk_retention);
 
     tauri::Builder::default()
         .plugin(tauri_plugin_oauth::init())
         .plugin(tauri_plugin_positioner::init())
+        .system_tray(tray::build_tray())
+        .on_system_tray_event(tray::handle_tray_event)
+        .on_window_event(|event| {
+            // Keep capturing from the tray when the window is closed mid-recording.
+            if let WindowEvent::CloseRequested { api, .. } = event.event() {
+                let state = event.window().state::<Arc<Mutex<RecordingState>>>();
+                let is_recording = state.try_lock().map_or(true, |guard| guard.is_recording());
+                if is_recording {
+                    api.prevent_close();
+                    let _ = event.window().hide();
+                }
+            }
+        })
+        .on_page_load(|window, _| capture_exclusion::apply_to_window(&window))
         .setup(move |app| {
-            let handle = app.handle();
-
             if let Some(options_window) = app.get_window("main") { 
               let _ = options_window.move_window(Position::Center);
               #[cfg(target_os = "macos")]
@@ -84,31 +175,94 @@ fn main() {
               set_shadow(&options_window, true).expect("Unsupported platform!");
             }
 
-            let data_directory = handle.path_resolver().app_data_dir().unwrap_or_else(|| PathBuf::new());
-            let recording_state = RecordingState {
-                screen_process: None,
-                screen_process_stdin: None,
-                video_process: None,
-                audio_process: None,
-                upload_handles: Mutex::new(vec![]),
-                recording_options: None,
-                shutdown_flag: Arc::new(AtomicBool::new(false)),
-                video_uploading_finished: Arc::new(AtomicBool::new(false)),
-                audio_uploading_finished: Arc::new(AtomicBool::new(false)),
-                data_dir: Some(data_directory),
-            };
+            let recording_state = RecordingState::new(recordings_directory.clone());
+
+            if let Err(e) = hotkeys::register_hotkeys(&app.handle(), &settings.hotkeys) {
+                warn!("Failed to register hotkeys: {}", e);
+            }
 
+            let control_api_settings = settings.control_api.clone();
             app.manage(Arc::new(Mutex::new(recording_state)));
+            app.manage(Arc::new(Mutex::new(settings)));
+            tray::spawn_tray_updater(app.handle());
+            network::spawn_metered_monitor(app.handle());
+            network::spawn_backlog_monitor(app.handle());
+            deep_link::register(&app.handle());
+            jobs::start(app.handle());
+
+            if let Err(e) = tauri::async_runtime::block_on(control_api::restart_control_api(&app.handle(), &control_api_settings)) {
+                warn!("Failed to start the control API: {}", e);
+            }
 
             Ok(())
         })
         .invoke_handler(tauri::generate_handler![
             start_dual_recording,
             stop_all_recordings,
+            pause_recording,
+            resume_recording,
+            cancel_recording,
+            retry_upload,
             enumerate_audio_devices,
-            upload_file
+            list_pulse_sources,
+            start_mic_preview,
+            stop_mic_preview,
+            upload_file,
+            export_logs,
+            get_ffmpeg_version,
+            detect_system_ffmpeg,
+            set_ffmpeg_path,
+            get_settings,
+            set_settings,
+            list_recordings,
+            get_recording,
+            delete_recording,
+            trim_recording,
+            transcribe_recording,
+            add_marker,
+            split_recording,
+            pause_uploads,
+            resume_uploads,
+            get_upload_pause_state,
+            get_upload_backlog,
+            get_upload_stats,
+            validate_recording_options,
+            test_recording,
+            get_runtime_metrics,
+            get_encoder_capabilities,
+            get_unclean_sessions,
+            export_diagnostics,
+            take_screenshot,
+            annotate_screenshot,
+            list_jobs,
+            retry_job,
+            set_poster_frame,
+            capture_frame_now,
+            set_zoom,
+            set_drawing,
+            set_window_capture_excluded,
+            list_capture_excluded_windows,
+            benchmark_capture,
+            acquire_camera_preview,
+            release_camera_preview,
+            get_preview_stream
         ])
         .plugin(tauri_plugin_context_menu::init())
-        .run(tauri::generate_context!())
-        .expect("Error while running tauri application");
+        .build(context)
+        .expect("Error while building tauri application")
+        .run(|app_handle, event| {
+            if let RunEvent::ExitRequested { api, .. } = event {
+                let state = app_handle.state::<Arc<Mutex<RecordingState>>>().inner().clone();
+                // A locked state means a command is mid-flight; treat it as busy.
+                let has_pending_work = state.try_lock().map_or(true, |guard| guard.has_pending_work());
+                if has_pending_work {
+                    api.prevent_exit();
+                    let app_handle = app_handle.clone();
+                    tauri::async_runtime::spawn(async move {
+                        shutdown_recording(&state).await;
+                        app_handle.exit(0);
+                    });
+                }
+            }
+        });
 }
\ No newline at end of file
@@ -0,0 +1,507 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use serde::{Serialize, Deserialize};
+use tracing::{info, warn};
+
+use crate::faults::check_disk_space;
+use crate::journal::{self, JournalEvent};
+use crate::settings::ChunkRetention;
+use crate::adaptive::CaptureChange;
+use crate::capture_watch::CaptureFailure;
+use crate::progress::{DurationMismatch, FrameDropReport};
+use crate::recording::RecordingOptions;
+use crate::telemetry::ResourceSummary;
+use crate::backpressure::BacklogWarning;
+
+pub const RECORDINGS_DIR: &str = "recordings";
+pub const MANIFEST_FILE: &str = "manifest.json";
+/// Digits in segment numbers, enough for over 80 hours of 3 second segments. Recordings
+/// made with three digits keep their names; ffmpeg just writes wider numbers past 999.
+const SEGMENT_NUMBER_WIDTH: usize = 5;
+
+/// Serializes read-modify-write cycles on manifests shared by the recording and upload tasks.
+static MANIFEST_LOCK: Mutex<()> = Mutex::new(());
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordingStatus {
+    Recording,
+    Uploading,
+    Complete,
+    Failed,
+}
+
+/// Why a recording ended, so intentional endings can be told apart from failures.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum StopReason {
+    /// Stopped from the app, the tray, the control API or Ctrl+C on the command line.
+    UserStop,
+    /// The `--duration` given on the command line ran out.
+    MaxDuration,
+    DiskFull,
+    FfmpegCrash,
+    /// Screen capture stopped producing frames.
+    CaptureStalled,
+    /// The display being recorded was unplugged or changed resolution.
+    DisplayChanged,
+    /// Capture broke when the system went to sleep.
+    SystemSleep,
+    AppExit,
+    /// Ended by `split_recording`, which continues under another video id.
+    Split,
+    Cancelled,
+}
+
+impl StopReason {
+    pub fn is_intentional(self) -> bool {
+        matches!(self, StopReason::UserStop | StopReason::MaxDuration | StopReason::AppExit | StopReason::Split | StopReason::Cancelled)
+    }
+
+    /// The name used in the manifest, also written to the session journal.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            StopReason::UserStop => "user_stop",
+            StopReason::MaxDuration => "max_duration",
+            StopReason::DiskFull => "disk_full",
+            StopReason::FfmpegCrash => "ffmpeg_crash",
+            StopReason::CaptureStalled => "capture_stalled",
+            StopReason::DisplayChanged => "display_changed",
+            StopReason::SystemSleep => "system_sleep",
+            StopReason::AppExit => "app_exit",
+            StopReason::Split => "split",
+            StopReason::Cancelled => "cancelled",
+        }
+    }
+}
+
+/// Written next to a recording's chunks so it can be recovered or listed later.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordingManifest {
+    pub video_id: String,
+    pub created_at: String,
+    pub updated_at: String,
+    pub status: RecordingStatus,
+    pub options: RecordingOptions,
+    /// Segment file names confirmed uploaded, keyed by video type ("screen", "audio").
+    #[serde(default)]
+    pub uploaded_segments: BTreeMap<String, BTreeSet<String>>,
+    /// Recorded time excluding pauses, set when the recording stops.
+    #[serde(default)]
+    pub duration_secs: Option<f64>,
+    /// Set when the recording stops.
+    #[serde(default)]
+    pub stop_reason: Option<StopReason>,
+    /// Set when capture dropped enough frames for the video to look choppy.
+    #[serde(default)]
+    pub frame_drops: Option<FrameDropReport>,
+    /// Storage key of the uploaded thumbnail screenshot.
+    #[serde(default)]
+    pub screenshot_key: Option<String>,
+    /// Recorded time of the frame picked with `set_poster_frame`; `None` while the
+    /// thumbnail is the screenshot taken when recording started.
+    #[serde(default)]
+    pub poster_time_ms: Option<u64>,
+    /// Probed length of each uploaded segment, keyed by video type and file name.
+    #[serde(default)]
+    pub segment_durations: BTreeMap<String, BTreeMap<String, f64>>,
+    /// Tracks whose segments don't add up to `duration_secs`, which points at lost frames.
+    #[serde(default)]
+    pub duration_mismatches: Vec<DurationMismatch>,
+    /// Last error of each segment whose upload failed and hasn't succeeded since, keyed
+    /// by video type and file name.
+    #[serde(default)]
+    pub upload_errors: BTreeMap<String, BTreeMap<String, String>>,
+    /// Empty or near-empty segments ffmpeg listed but that were never uploaded, keyed
+    /// by video type. They don't count as pending.
+    #[serde(default)]
+    pub skipped_segments: BTreeMap<String, BTreeSet<String>>,
+    /// Set when screen capture ended on its own and the recording was stopped early.
+    #[serde(default)]
+    pub capture_failure: Option<CaptureFailure>,
+    /// Changes made to the screen capture settings while recording, in order.
+    #[serde(default)]
+    pub capture_changes: Vec<CaptureChange>,
+    /// CPU, memory and GPU encoder usage while recording, and the warnings raised.
+    #[serde(default)]
+    pub resource_usage: Option<ResourceSummary>,
+    /// Raised while uploads fell behind capture.
+    #[serde(default)]
+    pub backlog_warnings: Vec<BacklogWarning>,
+}
+
+impl RecordingManifest {
+    pub fn new(options: &RecordingOptions) -> Self {
+        let now = Utc::now().to_rfc3339();
+        RecordingManifest {
+            video_id: options.video_id.clone(),
+            created_at: now.clone(),
+            updated_at: now,
+            status: RecordingStatus::Recording,
+            options: options.clone(),
+            uploaded_segments: BTreeMap::new(),
+            duration_secs: None,
+            stop_reason: None,
+            frame_drops: None,
+            screenshot_key: None,
+            poster_time_ms: None,
+            segment_durations: BTreeMap::new(),
+            duration_mismatches: vec![],
+            upload_errors: BTreeMap::new(),
+            skipped_segments: BTreeMap::new(),
+            capture_failure: None,
+            capture_changes: vec![],
+            resource_usage: None,
+            backlog_warnings: vec![],
+        }
+    }
+
+    pub fn load(recording_dir: &Path) -> Result<Self, String> {
+        let contents = std::fs::read_to_string(recording_dir.join(MANIFEST_FILE))
+            .map_err(|e| format!("Failed to read manifest: {}", e))?;
+        serde_json::from_str(&contents).map_err(|e| format!("Invalid manifest: {}", e))
+    }
+
+    /// Writes to a temporary file first so a crash never leaves a truncated manifest.
+    pub fn save(&self, recording_dir: &Path) -> Result<(), String> {
+        check_disk_space().map_err(|e| format!("Failed to write manifest: {}", e))?;
+        let contents = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        let temp_path = recording_dir.join(format!("{}.tmp", MANIFEST_FILE));
+        std::fs::write(&temp_path, contents).map_err(|e| format!("Failed to write manifest: {}", e))?;
+        std::fs::rename(&temp_path, recording_dir.join(MANIFEST_FILE)).map_err(|e| format!("Failed to write manifest: {}", e))
+    }
+}
+
+/// Loads, modifies and saves a manifest while holding the manifest lock.
+pub fn update_manifest<F>(recording_dir: &Path, update: F) -> Result<RecordingManifest, String>
+where
+    F: FnOnce(&mut RecordingManifest),
+{
+    let _lock = MANIFEST_LOCK.lock().map_err(|e| e.to_string())?;
+    let mut manifest = RecordingManifest::load(recording_dir)?;
+    update(&mut manifest);
+    manifest.updated_at = Utc::now().to_rfc3339();
+    manifest.save(recording_dir)?;
+    Ok(manifest)
+}
+
+pub fn set_recording_status(recording_dir: &Path, status: RecordingStatus) {
+    if let Err(e) = update_manifest(recording_dir, |manifest| manifest.status = status) {
+        warn!("Failed to update recording status in {}: {}", recording_dir.display(), e);
+    }
+}
+
+pub fn mark_segment_uploaded(recording_dir: &Path, video_type: &str, segment_filename: &str, duration_secs: f64) {
+    let result = update_manifest(recording_dir, |manifest| {
+        manifest.uploaded_segments
+            .entry(video_type.to_string())
+            .or_default()
+            .insert(segment_filename.to_string());
+        manifest.segment_durations
+            .entry(video_type.to_string())
+            .or_default()
+            .insert(segment_filename.to_string(), duration_secs);
+        if let Some(errors) = manifest.upload_errors.get_mut(video_type) {
+            errors.remove(segment_filename);
+        }
+    });
+    if let Err(e) = result {
+        warn!("Failed to record upload of {} in manifest: {}", segment_filename, e);
+    }
+    journal::append(recording_dir, JournalEvent::SegmentUploaded {
+        track: video_type.to_string(),
+        segment: segment_filename.to_string(),
+    });
+}
+
+pub fn mark_segment_skipped(recording_dir: &Path, video_type: &str, segment_filename: &str) {
+    let result = update_manifest(recording_dir, |manifest| {
+        manifest.skipped_segments
+            .entry(video_type.to_string())
+            .or_default()
+            .insert(segment_filename.to_string());
+    });
+    if let Err(e) = result {
+        warn!("Failed to record the skipped segment {} in manifest: {}", segment_filename, e);
+    }
+    journal::append(recording_dir, JournalEvent::SegmentSkipped {
+        track: video_type.to_string(),
+        segment: segment_filename.to_string(),
+    });
+}
+
+pub fn mark_segment_failed(recording_dir: &Path, video_type: &str, segment_filename: &str, error: &str) {
+    let result = update_manifest(recording_dir, |manifest| {
+        manifest.upload_errors
+            .entry(video_type.to_string())
+            .or_default()
+            .insert(segment_filename.to_string(), error.to_string());
+    });
+    if let Err(e) = result {
+        warn!("Failed to record the failed upload of {} in manifest: {}", segment_filename, e);
+    }
+    journal::append(recording_dir, JournalEvent::SegmentFailed {
+        track: video_type.to_string(),
+        segment: segment_filename.to_string(),
+        error: error.to_string(),
+    });
+}
+
+/// The ffmpeg segment pattern for chunks named `{prefix}_00000.{extension}`.
+pub fn segment_pattern(prefix: &str, extension: &str) -> String {
+    format!("{}_%0{}d.{}", prefix, SEGMENT_NUMBER_WIDTH, extension)
+}
+
+/// The file name `segment_pattern` gives segment `number`.
+pub fn segment_file_name(prefix: &str, number: usize, extension: &str) -> String {
+    format!("{}_{:0width$}.{}", prefix, number, extension, width = SEGMENT_NUMBER_WIDTH)
+}
+
+/// The number at the end of a segment's file name. Names of different widths don't sort
+/// as text (`1000` before `999`), so segments are ordered by this instead.
+pub fn segment_number(file_name: &str) -> Option<u64> {
+    let stem = file_name.rsplit_once('.').map_or(file_name, |(stem, _)| stem);
+    stem.rsplit_once('_')?.1.parse().ok()
+}
+
+/// Orders segments by number, keeping names without one in place at the end.
+pub fn sort_segments(segments: &mut [String]) {
+    segments.sort_by_key(|segment| segment_number(segment).unwrap_or(u64::MAX));
+}
+
+/// Segments listed by ffmpeg for `video_type` that have been neither confirmed uploaded
+/// nor skipped.
+pub fn pending_segments(recording_dir: &Path, video_type: &str) -> Result<Vec<String>, String> {
+    let manifest = RecordingManifest::load(recording_dir)?;
+    let uploaded = manifest.uploaded_segments.get(video_type);
+    let skipped = manifest.skipped_segments.get(video_type);
+
+    let segment_list = std::fs::read_to_string(recording_dir.join(video_type).join("segment_list.txt"))
+        .unwrap_or_default();
+
+    let mut pending: Vec<String> = segment_list.lines()
+        .filter(|line| !line.is_empty())
+        .filter(|segment| uploaded.map_or(true, |uploaded| !uploaded.contains(*segment)))
+        .filter(|segment| skipped.map_or(true, |skipped| !skipped.contains(*segment)))
+        .map(|segment| segment.to_string())
+        .collect();
+    sort_segments(&mut pending);
+    Ok(pending)
+}
+
+/// A segment still pending after its uploads finished.
+#[derive(Debug, Clone, Serialize)]
+pub struct ChunkFailure {
+    pub segment: String,
+    /// Why the last attempt failed; `None` if the segment was never attempted, e.g.
+    /// because the recording was cancelled.
+    pub error: Option<String>,
+}
+
+/// Pending segments per video type with their last upload errors. Types with nothing
+/// pending are left out.
+pub fn chunk_failures(recording_dir: &Path) -> BTreeMap<String, Vec<ChunkFailure>> {
+    let upload_errors = RecordingManifest::load(recording_dir)
+        .map(|manifest| manifest.upload_errors)
+        .unwrap_or_default();
+
+    let mut failures = BTreeMap::new();
+    for video_type in ["screen", "audio"] {
+        let errors = upload_errors.get(video_type);
+        let pending: Vec<ChunkFailure> = pending_segments(recording_dir, video_type)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|segment| ChunkFailure {
+                error: errors.and_then(|errors| errors.get(&segment)).cloned(),
+                segment,
+            })
+            .collect();
+        if !pending.is_empty() {
+            failures.insert(video_type.to_string(), pending);
+        }
+    }
+    failures
+}
+
+/// Marks the recording complete when every segment was uploaded, failed otherwise.
+pub fn finalize_upload_status(recording_dir: &Path) -> RecordingStatus {
+    let has_pending = ["screen", "audio"].iter()
+        .any(|video_type| pending_segments(recording_dir, video_type).map_or(true, |pending| !pending.is_empty()));
+
+    let status = if has_pending { RecordingStatus::Failed } else { RecordingStatus::Complete };
+    set_recording_status(recording_dir, status);
+    status
+}
+
+/// Recordings left in `Recording` or `Uploading` were interrupted by the app quitting
+/// or crashing. Nothing can be uploading at startup, so they are marked failed and
+/// can be picked up by `retry_upload`.
+pub fn mark_interrupted_recordings(data_dir: &Path) {
+    let entries = match std::fs::read_dir(recordings_root(data_dir)) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+
+    for entry in entries.flatten() {
+        let dir = entry.path();
+        let status = match RecordingManifest::load(&dir) {
+            Ok(manifest) => manifest.status,
+            Err(_) => continue,
+        };
+
+        if matches!(status, RecordingStatus::Recording | RecordingStatus::Uploading) {
+            info!("Marking interrupted recording {} as failed", dir.display());
+            set_recording_status(&dir, RecordingStatus::Failed);
+        }
+    }
+}
+
+/// Video ids become directory names, so only allow characters that can't escape the recordings dir.
+pub fn validate_video_id(video_id: &str) -> Result<(), String> {
+    let is_valid = !video_id.is_empty()
+        && video_id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if is_valid {
+        Ok(())
+    } else {
+        Err(format!("Invalid video id: {}", video_id))
+    }
+}
+
+pub fn recordings_root(data_dir: &Path) -> PathBuf {
+    data_dir.join(RECORDINGS_DIR)
+}
+
+pub fn recording_dir(data_dir: &Path, video_id: &str) -> Result<PathBuf, String> {
+    validate_video_id(video_id)?;
+    Ok(recordings_root(data_dir).join(video_id))
+}
+
+/// Deletes completed recordings older than `retention_days`. Recordings that never
+/// finished uploading are kept so they can still be recovered. `0` keeps everything.
+pub fn cleanup_recordings(data_dir: &Path, retention_days: u32) {
+    if retention_days == 0 {
+        return;
+    }
+
+    let entries = match std::fs::read_dir(recordings_root(data_dir)) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+
+    let cutoff = Utc::now() - ChronoDuration::days(retention_days as i64);
+
+    for entry in entries.flatten() {
+        let dir = entry.path();
+        let manifest = match RecordingManifest::load(&dir) {
+            Ok(manifest) => manifest,
+            Err(_) => continue,
+        };
+
+        let is_expired = DateTime::parse_from_rfc3339(&manifest.updated_at)
+            .map(|updated_at| updated_at.with_timezone(&Utc) < cutoff)
+            .unwrap_or(false);
+
+        if manifest.status == RecordingStatus::Complete && is_expired {
+            match std::fs::remove_dir_all(&dir) {
+                Ok(_) => info!("Removed expired recording {}", manifest.video_id),
+                Err(e) => warn!("Failed to remove expired recording {}: {}", manifest.video_id, e),
+            }
+        }
+    }
+}
+
+/// Removes the local copies of the segments the manifest lists as uploaded.
+pub fn remove_uploaded_chunks(recording_dir: &Path, video_type: &str) {
+    let manifest = match RecordingManifest::load(recording_dir) {
+        Ok(manifest) => manifest,
+        Err(_) => return,
+    };
+    let chunks_dir = recording_dir.join(video_type);
+    for segment in manifest.uploaded_segments.get(video_type).into_iter().flatten() {
+        let segment_path = chunks_dir.join(segment);
+        if segment_path.is_file() {
+            if let Err(e) = std::fs::remove_file(&segment_path) {
+                warn!("Failed to remove file after upload: {}", e);
+            }
+        }
+    }
+}
+
+/// Under `KeepDays`, removes the uploaded segments of completed recordings last
+/// updated more than that many days ago, keeping the rest of the recording.
+pub fn cleanup_chunks(data_dir: &Path, retention: ChunkRetention) {
+    let days = match retention {
+        ChunkRetention::KeepDays { days } => days,
+        ChunkRetention::KeepUntilConfirmed | ChunkRetention::KeepForever => return,
+    };
+
+    let entries = match std::fs::read_dir(recordings_root(data_dir)) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+
+    let cutoff = Utc::now() - ChronoDuration::days(days as i64);
+
+    for entry in entries.flatten() {
+        let dir = entry.path();
+        let manifest = match RecordingManifest::load(&dir) {
+            Ok(manifest) => manifest,
+            Err(_) => continue,
+        };
+
+        let is_expired = DateTime::parse_from_rfc3339(&manifest.updated_at)
+            .map(|updated_at| updated_at.with_timezone(&Utc) < cutoff)
+            .unwrap_or(false);
+
+        if manifest.status == RecordingStatus::Complete && is_expired {
+            info!("Removing the uploaded segments of {}", manifest.video_id);
+            for video_type in ["screen", "audio"] {
+                remove_uploaded_chunks(&dir, video_type);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn segment_number_reads_the_trailing_number() {
+        assert_eq!(segment_number("recording_chunk_00001.ts"), Some(1));
+        assert_eq!(segment_number("audio_recording_123456.aac"), Some(123456));
+        assert_eq!(segment_number("3f2a_video_id_00042.ts"), Some(42));
+        assert_eq!(segment_number("recording_chunk_00007"), Some(7));
+        assert_eq!(segment_number("segment_list.txt"), None);
+        assert_eq!(segment_number("recording.ts"), None);
+        assert_eq!(segment_number(""), None);
+    }
+
+    #[test]
+    fn segment_file_name_matches_the_pattern() {
+        assert_eq!(segment_pattern("recording_chunk", "ts"), "recording_chunk_%05d.ts");
+        assert_eq!(segment_file_name("recording_chunk", 3, "ts"), "recording_chunk_00003.ts");
+        assert_eq!(segment_number(&segment_file_name("video", 123456, "aac")), Some(123456));
+    }
+
+    #[test]
+    fn sort_segments_orders_by_number_not_text() {
+        let mut segments = vec![
+            "recording_chunk_100000.ts".to_string(),
+            "notes.txt".to_string(),
+            "recording_chunk_99999.ts".to_string(),
+            "recording_chunk_00002.ts".to_string(),
+            "recording_chunk_00010.ts".to_string(),
+        ];
+        sort_segments(&mut segments);
+        assert_eq!(segments, [
+            "recording_chunk_00002.ts",
+            "recording_chunk_00010.ts",
+            "recording_chunk_99999.ts",
+            "recording_chunk_100000.ts",
+            "notes.txt",
+        ]);
+    }
+}
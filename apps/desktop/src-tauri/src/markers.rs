@@ -0,0 +1,48 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use serde::{Serialize, Deserialize};
+use tauri::State;
+use tokio::sync::Mutex;
+use tracing::{debug, info};
+
+use crate::recording::RecordingOptions;
+use crate::session::RecordingState;
+use crate::upload::upload_sidecar_file;
+
+pub const MARKERS_FILE: &str = "markers.json";
+
+/// A labelled point in a recording, uploaded as chapter metadata.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Marker {
+    /// Recorded time excluding pauses.
+    pub offset_secs: f64,
+    pub label: String,
+}
+
+/// Marks the current point of the active recording and returns the marker.
+#[tauri::command]
+pub async fn add_marker(state: State<'_, Arc<Mutex<RecordingState>>>, label: String) -> Result<Marker, String> {
+    let mut guard = state.lock().await;
+    if !guard.is_recording() {
+        return Err("No recording is in progress".to_string());
+    }
+
+    let marker = Marker { offset_secs: guard.elapsed().as_secs_f64(), label: label.trim().to_string() };
+    info!("Added marker {:?} at {:.1}s", marker.label, marker.offset_secs);
+    guard.markers.push(marker.clone());
+
+    Ok(marker)
+}
+
+/// Writes the markers to `markers.json` and uploads it under the `chapters` type.
+pub async fn upload_markers(recording_dir: PathBuf, options: RecordingOptions, markers: Vec<Marker>) -> Result<(), String> {
+    let markers_path = recording_dir.join(MARKERS_FILE);
+    let contents = serde_json::to_string(&markers).map_err(|e| e.to_string())?;
+    tokio::fs::write(&markers_path, contents).await
+        .map_err(|e| format!("Failed to write markers: {}", e))?;
+    debug!("Recorded {} markers for {}", markers.len(), options.video_id);
+
+    upload_sidecar_file(&options, &markers_path, "chapters").await?;
+
+    Ok(())
+}
@@ -0,0 +1,106 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use serde::Serialize;
+
+use crate::network::upload_backlog;
+
+static ACTIVE_UPLOADS: AtomicUsize = AtomicUsize::new(0);
+static SEGMENTS_UPLOADED: AtomicU64 = AtomicU64::new(0);
+static BYTES_UPLOADED: AtomicU64 = AtomicU64::new(0);
+static ENCODER_FPS: Mutex<BTreeMap<String, f64>> = Mutex::new(BTreeMap::new());
+static ENCODER_SPEED: Mutex<BTreeMap<String, f64>> = Mutex::new(BTreeMap::new());
+
+/// Counters since the app started and gauges of what is running now, for support
+/// and debugging.
+#[derive(Debug, Clone, Serialize)]
+pub struct RuntimeMetrics {
+    /// Segment uploads holding an upload slot.
+    pub active_upload_tasks: usize,
+    /// Segments waiting for an upload slot, or for the connection.
+    pub queued_segments: usize,
+    /// Bytes of the queued and active uploads.
+    pub pending_bytes: u64,
+    pub segments_uploaded: u64,
+    pub bytes_uploaded: u64,
+    /// Latest encoder fps of each running capture, by video type.
+    pub encoder_fps: BTreeMap<String, f64>,
+    /// Resident memory of the app process; `None` where it can't be read.
+    pub memory_bytes: Option<u64>,
+}
+
+/// Counts a segment upload as active while it holds an upload slot.
+pub struct ActiveUpload;
+
+impl ActiveUpload {
+    pub fn start() -> Self {
+        ACTIVE_UPLOADS.fetch_add(1, Ordering::SeqCst);
+        ActiveUpload
+    }
+}
+
+impl Drop for ActiveUpload {
+    fn drop(&mut self) {
+        ACTIVE_UPLOADS.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+pub fn record_segment_uploaded(bytes: u64) {
+    SEGMENTS_UPLOADED.fetch_add(1, Ordering::SeqCst);
+    BYTES_UPLOADED.fetch_add(bytes, Ordering::SeqCst);
+}
+
+pub fn set_encoder_stats(video_type: &str, fps: f64, speed: f64) {
+    if let Ok(mut encoder_fps) = ENCODER_FPS.lock() {
+        encoder_fps.insert(video_type.to_string(), fps);
+    }
+    if let Ok(mut encoder_speed) = ENCODER_SPEED.lock() {
+        encoder_speed.insert(video_type.to_string(), speed);
+    }
+}
+
+pub fn clear_encoder_stats(video_type: &str) {
+    if let Ok(mut encoder_fps) = ENCODER_FPS.lock() {
+        encoder_fps.remove(video_type);
+    }
+    if let Ok(mut encoder_speed) = ENCODER_SPEED.lock() {
+        encoder_speed.remove(video_type);
+    }
+}
+
+/// Latest ffmpeg `speed=` of a running capture; below 1 it is falling behind.
+pub fn encoder_speed(video_type: &str) -> Option<f64> {
+    ENCODER_SPEED.lock().ok()?.get(video_type).copied()
+}
+
+#[tauri::command]
+pub async fn get_runtime_metrics() -> Result<RuntimeMetrics, String> {
+    let backlog = upload_backlog();
+    let active_upload_tasks = ACTIVE_UPLOADS.load(Ordering::SeqCst);
+    let memory_bytes = tokio::task::spawn_blocking(memory_usage).await.map_err(|e| e.to_string())?;
+
+    Ok(RuntimeMetrics {
+        active_upload_tasks,
+        queued_segments: backlog.pending_uploads.saturating_sub(active_upload_tasks),
+        pending_bytes: backlog.pending_bytes,
+        segments_uploaded: SEGMENTS_UPLOADED.load(Ordering::SeqCst),
+        bytes_uploaded: BYTES_UPLOADED.load(Ordering::SeqCst),
+        encoder_fps: ENCODER_FPS.lock().map(|encoder_fps| encoder_fps.clone()).unwrap_or_default(),
+        memory_bytes,
+    })
+}
+
+#[cfg(unix)]
+fn memory_usage() -> Option<u64> {
+    let output = std::process::Command::new("ps")
+        .args(["-o", "rss=", "-p", &std::process::id().to_string()])
+        .output()
+        .ok()?;
+    let rss_kib: u64 = String::from_utf8_lossy(&output.stdout).trim().parse().ok()?;
+    Some(rss_kib * 1024)
+}
+
+#[cfg(windows)]
+fn memory_usage() -> Option<u64> {
+    None
+}
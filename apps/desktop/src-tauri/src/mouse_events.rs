@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Mutex, Once};
+use std::time::{Duration, Instant};
+use rdev::{Button, EventType};
+use serde::Serialize;
+use tracing::{debug, warn};
+
+use crate::recording::RecordingOptions;
+use crate::upload::upload_sidecar_file;
+
+const EVENTS_FILE: &str = "events.json";
+/// Mouse moves closer together than this are dropped to keep the file small.
+const MOVE_INTERVAL_MS: u64 = 16;
+
+static LISTENER: Once = Once::new();
+/// Keyed by video id; a split briefly has two recordings collecting at once.
+static RECORDERS: Mutex<Option<HashMap<String, MouseRecorder>>> = Mutex::new(None);
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MouseEventKind {
+    Move,
+    Down,
+    Up,
+    Scroll,
+}
+
+/// One input event. `t` is milliseconds of recorded time, excluding pauses; `x`/`y`
+/// are screen coordinates in points. `b` is the button for `down`/`up` (0 left,
+/// 1 right, 2 middle) and `dx`/`dy` are the scroll deltas.
+#[derive(Debug, Clone, Serialize)]
+pub struct MouseEvent {
+    t: u64,
+    k: MouseEventKind,
+    x: f64,
+    y: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    b: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dx: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dy: Option<i64>,
+}
+
+struct MouseRecorder {
+    started_at: Instant,
+    paused_at: Option<Instant>,
+    paused_time: Duration,
+    position: (f64, f64),
+    last_move_ms: Option<u64>,
+    events: Vec<MouseEvent>,
+}
+
+impl MouseRecorder {
+    fn elapsed_ms(&self) -> u64 {
+        Instant::now()
+            .saturating_duration_since(self.started_at)
+            .saturating_sub(self.paused_time)
+            .as_millis() as u64
+    }
+
+    fn handle(&mut self, event_type: EventType) {
+        if let EventType::MouseMove { x, y } = event_type {
+            self.position = (x, y);
+        }
+        if self.paused_at.is_some() {
+            return;
+        }
+
+        let t = self.elapsed_ms();
+        let (x, y) = self.position;
+        let event = |k, b, dx, dy| MouseEvent { t, k, x, y, b, dx, dy };
+
+        match event_type {
+            EventType::MouseMove { .. } => {
+                if self.last_move_ms.map_or(false, |last| t < last + MOVE_INTERVAL_MS) {
+                    return;
+                }
+                self.last_move_ms = Some(t);
+                self.events.push(event(MouseEventKind::Move, None, None, None));
+            },
+            EventType::ButtonPress(button) => self.events.push(event(MouseEventKind::Down, Some(button_index(button)), None, None)),
+            EventType::ButtonRelease(button) => self.events.push(event(MouseEventKind::Up, Some(button_index(button)), None, None)),
+            EventType::Wheel { delta_x, delta_y } => self.events.push(event(MouseEventKind::Scroll, None, Some(delta_x), Some(delta_y))),
+            _ => {},
+        }
+    }
+}
+
+fn button_index(button: Button) -> u8 {
+    match button {
+        Button::Left => 0,
+        Button::Right => 1,
+        Button::Middle => 2,
+        Button::Unknown(index) => index,
+    }
+}
+
+/// Starts collecting mouse events for `video_id`. The OS hook can't be removed
+/// again, so a single listener thread is started on first use and simply ignores
+/// events while no recording is collecting them.
+pub fn start(video_id: &str) {
+    LISTENER.call_once(|| {
+        std::thread::spawn(|| {
+            let result = rdev::listen(|event| {
+                if let Ok(mut recorders) = RECORDERS.lock() {
+                    for recorder in recorders.iter_mut().flat_map(|recorders| recorders.values_mut()) {
+                        recorder.handle(event.event_type.clone());
+                    }
+                }
+            });
+            if let Err(e) = result {
+                warn!("Failed to listen for mouse events: {:?}", e);
+            }
+        });
+    });
+
+    if let Ok(mut recorders) = RECORDERS.lock() {
+        recorders.get_or_insert_with(HashMap::new).insert(video_id.to_string(), MouseRecorder {
+            started_at: Instant::now(),
+            paused_at: None,
+            paused_time: Duration::ZERO,
+            position: (0.0, 0.0),
+            last_move_ms: None,
+            events: vec![],
+        });
+    }
+}
+
+pub fn set_paused(paused: bool) {
+    if let Ok(mut recorders) = RECORDERS.lock() {
+        for recorder in recorders.iter_mut().flat_map(|recorders| recorders.values_mut()) {
+            if paused {
+                recorder.paused_at = Some(Instant::now());
+            } else if let Some(paused_at) = recorder.paused_at.take() {
+                recorder.paused_time += paused_at.elapsed();
+            }
+        }
+    }
+}
+
+/// Stops collecting for `video_id` and returns the events, or `None` if nothing was
+/// being collected.
+pub fn stop(video_id: &str) -> Option<Vec<MouseEvent>> {
+    RECORDERS.lock().ok()?.as_mut()?.remove(video_id).map(|recorder| recorder.events)
+}
+
+/// Writes the events to `events.json` and uploads it under the `events` type.
+pub async fn upload_events(recording_dir: PathBuf, options: RecordingOptions, events: Vec<MouseEvent>) -> Result<(), String> {
+    let events_path = recording_dir.join(EVENTS_FILE);
+    let contents = serde_json::to_string(&events).map_err(|e| e.to_string())?;
+    tokio::fs::write(&events_path, contents).await
+        .map_err(|e| format!("Failed to write mouse events: {}", e))?;
+    debug!("Recorded {} mouse events for {}", events.len(), options.video_id);
+
+    upload_sidecar_file(&options, &events_path, "events").await?;
+
+    Ok(())
+}
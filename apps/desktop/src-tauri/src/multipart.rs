@@ -0,0 +1,299 @@
+use std::io::SeekFrom;
+use std::path::Path;
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+use futures::stream::{FuturesUnordered, StreamExt};
+use reqwest::header::{CONTENT_LENGTH, ETAG};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+use tracing::{debug, info, warn};
+
+use crate::network::upload_backlog;
+use crate::recording::RecordingOptions;
+use crate::sts::{self, storage_headers, use_sts};
+use crate::upload::{rejected_upload, UploadFailure};
+
+const MIB: u64 = 1024 * 1024;
+/// S3's smallest part, other than the last one.
+const MIN_PART_SIZE_MB: u32 = 5;
+const MAX_PART_SIZE_MB: u32 = 64;
+const MAX_PARTS: u64 = 10_000;
+const MAX_PARALLEL_PARTS: usize = 16;
+/// Adaptive parts are sized to what the connection uploads in this long, so the round
+/// trip of each request is a small share of it.
+const TARGET_PART_SECS: f64 = 4.0;
+/// Round trip time worth one more part in flight under adaptive tuning. A single
+/// connection's throughput drops as latency grows; more of them make up for it.
+const RTT_PER_PARALLEL_PART: Duration = Duration::from_millis(50);
+/// Adaptive tuning adds a part in flight while throughput grows by at least this much.
+const MIN_THROUGHPUT_GAIN: f64 = 1.1;
+/// Attempts per part before the whole upload fails.
+const MAX_PART_ATTEMPTS: u32 = 3;
+
+const DEFAULT_SETTINGS: MultipartSettings = MultipartSettings {
+    enabled: true,
+    part_size_mb: 8,
+    parallel_parts: 4,
+    adaptive: true,
+};
+
+static SETTINGS: RwLock<MultipartSettings> = RwLock::new(DEFAULT_SETTINGS);
+
+/// How files larger than one part are uploaded when uploads are signed locally with
+/// STS credentials. Smaller files, and every file without STS, go up in one POST.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct MultipartSettings {
+    pub enabled: bool,
+    /// From 5 to 64. Under `adaptive`, only used until an upload has measured the
+    /// connection.
+    pub part_size_mb: u32,
+    /// Parts uploaded at once. Under `adaptive`, the least there are.
+    pub parallel_parts: usize,
+    /// Sizes parts from the measured upload throughput, starts more parts at once on
+    /// high latency connections, and adds more while that keeps raising throughput.
+    pub adaptive: bool,
+}
+
+impl Default for MultipartSettings {
+    fn default() -> Self {
+        DEFAULT_SETTINGS
+    }
+}
+
+impl MultipartSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(MIN_PART_SIZE_MB..=MAX_PART_SIZE_MB).contains(&self.part_size_mb) {
+            return Err(format!("Part size must be from {} to {} MB", MIN_PART_SIZE_MB, MAX_PART_SIZE_MB));
+        }
+        if !(1..=MAX_PARALLEL_PARTS).contains(&self.parallel_parts) {
+            return Err(format!("Parallel parts must be from 1 to {}", MAX_PARALLEL_PARTS));
+        }
+        Ok(())
+    }
+}
+
+pub fn set_multipart_settings(settings: MultipartSettings) {
+    if let Ok(mut current) = SETTINGS.write() {
+        *current = settings;
+    }
+}
+
+fn multipart_settings() -> MultipartSettings {
+    SETTINGS.read().map(|settings| *settings).unwrap_or_default()
+}
+
+/// Whether a file of `file_size` bytes is uploaded in parts.
+pub fn use_multipart(file_size: u64) -> bool {
+    let settings = multipart_settings();
+    settings.enabled && use_sts() && file_size > settings.part_size_mb as u64 * MIB
+}
+
+/// Uploads `file_path` to `file_key` in parts, several at a time, and has S3 put them
+/// together. An upload that fails is aborted, so its parts don't linger in the bucket.
+pub async fn upload(
+    options: &RecordingOptions,
+    file_path: &Path,
+    file_key: &str,
+    content_type: &str,
+    duration: Option<&str>,
+) -> Result<(), UploadFailure> {
+    let settings = multipart_settings();
+    let file_size = tokio::fs::metadata(file_path).await.map_err(|e| format!("Failed to read file: {}", e))?.len();
+    let client = reqwest::Client::new();
+
+    let mut headers = vec![("content-type".to_string(), content_type.to_string())];
+    if let Some(duration) = duration {
+        headers.push(("x-amz-meta-duration".to_string(), duration.to_string()));
+    }
+    headers.extend(storage_headers(&options.storage));
+    let started_at = Instant::now();
+    let response = send(&client, options, "POST", file_key, &[("uploads", "")], headers, None).await?;
+    let rtt = started_at.elapsed();
+    let created = response.text().await.map_err(|e| UploadFailure::Network(format!("Failed to read multipart upload: {}", e)))?;
+    let upload_id = xml_value(&created, "UploadId").ok_or("S3 returned no multipart upload id")?;
+
+    let result = match upload_parts(&client, options, file_path, file_key, &upload_id, file_size, settings, rtt).await {
+        Ok(parts) => complete(&client, options, file_key, &upload_id, &parts).await,
+        Err(e) => Err(e),
+    };
+    if result.is_err() {
+        if let Err(e) = send(&client, options, "DELETE", file_key, &[("uploadId", &upload_id)], vec![], None).await {
+            warn!("Failed to abort the multipart upload of {}: {}", file_key, failure_message(&e));
+        }
+    }
+    result
+}
+
+/// Uploads every part, returning their numbers and ETags in order.
+async fn upload_parts(
+    client: &reqwest::Client,
+    options: &RecordingOptions,
+    file_path: &Path,
+    file_key: &str,
+    upload_id: &str,
+    file_size: u64,
+    settings: MultipartSettings,
+    rtt: Duration,
+) -> Result<Vec<(u64, String)>, UploadFailure> {
+    let part_size = part_size(settings, file_size);
+    let part_count = (file_size + part_size - 1) / part_size;
+    let mut parallel = if settings.adaptive {
+        settings.parallel_parts.max((rtt.as_millis() / RTT_PER_PARALLEL_PART.as_millis()) as usize)
+    } else {
+        settings.parallel_parts
+    }.clamp(1, MAX_PARALLEL_PARTS);
+    info!("Uploading {} in {} parts of {} MB, {} at a time (round trip {:?})", file_key, part_count, part_size / MIB, parallel, rtt);
+
+    let started_at = Instant::now();
+    let mut uploaded_bytes = 0;
+    let mut best_throughput = 0.0;
+    let mut next_part = 1;
+    let mut in_flight = FuturesUnordered::new();
+    let mut parts = Vec::with_capacity(part_count as usize);
+
+    loop {
+        while in_flight.len() < parallel && next_part <= part_count {
+            let offset = (next_part - 1) * part_size;
+            in_flight.push(upload_part(client, options, file_path, file_key, upload_id, next_part, offset, part_size.min(file_size - offset)));
+            next_part += 1;
+        }
+        let Some(result) = in_flight.next().await else { break };
+        let (part_number, etag, len) = result?;
+        parts.push((part_number, etag));
+        uploaded_bytes += len;
+
+        if settings.adaptive && parallel < MAX_PARALLEL_PARTS {
+            let throughput = uploaded_bytes as f64 / started_at.elapsed().as_secs_f64().max(0.001);
+            if throughput >= best_throughput * MIN_THROUGHPUT_GAIN {
+                parallel += 1;
+                debug!("Throughput of {} is {:.1} MB/s, uploading {} parts at a time", file_key, throughput / MIB as f64, parallel);
+            }
+            best_throughput = f64::max(best_throughput, throughput);
+        }
+    }
+
+    parts.sort_by_key(|(part_number, _)| *part_number);
+    Ok(parts)
+}
+
+/// The configured part size, or under adaptive tuning what the connection uploads in
+/// `TARGET_PART_SECS`, grown where needed to stay within S3's part count.
+fn part_size(settings: MultipartSettings, file_size: u64) -> u64 {
+    let size = match upload_backlog().throughput_bytes_per_sec {
+        Some(throughput) if settings.adaptive => (throughput * TARGET_PART_SECS) as u64,
+        _ => settings.part_size_mb as u64 * MIB,
+    };
+    size.clamp(MIN_PART_SIZE_MB as u64 * MIB, MAX_PART_SIZE_MB as u64 * MIB)
+        .max((file_size + MAX_PARTS - 1) / MAX_PARTS)
+}
+
+/// Streams `len` bytes from `offset` as part `part_number`, retrying temporary and
+/// connection errors.
+async fn upload_part(
+    client: &reqwest::Client,
+    options: &RecordingOptions,
+    file_path: &Path,
+    file_key: &str,
+    upload_id: &str,
+    part_number: u64,
+    offset: u64,
+    len: u64,
+) -> Result<(u64, String, u64), UploadFailure> {
+    let part = part_number.to_string();
+    let mut attempt = 1;
+
+    loop {
+        let result = async {
+            let mut file = tokio::fs::File::open(file_path).await.map_err(|e| format!("Failed to read file: {}", e))?;
+            file.seek(SeekFrom::Start(offset)).await.map_err(|e| format!("Failed to read file: {}", e))?;
+            let body = reqwest::Body::wrap_stream(ReaderStream::new(file.take(len)));
+            let query = [("partNumber", part.as_str()), ("uploadId", upload_id)];
+            let response = send(client, options, "PUT", file_key, &query, vec![], Some((body, len))).await?;
+            response.headers()
+                .get(ETAG)
+                .and_then(|etag| etag.to_str().ok())
+                .map(str::to_string)
+                .ok_or(UploadFailure::Fatal(format!("S3 returned no ETag for part {}", part_number)))
+        }.await;
+
+        match result {
+            Ok(etag) => return Ok((part_number, etag, len)),
+            // A dropped connection only costs this part a retry, not the whole upload.
+            Err(UploadFailure::Retryable(e) | UploadFailure::Network(e)) if attempt < MAX_PART_ATTEMPTS => {
+                let backoff = Duration::from_secs(2u64.pow(attempt));
+                warn!("Part {} of {} failed (attempt {}), retrying in {:?}: {}", part_number, file_key, attempt, backoff, e);
+                tokio::time::sleep(backoff).await;
+                attempt += 1;
+            },
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+async fn complete(
+    client: &reqwest::Client,
+    options: &RecordingOptions,
+    file_key: &str,
+    upload_id: &str,
+    parts: &[(u64, String)],
+) -> Result<(), UploadFailure> {
+    let parts: String = parts.iter()
+        .map(|(part_number, etag)| format!("<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>", part_number, etag))
+        .collect();
+    let body = format!("<CompleteMultipartUpload>{}</CompleteMultipartUpload>", parts);
+    let len = body.len() as u64;
+
+    let response = send(client, options, "POST", file_key, &[("uploadId", upload_id)], vec![], Some((body.into(), len))).await?;
+    // S3 can fail to assemble the parts after it has already answered 200.
+    let result = response.text().await.map_err(|e| UploadFailure::Network(format!("Failed to read multipart completion: {}", e)))?;
+    if result.contains("<Error>") {
+        return Err(UploadFailure::Retryable(format!("Failed to complete the multipart upload: {}", result)));
+    }
+    info!("Completed the multipart upload of {}", file_key);
+    Ok(())
+}
+
+async fn send(
+    client: &reqwest::Client,
+    options: &RecordingOptions,
+    method: &str,
+    file_key: &str,
+    query: &[(&str, &str)],
+    headers: Vec<(String, String)>,
+    body: Option<(reqwest::Body, u64)>,
+) -> Result<reqwest::Response, UploadFailure> {
+    let request = sts::signed_request(options, method, file_key, query, headers).await?;
+    let method = reqwest::Method::from_bytes(method.as_bytes()).map_err(|e| e.to_string())?;
+
+    let mut builder = client.request(method, &request.url);
+    for (name, value) in request.headers {
+        builder = builder.header(name, value);
+    }
+    if let Some((body, len)) = body {
+        builder = builder.header(CONTENT_LENGTH, len).body(body);
+    }
+
+    let response = builder.send()
+        .await
+        .map_err(|e| UploadFailure::Network(format!("Failed to send multipart upload request: {}", e)))?;
+    if response.status().is_success() {
+        return Ok(response);
+    }
+    let status = response.status();
+    let error_body = response.text().await.unwrap_or_else(|_| "<no response body>".to_string());
+    Err(rejected_upload(status, &error_body).await)
+}
+
+fn xml_value(xml: &str, tag: &str) -> Option<String> {
+    let start = xml.find(&format!("<{}>", tag))? + tag.len() + 2;
+    let end = start + xml[start..].find(&format!("</{}>", tag))?;
+    Some(xml[start..end].to_string())
+}
+
+fn failure_message(failure: &UploadFailure) -> &str {
+    match failure {
+        UploadFailure::Network(message) | UploadFailure::Retryable(message) | UploadFailure::Fatal(message) => message,
+    }
+}
@@ -0,0 +1,308 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use ffmpeg_next as ffmpeg;
+use ffmpeg::format::Pixel;
+use ffmpeg::software::scaling;
+use ffmpeg::{codec, encoder, format, frame, Dictionary, Packet, Rational};
+use tracing::{debug, error, info};
+
+use crate::manifest::segment_file_name;
+
+/// Source of raw screen frames for the in-process encoder.
+pub trait CaptureBackend {
+    fn dimensions(&self) -> (u32, u32);
+    /// Captures one tightly packed RGBA frame of `dimensions()` size.
+    fn capture_rgba(&mut self) -> Result<Vec<u8>, String>;
+}
+
+pub struct MonitorCapture {
+    monitor: xcap::Monitor,
+}
+
+impl MonitorCapture {
+    pub fn new(screen_index: usize) -> Result<Self, String> {
+        let monitor = xcap::Monitor::all()
+            .map_err(|e| e.to_string())?
+            .into_iter()
+            .nth(screen_index)
+            .ok_or(format!("Screen {} not found", screen_index))?;
+        Ok(MonitorCapture { monitor })
+    }
+}
+
+impl CaptureBackend for MonitorCapture {
+    fn dimensions(&self) -> (u32, u32) {
+        (self.monitor.width(), self.monitor.height())
+    }
+
+    fn capture_rgba(&mut self) -> Result<Vec<u8>, String> {
+        self.monitor.capture_image()
+            .map(|image| image.into_raw())
+            .map_err(|e| e.to_string())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct NativeEncoderConfig {
+    pub screen_index: usize,
+    pub chunks_dir: PathBuf,
+    /// See `RecordingOptions::segment_prefix`.
+    pub segment_prefix: String,
+    pub framerate: u32,
+    pub segment_time_secs: u32,
+    pub preset: String,
+    pub crf: String,
+}
+
+enum EncoderControl {
+    Pause,
+    Resume,
+    Stop,
+}
+
+/// Handle to the encoder thread. Paused time is simply not captured, so the output
+/// stays one continuous stream without gaps or timestamp jumps.
+pub struct NativeEncoderHandle {
+    control: Sender<EncoderControl>,
+    thread: Option<JoinHandle<Result<(), String>>>,
+}
+
+impl NativeEncoderHandle {
+    pub fn pause(&self) -> Result<(), String> {
+        self.control.send(EncoderControl::Pause).map_err(|_| "Native encoder is not running".to_string())
+    }
+
+    pub fn resume(&self) -> Result<(), String> {
+        self.control.send(EncoderControl::Resume).map_err(|_| "Native encoder is not running".to_string())
+    }
+
+    /// Finishes the current segment and waits for the encoder thread. Blocks the caller.
+    pub fn stop(mut self) -> Result<(), String> {
+        let _ = self.control.send(EncoderControl::Stop);
+        match self.thread.take() {
+            Some(thread) => thread.join().map_err(|_| "Native encoder thread panicked".to_string())?,
+            None => Ok(()),
+        }
+    }
+}
+
+pub fn start_native_screen_encoder(config: NativeEncoderConfig) -> Result<NativeEncoderHandle, String> {
+    ffmpeg::init().map_err(|e| e.to_string())?;
+
+    let (control_tx, control_rx) = mpsc::channel();
+    let (ready_tx, ready_rx) = mpsc::channel();
+
+    let thread = std::thread::Builder::new()
+        .name("native-screen-encoder".to_string())
+        .spawn(move || {
+            let backend = match MonitorCapture::new(config.screen_index) {
+                Ok(backend) => {
+                    let _ = ready_tx.send(Ok(()));
+                    backend
+                },
+                Err(e) => {
+                    let _ = ready_tx.send(Err(e.clone()));
+                    return Err(e);
+                },
+            };
+
+            let result = run_encoder(backend, &config, control_rx);
+            if let Err(ref e) = result {
+                error!("Native screen encoder failed: {}", e);
+            }
+            result
+        })
+        .map_err(|e| e.to_string())?;
+
+    ready_rx.recv().map_err(|_| "Native encoder thread exited during startup".to_string())??;
+    info!("Native screen encoder started");
+
+    Ok(NativeEncoderHandle { control: control_tx, thread: Some(thread) })
+}
+
+fn run_encoder<B: CaptureBackend>(
+    mut backend: B,
+    config: &NativeEncoderConfig,
+    control: Receiver<EncoderControl>,
+) -> Result<(), String> {
+    let (width, height) = backend.dimensions();
+    // libx264 with yuv420p requires even dimensions.
+    let (out_width, out_height) = (width & !1, height & !1);
+    let frame_interval = Duration::from_secs_f64(1.0 / config.framerate as f64);
+    let segment_frames = (config.segment_time_secs * config.framerate) as i64;
+
+    let mut scaler = scaling::Context::get(
+        Pixel::RGBA, width, height,
+        Pixel::YUV420P, out_width, out_height,
+        scaling::Flags::BILINEAR,
+    ).map_err(|e| e.to_string())?;
+
+    let mut segment_index = 0;
+    let mut segment = Segment::open(config, segment_index, out_width, out_height)?;
+    let mut segment_start_pts = 0;
+    let mut last_pts = -1;
+
+    let mut paused = false;
+    let mut active_elapsed = Duration::ZERO;
+    let mut resumed_at = Instant::now();
+
+    loop {
+        match control.try_recv() {
+            Ok(EncoderControl::Pause) if !paused => {
+                active_elapsed += resumed_at.elapsed();
+                paused = true;
+                debug!("Native encoder paused");
+            },
+            Ok(EncoderControl::Resume) if paused => {
+                resumed_at = Instant::now();
+                paused = false;
+                debug!("Native encoder resumed");
+            },
+            Ok(EncoderControl::Stop) | Err(TryRecvError::Disconnected) => break,
+            Ok(_) | Err(TryRecvError::Empty) => {},
+        }
+
+        if paused {
+            std::thread::sleep(frame_interval);
+            continue;
+        }
+
+        let frame_started = Instant::now();
+        let elapsed = active_elapsed + resumed_at.elapsed();
+        let pts = (elapsed.as_secs_f64() * config.framerate as f64).round() as i64;
+        if pts <= last_pts {
+            std::thread::sleep(frame_interval);
+            continue;
+        }
+
+        if pts - segment_start_pts >= segment_frames {
+            segment.finish()?;
+            segment_index += 1;
+            segment_start_pts = pts;
+            segment = Segment::open(config, segment_index, out_width, out_height)?;
+        }
+
+        let rgba = backend.capture_rgba()?;
+        let mut input = frame::Video::new(Pixel::RGBA, width, height);
+        copy_rgba_into_frame(&rgba, width, height, &mut input);
+
+        let mut output = frame::Video::empty();
+        scaler.run(&input, &mut output).map_err(|e| e.to_string())?;
+        output.set_pts(Some(pts - segment_start_pts));
+        segment.encode(&output)?;
+        last_pts = pts;
+
+        if let Some(remaining) = frame_interval.checked_sub(frame_started.elapsed()) {
+            std::thread::sleep(remaining);
+        }
+    }
+
+    segment.finish()?;
+    info!("Native screen encoder finished after {} segments", segment_index + 1);
+
+    Ok(())
+}
+
+fn copy_rgba_into_frame(rgba: &[u8], width: u32, height: u32, frame: &mut frame::Video) {
+    let row_bytes = width as usize * 4;
+    let stride = frame.stride(0);
+    let data = frame.data_mut(0);
+
+    for row in 0..height as usize {
+        let src = &rgba[row * row_bytes..(row + 1) * row_bytes];
+        data[row * stride..row * stride + row_bytes].copy_from_slice(src);
+    }
+}
+
+/// One mpegts segment file with its own encoder, so every segment starts on a
+/// keyframe and can be uploaded and decoded independently.
+struct Segment {
+    output: format::context::Output,
+    encoder: encoder::video::Encoder,
+    encoder_time_base: Rational,
+    stream_time_base: Rational,
+    file_name: String,
+    chunks_dir: PathBuf,
+}
+
+impl Segment {
+    fn open(config: &NativeEncoderConfig, index: usize, width: u32, height: u32) -> Result<Self, String> {
+        let file_name = segment_file_name(&config.segment_prefix, index, "ts");
+        let path = config.chunks_dir.join(&file_name);
+
+        let mut output = format::output_as(&path, "mpegts").map_err(|e| e.to_string())?;
+        let codec = encoder::find_by_name("libx264").ok_or("libx264 encoder is not available".to_string())?;
+        let global_header = output.format().flags().contains(format::Flags::GLOBAL_HEADER);
+
+        let encoder_time_base = Rational(1, config.framerate as i32);
+        let mut video = codec::context::Context::new_with_codec(codec)
+            .encoder()
+            .video()
+            .map_err(|e| e.to_string())?;
+        video.set_width(width);
+        video.set_height(height);
+        video.set_format(Pixel::YUV420P);
+        video.set_time_base(encoder_time_base);
+        video.set_frame_rate(Some(Rational(config.framerate as i32, 1)));
+        video.set_gop(config.framerate);
+        if global_header {
+            video.set_flags(codec::Flags::GLOBAL_HEADER);
+        }
+
+        let mut options = Dictionary::new();
+        options.set("preset", &config.preset);
+        options.set("crf", &config.crf);
+        let encoder = video.open_with(options).map_err(|e| e.to_string())?;
+
+        let mut stream = output.add_stream(codec).map_err(|e| e.to_string())?;
+        stream.set_parameters(&encoder);
+        output.write_header().map_err(|e| e.to_string())?;
+        let stream_time_base = output.stream(0).ok_or("Output stream missing".to_string())?.time_base();
+
+        Ok(Segment {
+            output,
+            encoder,
+            encoder_time_base,
+            stream_time_base,
+            file_name,
+            chunks_dir: config.chunks_dir.clone(),
+        })
+    }
+
+    fn encode(&mut self, frame: &frame::Video) -> Result<(), String> {
+        self.encoder.send_frame(frame).map_err(|e| e.to_string())?;
+        self.write_packets()
+    }
+
+    fn write_packets(&mut self) -> Result<(), String> {
+        let mut packet = Packet::empty();
+        while self.encoder.receive_packet(&mut packet).is_ok() {
+            packet.set_stream(0);
+            packet.rescale_ts(self.encoder_time_base, self.stream_time_base);
+            packet.write_interleaved(&mut self.output).map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+
+    /// Flushes the encoder, closes the file and only then lists it in
+    /// `segment_list.txt`, matching how ffmpeg's segment muxer announces segments.
+    fn finish(mut self) -> Result<(), String> {
+        self.encoder.send_eof().map_err(|e| e.to_string())?;
+        self.write_packets()?;
+        self.output.write_trailer().map_err(|e| e.to_string())?;
+        append_to_segment_list(&self.chunks_dir, &self.file_name)
+    }
+}
+
+fn append_to_segment_list(chunks_dir: &Path, file_name: &str) -> Result<(), String> {
+    let mut segment_list = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(chunks_dir.join("segment_list.txt"))
+        .map_err(|e| e.to_string())?;
+    writeln!(segment_list, "{}", file_name).map_err(|e| e.to_string())
+}
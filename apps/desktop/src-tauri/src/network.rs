@@ -0,0 +1,282 @@
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+use serde::Serialize;
+use tauri::{AppHandle, Manager};
+use tracing::{debug, info};
+
+/// Emitted with an `UploadPauseState` whenever uploads are paused or resumed.
+pub const UPLOAD_PAUSE_EVENT: &str = "uploads://pause-state";
+
+/// Emitted with an `UploadBacklog` whenever connectivity or the number of queued
+/// uploads changes.
+pub const UPLOAD_BACKLOG_EVENT: &str = "uploads://backlog";
+
+const METERED_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+const BACKLOG_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_PROBE_BACKOFF: Duration = Duration::from_secs(30);
+#[cfg(target_os = "windows")]
+const CREATE_NO_WINDOW: u32 = 0x08000000;
+/// Weight of the newest upload in the throughput average.
+const THROUGHPUT_SMOOTHING: f64 = 0.3;
+
+static PAUSED_MANUALLY: AtomicBool = AtomicBool::new(false);
+static PAUSE_ON_METERED: AtomicBool = AtomicBool::new(true);
+static METERED: AtomicBool = AtomicBool::new(false);
+
+static ONLINE: AtomicBool = AtomicBool::new(true);
+static PROBING: AtomicBool = AtomicBool::new(false);
+static PENDING_UPLOADS: AtomicUsize = AtomicUsize::new(0);
+static PENDING_BYTES: AtomicU64 = AtomicU64::new(0);
+/// Moving average of upload throughput in bytes per second, once anything was uploaded.
+static THROUGHPUT: Mutex<Option<f64>> = Mutex::new(None);
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+pub struct UploadPauseState {
+    pub paused_manually: bool,
+    pub metered: bool,
+    /// Whether the upload loops are currently holding back new segments.
+    pub paused: bool,
+}
+
+pub fn upload_pause_state() -> UploadPauseState {
+    let paused_manually = PAUSED_MANUALLY.load(Ordering::SeqCst);
+    let metered = METERED.load(Ordering::SeqCst);
+    UploadPauseState {
+        paused_manually,
+        metered,
+        paused: paused_manually || (metered && PAUSE_ON_METERED.load(Ordering::SeqCst)),
+    }
+}
+
+/// Checked by the upload loops before starting each segment upload. Recording
+/// carries on locally while uploads are held back.
+pub fn uploads_allowed() -> bool {
+    !upload_pause_state().paused
+}
+
+pub fn set_pause_on_metered(enabled: bool) {
+    PAUSE_ON_METERED.store(enabled, Ordering::SeqCst);
+}
+
+#[tauri::command]
+pub fn pause_uploads(app: AppHandle) -> UploadPauseState {
+    info!("Uploads paused");
+    PAUSED_MANUALLY.store(true, Ordering::SeqCst);
+    emit_pause_state(&app)
+}
+
+#[tauri::command]
+pub fn resume_uploads(app: AppHandle) -> UploadPauseState {
+    info!("Uploads resumed");
+    PAUSED_MANUALLY.store(false, Ordering::SeqCst);
+    emit_pause_state(&app)
+}
+
+#[tauri::command]
+pub fn get_upload_pause_state() -> UploadPauseState {
+    upload_pause_state()
+}
+
+#[tauri::command]
+pub fn get_upload_backlog() -> UploadBacklog {
+    upload_backlog()
+}
+
+fn emit_pause_state(app: &AppHandle) -> UploadPauseState {
+    let state = upload_pause_state();
+    let _ = app.emit_all(UPLOAD_PAUSE_EVENT, state);
+    state
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+pub struct UploadBacklog {
+    pub online: bool,
+    /// Segments queued or in flight across all recordings.
+    pub pending_uploads: usize,
+    pub pending_bytes: u64,
+    pub throughput_bytes_per_sec: Option<f64>,
+    /// Time to drain the backlog at the measured throughput.
+    pub estimated_secs: Option<f64>,
+}
+
+pub fn upload_backlog() -> UploadBacklog {
+    let pending_bytes = PENDING_BYTES.load(Ordering::SeqCst);
+    let throughput_bytes_per_sec = THROUGHPUT.lock().ok().and_then(|throughput| *throughput);
+    UploadBacklog {
+        online: ONLINE.load(Ordering::SeqCst),
+        pending_uploads: PENDING_UPLOADS.load(Ordering::SeqCst),
+        pending_bytes,
+        throughput_bytes_per_sec,
+        estimated_secs: throughput_bytes_per_sec
+            .filter(|throughput| *throughput > 0.0)
+            .map(|throughput| pending_bytes as f64 / throughput),
+    }
+}
+
+/// Counts a segment as part of the upload backlog for as long as it is alive. Created
+/// before the upload task is spawned and moved into it, so aborted tasks are
+/// accounted for too.
+pub struct PendingUpload {
+    bytes: u64,
+}
+
+impl PendingUpload {
+    pub fn new(bytes: u64) -> Self {
+        PENDING_UPLOADS.fetch_add(1, Ordering::SeqCst);
+        PENDING_BYTES.fetch_add(bytes, Ordering::SeqCst);
+        PendingUpload { bytes }
+    }
+}
+
+impl Drop for PendingUpload {
+    fn drop(&mut self) {
+        PENDING_UPLOADS.fetch_sub(1, Ordering::SeqCst);
+        PENDING_BYTES.fetch_sub(self.bytes, Ordering::SeqCst);
+    }
+}
+
+pub fn record_upload_throughput(bytes: u64, elapsed: Duration) {
+    if bytes == 0 || elapsed.is_zero() {
+        return;
+    }
+    let sample = bytes as f64 / elapsed.as_secs_f64();
+    if let Ok(mut throughput) = THROUGHPUT.lock() {
+        *throughput = Some(match *throughput {
+            Some(average) => average + THROUGHPUT_SMOOTHING * (sample - average),
+            None => sample,
+        });
+    }
+}
+
+/// Called when an upload couldn't reach the server at all.
+pub fn set_offline() {
+    if ONLINE.swap(false, Ordering::SeqCst) {
+        info!("Connection lost, holding uploads until it is back");
+    }
+}
+
+/// Returns once the server is reachable again. Only one caller probes at a time, with
+/// backoff; the other queued uploads wait for its result instead of each timing out.
+pub async fn wait_until_online() {
+    while !ONLINE.load(Ordering::SeqCst) {
+        if PROBING.compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst).is_err() {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            continue;
+        }
+
+        let _probing = ProbeGuard;
+        let mut backoff = Duration::from_secs(1);
+        while !probe_server().await {
+            debug!("Server still unreachable, probing again in {:?}", backoff);
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(MAX_PROBE_BACKOFF);
+        }
+        info!("Connection is back, resuming uploads");
+        ONLINE.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Returns once an upload has found the server unreachable.
+pub async fn wait_until_offline() {
+    while ONLINE.load(Ordering::SeqCst) {
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+}
+
+/// Hands probing over to another waiting upload when the probing one is done, also
+/// when its task is aborted mid-probe.
+struct ProbeGuard;
+
+impl Drop for ProbeGuard {
+    fn drop(&mut self) {
+        PROBING.store(false, Ordering::SeqCst);
+    }
+}
+
+/// Any response counts; only a failure to connect means offline.
+async fn probe_server() -> bool {
+    let server_url: &'static str = dotenv_codegen::dotenv!("NEXT_PUBLIC_URL");
+    let client = match reqwest::Client::builder().timeout(PROBE_TIMEOUT).build() {
+        Ok(client) => client,
+        Err(_) => return false,
+    };
+    client.head(server_url).send().await.is_ok()
+}
+
+/// Emits the upload backlog whenever it changes.
+pub fn spawn_backlog_monitor(app: AppHandle) {
+    tauri::async_runtime::spawn(async move {
+        let mut last: Option<UploadBacklog> = None;
+        loop {
+            let backlog = upload_backlog();
+            let changed = last.map_or(true, |last| {
+                last.online != backlog.online
+                    || last.pending_uploads != backlog.pending_uploads
+                    || last.pending_bytes != backlog.pending_bytes
+            });
+            if changed {
+                let _ = app.emit_all(UPLOAD_BACKLOG_EVENT, backlog);
+                last = Some(backlog);
+            }
+            tokio::time::sleep(BACKLOG_CHECK_INTERVAL).await;
+        }
+    });
+}
+
+/// Polls whether the current connection is metered and emits the pause state when it
+/// changes. Detection is supported on Windows and on Linux with NetworkManager. macOS
+/// has no way to ask whether a connection is metered, so there, as everywhere else,
+/// the connection is treated as unmetered and only the manual pause applies.
+pub fn spawn_metered_monitor(app: AppHandle) {
+    tauri::async_runtime::spawn(async move {
+        loop {
+            let metered = tokio::task::spawn_blocking(is_metered_connection).await.unwrap_or(false);
+            if METERED.swap(metered, Ordering::SeqCst) != metered {
+                info!("Connection is {}metered", if metered { "" } else { "no longer " });
+                emit_pause_state(&app);
+            }
+            tokio::time::sleep(METERED_CHECK_INTERVAL).await;
+        }
+    });
+}
+
+fn is_metered_connection() -> bool {
+    let (program, args): (&str, &[&str]) = match std::env::consts::OS {
+        "windows" => ("powershell", &[
+            "-NoProfile",
+            "-Command",
+            "[Windows.Networking.Connectivity.NetworkInformation,Windows.Networking.Connectivity,ContentType=WindowsRuntime]::GetInternetConnectionProfile().GetConnectionCost().NetworkCostType",
+        ]),
+        "linux" => ("nmcli", &["-t", "-f", "GENERAL.METERED", "device", "show"]),
+        _ => return false,
+    };
+
+    let mut command = std::process::Command::new(program);
+    command.args(args);
+    // The check runs every 30 seconds, which would flash a console window each time.
+    #[cfg(target_os = "windows")]
+    {
+        use std::os::windows::process::CommandExt;
+        command.creation_flags(CREATE_NO_WINDOW);
+    }
+
+    // Not `run_command`, which panics when the tool isn't installed.
+    let output = match command.output() {
+        Ok(output) if output.status.success() => String::from_utf8_lossy(&output.stdout).into_owned(),
+        Ok(output) => {
+            debug!("Could not detect a metered connection: {} exited with {}", program, output.status);
+            return false;
+        },
+        Err(e) => {
+            debug!("Could not detect a metered connection: {}", e);
+            return false;
+        },
+    };
+
+    match std::env::consts::OS {
+        "windows" => matches!(output.trim(), "Fixed" | "Variable"),
+        _ => output.lines().any(|line| line.trim().starts_with("GENERAL.METERED:yes")),
+    }
+}
@@ -0,0 +1,24 @@
+use tauri::api::notification::Notification;
+use tauri::AppHandle;
+use tracing::warn;
+
+use crate::manifest::RecordingStatus;
+use crate::utils::share_link;
+
+/// Tells the user how an upload ended, even when the window is closed.
+pub fn notify_upload_finished(app: &AppHandle, video_id: &str, status: RecordingStatus) {
+    let (title, body) = match status {
+        RecordingStatus::Complete => ("Recording uploaded", format!("Your recording is ready to share: {}", share_link(video_id))),
+        RecordingStatus::Failed => ("Upload failed", "Some parts of your recording could not be uploaded. Open Cap to retry the upload.".to_string()),
+        _ => return,
+    };
+
+    let result = Notification::new(&app.config().tauri.bundle.identifier)
+        .title(title)
+        .body(body)
+        .show();
+
+    if let Err(e) = result {
+        warn!("Failed to show notification: {}", e);
+    }
+}
@@ -0,0 +1,286 @@
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use tauri::State;
+use tokio::sync::Mutex;
+
+use crate::audio::{input_device, validate_audio_format};
+use crate::devices::list_devices;
+use crate::recording::{CaptureMode, RecordingOptions};
+use crate::settings::Settings;
+
+const MAX_FRAMERATE: u32 = 120;
+const MAX_WIDTH: u32 = 7680;
+const MAX_HEIGHT: u32 = 4320;
+const MAX_SEGMENT_TIME_SECS: u32 = 600;
+const MAX_START_DELAY_SECS: u32 = 3600;
+const MAX_AUDIO_OFFSET_MS: i32 = 5000;
+
+/// A capture device, picked either by its ffmpeg index or by name (or path, for v4l2
+/// cameras given as `/dev/videoN`). Plain numbers always mean an index.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeviceId {
+    Index(u32),
+    Name(String),
+}
+
+impl fmt::Display for DeviceId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DeviceId::Index(index) => write!(f, "{}", index),
+            DeviceId::Name(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+impl FromStr for DeviceId {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Err("Device can't be empty".to_string());
+        }
+        Ok(s.parse().map(DeviceId::Index).unwrap_or_else(|_| DeviceId::Name(s.to_string())))
+    }
+}
+
+impl Serialize for DeviceId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            DeviceId::Index(index) => serializer.serialize_u32(*index),
+            DeviceId::Name(name) => serializer.serialize_str(name),
+        }
+    }
+}
+
+/// Output size, written as `1920x1080`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Resolution {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl fmt::Display for Resolution {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}x{}", self.width, self.height)
+    }
+}
+
+impl FromStr for Resolution {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid resolution: {}", s);
+        let (width, height) = s.trim().split_once(|c| c == 'x' || c == 'X').ok_or_else(invalid)?;
+        Ok(Resolution {
+            width: width.trim().parse().map_err(|_| invalid())?,
+            height: height.trim().parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+impl Serialize for Resolution {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawValue {
+    Number(u64),
+    Text(String),
+}
+
+/// Reads an optional typed value given as a number or a string. Empty strings and
+/// `null` mean unset, so options saved while these fields were plain strings still load.
+pub fn empty_as_none<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    let text = match Option::<RawValue>::deserialize(deserializer)? {
+        None => return Ok(None),
+        Some(RawValue::Number(number)) => number.to_string(),
+        Some(RawValue::Text(text)) => text,
+    };
+    if text.trim().is_empty() {
+        return Ok(None);
+    }
+    text.trim().parse().map(Some).map_err(serde::de::Error::custom)
+}
+
+/// Parses an optional value from a CLI flag or deep link parameter; empty means unset.
+pub fn parse_optional<T>(value: &str) -> Result<Option<T>, String>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    if value.trim().is_empty() {
+        return Ok(None);
+    }
+    value.trim().parse().map(Some).map_err(|e| e.to_string())
+}
+
+pub fn validate_framerate(framerate: u32) -> Result<(), String> {
+    if framerate == 0 || framerate > MAX_FRAMERATE {
+        return Err(format!("Framerate must be between 1 and {}", MAX_FRAMERATE));
+    }
+    Ok(())
+}
+
+pub fn validate_resolution(resolution: Resolution) -> Result<(), String> {
+    if resolution.width == 0 || resolution.height == 0 || resolution.width > MAX_WIDTH || resolution.height > MAX_HEIGHT {
+        return Err(format!("Resolution must be at most {}x{}", MAX_WIDTH, MAX_HEIGHT));
+    }
+    // x264 only encodes even dimensions.
+    if resolution.width % 2 != 0 || resolution.height % 2 != 0 {
+        return Err("Resolution must have an even width and height".to_string());
+    }
+    Ok(())
+}
+
+/// A problem with one option, named as in `RecordingOptions`.
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+impl FieldError {
+    fn new(field: &str, message: impl Into<String>) -> Self {
+        FieldError { field: field.to_string(), message: message.into() }
+    }
+}
+
+impl fmt::Display for FieldError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)
+    }
+}
+
+/// Checks ranges and combinations without touching any device.
+pub fn validate_options(options: &RecordingOptions) -> Vec<FieldError> {
+    let mut errors = vec![];
+
+    if let Some(framerate) = options.framerate {
+        if let Err(e) = validate_framerate(framerate) {
+            errors.push(FieldError::new("framerate", e));
+        }
+    }
+    if let Some(resolution) = options.resolution {
+        if let Err(e) = validate_resolution(resolution) {
+            errors.push(FieldError::new("resolution", e));
+        }
+    }
+    if options.segment_time_secs > MAX_SEGMENT_TIME_SECS {
+        errors.push(FieldError::new("segment_time_secs", format!("Segments can be at most {} seconds", MAX_SEGMENT_TIME_SECS)));
+    }
+    if let Err(e) = validate_audio_offset(options.audio_offset_ms) {
+        errors.push(FieldError::new("audio_offset_ms", e));
+    }
+    if let Err(e) = options.extra_ffmpeg_args.validate() {
+        errors.push(FieldError::new("extra_ffmpeg_args", e));
+    }
+    if options.start_delay_secs > MAX_START_DELAY_SECS {
+        errors.push(FieldError::new("start_delay_secs", format!("The start delay can be at most {} seconds", MAX_START_DELAY_SECS)));
+    }
+    if options.capture_mode == CaptureMode::Camera && options.muxed_audio.unwrap_or_default() {
+        errors.push(FieldError::new("muxed_audio", "Muxed capture only records the screen"));
+    }
+    if options.capture_mode == CaptureMode::Camera && options.live_drawing.unwrap_or_default() {
+        errors.push(FieldError::new("live_drawing", "Live drawing is only recorded over the screen"));
+    }
+    if options.capture_mode == CaptureMode::Camera && options.video_index.is_none() {
+        errors.push(FieldError::new("video_index", "Camera-only recording requires a camera"));
+    }
+    if let Err(e) = validate_audio_format(&options.audio_codec, options.audio_sample_rate, options.audio_channels, &options.audio_bitrate) {
+        errors.push(FieldError::new("audio_codec", e));
+    }
+    if let Err(e) = options.storage.validate() {
+        errors.push(FieldError::new("storage", e));
+    }
+
+    errors
+}
+
+pub fn validate_audio_offset(offset_ms: i32) -> Result<(), String> {
+    if offset_ms.abs() > MAX_AUDIO_OFFSET_MS {
+        return Err(format!("The audio offset can be at most {} ms either way", MAX_AUDIO_OFFSET_MS));
+    }
+    Ok(())
+}
+
+/// Checks that the selected display, camera and microphone are present.
+fn check_devices(options: &RecordingOptions) -> Vec<FieldError> {
+    let mut errors = vec![];
+
+    // Linux displays are X display names and can't be listed up front.
+    let video_devices = match std::env::consts::OS {
+        "macos" | "windows" => match list_devices() {
+            Ok(devices) => Some(devices.video_devices),
+            Err(e) => {
+                errors.push(FieldError::new("video_index", format!("Could not list devices: {}", e)));
+                None
+            },
+        },
+        _ => None,
+    };
+
+    let mut check = |field: &str, device: &DeviceId| {
+        let found = match (std::env::consts::OS, device, &video_devices) {
+            ("linux", DeviceId::Index(index), _) => Path::new(&format!("/dev/video{}", index)).exists(),
+            ("linux", DeviceId::Name(path), _) => Path::new(path).exists(),
+            (_, DeviceId::Index(index), Some(devices)) => (*index as usize) < devices.len(),
+            (_, DeviceId::Name(name), Some(devices)) => devices.iter().any(|device| device == name),
+            _ => true,
+        };
+        if !found {
+            errors.push(FieldError::new(field, format!("Device {} was not found", device)));
+        }
+    };
+
+    if std::env::consts::OS == "macos" {
+        if let Some(screen) = &options.screen_index {
+            check("screen_index", screen);
+        }
+    }
+    if let Some(camera) = &options.video_index {
+        check("video_index", camera);
+    }
+
+    if !options.audio_name.is_empty() {
+        if let Err(e) = input_device(Some(&options.audio_name)) {
+            errors.push(FieldError::new("audio_name", e));
+        }
+    }
+
+    errors
+}
+
+/// Validates `options` as `start_recording` would see them, with the saved settings
+/// filled in, and also checks that the devices exist. Returns every problem found so
+/// the frontend can mark each field.
+#[tauri::command]
+pub async fn validate_recording_options(
+    settings: State<'_, Arc<Mutex<Settings>>>,
+    mut options: RecordingOptions,
+) -> Result<(), Vec<FieldError>> {
+    settings.lock().await.apply_to(&mut options);
+
+    let mut errors = validate_options(&options);
+    let device_errors = tokio::task::spawn_blocking(move || check_devices(&options))
+        .await
+        .unwrap_or_else(|e| vec![FieldError::new("video_index", e.to_string())]);
+    errors.extend(device_errors);
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
@@ -0,0 +1,85 @@
+use std::process::Command;
+#[cfg(unix)]
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+use crate::journal::UncleanSession;
+
+/// How long an orphan gets to exit after being asked before it is killed.
+#[cfg(unix)]
+const TERMINATE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Terminates the ffmpeg processes left running by sessions that crashed. An orphaned
+/// capture process keeps the camera or microphone in use until it is stopped. A pid
+/// from the journal is only signalled while it still belongs to an ffmpeg, since the
+/// system may have reused it for another process.
+pub fn kill_orphaned_ffmpeg(sessions: &[UncleanSession]) {
+    for session in sessions {
+        for pid in session.ffmpeg_pids.iter().copied().filter(|pid| is_ffmpeg(*pid)) {
+            warn!("Terminating FFmpeg {} left running by recording {}", pid, session.video_id);
+            if let Err(e) = terminate(pid) {
+                warn!("Failed to terminate FFmpeg {}: {}", pid, e);
+                continue;
+            }
+            info!("Terminated orphaned FFmpeg {}", pid);
+        }
+    }
+}
+
+#[cfg(unix)]
+fn is_ffmpeg(pid: u32) -> bool {
+    Command::new("ps")
+        .args(["-o", "comm=", "-p", &pid.to_string()])
+        .output()
+        .map_or(false, |output| String::from_utf8_lossy(&output.stdout).to_lowercase().contains("ffmpeg"))
+}
+
+#[cfg(windows)]
+fn is_ffmpeg(pid: u32) -> bool {
+    Command::new("tasklist")
+        .args(["/FI", &format!("PID eq {}", pid), "/FO", "CSV", "/NH"])
+        .output()
+        .map_or(false, |output| String::from_utf8_lossy(&output.stdout).to_lowercase().contains("ffmpeg"))
+}
+
+/// Asks the process to quit, so ffmpeg closes the devices and its open segment, and
+/// kills it if it is still running after `TERMINATE_TIMEOUT`.
+#[cfg(unix)]
+fn terminate(pid: u32) -> Result<(), String> {
+    signal(pid, "-TERM")?;
+    let deadline = Instant::now() + TERMINATE_TIMEOUT;
+    while Instant::now() < deadline {
+        if !is_ffmpeg(pid) {
+            return Ok(());
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    signal(pid, "-KILL")
+}
+
+#[cfg(unix)]
+fn signal(pid: u32, signal: &str) -> Result<(), String> {
+    let status = Command::new("kill")
+        .args([signal, &pid.to_string()])
+        .status()
+        .map_err(|e| e.to_string())?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("kill exited with {}", status))
+    }
+}
+
+/// Windows has no signal an orphaned ffmpeg would act on, so it is killed outright.
+#[cfg(windows)]
+fn terminate(pid: u32) -> Result<(), String> {
+    let status = Command::new("taskkill")
+        .args(["/PID", &pid.to_string(), "/T", "/F"])
+        .status()
+        .map_err(|e| e.to_string())?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("taskkill exited with {}", status))
+    }
+}
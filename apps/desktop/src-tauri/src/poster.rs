@@ -0,0 +1,90 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tauri::State;
+use tokio::process::Command;
+use tokio::sync::Mutex;
+use tracing::info;
+
+use crate::manifest::{recording_dir, update_manifest, RecordingManifest};
+use crate::session::RecordingState;
+use crate::upload::upload_and_remove_file;
+use crate::upload_loop::load_segment_list;
+use crate::utils::ffmpeg_path;
+
+/// The thumbnail in the recording directory. It keeps the name of the screenshot taken
+/// when recording starts, so a chosen frame replaces that upload on the server.
+pub const POSTER_FILE: &str = "screen-capture.jpg";
+
+/// Extracts the frame at `timestamp_ms` of recorded time, pauses excluded, from the
+/// local screen segments and uploads it as the recording's thumbnail. Returns the
+/// storage key. The segment holding that frame has to still be on disk.
+#[tauri::command]
+pub async fn set_poster_frame(
+    state: State<'_, Arc<Mutex<RecordingState>>>,
+    video_id: String,
+    timestamp_ms: u64,
+) -> Result<String, String> {
+    let data_dir = state.lock().await.data_dir.clone()
+        .ok_or("Data directory is not set in the recording state".to_string())?;
+    let recording_dir = recording_dir(&data_dir, &video_id)?;
+    let manifest = RecordingManifest::load(&recording_dir)?;
+
+    let timestamp = timestamp_ms as f64 / 1000.0;
+    let (segment_path, offset) = find_frame(&recording_dir, &manifest, timestamp)?;
+    let poster_path = recording_dir.join(POSTER_FILE);
+    extract_frame(&segment_path, offset, &poster_path).await?;
+    info!("Extracted the poster frame of {} at {:.3}s", video_id, timestamp);
+
+    let file_key = upload_and_remove_file(&manifest.options, &poster_path, "screenshot").await?;
+    update_manifest(&recording_dir, |manifest| {
+        manifest.screenshot_key = Some(file_key.clone());
+        manifest.poster_time_ms = Some(timestamp_ms);
+    })?;
+
+    Ok(file_key)
+}
+
+/// The screen segment holding `timestamp` and the offset of the frame within it.
+/// Segments without a probed duration are assumed to have the nominal length.
+fn find_frame(recording_dir: &Path, manifest: &RecordingManifest, timestamp: f64) -> Result<(PathBuf, f64), String> {
+    let chunks_dir = recording_dir.join("screen");
+    let segments = load_segment_list(&chunks_dir.join("segment_list.txt")).unwrap_or_default();
+    let durations = manifest.segment_durations.get("screen");
+    let segment_time_secs = manifest.options.segment_time_secs as f64;
+
+    let mut start = 0.0;
+    for segment in &segments {
+        let duration = durations.and_then(|durations| durations.get(segment)).copied().unwrap_or(segment_time_secs);
+        if timestamp < start + duration {
+            let segment_path = chunks_dir.join(segment);
+            if !segment_path.is_file() {
+                return Err(format!("Segment {} is no longer on disk; keep segments with chunk_retention to pick a poster frame", segment));
+            }
+            return Ok((segment_path, timestamp - start));
+        }
+        start += duration;
+    }
+
+    Err(format!("{:.3}s is past the end of the recording ({:.3}s)", timestamp, start))
+}
+
+async fn extract_frame(segment_path: &Path, offset: f64, poster_path: &Path) -> Result<(), String> {
+    // ffmpeg writes nothing when the offset is past the last frame, so a stale
+    // thumbnail left behind must not pass for the new one.
+    let _ = std::fs::remove_file(poster_path);
+    let output = Command::new(ffmpeg_path()?)
+        .args(["-hide_banner", "-y", "-ss", &format!("{:.3}", offset)])
+        .arg("-i")
+        .arg(segment_path)
+        .args(["-frames:v", "1", "-q:v", "2"])
+        .arg(poster_path)
+        .output()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if !output.status.success() || !poster_path.is_file() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("Failed to extract the poster frame: {}", stderr.lines().last().unwrap_or_default()));
+    }
+    Ok(())
+}
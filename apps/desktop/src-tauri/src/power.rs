@@ -0,0 +1,47 @@
+use std::sync::Mutex;
+use keepawake::KeepAwake;
+use tracing::{info, warn};
+
+/// The OS assertion and how many recordings hold it; a split briefly has two.
+static ASSERTION: Mutex<(usize, Option<KeepAwake>)> = Mutex::new((0, None));
+
+/// Keeps the system and display awake while alive. A laptop that sleeps mid-recording
+/// suspends ffmpeg and the capture silently stops.
+pub struct WakeLock(());
+
+impl WakeLock {
+    pub fn acquire() -> WakeLock {
+        if let Ok(mut assertion) = ASSERTION.lock() {
+            assertion.0 += 1;
+            if assertion.1.is_none() {
+                let keep_awake = keepawake::Builder::default()
+                    .display(true)
+                    .idle(true)
+                    .sleep(true)
+                    .reason("Recording")
+                    .app_name("Cap")
+                    .app_reverse_domain("so.cap.desktop")
+                    .create();
+                match keep_awake {
+                    Ok(keep_awake) => {
+                        info!("Preventing sleep while recording");
+                        assertion.1 = Some(keep_awake);
+                    },
+                    Err(e) => warn!("Failed to prevent sleep while recording: {}", e),
+                }
+            }
+        }
+        WakeLock(())
+    }
+}
+
+impl Drop for WakeLock {
+    fn drop(&mut self) {
+        if let Ok(mut assertion) = ASSERTION.lock() {
+            assertion.0 = assertion.0.saturating_sub(1);
+            if assertion.0 == 0 && assertion.1.take().is_some() {
+                info!("Allowing sleep again");
+            }
+        }
+    }
+}
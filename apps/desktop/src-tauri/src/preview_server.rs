@@ -0,0 +1,251 @@
+use std::convert::Infallible;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{header, Body, Method, Request, Response, Server, StatusCode};
+use serde::Serialize;
+use tauri::{AppHandle, Manager};
+use tokio::sync::Mutex;
+use tracing::{error, info};
+
+use crate::control_api::{generate_token, tokens_match};
+use crate::manifest::{recording_dir, segment_number, sort_segments, RecordingManifest};
+use crate::session::RecordingState;
+
+/// Segment length assumed when neither the options nor a capture change give one.
+const DEFAULT_SEGMENT_SECS: f64 = 3.0;
+
+/// The running server's address and the token every path starts with.
+static SERVER: std::sync::Mutex<Option<(SocketAddr, Arc<String>)>> = std::sync::Mutex::new(None);
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PreviewStream {
+    /// HLS master playlist of the recording.
+    pub url: String,
+}
+
+/// Serves the recording `video_id` as an HLS stream made from its local segments, so
+/// the preview player can scrub through what has been captured before any of it is
+/// uploaded. The playlist grows as segments are closed and ends once the recording
+/// stops. Starts the server on a free localhost port the first time; its URLs carry a
+/// random token, since any local page could reach the port. Audio is included when it
+/// was recorded as AAC. Segments already removed after upload can't be played.
+#[tauri::command]
+pub async fn get_preview_stream(app: AppHandle, video_id: String) -> Result<PreviewStream, String> {
+    let data_dir = app.state::<Arc<Mutex<RecordingState>>>().lock().await.data_dir.clone().unwrap_or_default();
+    if !recording_dir(&data_dir, &video_id)?.join("screen").is_dir() {
+        return Err(format!("Recording {} has no local segments", video_id));
+    }
+
+    let (address, token) = ensure_server(&app)?;
+    Ok(PreviewStream { url: format!("http://{}/{}/{}/master.m3u8", address, token, video_id) })
+}
+
+fn ensure_server(app: &AppHandle) -> Result<(SocketAddr, Arc<String>), String> {
+    let mut server = SERVER.lock().map_err(|e| e.to_string())?;
+    if let Some(running) = server.as_ref() {
+        return Ok(running.clone());
+    }
+
+    let token = Arc::new(generate_token()?);
+    let app = app.clone();
+    let service_token = token.clone();
+    let make_service = make_service_fn(move |_connection| {
+        let app = app.clone();
+        let token = service_token.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| handle_request(app.clone(), token.clone(), request)))
+        }
+    });
+
+    let bound = Server::try_bind(&SocketAddr::from(([127, 0, 0, 1], 0)))
+        .map_err(|e| format!("Failed to start the preview server: {}", e))?
+        .serve(make_service);
+    let address = bound.local_addr();
+    info!("Preview server listening on {}", address);
+    tauri::async_runtime::spawn(async move {
+        if let Err(e) = bound.await {
+            error!("Preview server failed: {}", e);
+        }
+        if let Ok(mut server) = SERVER.lock() {
+            *server = None;
+        }
+    });
+
+    *server = Some((address, token.clone()));
+    Ok((address, token))
+}
+
+async fn handle_request(app: AppHandle, token: Arc<String>, request: Request<Body>) -> Result<Response<Body>, Infallible> {
+    if request.method() != Method::GET {
+        return Ok(error_response(StatusCode::METHOD_NOT_ALLOWED));
+    }
+    let path: Vec<&str> = request.uri().path().trim_start_matches('/').split('/').collect();
+    let (video_id, resource) = match path.as_slice() {
+        [provided, video_id, resource @ ..] if tokens_match(provided, &token) => (*video_id, resource),
+        _ => return Ok(error_response(StatusCode::NOT_FOUND)),
+    };
+
+    let state = app.state::<Arc<Mutex<RecordingState>>>().inner().clone();
+    let (data_dir, is_live) = {
+        let guard = state.lock().await;
+        let is_live = guard.is_recording()
+            && guard.recording_options.as_ref().map_or(false, |options| options.video_id == video_id);
+        (guard.data_dir.clone().unwrap_or_default(), is_live)
+    };
+    let recording_dir = match recording_dir(&data_dir, video_id) {
+        Ok(recording_dir) => recording_dir,
+        Err(_) => return Ok(error_response(StatusCode::NOT_FOUND)),
+    };
+    let manifest = match RecordingManifest::load(&recording_dir) {
+        Ok(manifest) => manifest,
+        Err(_) => return Ok(error_response(StatusCode::NOT_FOUND)),
+    };
+
+    let response = match resource {
+        ["master.m3u8"] => playlist_response(master_playlist(&recording_dir, &manifest)),
+        ["screen.m3u8"] => playlist_response(media_playlist(&recording_dir, &manifest, "screen", is_live)),
+        ["audio.m3u8"] if has_audio(&recording_dir, &manifest) => {
+            playlist_response(media_playlist(&recording_dir, &manifest, "audio", is_live))
+        },
+        [video_type @ ("screen" | "audio"), segment] => segment_response(&recording_dir, video_type, segment).await,
+        _ => error_response(StatusCode::NOT_FOUND),
+    };
+    Ok(response)
+}
+
+/// Closed segments of `video_type`, in recording order.
+fn listed_segments(recording_dir: &Path, video_type: &str) -> Vec<String> {
+    let segment_list = std::fs::read_to_string(recording_dir.join(video_type).join("segment_list.txt")).unwrap_or_default();
+    let mut segments: Vec<String> = segment_list.lines().filter(|line| !line.is_empty()).map(str::to_string).collect();
+    sort_segments(&mut segments);
+    segments
+}
+
+/// ADTS audio plays in HLS; the Opus track, in WebM, doesn't.
+fn has_audio(recording_dir: &Path, manifest: &RecordingManifest) -> bool {
+    manifest.options.audio_codec != "opus" && !listed_segments(recording_dir, "audio").is_empty()
+}
+
+/// Segment durations: measured for uploaded segments, otherwise the segment time the
+/// segment was recorded with.
+fn segment_durations(recording_dir: &Path, manifest: &RecordingManifest, video_type: &str) -> Vec<(String, f64)> {
+    let measured = manifest.segment_durations.get(video_type);
+    let segment_time_secs = match manifest.options.segment_time_secs {
+        0 => DEFAULT_SEGMENT_SECS,
+        secs => secs as f64,
+    };
+
+    listed_segments(recording_dir, video_type)
+        .into_iter()
+        .map(|segment| {
+            let nominal = match video_type {
+                "screen" => {
+                    let number = segment_number(&segment).unwrap_or(0);
+                    manifest.capture_changes.iter()
+                        .filter(|change| change.from_segment <= number)
+                        .last()
+                        .map_or(segment_time_secs, |change| change.segment_time_secs as f64)
+                },
+                _ => segment_time_secs,
+            };
+            let duration = measured.and_then(|measured| measured.get(&segment)).copied().unwrap_or(nominal);
+            (segment, duration)
+        })
+        .collect()
+}
+
+fn master_playlist(recording_dir: &Path, manifest: &RecordingManifest) -> String {
+    let with_audio = has_audio(recording_dir, manifest);
+    let (bytes, secs) = ["screen", "audio"]
+        .iter()
+        .filter(|video_type| **video_type == "screen" || with_audio)
+        .flat_map(|video_type| {
+            segment_durations(recording_dir, manifest, video_type)
+                .into_iter()
+                .map(move |(segment, duration)| (recording_dir.join(video_type).join(segment), duration))
+        })
+        .fold((0u64, 0.0f64), |(bytes, secs), (path, duration)| {
+            (bytes + path.metadata().map_or(0, |metadata| metadata.len()), secs + duration)
+        });
+    // Players only use the bandwidth to pick between variants, of which there is one.
+    let bandwidth = if secs > 0.0 { (bytes as f64 * 8.0 / secs) as u64 } else { 0 }.max(1);
+
+    let mut playlist = "#EXTM3U\n#EXT-X-VERSION:3\n".to_string();
+    if with_audio {
+        playlist.push_str("#EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"audio\",NAME=\"Microphone\",DEFAULT=YES,AUTOSELECT=YES,URI=\"audio.m3u8\"\n");
+        let _ = writeln!(playlist, "#EXT-X-STREAM-INF:BANDWIDTH={},AUDIO=\"audio\"", bandwidth);
+    } else {
+        let _ = writeln!(playlist, "#EXT-X-STREAM-INF:BANDWIDTH={}", bandwidth);
+    }
+    playlist.push_str("screen.m3u8\n");
+    playlist
+}
+
+/// An `EVENT` playlist while the recording runs, so players keep polling it for new
+/// segments. Adaptive capture restarts ffmpeg with new settings, which is marked as a
+/// discontinuity.
+fn media_playlist(recording_dir: &Path, manifest: &RecordingManifest, video_type: &str, is_live: bool) -> String {
+    let segments = segment_durations(recording_dir, manifest, video_type);
+    let target_duration = segments.iter().map(|(_, duration)| duration.ceil() as u64).max().unwrap_or(0).max(1);
+    let restarts: Vec<u64> = match video_type {
+        "screen" => manifest.capture_changes.iter().map(|change| change.from_segment).collect(),
+        _ => vec![],
+    };
+
+    let mut playlist = format!(
+        "#EXTM3U\n#EXT-X-VERSION:3\n#EXT-X-TARGETDURATION:{}\n#EXT-X-MEDIA-SEQUENCE:0\n#EXT-X-PLAYLIST-TYPE:{}\n",
+        target_duration,
+        if is_live { "EVENT" } else { "VOD" },
+    );
+    for (index, (segment, duration)) in segments.iter().enumerate() {
+        if index > 0 && segment_number(segment).map_or(false, |number| restarts.contains(&number)) {
+            playlist.push_str("#EXT-X-DISCONTINUITY\n");
+        }
+        let _ = write!(playlist, "#EXTINF:{:.3},\n{}/{}\n", duration, video_type, segment);
+    }
+    if !is_live {
+        playlist.push_str("#EXT-X-ENDLIST\n");
+    }
+    playlist
+}
+
+/// Only segments in the segment list are served, which keeps requests inside the
+/// recording's folders.
+async fn segment_response(recording_dir: &Path, video_type: &str, segment: &str) -> Response<Body> {
+    if !listed_segments(recording_dir, video_type).iter().any(|listed| listed == segment) {
+        return error_response(StatusCode::NOT_FOUND);
+    }
+    let content_type = match segment.rsplit_once('.').map(|(_, extension)| extension) {
+        Some("aac") => "audio/aac",
+        _ => "video/mp2t",
+    };
+    match tokio::fs::read(recording_dir.join(video_type).join(segment)).await {
+        Ok(bytes) => response_builder(StatusCode::OK)
+            .header(header::CONTENT_TYPE, content_type)
+            .body(Body::from(bytes))
+            .unwrap_or_default(),
+        Err(_) => error_response(StatusCode::NOT_FOUND),
+    }
+}
+
+fn playlist_response(playlist: String) -> Response<Body> {
+    response_builder(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/vnd.apple.mpegurl")
+        .header(header::CACHE_CONTROL, "no-cache")
+        .body(Body::from(playlist))
+        .unwrap_or_default()
+}
+
+fn error_response(status: StatusCode) -> Response<Body> {
+    response_builder(status).body(Body::empty()).unwrap_or_default()
+}
+
+/// The player runs in the webview, whose origin isn't the server's.
+fn response_builder(status: StatusCode) -> hyper::http::response::Builder {
+    Response::builder()
+        .status(status)
+        .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+}
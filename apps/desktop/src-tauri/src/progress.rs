@@ -0,0 +1,319 @@
+use std::path::{Path, PathBuf};
+use serde::{Serialize, Deserialize};
+use tauri::{AppHandle, Manager};
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::task::JoinHandle;
+use tokio::time::{Duration, Instant, MissedTickBehavior};
+use tracing::{debug, warn};
+
+use crate::manifest::{update_manifest, RecordingManifest};
+use crate::metrics::{clear_encoder_stats, set_encoder_stats};
+
+pub const STATS_EVENT: &str = "recording://stats";
+/// Emitted once with a `FrameDropReport` when a recording starts losing frames.
+pub const FRAME_DROP_EVENT: &str = "recording://frame-drops";
+/// Emitted with the recording's `DurationMismatch`es when its segments don't add up.
+pub const DURATION_MISMATCH_EVENT: &str = "recording://duration-mismatch";
+
+const STATS_INTERVAL: Duration = Duration::from_secs(1);
+/// Frames to see before judging, so start-up hiccups don't count.
+const FRAME_DROP_MIN_FRAMES: u64 = 150;
+/// Share of frames ffmpeg may drop before the recording counts as choppy.
+const FRAME_DROP_MAX_RATIO: f64 = 0.05;
+/// Lowest share of the target frame rate the encoder may sustain.
+const MIN_FPS_RATIO: f64 = 0.9;
+/// How far the segments may add up from the recorded time, whichever is larger. Capture
+/// starts and stops a little apart from the clock, so some slack is always expected.
+const DURATION_TOLERANCE_SECS: f64 = 2.0;
+const DURATION_TOLERANCE_RATIO: f64 = 0.02;
+
+/// One parsed `frame= ... fps= ... bitrate= ... drop= ...` status line from ffmpeg.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FfmpegProgress {
+    pub frame: u64,
+    pub fps: f64,
+    pub bitrate_kbps: f64,
+    pub dup: u64,
+    pub drop: u64,
+    pub speed: f64,
+    pub time_secs: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RecordingStats {
+    pub video_id: String,
+    pub video_type: String,
+    pub frame: u64,
+    pub fps: f64,
+    pub bitrate_kbps: f64,
+    pub dropped_frames: u64,
+    pub duplicated_frames: u64,
+    pub speed: f64,
+    pub time_secs: f64,
+}
+
+/// Why a recording may look choppy, kept in the manifest once drops pass the threshold.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct FrameDropReport {
+    pub video_type: String,
+    pub dropped_frames: u64,
+    pub total_frames: u64,
+    /// Frames encoded per second of capture time, against `target_fps`.
+    pub effective_fps: f64,
+    pub target_fps: f64,
+}
+
+impl FrameDropReport {
+    fn exceeds_threshold(&self) -> bool {
+        if self.total_frames < FRAME_DROP_MIN_FRAMES {
+            return false;
+        }
+        let drop_ratio = self.dropped_frames as f64 / (self.total_frames + self.dropped_frames) as f64;
+        drop_ratio > FRAME_DROP_MAX_RATIO || self.effective_fps < self.target_fps * MIN_FPS_RATIO
+    }
+}
+
+/// A track whose segments add up to noticeably more or less than the recorded time,
+/// which usually means frames or samples were lost without ffmpeg reporting drops.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct DurationMismatch {
+    pub video_type: String,
+    pub segments_secs: f64,
+    pub recorded_secs: f64,
+}
+
+#[derive(Serialize)]
+struct DurationMismatchPayload<'a> {
+    video_id: &'a str,
+    mismatches: &'a [DurationMismatch],
+}
+
+/// Compares the summed segment durations of each track against the recorded time once
+/// its segments are uploaded, stores the result in the manifest and emits a
+/// `recording://duration-mismatch` event when a track is off. Tracks with segments that
+/// were never probed are skipped, as their total isn't known.
+pub fn reconcile_duration(app: Option<&AppHandle>, recording_dir: &Path) {
+    let manifest = match RecordingManifest::load(recording_dir) {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            warn!("Failed to load the manifest to check durations: {}", e);
+            return;
+        },
+    };
+    let recorded_secs = match manifest.duration_secs {
+        Some(recorded_secs) => recorded_secs,
+        None => return,
+    };
+    let tolerance = DURATION_TOLERANCE_SECS.max(recorded_secs * DURATION_TOLERANCE_RATIO);
+
+    let mut mismatches = vec![];
+    for video_type in ["screen", "audio"] {
+        let segment_list = std::fs::read_to_string(recording_dir.join(video_type).join("segment_list.txt"))
+            .unwrap_or_default();
+        let skipped = manifest.skipped_segments.get(video_type);
+        let segments: Vec<&str> = segment_list.lines()
+            .filter(|line| !line.is_empty())
+            .filter(|segment| skipped.map_or(true, |skipped| !skipped.contains(*segment)))
+            .collect();
+        let durations = manifest.segment_durations.get(video_type);
+        let segments_secs: Option<f64> = segments.iter()
+            .map(|segment| durations.and_then(|durations| durations.get(*segment)))
+            .sum();
+
+        match segments_secs {
+            Some(segments_secs) if !segments.is_empty() => {
+                debug!("{} segments of {} add up to {:.2}s of {:.2}s recorded", video_type, manifest.video_id, segments_secs, recorded_secs);
+                if (segments_secs - recorded_secs).abs() > tolerance {
+                    mismatches.push(DurationMismatch { video_type: video_type.to_string(), segments_secs, recorded_secs });
+                }
+            },
+            Some(_) => {},
+            None => debug!("Not all {} segments of {} were probed, skipping the duration check", video_type, manifest.video_id),
+        }
+    }
+
+    if !mismatches.is_empty() {
+        warn!("Segments of {} don't add up to the recorded time: {:?}", manifest.video_id, mismatches);
+        if let Some(app) = app {
+            let _ = app.emit_all(DURATION_MISMATCH_EVENT, DurationMismatchPayload { video_id: &manifest.video_id, mismatches: &mismatches });
+        }
+    }
+    if let Err(e) = update_manifest(recording_dir, |manifest| manifest.duration_mismatches = mismatches) {
+        warn!("Failed to record duration mismatches in the manifest: {}", e);
+    }
+}
+
+/// Follows the drop counter and the frame count against wall-clock capture time.
+/// Time in which no frames arrive, e.g. while paused, isn't counted.
+struct FrameDropTracker {
+    video_type: String,
+    target_fps: f64,
+    last_frame: u64,
+    last_update: Option<Instant>,
+    capture_secs: f64,
+    dropped_frames: u64,
+    reported: bool,
+}
+
+impl FrameDropTracker {
+    fn update(&mut self, progress: &FfmpegProgress) {
+        let now = Instant::now();
+        if let Some(last_update) = self.last_update {
+            if progress.frame > self.last_frame {
+                self.capture_secs += now.duration_since(last_update).as_secs_f64();
+            }
+        }
+        self.last_update = Some(now);
+        self.last_frame = progress.frame;
+        self.dropped_frames = progress.drop;
+    }
+
+    fn report(&self) -> FrameDropReport {
+        FrameDropReport {
+            video_type: self.video_type.clone(),
+            dropped_frames: self.dropped_frames,
+            total_frames: self.last_frame,
+            effective_fps: if self.capture_secs > 0.0 { self.last_frame as f64 / self.capture_secs } else { self.target_fps },
+            target_fps: self.target_fps,
+        }
+    }
+}
+
+/// Parses an ffmpeg status line, returning `None` for any other stderr output.
+pub fn parse_progress_line(line: &str) -> Option<FfmpegProgress> {
+    let frame = progress_field(line, "frame")?.parse().ok()?;
+
+    Some(FfmpegProgress {
+        frame,
+        fps: progress_field(line, "fps").and_then(|v| v.parse().ok()).unwrap_or(0.0),
+        bitrate_kbps: progress_field(line, "bitrate")
+            .and_then(|v| v.trim_end_matches("kbits/s").parse().ok())
+            .unwrap_or(0.0),
+        dup: progress_field(line, "dup").and_then(|v| v.parse().ok()).unwrap_or(0),
+        drop: progress_field(line, "drop").and_then(|v| v.parse().ok()).unwrap_or(0),
+        speed: progress_field(line, "speed")
+            .and_then(|v| v.trim_end_matches('x').parse().ok())
+            .unwrap_or(0.0),
+        time_secs: progress_field(line, "time").and_then(parse_timestamp).unwrap_or(0.0),
+    })
+}
+
+/// ffmpeg pads values after `=` with spaces (`frame=  120`), so skip them before reading the value.
+fn progress_field<'a>(line: &'a str, key: &str) -> Option<&'a str> {
+    let needle = format!("{}=", key);
+    let mut search_from = 0;
+
+    while let Some(offset) = line[search_from..].find(&needle) {
+        let start = search_from + offset;
+        let at_boundary = start == 0 || line[..start].ends_with(char::is_whitespace);
+        if at_boundary {
+            let value = line[start + needle.len()..].trim_start();
+            return value.split_whitespace().next();
+        }
+        search_from = start + needle.len();
+    }
+
+    None
+}
+
+/// Parses `HH:MM:SS.ms` into seconds.
+pub fn parse_timestamp(value: &str) -> Option<f64> {
+    let parts: Vec<&str> = value.trim().split(':').collect();
+    if parts.len() != 3 {
+        return None;
+    }
+
+    let hours: f64 = parts[0].parse().ok()?;
+    let minutes: f64 = parts[1].parse().ok()?;
+    let seconds: f64 = parts[2].parse().ok()?;
+
+    Some(hours * 3600.0 + minutes * 60.0 + seconds)
+}
+
+/// Consumes ffmpeg stderr lines and emits the latest progress as a `recording://stats`
+/// event once per second. Frame drops are tracked against `target_fps`; past the
+/// threshold a `recording://frame-drops` event is emitted and the final report is
+/// written to the manifest. The task ends when the stderr stream closes.
+pub fn spawn_stats_task(
+    app: Option<AppHandle>,
+    recording_dir: PathBuf,
+    video_id: String,
+    video_type: String,
+    target_fps: f64,
+    mut lines: UnboundedReceiver<String>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut latest: Option<FfmpegProgress> = None;
+        let mut interval = tokio::time::interval(STATS_INTERVAL);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        let mut drops = FrameDropTracker {
+            video_type: video_type.clone(),
+            target_fps,
+            last_frame: 0,
+            last_update: None,
+            capture_secs: 0.0,
+            dropped_frames: 0,
+            reported: false,
+        };
+
+        loop {
+            tokio::select! {
+                line = lines.recv() => {
+                    match line {
+                        Some(line) => {
+                            if let Some(progress) = parse_progress_line(&line) {
+                                latest = Some(progress);
+                            }
+                        },
+                        None => break,
+                    }
+                },
+                _ = interval.tick() => {
+                    if let Some(progress) = latest.take() {
+                        drops.update(&progress);
+
+                        let report = drops.report();
+                        if !drops.reported && report.exceeds_threshold() {
+                            drops.reported = true;
+                            warn!("{} capture for {} is dropping frames: {:?}", video_type, video_id, report);
+                            if let Some(ref app) = app {
+                                let _ = app.emit_all(FRAME_DROP_EVENT, report);
+                            }
+                        }
+
+                        set_encoder_stats(&video_type, progress.fps, progress.speed);
+                        let stats = RecordingStats {
+                            video_id: video_id.clone(),
+                            video_type: video_type.clone(),
+                            frame: progress.frame,
+                            fps: progress.fps,
+                            bitrate_kbps: progress.bitrate_kbps,
+                            dropped_frames: progress.drop,
+                            duplicated_frames: progress.dup,
+                            speed: progress.speed,
+                            time_secs: progress.time_secs,
+                        };
+                        if let Some(ref app) = app {
+                            if let Err(e) = app.emit_all(STATS_EVENT, stats) {
+                                warn!("Failed to emit recording stats: {}", e);
+                            }
+                        }
+                    }
+                },
+            }
+        }
+
+        clear_encoder_stats(&video_type);
+        if let Some(progress) = latest {
+            drops.update(&progress);
+        }
+        let report = drops.report();
+        if report.exceeds_threshold() {
+            if let Err(e) = update_manifest(&recording_dir, |manifest| manifest.frame_drops = Some(report)) {
+                warn!("Failed to record frame drops in the manifest: {}", e);
+            }
+        }
+
+        debug!("Stats task for {} {} finished", video_type, video_id);
+    })
+}
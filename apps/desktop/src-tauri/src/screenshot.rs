@@ -0,0 +1,234 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use serde::Serialize;
+use tauri::{AppHandle, State};
+use tokio::process::Command;
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+use xcap::image::{DynamicImage, RgbaImage};
+use xcap::Monitor;
+
+use crate::annotate::{self, AnnotationOp};
+use crate::capture::resolve_device_input;
+use crate::clipboard::copy_image;
+use crate::manifest::validate_video_id;
+use crate::recording::RecordingOptions;
+use crate::session::RecordingState;
+use crate::settings::Settings;
+use crate::upload::{upload_and_remove_file, upload_sidecar_file};
+use crate::upload_loop::load_segment_list;
+use crate::utils::ffmpeg_path;
+
+pub const SCREENSHOTS_DIR: &str = "screenshots";
+/// Holds the stills grabbed with `capture_frame_now`, inside the recording directory.
+const STILLS_DIR: &str = "stills";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Screenshot {
+    /// Storage key of the uploaded image; `None` until it is uploaded.
+    pub file_key: Option<String>,
+    /// The local copy, removed once uploaded.
+    pub path: String,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// A frame grabbed from the active recording.
+#[derive(Debug, Clone, Serialize)]
+pub struct Still {
+    pub file_key: String,
+    /// The local copy, kept in the recording's `stills` directory.
+    pub path: String,
+    /// Recorded time excluding pauses when the still was taken.
+    pub offset_secs: f64,
+}
+
+/// Accepts both avfoundation names ("Capture screen 1") and plain indices ("1").
+pub fn parse_screen_index(screen_index: &str) -> usize {
+    screen_index.split_whitespace()
+        .last()
+        .and_then(|index| index.parse().ok())
+        .unwrap_or(0)
+}
+
+/// Grabs the display at `screen_index` through the OS screenshot API. Unlike an
+/// ffmpeg grab, this doesn't open the capture device the recording is using, and
+/// the pixels keep the display's colors instead of going through a YUV conversion.
+pub fn capture_screen(screen_index: usize) -> Result<RgbaImage, String> {
+    Monitor::all()
+        .map_err(|e| format!("Failed to list screens: {}", e))?
+        .into_iter()
+        .nth(screen_index)
+        .ok_or(format!("Screen {} not found", screen_index))?
+        .capture_image()
+        .map_err(|e| format!("Failed to capture screen {}: {}", screen_index, e))
+}
+
+/// Saves `image` as a JPEG, which has no alpha channel.
+pub fn save_jpeg(image: RgbaImage, path: &Path) -> Result<(), String> {
+    DynamicImage::ImageRgba8(image)
+        .to_rgb8()
+        .save(path)
+        .map_err(|e| format!("Failed to save screenshot: {}", e))
+}
+
+/// Captures the screen and saves it to `path` as a JPEG.
+pub async fn save_screenshot(screen_index: usize, path: PathBuf) -> Result<(u32, u32), String> {
+    tokio::task::spawn_blocking(move || {
+        let image = capture_screen(screen_index)?;
+        let dimensions = image.dimensions();
+        save_jpeg(image, &path)?;
+        Ok(dimensions)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// Where a standalone screenshot for `video_id` is kept until it is uploaded.
+fn screenshot_path(app: &AppHandle, video_id: &str) -> Result<PathBuf, String> {
+    validate_video_id(video_id)?;
+    let data_dir = app.path_resolver().app_data_dir()
+        .ok_or("Data directory is not available".to_string())?;
+    let screenshots_dir = data_dir.join(SCREENSHOTS_DIR);
+    std::fs::create_dir_all(&screenshots_dir).map_err(|e| e.to_string())?;
+    Ok(screenshots_dir.join(format!("{}.jpg", video_id)))
+}
+
+/// Uploads the screenshot at `path`, first copying it to the clipboard when `copy` is
+/// set. A failed copy is only logged, since the upload is what the caller waits for.
+async fn upload_screenshot(options: RecordingOptions, path: &Path, width: u32, height: u32, copy: bool) -> Result<Screenshot, String> {
+    if copy {
+        let copied_path = path.to_path_buf();
+        match tokio::task::spawn_blocking(move || copy_image(&copied_path)).await {
+            Ok(Ok(())) => info!("Screenshot copied to the clipboard"),
+            Ok(Err(e)) => warn!("{}", e),
+            Err(e) => warn!("Failed to copy screenshot to the clipboard: {}", e),
+        }
+    }
+
+    let file_key = upload_and_remove_file(&options, path, "screenshot").await?;
+    let path = path.to_string_lossy().into_owned();
+    info!("Screenshot uploaded as {}", file_key);
+
+    Ok(Screenshot { file_key: Some(file_key), path, width, height })
+}
+
+/// Takes a screenshot of the screen in `options` and uploads it for `options.video_id`.
+/// With `upload` set to false it is only saved, for `annotate_screenshot` to edit and
+/// upload.
+#[tauri::command]
+pub async fn take_screenshot(
+    app: AppHandle,
+    settings: State<'_, Arc<Mutex<Settings>>>,
+    options: RecordingOptions,
+    upload: Option<bool>,
+) -> Result<Screenshot, String> {
+    let copy = settings.lock().await.copy_screenshot_to_clipboard;
+    let path = screenshot_path(&app, &options.video_id)?;
+    let screen_input = resolve_device_input(options.screen_index.clone()).await?;
+    let (width, height) = save_screenshot(parse_screen_index(&screen_input), path.clone()).await?;
+    info!("Screenshot captured: {}", path.display());
+
+    if upload == Some(false) {
+        return Ok(Screenshot { file_key: None, path: path.to_string_lossy().into_owned(), width, height });
+    }
+    upload_screenshot(options, &path, width, height, copy).await
+}
+
+/// Applies `ops` to the screenshot saved by `take_screenshot` for `options.video_id`,
+/// then uploads it.
+#[tauri::command]
+pub async fn annotate_screenshot(
+    app: AppHandle,
+    settings: State<'_, Arc<Mutex<Settings>>>,
+    options: RecordingOptions,
+    ops: Vec<AnnotationOp>,
+) -> Result<Screenshot, String> {
+    let copy = settings.lock().await.copy_screenshot_to_clipboard;
+    let path = screenshot_path(&app, &options.video_id)?;
+    let annotated_path = path.clone();
+    let (width, height) = tokio::task::spawn_blocking(move || {
+        let image = xcap::image::open(&annotated_path)
+            .map_err(|e| format!("Failed to open screenshot: {}", e))?
+            .to_rgba8();
+        let image = annotate::apply(image, &ops)?;
+        let dimensions = image.dimensions();
+        save_jpeg(image, &annotated_path)?;
+        Ok::<_, String>(dimensions)
+    })
+    .await
+    .map_err(|e| e.to_string())??;
+    info!("Annotated screenshot {}", path.display());
+
+    upload_screenshot(options, &path, width, height, copy).await
+}
+
+/// Saves the most recent recorded frame of the active recording and uploads it under
+/// the `stills` type. The frame comes from the segment ffmpeg is writing, or from the
+/// last finished one when the new segment has no complete frame yet, so it trails the
+/// screen by at most a segment.
+#[tauri::command]
+pub async fn capture_frame_now(state: State<'_, Arc<Mutex<RecordingState>>>) -> Result<Still, String> {
+    let guard = state.lock().await;
+    if !guard.is_recording() {
+        return Err("No recording is in progress".to_string());
+    }
+    let offset_secs = guard.elapsed().as_secs_f64();
+    let recording_dir = guard.recording_dir.clone().ok_or("Recording directory is not set".to_string())?;
+    let options = guard.recording_options.clone().ok_or("Recording options are not set".to_string())?;
+    drop(guard);
+
+    let stills_dir = recording_dir.join(STILLS_DIR);
+    std::fs::create_dir_all(&stills_dir).map_err(|e| e.to_string())?;
+    let still_path = stills_dir.join(format!("still_{:08}.jpg", (offset_secs * 1000.0) as u64));
+
+    let chunks_dir = recording_dir.join("screen");
+    let latest_finished = load_segment_list(&chunks_dir.join("segment_list.txt"))
+        .unwrap_or_default()
+        .pop()
+        .map(|segment| chunks_dir.join(segment));
+    let mut extracted = Err("The recording has no screen segments yet".to_string());
+    for segment_path in latest_segment_on_disk(&chunks_dir).into_iter().chain(latest_finished) {
+        extracted = extract_last_frame(&segment_path, &still_path).await;
+        if extracted.is_ok() {
+            break;
+        }
+    }
+    extracted?;
+    info!("Captured a still of {} at {:.1}s", options.video_id, offset_secs);
+
+    let file_key = upload_sidecar_file(&options, &still_path, "stills").await?;
+    Ok(Still { file_key, path: still_path.to_string_lossy().into_owned(), offset_secs })
+}
+
+/// The highest numbered `.ts` segment in `chunks_dir`, which is the one being written.
+fn latest_segment_on_disk(chunks_dir: &Path) -> Option<PathBuf> {
+    std::fs::read_dir(chunks_dir).ok()?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().map_or(false, |ext| ext == "ts"))
+        .filter_map(|path| Some((path.file_name()?.to_str()?.to_string(), path)))
+        // Segment numbers widen past their padding, so longer names are later.
+        .max_by(|(a, _), (b, _)| a.len().cmp(&b.len()).then_with(|| a.cmp(b)))
+        .map(|(_, path)| path)
+}
+
+/// Decodes `segment_path` up to where it ends, which for the segment being written
+/// is the last frame flushed so far, and saves that frame to `still_path`.
+async fn extract_last_frame(segment_path: &Path, still_path: &Path) -> Result<(), String> {
+    let _ = std::fs::remove_file(still_path);
+    let output = Command::new(ffmpeg_path()?)
+        .args(["-hide_banner", "-y", "-i"])
+        .arg(segment_path)
+        .args(["-map", "0:v:0", "-update", "1", "-q:v", "2"])
+        .arg(still_path)
+        .output()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if !still_path.is_file() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("Failed to extract a frame from {}: {}", segment_path.display(), stderr.lines().last().unwrap_or_default()));
+    }
+    Ok(())
+}
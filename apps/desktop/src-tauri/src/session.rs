@@ -0,0 +1,1120 @@
+use std::path::{Path, PathBuf};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::process::Stdio;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+use tokio::sync:: {Mutex, Semaphore};
+use tokio::task::JoinHandle;
+use tokio::time::{Duration};
+use tokio_util::sync::CancellationToken;
+use serde::Serialize;
+use tauri::{AppHandle, Manager, State};
+use tracing::{debug, error, info, warn};
+
+use crate::capture::{
+  clean_and_create_dir, construct_recording_args, graceful_stop_ffmpeg, resolve_device_input, signal_ffmpeg,
+  start_screen_recording_process, FFMPEG_EXIT_TIMEOUT,
+};
+use crate::adaptive::{spawn_adaptive_capture, ScreenCapture};
+use crate::capture_watch::{spawn_capture_watch, CaptureFailure};
+use crate::clipboard::copy_text;
+use crate::faults;
+use crate::poster::POSTER_FILE;
+use crate::screenshot::{parse_screen_index, save_screenshot};
+use crate::frame_gate::{split_capture_args, spawn_frame_gate, FrameGate};
+use crate::jobs::{self, JobKind};
+use crate::journal::{self, JournalEvent};
+use crate::logging::ffmpeg_log_path;
+use crate::progress::{reconcile_duration, spawn_stats_task};
+use crate::recording::{CaptureMode, RecordingOptions};
+use crate::upload_loop::{join_upload_loops, load_segment_list, start_upload_loop, UploadLoops};
+use crate::utils::{ffmpeg_path, monitor_and_log_recording_start, share_link};
+use crate::upload::{upload_and_remove_file, Uploader};
+use crate::verify::verify_uploads;
+use crate::audio::AudioRecorder;
+use crate::options::validate_options;
+use crate::device_arbitration::{acquire as acquire_devices, recording_devices, DeviceLease, DeviceUser};
+use crate::backup::retry_backups;
+use crate::settings::{ChunkRetention, Settings};
+use crate::manifest::{
+  chunk_failures, finalize_upload_status, pending_segments, recording_dir, segment_file_name,
+  set_recording_status, update_manifest, validate_video_id,
+  ChunkFailure, RecordingManifest, RecordingStatus, StopReason,
+};
+use crate::notifications::notify_upload_finished;
+use crate::upload_stats;
+use crate::transcription::SPEECH_TRACK_FILE;
+use crate::timeline::spawn_timeline_task;
+use crate::telemetry::spawn_telemetry_task;
+use crate::backpressure::spawn_backpressure_task;
+use crate::mouse_events;
+use crate::power::WakeLock;
+use crate::focus::DoNotDisturb;
+use crate::markers::{upload_markers, Marker};
+use crate::webhooks::{notify, notify_upload_result, WebhookEvent};
+use crate::zoom::Zoom;
+#[cfg(feature = "native-encoder")]
+use crate::native_encoder::{start_native_screen_encoder, NativeEncoderConfig, NativeEncoderHandle};
+#[cfg(feature = "native-encoder")]
+use crate::utils::send_metadata_api;
+
+pub struct RecordingState {
+  pub screen_process: Option<tokio::process::Child>,
+  pub screen_process_stdin: Option<tokio::process::ChildStdin>,
+  pub video_process: Option<tokio::process::Child>,
+  pub audio_process: Option<AudioRecorder>,
+  pub recording_options: Option<RecordingOptions>,
+  /// Cancelled once capture has stopped, so the upload loops make their last pass.
+  pub shutdown: CancellationToken,
+  /// Resolves when both upload loops of the recording are done.
+  pub uploads: Option<UploadLoops>,
+  pub data_dir: Option<PathBuf>,
+  pub recording_dir: Option<PathBuf>,
+  pub started_at: Option<Instant>,
+  /// When the current pause began; `None` while capturing.
+  pub paused_at: Option<Instant>,
+  /// Total time spent paused in earlier pauses.
+  pub paused_time: Duration,
+  /// Set by `cancel_recording` so the upload loops stop without uploading the rest.
+  pub upload_cancelled: Arc<AtomicBool>,
+  pub markers: Vec<Marker>,
+  /// Held from start until capture stops.
+  pub wake_lock: Option<WakeLock>,
+  /// Set while recording when the `do_not_disturb` setting is on.
+  pub do_not_disturb: Option<DoNotDisturb>,
+  /// Set during the `start_delay_secs` countdown; cancelling it aborts the start.
+  pub countdown: Option<CancellationToken>,
+  /// Copies a restarted screen ffmpeg's segment list into `segment_list.txt`; done
+  /// once the process has exited.
+  pub segment_list_relay: Option<JoinHandle<()>>,
+  /// Set when the screen is captured and encoded by separate processes, so pausing
+  /// drops frames instead of suspending ffmpeg.
+  pub frame_gate: Option<FrameGate>,
+  /// Set once `set_zoom` has been used on the current capture.
+  pub zoom: Option<Zoom>,
+  /// Keeps previews and tests off the recording's devices until capture stops.
+  pub device_lease: Option<DeviceLease>,
+  /// Where the recording's segments and thumbnail are uploaded to.
+  pub uploader: Option<Arc<dyn Uploader>>,
+  #[cfg(feature = "native-encoder")]
+  pub native_encoder: Option<NativeEncoderHandle>,
+}
+
+/// Emitted with a `Countdown` every second of a recording's start delay.
+pub const COUNTDOWN_EVENT: &str = "recording://countdown";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Countdown {
+  pub video_id: String,
+  pub remaining_secs: u32,
+}
+
+/// How long quitting the app waits for uploads; the rest can be retried on next launch.
+const SHUTDOWN_UPLOAD_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long stopping waits for the uploaded segments to be verified.
+const UPLOAD_STATUS_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordingPhase {
+  Idle,
+  Countdown,
+  Recording,
+  Paused,
+  Uploading,
+}
+
+impl RecordingState {
+  pub fn new(data_dir: PathBuf) -> Self {
+    RecordingState {
+      screen_process: None,
+      screen_process_stdin: None,
+      video_process: None,
+      audio_process: None,
+      recording_options: None,
+      shutdown: CancellationToken::new(),
+      uploads: None,
+      data_dir: Some(data_dir),
+      recording_dir: None,
+      started_at: None,
+      paused_at: None,
+      paused_time: Duration::ZERO,
+      upload_cancelled: Arc::new(AtomicBool::new(false)),
+      markers: vec![],
+      wake_lock: None,
+      do_not_disturb: None,
+      countdown: None,
+      segment_list_relay: None,
+      frame_gate: None,
+      zoom: None,
+      device_lease: None,
+      uploader: None,
+      #[cfg(feature = "native-encoder")]
+      native_encoder: None,
+    }
+  }
+
+  /// Moves the active capture out, leaving an idle state that can start a new
+  /// recording while the returned one is still running.
+  fn take_capture(&mut self) -> RecordingState {
+    let data_dir = self.data_dir.clone().unwrap_or_default();
+    std::mem::replace(self, RecordingState::new(data_dir))
+  }
+
+  /// True from a successful start until the recording is stopped.
+  pub fn is_recording(&self) -> bool {
+    self.started_at.is_some()
+  }
+
+  pub fn is_paused(&self) -> bool {
+    self.paused_at.is_some()
+  }
+
+  /// Whether the capture can be paused: through the frame gate, or by suspending
+  /// ffmpeg, which only works on Unix.
+  pub fn can_pause(&self) -> bool {
+    cfg!(unix) || self.frame_gate.is_some() || self.screen_process.is_none()
+  }
+
+  /// Recorded time so far, excluding pauses.
+  pub fn elapsed(&self) -> Duration {
+    match self.started_at {
+      Some(started_at) => self.paused_at.unwrap_or_else(Instant::now)
+        .saturating_duration_since(started_at)
+        .saturating_sub(self.paused_time),
+      None => Duration::ZERO,
+    }
+  }
+
+  pub fn phase(&self) -> RecordingPhase {
+    if self.countdown.is_some() {
+      RecordingPhase::Countdown
+    } else if self.is_recording() {
+      if self.is_paused() { RecordingPhase::Paused } else { RecordingPhase::Recording }
+    } else if self.has_pending_work() {
+      RecordingPhase::Uploading
+    } else {
+      RecordingPhase::Idle
+    }
+  }
+
+  /// True while capture is running or the last recording's segments are still uploading.
+  pub fn has_pending_work(&self) -> bool {
+    self.is_recording()
+      || self.uploads.as_ref().map_or(false, |uploads| uploads.peek().is_none())
+  }
+}
+
+
+#[tauri::command]
+pub async fn start_dual_recording(
+  app: AppHandle,
+  state: State<'_, Arc<Mutex<RecordingState>>>,
+  settings: State<'_, Arc<Mutex<Settings>>>,
+  options: RecordingOptions,
+) -> Result<(), String> {
+  start_recording(Some(app), state.inner().clone(), settings.inner().clone(), options).await
+}
+
+/// Starts capture and runs the upload loops until the recording is stopped, so
+/// callers outside the command (deep links, the CLI) should spawn it. Without an
+/// `AppHandle` no stats events are emitted.
+pub async fn start_recording(
+  app: Option<AppHandle>,
+  state: Arc<Mutex<RecordingState>>,
+  settings: Arc<Mutex<Settings>>,
+  mut options: RecordingOptions,
+) -> Result<(), String> {
+  info!("Starting screen recording...");
+  let settings = settings.lock().await.clone();
+  settings.apply_to(&mut options);
+
+  if let Some(error) = validate_options(&options).first() {
+    return Err(error.to_string());
+  }
+
+  if options.start_delay_secs > 0 {
+    let countdown = CancellationToken::new();
+    {
+      let mut guard = state.lock().await;
+      if guard.is_recording() || guard.countdown.is_some() {
+        return Err("A recording is already in progress".to_string());
+      }
+      guard.countdown = Some(countdown.clone());
+    }
+    let result = run_countdown(app.as_ref(), &options.video_id, options.start_delay_secs, &countdown).await;
+    state.lock().await.countdown = None;
+    result?;
+  }
+
+  if state.lock().await.is_recording() {
+    return Err("A recording is already in progress".to_string());
+  }
+  // Waiting for the devices can take a while, so it happens before the state is locked.
+  let device_lease = acquire_devices(app.as_ref(), DeviceUser::Recording, recording_devices(&options).await?).await?;
+
+  let mut state_guard = state.lock().await;
+  if state_guard.is_recording() {
+    return Err("A recording is already in progress".to_string());
+  }
+
+  let shutdown = CancellationToken::new();
+
+  let ffmpeg_binary = ffmpeg_path()?;
+
+  let data_dir = state_guard.data_dir.as_ref()
+      .ok_or("Data directory is not set in the recording state".to_string())?.clone();
+
+  debug!("data_dir: {:?}", data_dir);
+  
+  let recording_dir = recording_dir(&data_dir, &options.video_id)?;
+  let screen_chunks_dir = recording_dir.join("screen");
+  let audio_chunks_dir = recording_dir.join("audio");
+  clean_and_create_dir(&screen_chunks_dir)?;
+  clean_and_create_dir(&audio_chunks_dir)?;
+  RecordingManifest::new(&options).save(&recording_dir)?;
+  journal::append(&recording_dir, JournalEvent::Started { options: options.clone(), app_pid: std::process::id() });
+  upload_stats::reset(&options.video_id);
+
+  if !options.muxed_audio.unwrap_or_default() {
+    state_guard.audio_process = Some(AudioRecorder::new());
+  }
+  
+  let audio_name = if options.audio_name.is_empty() {
+    None
+  } else {
+    Some(options.audio_name.clone())
+  };
+  
+  let screen_input = resolve_device_input(options.screen_index.clone()).await?;
+  let camera_input = resolve_device_input(options.video_index.clone()).await?;
+
+  let screenshot_output_path = recording_dir.join(POSTER_FILE);
+  // The camera can't be opened twice, so camera-only thumbnails come from the first segment.
+  let screenshot_source = match options.capture_mode {
+    CaptureMode::Camera => ScreenshotSource::FirstSegment(screen_chunks_dir.join(segment_file_name(&options.segment_prefix("screen"), 0, "ts"))),
+    CaptureMode::Screen => ScreenshotSource::Screen(parse_screen_index(&screen_input)),
+  };
+
+  if let Some(ref mut audio_process) = state_guard.audio_process {
+      let audio_log_path = ffmpeg_log_path(&data_dir, &options.video_id, "audio");
+      let speech_track = settings.transcription_enabled.then(|| audio_chunks_dir.join(SPEECH_TRACK_FILE));
+      audio_process.start_audio_recording(options.clone(), &audio_chunks_dir, audio_name.as_deref(), audio_log_path, speech_track, app.clone()).await.map_err(|e| e.to_string())?;
+  }
+
+  // The native encoder only captures displays, without audio.
+  #[cfg(feature = "native-encoder")]
+  let use_ffmpeg = options.capture_mode == CaptureMode::Camera || options.muxed_audio.unwrap_or_default();
+  #[cfg(not(feature = "native-encoder"))]
+  let use_ffmpeg = true;
+
+  #[cfg(feature = "native-encoder")]
+  if !use_ffmpeg {
+    info!("Starting native screen encoder...");
+
+    let encoder_config = NativeEncoderConfig {
+      screen_index: parse_screen_index(&screen_input),
+      chunks_dir: screen_chunks_dir.clone(),
+      segment_prefix: options.segment_prefix("screen"),
+      framerate: 30,
+      segment_time_secs: options.segment_time_secs,
+      preset: "ultrafast".to_string(),
+      crf: "28".to_string(),
+    };
+    let encoder = tokio::task::spawn_blocking(move || start_native_screen_encoder(encoder_config))
+      .await
+      .map_err(|e| e.to_string())??;
+    state_guard.native_encoder = Some(encoder);
+
+    let timestamp = chrono::Utc::now().timestamp() as f64;
+    if send_metadata_api(&options.video_id, timestamp, "video").await.is_err() {
+      error!("Failed to send metadata to API.");
+    }
+  }
+
+  if use_ffmpeg {
+    let ffmpeg_screen_args = match options.capture_mode {
+      CaptureMode::Screen => construct_recording_args(&options, &screen_chunks_dir, "screen", &screen_input).await,
+      CaptureMode::Camera => construct_recording_args(&options, &screen_chunks_dir, "camera", &camera_input).await,
+    }.map_err(|e| e.to_string())?;
+    debug!("Screen args: {:?}", ffmpeg_screen_args);
+
+    // The mic muxed into the screen capture can't be gated with the frames, and the
+    // drawing overlay needs the filter chain in the capture's single process. ffmpeg
+    // can't be suspended on Windows, so the gate is the only way to pause there.
+    let gate_frames = (settings.frame_accurate_pause || cfg!(windows))
+      && !options.muxed_audio.unwrap_or_default()
+      && !options.live_drawing.unwrap_or_default();
+    let (capture_args, encoder_args) = if gate_frames {
+      let (capture_args, encoder_args) = split_capture_args(&ffmpeg_screen_args)?;
+      (capture_args, Some(encoder_args))
+    } else {
+      (ffmpeg_screen_args.clone(), None)
+    };
+
+    info!("Starting screen recording process...");
+
+    let (mut screen_child, screen_stderr, screen_stdin) = start_screen_recording_process(&ffmpeg_binary, &capture_args)
+      .await
+      .map_err(|e| e.to_string())?;
+
+    info!("Screen recording process started.");
+    if let Some(pid) = screen_child.id() {
+      journal::append(&recording_dir, JournalEvent::ProcessStarted { track: "screen".to_string(), pid });
+    }
+
+    if let Some(encoder_args) = encoder_args {
+      debug!("Screen encoder args: {:?}", encoder_args);
+      let frames = screen_child.stdout.take().ok_or("FFmpeg stdout is not available".to_string())?;
+      let encoder_log_path = ffmpeg_log_path(&data_dir, &options.video_id, "screen_encoder");
+      let gate = spawn_frame_gate(&ffmpeg_binary, &encoder_args, frames, &options.video_id, encoder_log_path)?;
+      if let Some(pid) = gate.encoder_pid {
+        journal::append(&recording_dir, JournalEvent::ProcessStarted { track: "screen_encoder".to_string(), pid });
+      }
+      state_guard.frame_gate = Some(gate);
+    }
+
+    let video_id_clone = options.video_id.clone();
+    let screen_log_path = ffmpeg_log_path(&data_dir, &video_id_clone, "screen");
+    let target_fps = match options.capture_mode {
+      CaptureMode::Camera => options.framerate.unwrap_or(30) as f64,
+      CaptureMode::Screen => 30.0,
+    };
+    let (screen_line_tx, screen_line_rx) = tokio::sync::mpsc::unbounded_channel();
+    spawn_stats_task(app.clone(), recording_dir.clone(), options.video_id.clone(), "screen".to_string(), target_fps, screen_line_rx);
+    let screen_started_future = monitor_and_log_recording_start(screen_stderr, &video_id_clone, "video", screen_log_path, Some(screen_line_tx), true);
+
+    screen_started_future.await.map_err(String::from)?;
+
+    state_guard.screen_process = Some(screen_child);
+    debug!("Set screen child");
+    state_guard.screen_process_stdin = Some(screen_stdin);
+    debug!("Set screen stdin");
+
+    let screen_log_path = ffmpeg_log_path(&data_dir, &options.video_id, "screen");
+    spawn_capture_watch(app.clone(), state.clone(), options.video_id.clone(), recording_dir.clone(), screen_log_path);
+    // Restarts would have to replace both processes of a gated capture.
+    let backlog_limit_bytes = (settings.reduce_bitrate_on_backlog && settings.max_upload_backlog_mb > 0)
+      .then(|| settings.max_upload_backlog_mb * 1024 * 1024);
+    if !gate_frames && (settings.adaptive_quality || settings.adaptive_segment_time || backlog_limit_bytes.is_some()) {
+      spawn_adaptive_capture(state.clone(), ScreenCapture {
+        app: app.clone(),
+        video_id: options.video_id.clone(),
+        recording_dir: recording_dir.clone(),
+        data_dir: data_dir.clone(),
+        ffmpeg_binary: ffmpeg_binary.clone(),
+        args: ffmpeg_screen_args,
+        adapt_quality: settings.adaptive_quality,
+        adapt_segment_time: settings.adaptive_segment_time,
+        backlog_limit_bytes,
+      });
+    }
+  }
+
+
+  let options_clone = options.clone();
+  let uploader = settings.uploader();
+  let screenshot_uploader = uploader.clone();
+
+  // Spawn the screenshot task without directly awaiting it
+  tokio::spawn(async move {
+      if let Err(e) = take_screenshot(
+          ffmpeg_binary.clone(),
+          screenshot_source,
+          screenshot_output_path.clone(),
+          options_clone,
+          screenshot_uploader,
+      ).await {
+          error!("Failed to take and upload screenshot: {}", e);
+      }
+  });
+
+  state_guard.recording_options = Some(options.clone());
+  state_guard.recording_dir = Some(recording_dir.clone());
+  state_guard.shutdown = shutdown.clone();
+  state_guard.started_at = Some(Instant::now());
+  state_guard.wake_lock = Some(WakeLock::acquire());
+  state_guard.do_not_disturb = settings.do_not_disturb.then(DoNotDisturb::enable);
+  state_guard.paused_at = None;
+  state_guard.paused_time = Duration::ZERO;
+  state_guard.markers = vec![];
+  state_guard.zoom = None;
+  state_guard.device_lease = Some(device_lease);
+  state_guard.uploader = Some(uploader.clone());
+  state_guard.upload_cancelled = Arc::new(AtomicBool::new(false));
+  let upload_cancelled = state_guard.upload_cancelled.clone();
+  notify(WebhookEvent::RecordingStarted, &options.video_id, None, None);
+  if settings.record_mouse_events {
+      mouse_events::start(&options.video_id);
+  }
+  if settings.track_active_window {
+      spawn_timeline_task(state.clone(), recording_dir.clone(), options.clone(), upload_cancelled.clone());
+  }
+  spawn_telemetry_task(app.clone(), state.clone(), recording_dir.clone(), options.video_id.clone());
+  spawn_backpressure_task(app.clone(), state.clone(), recording_dir.clone(), options.video_id.clone(), settings.max_upload_backlog_mb * 1024 * 1024);
+
+  let upload_limit = Arc::new(Semaphore::new(settings.max_concurrent_uploads));
+  // Renditions are made from the local segments, so they are kept until that job is
+  // done and removes them itself.
+  let chunk_retention = match settings.chunk_retention {
+      ChunkRetention::KeepUntilConfirmed if !settings.renditions.is_empty() => ChunkRetention::KeepForever,
+      chunk_retention => chunk_retention,
+  };
+  let screen_upload = tokio::spawn(start_upload_loop(recording_dir.clone(), options.clone(), "screen".to_string(), shutdown.clone(), upload_cancelled.clone(), upload_limit.clone(), uploader.clone()));
+  let audio_upload = tokio::spawn(start_upload_loop(recording_dir.clone(), options.clone(), "audio".to_string(), shutdown.clone(), upload_cancelled.clone(), upload_limit, uploader.clone()));
+  let uploads = join_upload_loops(screen_upload, audio_upload);
+  state_guard.uploads = Some(uploads.clone());
+
+  drop(state_guard);
+  faults::arm(state.clone(), options.video_id.clone());
+
+  info!("Starting upload loops...");
+
+
+  match uploads.await {
+      Ok(_) if upload_cancelled.load(Ordering::SeqCst) => {
+          info!("Recording {} was cancelled", options.video_id);
+      },
+      Ok(_) => {
+          info!("Both upload loops completed successfully.");
+          if let Err(e) = verify_uploads(&recording_dir, &options, uploader.as_ref(), chunk_retention).await {
+              warn!("Failed to verify the uploads of {}: {}", options.video_id, e);
+          }
+          let status = finalize_upload_status(&recording_dir);
+          info!("Recording {} finished with status {:?}", options.video_id, status);
+          reconcile_duration(app.as_ref(), &recording_dir);
+          report_upload_result(app.as_ref(), &recording_dir, &options.video_id, status);
+
+          if !options.muxed_audio.unwrap_or_default() {
+              jobs::enqueue(&options.video_id, JobKind::Waveform);
+          }
+          if settings.transcription_enabled {
+              jobs::enqueue(&options.video_id, JobKind::Transcribe { model_path: settings.whisper_model_path.clone() });
+          }
+          if settings.remux_to_mp4 {
+              jobs::enqueue(&options.video_id, JobKind::RemuxMp4);
+          }
+          if settings.export_webm {
+              jobs::enqueue(&options.video_id, JobKind::ExportWebm { two_pass: settings.webm_two_pass });
+          }
+          if !settings.renditions.is_empty() {
+              jobs::enqueue(&options.video_id, JobKind::Renditions {
+                  heights: settings.renditions.clone(),
+                  remove_segments: settings.chunk_retention == ChunkRetention::KeepUntilConfirmed,
+              });
+          }
+          jobs::enqueue(&options.video_id, JobKind::Chapters);
+          jobs::enqueue(&options.video_id, JobKind::UploadManifest);
+      },
+      Err(e) => {
+          error!("An error occurred: {}", e);
+          set_recording_status(&recording_dir, RecordingStatus::Failed);
+          report_upload_result(app.as_ref(), &recording_dir, &options.video_id, RecordingStatus::Failed);
+      },
+  }
+
+  Ok(())
+}
+
+/// Emits `recording://countdown` once a second until `delay_secs` have passed.
+async fn run_countdown(app: Option<&AppHandle>, video_id: &str, delay_secs: u32, countdown: &CancellationToken) -> Result<(), String> {
+  info!("Starting {} in {} seconds", video_id, delay_secs);
+  for remaining_secs in (1..=delay_secs).rev() {
+    if let Some(app) = app {
+      let _ = app.emit_all(COUNTDOWN_EVENT, Countdown { video_id: video_id.to_string(), remaining_secs });
+    }
+    tokio::select! {
+      _ = countdown.cancelled() => {
+        info!("Countdown for {} was cancelled", video_id);
+        return Err("The recording was cancelled before it started".to_string());
+      },
+      _ = tokio::time::sleep(Duration::from_secs(1)) => {},
+    }
+  }
+  Ok(())
+}
+
+/// Spawns `start_recording` and waits until capture is running. The returned task
+/// keeps running the upload loops until the recording is stopped.
+pub async fn spawn_recording(
+  app: Option<AppHandle>,
+  state: Arc<Mutex<RecordingState>>,
+  settings: Arc<Mutex<Settings>>,
+  options: RecordingOptions,
+) -> Result<JoinHandle<Result<(), String>>, String> {
+  let mut recording = tokio::spawn(start_recording(app, state.clone(), settings, options));
+
+  // The start holds the state lock until capture is running.
+  loop {
+    tokio::select! {
+      result = &mut recording => {
+        return Err(match result {
+          Ok(Err(e)) => e,
+          Err(e) => e.to_string(),
+          Ok(Ok(())) => "Recording stopped unexpectedly".to_string(),
+        });
+      },
+      _ = tokio::time::sleep(Duration::from_millis(100)) => {
+        if state.try_lock().map_or(false, |guard| guard.is_recording()) {
+          return Ok(recording);
+        }
+      },
+    }
+  }
+}
+
+/// Ends the current recording and continues under `video_id` with the same options.
+/// The new capture is started before the old one stops, so the two overlap slightly
+/// instead of leaving a gap.
+#[tauri::command]
+pub async fn split_recording(
+  app: AppHandle,
+  state: State<'_, Arc<Mutex<RecordingState>>>,
+  settings: State<'_, Arc<Mutex<Settings>>>,
+  video_id: String,
+) -> Result<(), String> {
+  validate_video_id(&video_id)?;
+  let state = state.inner().clone();
+
+  let mut guard = state.lock().await;
+  if !guard.is_recording() {
+    return Err("No recording is in progress".to_string());
+  }
+  if guard.is_paused() {
+    return Err("Resume the recording before splitting it".to_string());
+  }
+  let mut options = guard.recording_options.clone().ok_or("Recording options are not set".to_string())?;
+  if options.video_id == video_id {
+    return Err("The new recording needs a different video id".to_string());
+  }
+  let previous_video_id = std::mem::replace(&mut options.video_id, video_id);
+  options.start_delay_secs = 0;
+  let mut previous = guard.take_capture();
+  drop(guard);
+  if let Some(ref lease) = previous.device_lease {
+    lease.set_shared(true);
+  }
+
+  info!("Splitting recording {} into {}", previous_video_id, options.video_id);
+  let new_video_id = options.video_id.clone();
+  if let Err(e) = spawn_recording(Some(app), state.clone(), settings.inner().clone(), options).await {
+    error!("Failed to start {}, continuing {}: {}", new_video_id, previous_video_id, e);
+    let mut guard = state.lock().await;
+    if let Some(ref data_dir) = guard.data_dir {
+      if let Ok(new_recording_dir) = recording_dir(data_dir, &new_video_id) {
+        let _ = tokio::fs::remove_dir_all(new_recording_dir).await;
+      }
+    }
+    if let Some(ref lease) = previous.device_lease {
+      lease.set_shared(false);
+    }
+    *guard = previous;
+    return Err(e);
+  }
+
+  stop_capture(&mut previous, StopReason::Split).await;
+  info!("Recording {} continues as {}", previous_video_id, new_video_id);
+
+  Ok(())
+}
+
+/// What a stopped recording ended up as, for the completion screen.
+#[derive(Debug, Clone, Serialize)]
+pub struct RecordingSummary {
+    pub video_id: String,
+    pub status: RecordingStatus,
+    /// Recorded time excluding pauses.
+    pub duration_secs: Option<f64>,
+    /// Number of segments recorded per track ("screen", "audio").
+    pub segment_counts: BTreeMap<String, usize>,
+    pub bytes_uploaded: u64,
+    pub screenshot_url: Option<String>,
+    /// Segments that could not be uploaded, per track; `retry_upload` picks them up.
+    pub failed_chunks: BTreeMap<String, Vec<String>>,
+    /// `failed_chunks` with the error each one last failed with.
+    pub chunk_failures: BTreeMap<String, Vec<ChunkFailure>>,
+    /// Why capture stopped early, when it did.
+    pub capture_failure: Option<CaptureFailure>,
+    pub stop_reason: Option<StopReason>,
+    /// Where the recording can be watched; set once it is completely uploaded to Cap.
+    pub share_url: Option<String>,
+}
+
+/// Returns `None` when nothing was being recorded.
+#[tauri::command]
+pub async fn stop_all_recordings(
+    state: State<'_, Arc<Mutex<RecordingState>>>,
+    settings: State<'_, Arc<Mutex<Settings>>>,
+) -> Result<Option<RecordingSummary>, String> {
+    info!("!!STOPPING screen recording...");
+
+    let mut summary = stop_recording(&state, StopReason::UserStop).await;
+    let settings = settings.lock().await.clone();
+    if let Some(summary) = summary.as_mut() {
+        // Local uploads never reach Cap, so there is nothing to share.
+        if settings.local_upload_dir.is_some() {
+            summary.share_url = None;
+        }
+        if let (true, Some(share_url)) = (settings.copy_share_link_to_clipboard, summary.share_url.as_deref()) {
+            match copy_text(share_url) {
+                Ok(()) => info!("Copied share link {} to the clipboard", share_url),
+                Err(e) => warn!("{}", e),
+            }
+        }
+    }
+
+    Ok(summary)
+}
+
+/// Stops capture and waits for the remaining segments to upload and be verified.
+pub async fn stop_recording(state: &Mutex<RecordingState>, reason: StopReason) -> Option<RecordingSummary> {
+    let mut guard = state.lock().await;
+    if let Some(countdown) = guard.countdown.take() {
+        countdown.cancel();
+        return None;
+    }
+
+    if !guard.is_recording() {
+        return None;
+    }
+
+    stop_capture(&mut guard, reason).await;
+    // Uploads can take a while, or wait for the connection; the state stays usable
+    // meanwhile so the status can be read and the app can quit.
+    let (uploads, recording_dir, uploader) = (guard.uploads.clone(), guard.recording_dir.clone(), guard.uploader.clone());
+    drop(guard);
+    wait_for_uploads(uploads).await;
+    
+    info!("All recordings and uploads stopped.");
+
+    let recording_dir = recording_dir?;
+    let status = wait_for_upload_status(&recording_dir).await;
+    let summary = recording_summary(&recording_dir, status, uploader.as_deref());
+    let mut guard = state.lock().await;
+    if guard.recording_dir.as_ref() == Some(&recording_dir) {
+        guard.recording_dir = None;
+    }
+    Some(summary)
+}
+
+/// The upload loops finish before their segments are verified; the recording task sets
+/// the final status once that is done too. Gives up after `UPLOAD_STATUS_TIMEOUT`, in
+/// which case the recording is reported as still uploading.
+async fn wait_for_upload_status(recording_dir: &Path) -> RecordingStatus {
+    let wait = async {
+        loop {
+            match RecordingManifest::load(recording_dir) {
+                Ok(manifest) if manifest.status != RecordingStatus::Uploading => return manifest.status,
+                Ok(_) => tokio::time::sleep(Duration::from_millis(100)).await,
+                Err(e) => {
+                    warn!("Failed to read the upload status: {}", e);
+                    return RecordingStatus::Failed;
+                },
+            }
+        }
+    };
+    match tokio::time::timeout(UPLOAD_STATUS_TIMEOUT, wait).await {
+        Ok(status) => status,
+        Err(_) => {
+            warn!("Uploads of {} were not verified in time", recording_dir.display());
+            RecordingStatus::Uploading
+        },
+    }
+}
+
+fn recording_summary(recording_dir: &Path, status: RecordingStatus, uploader: Option<&dyn Uploader>) -> RecordingSummary {
+    let manifest = RecordingManifest::load(recording_dir).ok();
+    let video_id = recording_dir.file_name().and_then(|name| name.to_str()).unwrap_or_default().to_string();
+
+    let mut segment_counts = BTreeMap::new();
+    let mut failed_chunks = BTreeMap::new();
+    for video_type in ["screen", "audio"] {
+        let segments = load_segment_list(&recording_dir.join(video_type).join("segment_list.txt")).unwrap_or_default();
+        segment_counts.insert(video_type.to_string(), segments.len());
+        let pending = pending_segments(recording_dir, video_type).unwrap_or_default();
+        if !pending.is_empty() {
+            failed_chunks.insert(video_type.to_string(), pending);
+        }
+    }
+
+    RecordingSummary {
+        bytes_uploaded: upload_stats::upload_stats(&video_id).map_or(0, |stats| stats.bytes_uploaded),
+        screenshot_url: manifest.as_ref().and_then(|manifest| {
+            let key = manifest.screenshot_key.as_ref()?;
+            uploader?.object_url(&manifest.options, key)
+        }),
+        duration_secs: manifest.as_ref().and_then(|manifest| manifest.duration_secs),
+        stop_reason: manifest.as_ref().and_then(|manifest| manifest.stop_reason),
+        capture_failure: manifest.and_then(|manifest| manifest.capture_failure),
+        share_url: (status == RecordingStatus::Complete).then(|| share_link(&video_id)),
+        video_id,
+        status,
+        segment_counts,
+        failed_chunks,
+        chunk_failures: chunk_failures(recording_dir),
+    }
+}
+
+#[tauri::command]
+pub async fn pause_recording(state: State<'_, Arc<Mutex<RecordingState>>>) -> Result<(), String> {
+    set_paused(&mut *state.lock().await, true)
+}
+
+#[tauri::command]
+pub async fn resume_recording(state: State<'_, Arc<Mutex<RecordingState>>>) -> Result<(), String> {
+    set_paused(&mut *state.lock().await, false)
+}
+
+/// Stops capture and discards the recording: remaining segments are not uploaded and
+/// the local files are deleted. Segments that were already uploaded stay uploaded.
+#[tauri::command]
+pub async fn cancel_recording(state: State<'_, Arc<Mutex<RecordingState>>>) -> Result<(), String> {
+    discard_recording(&state).await
+}
+
+pub async fn discard_recording(state: &Mutex<RecordingState>) -> Result<(), String> {
+    let mut guard = state.lock().await;
+    if let Some(countdown) = guard.countdown.take() {
+        countdown.cancel();
+        return Ok(());
+    }
+    if !guard.is_recording() {
+        return Err("No recording is in progress".to_string());
+    }
+
+    info!("Cancelling recording...");
+    guard.upload_cancelled.store(true, Ordering::SeqCst);
+    stop_capture(&mut guard, StopReason::Cancelled).await;
+    let (uploads, recording_dir) = (guard.uploads.clone(), guard.recording_dir.take());
+    drop(guard);
+    wait_for_uploads(uploads).await;
+
+    if let Some(recording_dir) = recording_dir {
+        tokio::fs::remove_dir_all(&recording_dir).await
+            .map_err(|e| format!("Failed to delete cancelled recording: {}", e))?;
+    }
+    info!("Recording cancelled.");
+
+    Ok(())
+}
+
+pub fn set_paused(guard: &mut RecordingState, paused: bool) -> Result<(), String> {
+    if !guard.is_recording() {
+        return Err("No recording is in progress".to_string());
+    }
+    if guard.is_paused() == paused {
+        return Ok(());
+    }
+    if !guard.can_pause() {
+        return Err("Recordings with muxed audio or live drawing can't be paused on Windows".to_string());
+    }
+
+    #[cfg(feature = "native-encoder")]
+    if let Some(ref encoder) = guard.native_encoder {
+        if paused { encoder.pause()? } else { encoder.resume()? }
+    }
+
+    if let Some(ref gate) = guard.frame_gate {
+        gate.set_paused(paused);
+    } else if let Some(ref screen_process) = guard.screen_process {
+        signal_ffmpeg(screen_process, paused)?;
+    }
+
+    if let Some(ref mut audio_process) = guard.audio_process {
+        if paused { audio_process.pause()? } else { audio_process.trigger_play()? }
+    }
+
+    mouse_events::set_paused(paused);
+    if paused {
+        guard.paused_at = Some(Instant::now());
+    } else if let Some(paused_at) = guard.paused_at.take() {
+        guard.paused_time += paused_at.elapsed();
+    }
+    info!("Recording {}", if paused { "paused" } else { "resumed" });
+
+    Ok(())
+}
+
+/// Called when the app is about to exit. Stops capture so ffmpeg finalizes the last
+/// segment, then gives uploads a bounded amount of time. Anything still pending is
+/// left in the manifest for `retry_upload`.
+pub async fn shutdown_recording(state: &Mutex<RecordingState>) {
+    let mut guard = state.lock().await;
+
+    if guard.is_recording() {
+        info!("App is exiting, stopping the active recording...");
+        stop_capture(&mut guard, StopReason::AppExit).await;
+    }
+    let (uploads, recording_dir) = (guard.uploads.clone(), guard.recording_dir.clone());
+    drop(guard);
+
+    if tokio::time::timeout(SHUTDOWN_UPLOAD_TIMEOUT, wait_for_uploads(uploads)).await.is_err() {
+        if let Some(ref recording_dir) = recording_dir {
+            warn!("Uploads did not finish before exit, leaving them for retry");
+            set_recording_status(recording_dir, RecordingStatus::Failed);
+        }
+    }
+}
+
+/// Stops the capture processes; `reason` goes into the manifest, the session journal
+/// and the `recording_stopped` webhook.
+async fn stop_capture(guard: &mut RecordingState, reason: StopReason) {
+    let duration_secs = guard.is_recording().then(|| guard.elapsed().as_secs_f64());
+
+    // A suspended ffmpeg can't read the quit command.
+    if guard.is_paused() {
+        if let Err(e) = set_paused(guard, false) {
+            error!("Failed to resume recording before stopping: {}", e);
+        }
+    }
+
+    info!("Stopping screen recording...");
+
+    // Both ffmpegs finish their last segment at the same time, rather than one after the other.
+    if let Some(stdin) = guard.screen_process_stdin.take() {
+        info!("Sending quit command to FFmpeg...");
+        if let Err(e) = graceful_stop_ffmpeg(stdin).await {
+            error!("Failed to send quit command to FFmpeg: {}", e);
+        }
+    }
+
+    if let Some(mut audio_process) = guard.audio_process.take() {
+        info!("Stopping audio recording...");
+        if let Err(e) = audio_process.stop_audio_recording().await {
+            error!("Failed to stop audio recording: {}", e);
+        }
+    }
+
+    #[cfg(feature = "native-encoder")]
+    if let Some(encoder) = guard.native_encoder.take() {
+        info!("Stopping native screen encoder...");
+        match tokio::task::spawn_blocking(move || encoder.stop()).await {
+            Ok(Err(e)) => error!("Native screen encoder stopped with an error: {}", e),
+            Err(e) => error!("Failed to join native screen encoder: {}", e),
+            Ok(Ok(())) => {},
+        }
+    }
+
+    if let Some(mut screen_process) = guard.screen_process.take() {
+        match tokio::time::timeout(FFMPEG_EXIT_TIMEOUT, screen_process.wait()).await {
+            Ok(Ok(status)) => debug!("Screen FFmpeg exited with {}", status),
+            Ok(Err(e)) => error!("Failed to wait for screen FFmpeg: {}", e),
+            Err(_) => {
+                warn!("Screen FFmpeg did not exit in time, killing it");
+                let _ = screen_process.kill().await;
+            },
+        }
+    }
+    // The capture process has exited, which ends the encoder's input.
+    if let Some(gate) = guard.frame_gate.take() {
+        gate.finish().await;
+    }
+    guard.device_lease = None;
+    // The upload loops' last pass reads the segment list, so it has to be complete.
+    if let Some(relay) = guard.segment_list_relay.take() {
+        let _ = relay.await;
+    }
+
+    if let (true, Some(recording_dir)) = (guard.is_recording(), guard.recording_dir.as_ref()) {
+        journal::append(recording_dir, JournalEvent::Stopped { reason: reason.as_str().to_string() });
+    }
+    guard.shutdown.cancel();
+    guard.started_at = None;
+    guard.paused_at = None;
+    guard.wake_lock = None;
+    guard.do_not_disturb = None;
+
+    let markers = std::mem::take(&mut guard.markers);
+    let mouse_events = guard.recording_options.as_ref().and_then(|options| mouse_events::stop(&options.video_id));
+    if let (false, Some(recording_dir), Some(options)) =
+        (guard.upload_cancelled.load(Ordering::SeqCst), guard.recording_dir.clone(), guard.recording_options.clone()) {
+        tauri::async_runtime::spawn(async move {
+            let video_id = options.video_id.clone();
+            if !markers.is_empty() {
+                if let Err(e) = upload_markers(recording_dir.clone(), options.clone(), markers).await {
+                    warn!("Failed to upload markers for {}: {}", video_id, e);
+                }
+            }
+            if let Some(events) = mouse_events {
+                if let Err(e) = mouse_events::upload_events(recording_dir, options, events).await {
+                    warn!("Failed to upload mouse events for {}: {}", video_id, e);
+                }
+            }
+        });
+    }
+
+    // The recording task sets the final status; without a capture there is none to
+    // wait for.
+    if let (Some(recording_dir), Some(duration_secs)) = (guard.recording_dir.as_ref(), duration_secs) {
+        let result = update_manifest(recording_dir, |manifest| {
+            manifest.status = RecordingStatus::Uploading;
+            manifest.duration_secs = manifest.duration_secs.or(Some(duration_secs));
+            manifest.stop_reason = Some(reason);
+        });
+        if let Err(e) = result {
+            warn!("Failed to update manifest in {}: {}", recording_dir.display(), e);
+        }
+    }
+
+    if let (Some(duration_secs), Some(options)) = (duration_secs, &guard.recording_options) {
+        notify(WebhookEvent::RecordingStopped, &options.video_id, Some(duration_secs), Some(reason));
+    }
+}
+
+/// Awaited with the state unlocked, since uploads can wait a long time for the connection.
+async fn wait_for_uploads(uploads: Option<UploadLoops>) {
+    if let Some(uploads) = uploads {
+        debug!("Waiting for uploads to finish...");
+        let _ = uploads.await;
+    }
+}
+
+/// Uploads whatever segments of a previous recording are still pending, e.g. after
+/// an extended network outage. Segments the manifest lists as uploaded are skipped.
+#[tauri::command]
+pub async fn retry_upload(
+    app: AppHandle,
+    state: State<'_, Arc<Mutex<RecordingState>>>,
+    settings: State<'_, Arc<Mutex<Settings>>>,
+    video_id: String,
+) -> Result<RecordingStatus, String> {
+    let (max_concurrent_uploads, uploader, chunk_retention) = {
+        let settings = settings.lock().await;
+        (settings.max_concurrent_uploads, settings.uploader(), settings.chunk_retention)
+    };
+
+    let guard = state.lock().await;
+    let data_dir = guard.data_dir.clone()
+        .ok_or("Data directory is not set in the recording state".to_string())?;
+    let recording_dir = recording_dir(&data_dir, &video_id)?;
+    let manifest = RecordingManifest::load(&recording_dir)?;
+
+    let is_active = guard.recording_dir.as_ref() == Some(&recording_dir);
+    if is_active && matches!(manifest.status, RecordingStatus::Recording | RecordingStatus::Uploading) {
+        return Err("This recording is still being uploaded".to_string());
+    }
+    drop(guard);
+
+    info!("Retrying upload for recording {}", video_id);
+    upload_stats::reset(&video_id);
+    set_recording_status(&recording_dir, RecordingStatus::Uploading);
+
+    // With shutdown already cancelled, each loop makes a single pass over the chunks.
+    let shutdown = CancellationToken::new();
+    shutdown.cancel();
+    let upload_limit = Arc::new(Semaphore::new(max_concurrent_uploads));
+    let upload_cancelled = Arc::new(AtomicBool::new(false));
+    let screen_upload = start_upload_loop(recording_dir.clone(), manifest.options.clone(), "screen".to_string(), shutdown.clone(), upload_cancelled.clone(), upload_limit.clone(), uploader.clone());
+    let audio_upload = start_upload_loop(recording_dir.clone(), manifest.options.clone(), "audio".to_string(), shutdown, upload_cancelled, upload_limit, uploader.clone());
+
+    if let Err(e) = tokio::try_join!(screen_upload, audio_upload) {
+        error!("Retrying upload for {} failed: {}", video_id, e);
+        set_recording_status(&recording_dir, RecordingStatus::Failed);
+        report_upload_result(Some(&app), &recording_dir, &video_id, RecordingStatus::Failed);
+        return Err(e);
+    }
+
+    if let Err(e) = verify_uploads(&recording_dir, &manifest.options, uploader.as_ref(), chunk_retention).await {
+        warn!("Failed to verify the uploads of {}: {}", video_id, e);
+    }
+    let status = finalize_upload_status(&recording_dir);
+    info!("Retried upload for {} finished with status {:?}", video_id, status);
+    reconcile_duration(Some(&app), &recording_dir);
+    if !manifest.options.muxed_audio.unwrap_or_default() {
+        jobs::enqueue(&video_id, JobKind::Waveform);
+    }
+    if let Err(e) = retry_backups(&recording_dir, &manifest.options).await {
+        warn!("Failed to back up {}: {}", video_id, e);
+    }
+    report_upload_result(Some(&app), &recording_dir, &video_id, status);
+
+    Ok(status)
+}
+
+fn report_upload_result(app: Option<&AppHandle>, recording_dir: &Path, video_id: &str, status: RecordingStatus) {
+    notify_upload_result(recording_dir, status);
+    if let Some(app) = app {
+        notify_upload_finished(app, video_id, status);
+        upload_stats::emit_summary(app, video_id, status);
+        upload_stats::emit_failure_report(app, recording_dir, video_id);
+    }
+}
+
+/// Where the thumbnail of a recording is taken from.
+enum ScreenshotSource {
+    /// The display being recorded, grabbed natively.
+    Screen(usize),
+    /// The first frame of a recorded segment.
+    FirstSegment(PathBuf),
+}
+
+async fn take_screenshot(
+    ffmpeg_binary: PathBuf,
+    source: ScreenshotSource,
+    screenshot_path: PathBuf,
+    options: RecordingOptions,
+    uploader: Arc<dyn Uploader>,
+) -> Result<(), String> {
+    info!("Waiting for 3 seconds before taking the screenshot...");
+    tokio::time::sleep(Duration::from_secs(3)).await;
+    
+    match source {
+        ScreenshotSource::Screen(screen_index) => {
+            save_screenshot(screen_index, screenshot_path.clone()).await?;
+        },
+        ScreenshotSource::FirstSegment(segment_path) => {
+            let status = tokio::process::Command::new(&ffmpeg_binary)
+                .arg("-y")
+                .arg("-i")
+                .arg(&segment_path)
+                .args(["-vframes", "1"])
+                .arg(&screenshot_path)
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status()
+                .await
+                .map_err(|e| format!("Failed to execute screenshot command: {}", e))?;
+            if !status.success() {
+                return Err(format!("Screenshot command exited with {}", status));
+            }
+        },
+    }
+    info!("Screenshot captured: {}", screenshot_path.display());
+
+    match uploader.upload_file(&options, &screenshot_path, "screenshot").await {
+        Ok(file_key) => {
+            info!("Screenshot uploaded successfully.");
+            if let Err(e) = tokio::fs::remove_file(&screenshot_path).await {
+                warn!("Failed to remove screenshot after upload: {}", e);
+            }
+            if let Some(recording_dir) = screenshot_path.parent() {
+                if let Err(e) = update_manifest(recording_dir, |manifest| manifest.screenshot_key = Some(file_key)) {
+                    warn!("Failed to record the screenshot in the manifest: {}", e);
+                }
+            }
+        },
+        Err(e) => error!("Failed to upload screenshot: {}", e),
+    }
+
+    Ok(())
+}
+
+async fn upload_jpeg_files(
+    dir_path: &PathBuf,
+    options: Option<RecordingOptions>,
+) -> Result<(), String> {
+    let dir_entries = std::fs::read_dir(dir_path).map_err(|e| format!("Failed to read dir: {}", e))?;
+    for entry in dir_entries {
+        let entry = entry.map_err(|e| format!("Failed to process dir entry: {}", e))?;
+        let path = entry.path();
+        if path.is_file() && path.extension().map_or(false, |ext| ext == "jpeg" || ext == "jpg") {
+            info!("Found JPEG file for upload: {}", path.display());
+            let options = options.as_ref().ok_or("No recording options provided".to_string())?;
+            upload_and_remove_file(options, &path, "screenshot").await.map_err(|e| format!("Failed to upload JPEG: {}", e))?;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,430 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use serde::{Serialize, Deserialize};
+use tauri::{AppHandle, State};
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+use crate::audio::validate_audio_format;
+use crate::backup::{set_backup_destination, BackupDestination};
+use crate::control_api::{generate_token, restart_control_api, ControlApiSettings};
+use crate::capture::ExtraFfmpegArgs;
+use crate::faults::wrap_uploader;
+use crate::options::{empty_as_none, validate_audio_offset, validate_framerate, validate_resolution, DeviceId, Resolution};
+use crate::hotkeys::{register_hotkeys, HotkeySettings};
+use crate::jobs::set_max_concurrent_jobs;
+use crate::recording::{CameraBackground, CaptureMode, ChunkNaming, RecordingOptions, UploadOrder, VideoQuality};
+use crate::session::RecordingState;
+use crate::utils::ensure_ffmpeg;
+use crate::network::set_pause_on_metered;
+use crate::multipart::{set_multipart_settings, MultipartSettings};
+use crate::sts::set_use_sts;
+use crate::upload::{LocalDirUploader, S3Uploader, StorageOptions, Uploader};
+use crate::webhooks::{set_webhooks, validate_webhooks, Webhook};
+
+const SETTINGS_FILE: &str = "settings.json";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// User-provided ffmpeg binary, used instead of the bundled one when it validates.
+    pub ffmpeg_path: Option<String>,
+    /// Where recordings are written; defaults to the app data dir.
+    pub data_dir: Option<String>,
+    #[serde(deserialize_with = "empty_as_none")]
+    pub screen_index: Option<DeviceId>,
+    #[serde(deserialize_with = "empty_as_none")]
+    pub video_index: Option<DeviceId>,
+    pub audio_name: String,
+    #[serde(deserialize_with = "empty_as_none")]
+    pub framerate: Option<u32>,
+    #[serde(deserialize_with = "empty_as_none")]
+    pub resolution: Option<Resolution>,
+    pub segment_time_secs: u32,
+    pub audio_codec: String,
+    pub audio_sample_rate: u32,
+    pub audio_channels: u16,
+    pub audio_bitrate: String,
+    pub force_full_range: bool,
+    pub video_quality: VideoQuality,
+    pub max_concurrent_uploads: usize,
+    /// Post-processing jobs, such as transcription, run at the same time.
+    pub max_concurrent_jobs: usize,
+    /// Joins each finished recording into `recording.mp4` next to its segments. Needs
+    /// a `chunk_retention` that keeps the segments after upload.
+    pub remux_to_mp4: bool,
+    /// Also encodes each finished recording into a VP9 and Opus `recording.webm`. Like
+    /// `remux_to_mp4`, this needs the segments kept after upload.
+    pub export_webm: bool,
+    /// Encodes the WebM export in two passes; slower, but smaller for the same quality.
+    pub webm_two_pass: bool,
+    /// Heights, like 720 and 480, of smaller copies transcoded and uploaded after each
+    /// recording for viewers on slow connections. Empty by default.
+    pub renditions: Vec<u32>,
+    /// Days to keep fully uploaded recordings on disk; `0`, the default, keeps them forever.
+    pub retention_days: u32,
+    /// When the local copies of uploaded segments are removed.
+    pub chunk_retention: ChunkRetention,
+    pub hotkeys: HotkeySettings,
+    pub control_api: ControlApiSettings,
+    pub webhooks: Vec<Webhook>,
+    /// Captions each recording with Whisper once its uploads finish.
+    pub transcription_enabled: bool,
+    /// Whisper `ggml` model file used for transcription.
+    pub whisper_model_path: String,
+    /// Records which app and window title are focused during a recording and uploads
+    /// the timeline with it. Off by default since titles can be sensitive.
+    pub track_active_window: bool,
+    /// Records mouse moves, clicks and scrolls alongside the video for the editor.
+    /// Needs the accessibility permission on macOS.
+    pub record_mouse_events: bool,
+    /// Holds back uploads while the connection is metered; they resume once it isn't.
+    /// Has no effect on macOS, which can't tell whether a connection is metered.
+    pub pause_uploads_on_metered: bool,
+    /// Default S3 object settings for uploads, e.g. a cheaper storage class.
+    pub storage: StorageOptions,
+    /// Signs uploads locally with short-lived credentials from the Cap API instead of
+    /// requesting a signed POST for every segment.
+    pub use_sts_credentials: bool,
+    /// Part size and parallelism of multipart uploads, which need `use_sts_credentials`.
+    pub multipart_upload: MultipartSettings,
+    /// Copies every segment here as well as to Cap, for an on-prem copy of each
+    /// recording.
+    pub backup_destination: Option<BackupDestination>,
+    /// Silences notifications while recording. On macOS this runs the "Cap Focus On"
+    /// and "Cap Focus Off" shortcuts, which have to be created in the Shortcuts app.
+    pub do_not_disturb: bool,
+    /// Starts uploading each segment as soon as ffmpeg moves on to the next one.
+    pub early_segment_upload: bool,
+    /// Order a backlog of segments is uploaded in.
+    pub upload_order: UploadOrder,
+    /// Lowers the screen encoder's quality, then frame rate, when it can't keep up
+    /// with real time, rather than letting it drop more and more frames.
+    pub adaptive_quality: bool,
+    /// Lengthens screen segments while uploads can't keep up, so each request carries
+    /// more video, and shortens them again once the backlog clears.
+    pub adaptive_segment_time: bool,
+    /// Segments waiting for upload, in MB, past which a recording warns that uploads
+    /// are falling behind; `0` never warns.
+    pub max_upload_backlog_mb: u64,
+    /// Also lowers the screen encoder's quality while more than `max_upload_backlog_mb`
+    /// waits for upload, so a long recording without a connection fills the disk more
+    /// slowly. Not available with frame accurate pausing.
+    pub reduce_bitrate_on_backlog: bool,
+    /// Pauses by dropping frames between a capture and an encoder process instead of
+    /// suspending ffmpeg, so a paused recording has no jump in its timestamps. Costs
+    /// an extra process and copying every frame; adaptive capture is off with it.
+    /// Always on on Windows, where ffmpeg can't be suspended.
+    pub frame_accurate_pause: bool,
+    /// Also puts screenshots from `take_screenshot` on the clipboard, annotated if they
+    /// went through `annotate_screenshot`.
+    pub copy_screenshot_to_clipboard: bool,
+    /// Copies the share link of a recording to the clipboard when `stop_all_recordings`
+    /// returns with it uploaded.
+    pub copy_share_link_to_clipboard: bool,
+    /// Background treatment for camera recordings.
+    pub camera_background: CameraBackground,
+    /// Records the mic in the screen capture process; see `RecordingOptions::muxed_audio`.
+    pub muxed_audio: bool,
+    /// Automatic gain control on the mic; see `RecordingOptions::auto_gain`.
+    pub auto_gain: bool,
+    /// Records live drawing over the screen; see `RecordingOptions::live_drawing`.
+    pub live_drawing: bool,
+    /// Appended to the video capture's ffmpeg command, for power users.
+    pub extra_ffmpeg_args: ExtraFfmpegArgs,
+    /// Audio offset in milliseconds per mic name, applied to recordings with that mic.
+    /// See `RecordingOptions::audio_offset_ms`.
+    pub audio_offsets_ms: BTreeMap<String, i32>,
+    /// Copies segments into this directory instead of uploading them to Cap, for
+    /// running recordings end to end without network access.
+    pub local_upload_dir: Option<String>,
+    /// How segment files are named; see `RecordingOptions::chunk_naming`.
+    pub chunk_naming: ChunkNaming,
+}
+
+/// How long segment files stay on disk after they are uploaded. Verified segments are
+/// safe to remove; keeping them allows trimming into uploaded parts and local
+/// playback.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(tag = "policy", rename_all = "snake_case")]
+pub enum ChunkRetention {
+    /// Removed as soon as the server confirms it has them.
+    #[default]
+    KeepUntilConfirmed,
+    /// Removed at the first launch `days` after the recording completed.
+    KeepDays { days: u32 },
+    KeepForever,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            ffmpeg_path: None,
+            data_dir: None,
+            screen_index: None,
+            video_index: None,
+            audio_name: String::new(),
+            framerate: Some(30),
+            resolution: None,
+            segment_time_secs: 3,
+            audio_codec: "aac".to_string(),
+            audio_sample_rate: 0,
+            audio_channels: 0,
+            audio_bitrate: "128k".to_string(),
+            force_full_range: false,
+            video_quality: VideoQuality::Standard,
+            max_concurrent_uploads: 4,
+            max_concurrent_jobs: 1,
+            remux_to_mp4: false,
+            export_webm: false,
+            webm_two_pass: false,
+            renditions: vec![],
+            retention_days: 0,
+            chunk_retention: ChunkRetention::KeepUntilConfirmed,
+            hotkeys: HotkeySettings::default(),
+            control_api: ControlApiSettings::default(),
+            webhooks: vec![],
+            transcription_enabled: false,
+            whisper_model_path: String::new(),
+            track_active_window: false,
+            record_mouse_events: false,
+            pause_uploads_on_metered: true,
+            storage: StorageOptions::default(),
+            use_sts_credentials: false,
+            multipart_upload: MultipartSettings::default(),
+            backup_destination: None,
+            do_not_disturb: false,
+            early_segment_upload: false,
+            upload_order: UploadOrder::Ordered,
+            adaptive_quality: true,
+            adaptive_segment_time: true,
+            max_upload_backlog_mb: 2048,
+            reduce_bitrate_on_backlog: false,
+            frame_accurate_pause: false,
+            copy_screenshot_to_clipboard: false,
+            copy_share_link_to_clipboard: false,
+            camera_background: CameraBackground::None,
+            muxed_audio: false,
+            auto_gain: false,
+            live_drawing: false,
+            extra_ffmpeg_args: ExtraFfmpegArgs::default(),
+            audio_offsets_ms: BTreeMap::new(),
+            local_upload_dir: None,
+            chunk_naming: ChunkNaming::VideoId,
+        }
+    }
+}
+
+impl Settings {
+    /// Reads settings from the config dir, falling back to defaults when the file is
+    /// missing or unreadable so a bad file never prevents the app from starting.
+    pub fn load(config_dir: &Path) -> Settings {
+        let path = settings_path(config_dir);
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(_) => return Settings::default(),
+        };
+
+        serde_json::from_str(&contents).unwrap_or_else(|e| {
+            warn!("Ignoring invalid settings file {}: {}", path.display(), e);
+            Settings::default()
+        })
+    }
+
+    pub fn save(&self, config_dir: &Path) -> Result<(), String> {
+        std::fs::create_dir_all(config_dir).map_err(|e| e.to_string())?;
+        let contents = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        std::fs::write(settings_path(config_dir), contents).map_err(|e| format!("Failed to save settings: {}", e))
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.segment_time_secs == 0 {
+            return Err("Segment time must be at least 1 second".to_string());
+        }
+        if self.max_concurrent_uploads == 0 {
+            return Err("At least one concurrent upload is required".to_string());
+        }
+        if self.max_concurrent_jobs == 0 {
+            return Err("At least one concurrent job is required".to_string());
+        }
+        if let Some(height) = self.renditions.iter().find(|height| !(144..=2160).contains(*height) || *height % 2 != 0) {
+            return Err(format!("Rendition height must be an even number from 144 to 2160, got {}", height));
+        }
+        if let Some(framerate) = self.framerate {
+            validate_framerate(framerate)?;
+        }
+        if let Some(resolution) = self.resolution {
+            validate_resolution(resolution)?;
+        }
+        validate_audio_format(&self.audio_codec, self.audio_sample_rate, self.audio_channels, &self.audio_bitrate)?;
+        if self.transcription_enabled && !Path::new(&self.whisper_model_path).is_file() {
+            return Err("Transcription requires a Whisper model file".to_string());
+        }
+        for offset_ms in self.audio_offsets_ms.values() {
+            validate_audio_offset(*offset_ms)?;
+        }
+        self.extra_ffmpeg_args.validate()?;
+        self.storage.validate()?;
+        self.multipart_upload.validate()?;
+        if let Some(destination) = &self.backup_destination {
+            destination.validate()?;
+        }
+        self.hotkeys.validate()?;
+        validate_webhooks(&self.webhooks)
+    }
+
+    /// Fills every option the frontend left empty with the saved default.
+    pub fn apply_to(&self, options: &mut RecordingOptions) {
+        fill_if_none(&mut options.screen_index, &self.screen_index);
+        fill_if_none(&mut options.video_index, &self.video_index);
+        fill_if_empty(&mut options.audio_name, &self.audio_name);
+        fill_if_none(&mut options.framerate, &self.framerate);
+        fill_if_none(&mut options.resolution, &self.resolution);
+        if options.segment_time_secs == 0 {
+            options.segment_time_secs = self.segment_time_secs;
+        }
+        fill_if_empty(&mut options.audio_codec, &self.audio_codec);
+        fill_if_empty(&mut options.audio_bitrate, &self.audio_bitrate);
+        if options.audio_sample_rate == 0 {
+            options.audio_sample_rate = self.audio_sample_rate;
+        }
+        if options.audio_channels == 0 {
+            options.audio_channels = self.audio_channels;
+        }
+        options.force_full_range.get_or_insert(self.force_full_range);
+        options.video_quality.get_or_insert(self.video_quality);
+        options.storage.fill_from(&self.storage);
+        options.early_segment_upload.get_or_insert(self.early_segment_upload);
+        options.upload_order.get_or_insert(self.upload_order);
+        options.chunk_naming.get_or_insert(self.chunk_naming);
+        options.camera_background.get_or_insert(self.camera_background);
+        if options.capture_mode == CaptureMode::Screen {
+            options.muxed_audio.get_or_insert(self.muxed_audio);
+            options.live_drawing.get_or_insert(self.live_drawing);
+        }
+        options.auto_gain.get_or_insert(self.auto_gain);
+        if options.extra_ffmpeg_args.is_empty() {
+            options.extra_ffmpeg_args = self.extra_ffmpeg_args.clone();
+        }
+        if options.audio_offset_ms == 0 {
+            options.audio_offset_ms = self.audio_offsets_ms.get(&options.audio_name).copied().unwrap_or(0);
+        }
+    }
+
+    /// Where segments are uploaded, honouring the `local_upload_dir` override.
+    pub fn uploader(&self) -> Arc<dyn Uploader> {
+        let uploader: Arc<dyn Uploader> = match &self.local_upload_dir {
+            Some(dir) => Arc::new(LocalDirUploader::new(PathBuf::from(dir))),
+            None => Arc::new(S3Uploader),
+        };
+        wrap_uploader(uploader)
+    }
+
+    /// The recordings directory, honouring the `data_dir` override.
+    pub fn resolve_data_dir(&self, app_data_dir: PathBuf) -> PathBuf {
+        self.data_dir.as_ref().map(PathBuf::from).unwrap_or(app_data_dir)
+    }
+}
+
+fn fill_if_empty(value: &mut String, default: &str) {
+    if value.is_empty() {
+        *value = default.to_string();
+    }
+}
+
+fn fill_if_none<T: Clone>(value: &mut Option<T>, default: &Option<T>) {
+    if value.is_none() {
+        *value = default.clone();
+    }
+}
+
+/// Applies the settings that can be refused by the OS or fail to start, restoring
+/// the previous ones when any of them fails.
+async fn apply_restartable(app: &AppHandle, app_data_dir: &Path, new_settings: &Settings, previous: &Settings) -> Result<(), String> {
+    let hotkeys_changed = new_settings.hotkeys != previous.hotkeys;
+    let control_api_changed = new_settings.control_api != previous.control_api;
+
+    if hotkeys_changed {
+        if let Err(e) = register_hotkeys(app, &new_settings.hotkeys) {
+            let _ = register_hotkeys(app, &previous.hotkeys);
+            return Err(e);
+        }
+    }
+
+    let mut result = Ok(());
+    if control_api_changed {
+        result = restart_control_api(app, &new_settings.control_api).await;
+    }
+    if result.is_ok() && new_settings.ffmpeg_path != previous.ffmpeg_path {
+        let custom_path = new_settings.ffmpeg_path.clone().map(PathBuf::from);
+        let ffmpeg_data_dir = app_data_dir.to_path_buf();
+        result = tokio::task::spawn_blocking(move || ensure_ffmpeg(&ffmpeg_data_dir, custom_path.as_deref()))
+            .await
+            .map_err(|e| e.to_string())
+            .and_then(|ensured| ensured.map(|_| ()));
+    }
+
+    if result.is_err() {
+        if hotkeys_changed {
+            let _ = register_hotkeys(app, &previous.hotkeys);
+        }
+        if control_api_changed {
+            let _ = restart_control_api(app, &previous.control_api).await;
+        }
+    }
+    result
+}
+
+pub fn settings_path(config_dir: &Path) -> PathBuf {
+    config_dir.join(SETTINGS_FILE)
+}
+
+#[tauri::command]
+pub async fn get_settings(settings: State<'_, Arc<Mutex<Settings>>>) -> Result<Settings, String> {
+    Ok(settings.lock().await.clone())
+}
+
+#[tauri::command]
+pub async fn set_settings(
+    app: AppHandle,
+    settings: State<'_, Arc<Mutex<Settings>>>,
+    recording_state: State<'_, Arc<Mutex<RecordingState>>>,
+    mut new_settings: Settings,
+) -> Result<Settings, String> {
+    new_settings.validate()?;
+    if new_settings.control_api.enabled && new_settings.control_api.token.is_empty() {
+        new_settings.control_api.token = generate_token()?;
+    }
+
+    let app_data_dir = app.path_resolver().app_data_dir()
+        .ok_or("Data directory is not available".to_string())?;
+    let config_dir = app.path_resolver().app_config_dir()
+        .ok_or("Config directory is not available".to_string())?;
+
+    let mut settings_guard = settings.lock().await;
+
+    let data_dir = new_settings.resolve_data_dir(app_data_dir.clone());
+    std::fs::create_dir_all(&data_dir).map_err(|e| format!("Failed to create data directory: {}", e))?;
+    new_settings.save(&config_dir)?;
+
+    if let Err(e) = apply_restartable(&app, &app_data_dir, &new_settings, &settings_guard).await {
+        // What is live is still the previous settings, so that is what stays saved.
+        settings_guard.save(&config_dir)?;
+        return Err(e);
+    }
+
+    set_webhooks(new_settings.webhooks.clone());
+    set_pause_on_metered(new_settings.pause_uploads_on_metered);
+    set_use_sts(new_settings.use_sts_credentials);
+    set_multipart_settings(new_settings.multipart_upload);
+    set_backup_destination(new_settings.backup_destination.clone());
+    set_max_concurrent_jobs(new_settings.max_concurrent_jobs);
+    *settings_guard = new_settings.clone();
+    drop(settings_guard);
+
+    recording_state.lock().await.data_dir = Some(data_dir);
+    info!("Settings updated");
+
+    Ok(new_settings)
+}
@@ -0,0 +1,286 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use base64::Engine;
+use chrono::{DateTime, Duration, Utc};
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use serde_json::Value as JsonValue;
+use sha2::{Digest, Sha256};
+use tokio::sync::Mutex;
+use tracing::info;
+
+use crate::recording::RecordingOptions;
+use crate::upload::StorageOptions;
+
+/// Credentials are refreshed once they are this close to expiring, so an upload
+/// never starts with a token that runs out halfway through.
+const REFRESH_MARGIN_MINS: i64 = 5;
+const POLICY_VALIDITY_MINS: i64 = 15;
+/// Signed requests leave the body out of the signature, so it can be streamed.
+const UNSIGNED_PAYLOAD: &str = "UNSIGNED-PAYLOAD";
+
+static USE_STS: AtomicBool = AtomicBool::new(false);
+static CREDENTIALS: Mutex<Option<StsCredentials>> = Mutex::const_new(None);
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CredentialsResponse {
+    access_key_id: String,
+    secret_access_key: String,
+    session_token: String,
+    /// RFC 3339.
+    expiration: String,
+}
+
+/// Short-lived credentials issued by the Cap API for one user's uploads.
+#[derive(Debug, Clone)]
+struct StsCredentials {
+    user_id: String,
+    access_key_id: String,
+    secret_access_key: String,
+    session_token: String,
+    expiration: DateTime<Utc>,
+}
+
+impl StsCredentials {
+    fn is_fresh(&self, user_id: &str) -> bool {
+        self.user_id == user_id && self.expiration - Duration::minutes(REFRESH_MARGIN_MINS) > Utc::now()
+    }
+}
+
+/// A presigned POST: the URL to send the multipart form to and the fields it needs.
+pub struct PresignedPost {
+    pub url: String,
+    pub fields: Vec<(String, String)>,
+}
+
+/// A request to S3 signed with an `Authorization` header.
+pub struct SignedRequest {
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+}
+
+pub fn set_use_sts(enabled: bool) {
+    USE_STS.store(enabled, Ordering::SeqCst);
+}
+
+pub fn use_sts() -> bool {
+    USE_STS.load(Ordering::SeqCst)
+}
+
+/// Drops the cached credentials, e.g. after S3 rejected them, so the next upload
+/// fetches new ones.
+pub async fn invalidate_credentials() {
+    *CREDENTIALS.lock().await = None;
+}
+
+/// Signs a POST for `file_key` with the user's STS credentials, fetching or refreshing
+/// them first when needed. Long recordings keep uploading past the first token's
+/// expiry since every segment checks the credentials before it is signed.
+pub async fn presigned_post(options: &RecordingOptions, file_key: &str, duration: Option<&str>) -> Result<PresignedPost, String> {
+    let credentials = current_credentials(&options.user_id).await?;
+
+    let now = Utc::now();
+    let date = now.format("%Y%m%d").to_string();
+    let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+    let credential = format!("{}/{}/{}/s3/aws4_request", credentials.access_key_id, date, options.aws_region);
+
+    let mut fields = vec![
+        ("key".to_string(), file_key.to_string()),
+        ("x-amz-algorithm".to_string(), "AWS4-HMAC-SHA256".to_string()),
+        ("x-amz-credential".to_string(), credential),
+        ("x-amz-date".to_string(), amz_date),
+        ("x-amz-security-token".to_string(), credentials.session_token.clone()),
+    ];
+    if let Some(duration) = duration {
+        fields.push(("x-amz-meta-duration".to_string(), duration.to_string()));
+    }
+    fields.extend(storage_fields(&options.storage));
+
+    let mut conditions = vec![serde_json::json!({ "bucket": options.aws_bucket })];
+    conditions.extend(fields.iter().map(|(name, value)| {
+        let mut condition = serde_json::Map::new();
+        condition.insert(name.clone(), JsonValue::String(value.clone()));
+        JsonValue::Object(condition)
+    }));
+    let policy = serde_json::json!({
+        "expiration": (now + Duration::minutes(POLICY_VALIDITY_MINS)).format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string(),
+        "conditions": conditions,
+    });
+    let policy = base64::engine::general_purpose::STANDARD.encode(policy.to_string());
+
+    let signature = hex(&hmac_sha256(&signing_key(&credentials, &date, &options.aws_region), policy.as_bytes()));
+
+    fields.push(("policy".to_string(), policy));
+    fields.push(("x-amz-signature".to_string(), signature));
+
+    Ok(PresignedPost {
+        url: format!("{}/", bucket_url(options)),
+        fields,
+    })
+}
+
+/// The bucket uploads signed with STS credentials go to.
+pub fn bucket_url(options: &RecordingOptions) -> String {
+    format!("https://{}.s3.{}.amazonaws.com", options.aws_bucket, options.aws_region)
+}
+
+/// Signs a `method` request for `file_key` with the user's STS credentials, for the S3
+/// calls a presigned POST can't make, like the steps of a multipart upload. `headers`
+/// are signed along with the ones the signature needs.
+pub async fn signed_request(
+    options: &RecordingOptions,
+    method: &str,
+    file_key: &str,
+    query: &[(&str, &str)],
+    mut headers: Vec<(String, String)>,
+) -> Result<SignedRequest, String> {
+    let credentials = current_credentials(&options.user_id).await?;
+
+    let now = Utc::now();
+    let date = now.format("%Y%m%d").to_string();
+    let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+    let host = format!("{}.s3.{}.amazonaws.com", options.aws_bucket, options.aws_region);
+    let path = format!("/{}", uri_encode(file_key, false));
+
+    let mut query: Vec<(String, String)> = query.iter().map(|(name, value)| (uri_encode(name, true), uri_encode(value, true))).collect();
+    query.sort();
+    let query = query.iter().map(|(name, value)| format!("{}={}", name, value)).collect::<Vec<_>>().join("&");
+
+    headers.extend([
+        ("host".to_string(), host.clone()),
+        ("x-amz-content-sha256".to_string(), UNSIGNED_PAYLOAD.to_string()),
+        ("x-amz-date".to_string(), amz_date.clone()),
+        ("x-amz-security-token".to_string(), credentials.session_token.clone()),
+    ]);
+    let mut headers: Vec<(String, String)> = headers.into_iter().map(|(name, value)| (name.to_lowercase(), value.trim().to_string())).collect();
+    headers.sort();
+    let signed_headers = headers.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>().join(";");
+    let canonical_headers: String = headers.iter().map(|(name, value)| format!("{}:{}\n", name, value)).collect();
+
+    let canonical_request = format!("{}\n{}\n{}\n{}\n{}\n{}", method, path, query, canonical_headers, signed_headers, UNSIGNED_PAYLOAD);
+    let scope = format!("{}/{}/s3/aws4_request", date, options.aws_region);
+    let string_to_sign = format!("AWS4-HMAC-SHA256\n{}\n{}\n{}", amz_date, scope, hex(&Sha256::digest(canonical_request.as_bytes())));
+    let signature = hex(&hmac_sha256(&signing_key(&credentials, &date, &options.aws_region), string_to_sign.as_bytes()));
+
+    // reqwest sets the host itself.
+    headers.retain(|(name, _)| name != "host");
+    headers.push((
+        "authorization".to_string(),
+        format!("AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}", credentials.access_key_id, scope, signed_headers, signature),
+    ));
+    let url = if query.is_empty() { format!("https://{}{}", host, path) } else { format!("https://{}{}?{}", host, path, query) };
+
+    Ok(SignedRequest { url, headers })
+}
+
+/// The object settings `storage_fields` puts in a POST form, as request headers.
+pub fn storage_headers(storage: &StorageOptions) -> Vec<(String, String)> {
+    let mut headers: Vec<(String, String)> = storage_fields(storage).into_iter().filter(|(name, _)| name != "tagging").collect();
+    if !storage.tags.is_empty() {
+        let tags = storage.tags.iter()
+            .map(|(key, value)| format!("{}={}", uri_encode(key, true), uri_encode(value, true)))
+            .collect::<Vec<_>>()
+            .join("&");
+        headers.push(("x-amz-tagging".to_string(), tags));
+    }
+    headers
+}
+
+async fn current_credentials(user_id: &str) -> Result<StsCredentials, String> {
+    let mut cached = CREDENTIALS.lock().await;
+    if let Some(credentials) = cached.as_ref().filter(|credentials| credentials.is_fresh(user_id)) {
+        return Ok(credentials.clone());
+    }
+
+    let credentials = fetch_credentials(user_id).await?;
+    info!("Fetched upload credentials valid until {}", credentials.expiration);
+    *cached = Some(credentials.clone());
+
+    Ok(credentials)
+}
+
+async fn fetch_credentials(user_id: &str) -> Result<StsCredentials, String> {
+    let server_url_base: &'static str = dotenv_codegen::dotenv!("NEXT_PUBLIC_URL");
+    let server_url = format!("{}/api/upload/credentials", server_url_base);
+
+    let response = reqwest::Client::new()
+        .post(server_url)
+        .json(&serde_json::json!({ "userId": user_id }))
+        .send()
+        .await
+        .map_err(|e| format!("Failed to request upload credentials: {}", e))?;
+    if !response.status().is_success() {
+        return Err(format!("Failed to request upload credentials. Status: {}", response.status()));
+    }
+
+    let body: JsonValue = response.json().await
+        .map_err(|e| format!("Failed to read upload credentials: {}", e))?;
+    let credentials: CredentialsResponse = serde_json::from_value(body["credentials"].clone())
+        .map_err(|e| format!("Failed to deserialize upload credentials: {}", e))?;
+    let expiration = DateTime::parse_from_rfc3339(&credentials.expiration)
+        .map_err(|e| format!("Invalid upload credentials expiration: {}", e))?
+        .with_timezone(&Utc);
+
+    Ok(StsCredentials {
+        user_id: user_id.to_string(),
+        access_key_id: credentials.access_key_id,
+        secret_access_key: credentials.secret_access_key,
+        session_token: credentials.session_token,
+        expiration,
+    })
+}
+
+fn storage_fields(storage: &StorageOptions) -> Vec<(String, String)> {
+    let mut fields = vec![];
+    if !storage.storage_class.is_empty() {
+        fields.push(("x-amz-storage-class".to_string(), storage.storage_class.clone()));
+    }
+    if !storage.server_side_encryption.is_empty() {
+        fields.push(("x-amz-server-side-encryption".to_string(), storage.server_side_encryption.clone()));
+    }
+    if !storage.sse_kms_key_id.is_empty() {
+        fields.push(("x-amz-server-side-encryption-aws-kms-key-id".to_string(), storage.sse_kms_key_id.clone()));
+    }
+    if !storage.tags.is_empty() {
+        let tags: String = storage.tags.iter()
+            .map(|(key, value)| format!("<Tag><Key>{}</Key><Value>{}</Value></Tag>", escape_xml(key), escape_xml(value)))
+            .collect();
+        fields.push(("tagging".to_string(), format!("<Tagging><TagSet>{}</TagSet></Tagging>", tags)));
+    }
+    for (key, value) in &storage.metadata {
+        fields.push((format!("x-amz-meta-{}", key.to_lowercase()), value.clone()));
+    }
+    fields
+}
+
+fn escape_xml(value: &str) -> String {
+    value.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;").replace('\'', "&apos;")
+}
+
+fn signing_key(credentials: &StsCredentials, date: &str, region: &str) -> Vec<u8> {
+    [date, region, "s3", "aws4_request"]
+        .iter()
+        .fold(format!("AWS4{}", credentials.secret_access_key).into_bytes(), |key, part| hmac_sha256(&key, part.as_bytes()))
+}
+
+/// Percent-encodes all but the unreserved characters, as SigV4 expects. Paths keep
+/// their slashes.
+fn uri_encode(value: &str, encode_slash: bool) -> String {
+    value.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (byte as char).to_string(),
+            b'/' if !encode_slash => "/".to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
@@ -0,0 +1,217 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use serde::{Serialize, Deserialize};
+use sysinfo::{Pid, System};
+use tauri::{AppHandle, Manager};
+use tokio::process::Command;
+use tokio::sync::Mutex;
+use tracing::{debug, warn};
+
+use crate::manifest::update_manifest;
+use crate::session::RecordingState;
+
+pub const RESOURCE_WARNING_EVENT: &str = "recording://resource-warning";
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(2);
+/// Usage above this, in percent, counts as saturated.
+const SATURATION_PERCENT: f32 = 90.0;
+/// Consecutive saturated samples before warning, so a short spike stays quiet.
+const SATURATED_SAMPLES: u32 = 3;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Resource {
+    Cpu,
+    GpuEncoder,
+    Memory,
+}
+
+/// Sent once per resource and recording when it stays saturated.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResourceWarning {
+    pub video_id: String,
+    pub resource: Resource,
+    pub percent: f32,
+    /// Recorded time when the warning was raised.
+    pub offset_secs: f64,
+    pub message: String,
+}
+
+/// Average and peak usage over a recording, in percent unless noted.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ResourceSummary {
+    pub samples: u32,
+    /// The app and its ffmpeg processes, as a share of all cores.
+    pub process_cpu_avg: f32,
+    pub process_cpu_peak: f32,
+    pub system_cpu_avg: f32,
+    pub system_cpu_peak: f32,
+    /// `None` where encoder utilization can't be read, which is anything but NVIDIA GPUs.
+    pub gpu_encoder_avg: Option<f32>,
+    pub gpu_encoder_peak: Option<f32>,
+    /// Resident memory of the app and its ffmpeg processes.
+    pub process_memory_peak_bytes: u64,
+    pub system_memory_peak: f32,
+    #[serde(default)]
+    pub warnings: Vec<ResourceWarning>,
+}
+
+struct Sample {
+    process_cpu: f32,
+    system_cpu: f32,
+    process_memory_bytes: u64,
+    system_memory: f32,
+}
+
+/// Samples CPU, memory and GPU encoder usage every two seconds while the recording
+/// runs, ignoring pauses. Warns through `RESOURCE_WARNING_EVENT` when one stays
+/// saturated, and saves a summary to the manifest once the recording stops.
+pub fn spawn_telemetry_task(
+    app: Option<AppHandle>,
+    state: Arc<Mutex<RecordingState>>,
+    recording_dir: PathBuf,
+    video_id: String,
+) {
+    tauri::async_runtime::spawn(async move {
+        let mut system = System::new();
+        let mut summary = ResourceSummary::default();
+        let mut totals = (0.0f32, 0.0f32, 0.0f32);
+        let mut gpu_samples = 0u32;
+        let mut gpu_available = true;
+        let mut saturated = [(Resource::Cpu, 0u32), (Resource::GpuEncoder, 0), (Resource::Memory, 0)];
+        // CPU usage is measured since the previous refresh, so the first one only sets
+        // the baseline.
+        sample(&mut system);
+
+        loop {
+            tokio::time::sleep(SAMPLE_INTERVAL).await;
+            let guard = state.lock().await;
+            if !guard.is_recording() || guard.recording_dir.as_ref() != Some(&recording_dir) {
+                break;
+            }
+            let is_paused = guard.is_paused();
+            let offset_secs = guard.elapsed().as_secs_f64();
+            drop(guard);
+
+            let sampled = tokio::task::spawn_blocking(move || {
+                let sample = sample(&mut system);
+                (system, sample)
+            }).await;
+            let sample = match sampled {
+                Ok((sampled_system, sample)) => {
+                    system = sampled_system;
+                    sample
+                },
+                Err(e) => {
+                    warn!("Failed to sample resource usage: {}", e);
+                    break;
+                },
+            };
+            let gpu_encoder = if gpu_available { gpu_encoder_utilization().await } else { None };
+            gpu_available = gpu_encoder.is_some();
+            if is_paused {
+                continue;
+            }
+
+            summary.samples += 1;
+            totals.0 += sample.process_cpu;
+            totals.1 += sample.system_cpu;
+            summary.process_cpu_peak = summary.process_cpu_peak.max(sample.process_cpu);
+            summary.system_cpu_peak = summary.system_cpu_peak.max(sample.system_cpu);
+            summary.process_memory_peak_bytes = summary.process_memory_peak_bytes.max(sample.process_memory_bytes);
+            summary.system_memory_peak = summary.system_memory_peak.max(sample.system_memory);
+            if let Some(gpu_encoder) = gpu_encoder {
+                gpu_samples += 1;
+                totals.2 += gpu_encoder;
+                summary.gpu_encoder_peak = Some(summary.gpu_encoder_peak.unwrap_or(0.0).max(gpu_encoder));
+            }
+            debug!("Resource usage: cpu {:.0}% (app {:.0}%), memory {:.0}%, gpu encoder {:?}", sample.system_cpu, sample.process_cpu, sample.system_memory, gpu_encoder);
+
+            for (resource, count) in saturated.iter_mut() {
+                let percent = match resource {
+                    Resource::Cpu => Some(sample.system_cpu),
+                    Resource::GpuEncoder => gpu_encoder,
+                    Resource::Memory => Some(sample.system_memory),
+                };
+                *count = match percent {
+                    Some(percent) if percent >= SATURATION_PERCENT => *count + 1,
+                    _ => 0,
+                };
+                let Some(percent) = percent else { continue };
+                let already_warned = summary.warnings.iter().any(|warning| warning.resource == *resource);
+                if *count == SATURATED_SAMPLES && !already_warned {
+                    let warning = ResourceWarning {
+                        video_id: video_id.clone(),
+                        resource: *resource,
+                        percent,
+                        offset_secs,
+                        message: warning_message(*resource, percent),
+                    };
+                    warn!("{} for {}", warning.message, video_id);
+                    if let Some(ref app) = app {
+                        let _ = app.emit_all(RESOURCE_WARNING_EVENT, &warning);
+                    }
+                    summary.warnings.push(warning);
+                }
+            }
+        }
+
+        if summary.samples == 0 {
+            return;
+        }
+        summary.process_cpu_avg = totals.0 / summary.samples as f32;
+        summary.system_cpu_avg = totals.1 / summary.samples as f32;
+        summary.gpu_encoder_avg = (gpu_samples > 0).then(|| totals.2 / gpu_samples as f32);
+        if let Err(e) = update_manifest(&recording_dir, |manifest| manifest.resource_usage = Some(summary)) {
+            warn!("Failed to save resource usage for {}: {}", video_id, e);
+        }
+    });
+}
+
+fn warning_message(resource: Resource, percent: f32) -> String {
+    match resource {
+        Resource::Cpu => format!("CPU at {:.0}% — consider lowering the frame rate or quality", percent),
+        Resource::GpuEncoder => format!("Encoder at {:.0}% — consider lowering resolution", percent),
+        Resource::Memory => format!("Memory at {:.0}% — consider closing other apps", percent),
+    }
+}
+
+/// The app's own usage includes its ffmpeg children, which do the encoding.
+fn sample(system: &mut System) -> Sample {
+    system.refresh_cpu();
+    system.refresh_memory();
+    system.refresh_processes();
+
+    let app_pid = Pid::from_u32(std::process::id());
+    let (process_cpu, process_memory_bytes) = system.processes()
+        .values()
+        .filter(|process| process.pid() == app_pid || process.parent() == Some(app_pid))
+        .fold((0.0, 0), |(cpu, memory), process| (cpu + process.cpu_usage(), memory + process.memory()));
+    let system_memory = match system.total_memory() {
+        0 => 0.0,
+        total => system.used_memory() as f32 / total as f32 * 100.0,
+    };
+
+    Sample {
+        process_cpu: process_cpu / system.cpus().len().max(1) as f32,
+        system_cpu: system.global_cpu_info().cpu_usage(),
+        process_memory_bytes,
+        system_memory,
+    }
+}
+
+/// Busiest GPU's video encoder utilization, from `nvidia-smi`.
+async fn gpu_encoder_utilization() -> Option<f32> {
+    let output = Command::new("nvidia-smi")
+        .args(["--query-gpu=utilization.encoder", "--format=csv,noheader,nounits"])
+        .output()
+        .await
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.trim().parse::<f32>().ok())
+        .reduce(f32::max)
+}
@@ -0,0 +1,173 @@
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use serde::Serialize;
+use tauri::{AppHandle, State};
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+use crate::audio_levels::{measure_input_level, AudioLevel};
+use crate::device_arbitration::{acquire as acquire_devices, recording_devices, DeviceUser};
+use crate::manifest::segment_file_name;
+use crate::options::validate_options;
+use crate::progress::parse_progress_line;
+use crate::capture::{
+    clean_and_create_dir, construct_recording_args, graceful_stop_ffmpeg, resolve_device_input, start_screen_recording_process,
+    FFMPEG_EXIT_TIMEOUT,
+};
+use crate::recording::{CaptureMode, RecordingOptions};
+use crate::session::RecordingState;
+use crate::settings::Settings;
+use crate::utils::ffmpeg_path;
+
+const TEST_DURATION: Duration = Duration::from_secs(5);
+const TEST_DIR: &str = "test-recording";
+
+/// What a short local test capture measured.
+#[derive(Debug, Clone, Serialize)]
+pub struct TestRecordingResult {
+    /// Frames actually written per second of video.
+    pub fps: f64,
+    pub target_fps: f64,
+    pub width: u32,
+    pub height: u32,
+    pub duration_secs: f64,
+    pub file_size_bytes: u64,
+    /// Loudest mic levels during the test; `None` if the mic couldn't be opened.
+    pub audio_level: Option<AudioLevel>,
+    pub audio_error: Option<String>,
+}
+
+/// Records about five seconds with `options` (and the saved settings) into a scratch
+/// directory, without uploading, and reports what came out. Always captures with
+/// ffmpeg, even when the native encoder is built in.
+#[tauri::command]
+pub async fn test_recording(
+    app: AppHandle,
+    state: State<'_, Arc<Mutex<RecordingState>>>,
+    settings: State<'_, Arc<Mutex<Settings>>>,
+    mut options: RecordingOptions,
+) -> Result<TestRecordingResult, String> {
+    let guard = state.lock().await;
+    if guard.is_recording() {
+        return Err("Stop the current recording before running a test".to_string());
+    }
+    let data_dir = guard.data_dir.clone()
+        .ok_or("Data directory is not set in the recording state".to_string())?;
+    drop(guard);
+
+    settings.lock().await.apply_to(&mut options);
+    if let Some(error) = validate_options(&options).first() {
+        return Err(error.to_string());
+    }
+    // One segment covers the whole test.
+    options.segment_time_secs = TEST_DURATION.as_secs() as u32 * 2;
+
+    let test_dir = data_dir.join(TEST_DIR);
+    clean_and_create_dir(&test_dir)?;
+
+    let result = record_test_clip(&app, &test_dir, &options).await;
+    if let Err(e) = tokio::fs::remove_dir_all(&test_dir).await {
+        warn!("Failed to remove the test recording: {}", e);
+    }
+    result
+}
+
+async fn record_test_clip(app: &AppHandle, test_dir: &Path, options: &RecordingOptions) -> Result<TestRecordingResult, String> {
+    let (video_type, input, target_fps) = match options.capture_mode {
+        CaptureMode::Screen => ("screen", resolve_device_input(options.screen_index.clone()).await?, 30.0),
+        CaptureMode::Camera => ("camera", resolve_device_input(options.video_index.clone()).await?, options.framerate.unwrap_or(30) as f64),
+    };
+    let args = construct_recording_args(options, test_dir, video_type, &input).await?;
+    let ffmpeg_binary = ffmpeg_path()?;
+
+    let _device_lease = acquire_devices(Some(app), DeviceUser::TestRecording, recording_devices(options).await?).await?;
+    info!("Starting a test recording...");
+    let audio_name = (!options.audio_name.is_empty()).then(|| options.audio_name.clone());
+    let audio = tokio::task::spawn_blocking(move || measure_input_level(audio_name.as_deref(), TEST_DURATION));
+
+    let (mut child, stderr, stdin) = start_screen_recording_process(&ffmpeg_binary, &args)
+        .await
+        .map_err(|e| e.to_string())?;
+    // Keep the last lines for the error message, and keep the pipe from filling up.
+    let stderr_task = tokio::spawn(async move {
+        let mut lines = BufReader::new(stderr).lines();
+        let mut tail: Vec<String> = vec![];
+        while let Ok(Some(line)) = lines.next_line().await {
+            tail.push(line);
+            if tail.len() > 10 {
+                tail.remove(0);
+            }
+        }
+        tail.join("\n")
+    });
+
+    tokio::time::sleep(TEST_DURATION).await;
+    if let Err(e) = graceful_stop_ffmpeg(stdin).await {
+        warn!("Failed to send quit command to the test recording: {}", e);
+    }
+    if tokio::time::timeout(FFMPEG_EXIT_TIMEOUT, child.wait()).await.is_err() {
+        let _ = child.kill().await;
+    }
+    let stderr_tail = stderr_task.await.unwrap_or_default();
+
+    let (audio_level, audio_error) = match audio.await.map_err(|e| e.to_string()).and_then(|level| level) {
+        Ok(level) => (Some(level), None),
+        Err(e) => (None, Some(e)),
+    };
+
+    let clip_path = test_dir.join(segment_file_name(&options.segment_prefix(video_type), 0, "ts"));
+    let file_size_bytes = match tokio::fs::metadata(&clip_path).await {
+        Ok(metadata) => metadata.len(),
+        Err(_) => return Err(format!("The test recording produced no video:\n{}", stderr_tail)),
+    };
+    let probe = probe_clip(&ffmpeg_binary, &clip_path).await?;
+
+    Ok(TestRecordingResult {
+        fps: if probe.duration_secs > 0.0 { probe.frames as f64 / probe.duration_secs } else { 0.0 },
+        target_fps,
+        width: probe.width,
+        height: probe.height,
+        duration_secs: probe.duration_secs,
+        file_size_bytes,
+        audio_level,
+        audio_error,
+    })
+}
+
+struct ClipProbe {
+    width: u32,
+    height: u32,
+    frames: u64,
+    duration_secs: f64,
+}
+
+/// Decodes the clip and reads its size from the stream info and the frame count and
+/// length from ffmpeg's final progress line.
+async fn probe_clip(ffmpeg_binary: &Path, clip_path: &Path) -> Result<ClipProbe, String> {
+    let output = tokio::process::Command::new(ffmpeg_binary)
+        .arg("-i").arg(clip_path)
+        .args(["-map", "0:v:0", "-f", "null", "-"])
+        .output()
+        .await
+        .map_err(|e| format!("Failed to probe the test recording: {}", e))?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    let (width, height) = stderr.lines()
+        .filter(|line| line.contains("Video:"))
+        .flat_map(|line| line.split(|c: char| c == ',' || c.is_whitespace()))
+        .find_map(|token| {
+            let (width, height) = token.split_once('x')?;
+            Some((width.parse().ok()?, height.parse().ok()?))
+        })
+        .ok_or("Could not read the test recording's resolution")?;
+
+    // Progress lines are separated by carriage returns.
+    let progress = stderr.split(|c| c == '\r' || c == '\n')
+        .filter_map(parse_progress_line)
+        .last()
+        .ok_or("Could not read the test recording's frame count")?;
+
+    Ok(ClipProbe { width, height, frames: progress.frame, duration_secs: progress.time_secs })
+}
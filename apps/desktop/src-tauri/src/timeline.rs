@@ -0,0 +1,82 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use serde::{Serialize, Deserialize};
+use tokio::sync::Mutex;
+use tracing::{debug, warn};
+
+use crate::recording::RecordingOptions;
+use crate::session::RecordingState;
+use crate::upload::upload_sidecar_file;
+
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+pub const TIMELINE_FILE: &str = "timeline.json";
+
+/// The focused window from `offset_secs` of recorded time until the next entry.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TimelineEntry {
+    pub offset_secs: f64,
+    pub app_name: String,
+    pub title: String,
+}
+
+/// Samples the focused window every second while the recording runs, ignoring
+/// pauses, and uploads the changes as `timeline.json` under the `timeline` type once
+/// it stops.
+pub fn spawn_timeline_task(
+    state: Arc<Mutex<RecordingState>>,
+    recording_dir: PathBuf,
+    options: RecordingOptions,
+    upload_cancelled: Arc<AtomicBool>,
+) {
+    tauri::async_runtime::spawn(async move {
+        let mut timeline: Vec<TimelineEntry> = vec![];
+
+        loop {
+            let guard = state.lock().await;
+            if !guard.is_recording() || guard.recording_dir.as_ref() != Some(&recording_dir) {
+                break;
+            }
+            let is_paused = guard.is_paused();
+            let offset_secs = guard.elapsed().as_secs_f64();
+            drop(guard);
+
+            if !is_paused {
+                match tokio::task::spawn_blocking(active_win_pos_rs::get_active_window).await {
+                    Ok(Ok(window)) => {
+                        let changed = timeline.last()
+                            .map_or(true, |last| last.app_name != window.app_name || last.title != window.title);
+                        if changed {
+                            timeline.push(TimelineEntry { offset_secs, app_name: window.app_name, title: window.title });
+                        }
+                    },
+                    Ok(Err(())) => debug!("No active window to sample"),
+                    Err(e) => warn!("Failed to sample the active window: {}", e),
+                }
+            }
+
+            tokio::time::sleep(SAMPLE_INTERVAL).await;
+        }
+
+        if upload_cancelled.load(Ordering::SeqCst) {
+            return;
+        }
+
+        if let Err(e) = upload_timeline(&recording_dir, &options, &timeline).await {
+            warn!("Failed to upload the window timeline for {}: {}", options.video_id, e);
+        }
+    });
+}
+
+async fn upload_timeline(recording_dir: &Path, options: &RecordingOptions, timeline: &[TimelineEntry]) -> Result<(), String> {
+    let timeline_path = recording_dir.join(TIMELINE_FILE);
+    let contents = serde_json::to_string(timeline).map_err(|e| e.to_string())?;
+    tokio::fs::write(&timeline_path, contents).await
+        .map_err(|e| format!("Failed to write window timeline: {}", e))?;
+    debug!("Recorded {} window changes for {}", timeline.len(), options.video_id);
+
+    upload_sidecar_file(options, &timeline_path, "timeline").await?;
+
+    Ok(())
+}
@@ -0,0 +1,154 @@
+use std::path::Path;
+use std::sync::Arc;
+use tauri::State;
+use tokio::sync::Mutex;
+#[cfg(feature = "transcription")]
+use tracing::info;
+
+use crate::manifest::{recording_dir, RecordingManifest, RecordingStatus};
+use crate::recording::RecordingOptions;
+use crate::session::RecordingState;
+use crate::settings::Settings;
+#[cfg(feature = "transcription")]
+use crate::upload::upload_sidecar_file;
+#[cfg(feature = "transcription")]
+use crate::utils::ffmpeg_path;
+
+/// Written next to the audio segments while recording when transcription is enabled.
+pub const SPEECH_TRACK_FILE: &str = "speech.wav";
+pub const CAPTIONS_FILE: &str = "captions.vtt";
+
+/// Transcribes a finished recording with the configured Whisper model, then uploads
+/// the captions under the `captions` type. Returns the local path of the `.vtt` file.
+#[tauri::command]
+pub async fn transcribe_recording(
+    state: State<'_, Arc<Mutex<RecordingState>>>,
+    settings: State<'_, Arc<Mutex<Settings>>>,
+    video_id: String,
+) -> Result<String, String> {
+    let model_path = settings.lock().await.whisper_model_path.clone();
+
+    let guard = state.lock().await;
+    let data_dir = guard.data_dir.clone()
+        .ok_or("Data directory is not set in the recording state".to_string())?;
+    let recording_dir = recording_dir(&data_dir, &video_id)?;
+    drop(guard);
+
+    let manifest = RecordingManifest::load(&recording_dir)?;
+    if manifest.status == RecordingStatus::Recording {
+        return Err("Cannot transcribe a recording that is still in progress".to_string());
+    }
+
+    transcribe_and_upload(&recording_dir, &manifest.options, &model_path).await
+}
+
+#[cfg(not(feature = "transcription"))]
+pub async fn transcribe_and_upload(_recording_dir: &Path, _options: &RecordingOptions, _model_path: &str) -> Result<String, String> {
+    Err("This build of Cap does not include transcription support".to_string())
+}
+
+#[cfg(feature = "transcription")]
+pub async fn transcribe_and_upload(recording_dir: &Path, options: &RecordingOptions, model_path: &str) -> Result<String, String> {
+    if model_path.is_empty() {
+        return Err("No Whisper model is configured".to_string());
+    }
+
+    let speech_track = recording_dir.join("audio").join(SPEECH_TRACK_FILE);
+    if !speech_track.is_file() {
+        return Err("This recording has no speech track; enable transcription before recording".to_string());
+    }
+
+    info!("Transcribing {}", options.video_id);
+    let samples = load_samples(&speech_track).await?;
+    let model_path = model_path.to_string();
+    let segments = tokio::task::spawn_blocking(move || whisper::transcribe(&model_path, &samples))
+        .await
+        .map_err(|e| e.to_string())??;
+
+    let captions_dir = recording_dir.join("captions");
+    tokio::fs::create_dir_all(&captions_dir).await.map_err(|e| e.to_string())?;
+    let captions_path = captions_dir.join(CAPTIONS_FILE);
+    tokio::fs::write(&captions_path, to_vtt(&segments)).await
+        .map_err(|e| format!("Failed to write captions: {}", e))?;
+    info!("Wrote {} caption cues for {}", segments.len(), options.video_id);
+
+    upload_sidecar_file(options, &captions_path, "captions").await?;
+
+    Ok(captions_path.to_string_lossy().into_owned())
+}
+
+/// Decodes the speech track to the 16 kHz mono f32 samples Whisper expects.
+#[cfg(feature = "transcription")]
+async fn load_samples(path: &Path) -> Result<Vec<f32>, String> {
+    let output = tokio::process::Command::new(ffmpeg_path()?)
+        .arg("-i")
+        .arg(path)
+        .args(["-f", "f32le", "-ac", "1", "-ar", "16000", "-"])
+        .output()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if !output.status.success() {
+        return Err(format!("Failed to decode speech track: {}", String::from_utf8_lossy(&output.stderr).trim()));
+    }
+
+    Ok(output.stdout.chunks_exact(4)
+        .map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        .collect())
+}
+
+/// A caption cue with times in milliseconds.
+#[cfg(feature = "transcription")]
+struct Cue {
+    start_ms: i64,
+    end_ms: i64,
+    text: String,
+}
+
+#[cfg(feature = "transcription")]
+fn to_vtt(cues: &[Cue]) -> String {
+    let mut vtt = String::from("WEBVTT\n\n");
+    for cue in cues {
+        vtt.push_str(&format!("{} --> {}\n{}\n\n", vtt_timestamp(cue.start_ms), vtt_timestamp(cue.end_ms), cue.text.trim()));
+    }
+    vtt
+}
+
+pub fn vtt_timestamp(ms: i64) -> String {
+    format!("{:02}:{:02}:{:02}.{:03}", ms / 3_600_000, ms / 60_000 % 60, ms / 1000 % 60, ms % 1000)
+}
+
+#[cfg(feature = "transcription")]
+mod whisper {
+    use whisper_rs::{FullParams, SamplingStrategy, WhisperContext, WhisperContextParameters};
+
+    use super::Cue;
+
+    pub fn transcribe(model_path: &str, samples: &[f32]) -> Result<Vec<Cue>, String> {
+        let context = WhisperContext::new_with_params(model_path, WhisperContextParameters::default())
+            .map_err(|e| format!("Failed to load Whisper model: {}", e))?;
+        let mut state = context.create_state().map_err(|e| e.to_string())?;
+
+        let mut params = FullParams::new(SamplingStrategy::Greedy { best_of: 1 });
+        params.set_language(Some("auto"));
+        params.set_print_special(false);
+        params.set_print_progress(false);
+        params.set_print_realtime(false);
+        params.set_print_timestamps(false);
+
+        state.full(params, samples).map_err(|e| format!("Transcription failed: {}", e))?;
+
+        let segment_count = state.full_n_segments().map_err(|e| e.to_string())?;
+        let mut cues = Vec::with_capacity(segment_count as usize);
+        for i in 0..segment_count {
+            // Whisper reports times in centiseconds.
+            cues.push(Cue {
+                start_ms: state.full_get_segment_t0(i).map_err(|e| e.to_string())? * 10,
+                end_ms: state.full_get_segment_t1(i).map_err(|e| e.to_string())? * 10,
+                text: state.full_get_segment_text(i).map_err(|e| e.to_string())?,
+            });
+        }
+
+        Ok(cues)
+    }
+}
@@ -0,0 +1,122 @@
+use std::sync::Arc;
+use std::time::Duration;
+use tauri::{AppHandle, CustomMenuItem, Manager, SystemTray, SystemTrayEvent, SystemTrayMenu, SystemTrayMenuItem};
+use tokio::sync::Mutex;
+use tracing::warn;
+
+use crate::hotkeys::{emit_action, HotkeyAction};
+use crate::session::{shutdown_recording, RecordingPhase, RecordingState};
+
+const STATUS_ITEM: &str = "status";
+const START_ITEM: &str = "start";
+const STOP_ITEM: &str = "stop";
+const PAUSE_ITEM: &str = "pause";
+const SHOW_ITEM: &str = "show";
+const QUIT_ITEM: &str = "quit";
+
+pub fn build_tray() -> SystemTray {
+    let menu = SystemTrayMenu::new()
+        .add_item(CustomMenuItem::new(STATUS_ITEM, "Idle").disabled())
+        .add_native_item(SystemTrayMenuItem::Separator)
+        .add_item(CustomMenuItem::new(START_ITEM, "Start Recording"))
+        .add_item(CustomMenuItem::new(STOP_ITEM, "Stop Recording").disabled())
+        .add_item(CustomMenuItem::new(PAUSE_ITEM, "Pause Recording").disabled())
+        .add_native_item(SystemTrayMenuItem::Separator)
+        .add_item(CustomMenuItem::new(SHOW_ITEM, "Show Cap"))
+        .add_item(CustomMenuItem::new(QUIT_ITEM, "Quit Cap"));
+
+    SystemTray::new().with_menu(menu)
+}
+
+/// Recording items emit the same actions as the global hotkeys, so the frontend
+/// handles both in one place.
+pub fn handle_tray_event(app: &AppHandle, event: SystemTrayEvent) {
+    let id = match event {
+        SystemTrayEvent::MenuItemClick { id, .. } => id,
+        _ => return,
+    };
+
+    match id.as_str() {
+        START_ITEM => emit_action(app, HotkeyAction::StartRecording),
+        STOP_ITEM => emit_action(app, HotkeyAction::StopRecording),
+        PAUSE_ITEM => emit_action(app, HotkeyAction::PauseRecording),
+        SHOW_ITEM => show_main_window(app),
+        QUIT_ITEM => {
+            let app = app.clone();
+            let state = app.state::<Arc<Mutex<RecordingState>>>().inner().clone();
+            tauri::async_runtime::spawn(async move {
+                shutdown_recording(&state).await;
+                app.exit(0);
+            });
+        },
+        _ => {},
+    }
+}
+
+pub fn show_main_window(app: &AppHandle) {
+    if let Some(window) = app.get_window("main") {
+        let _ = window.show();
+        let _ = window.unminimize();
+        let _ = window.set_focus();
+    }
+}
+
+/// Refreshes the tray menu once a second with the recording status and elapsed time.
+pub fn spawn_tray_updater(app: AppHandle) {
+    tauri::async_runtime::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(1));
+        let mut last_shown = None;
+
+        loop {
+            interval.tick().await;
+
+            let state = app.state::<Arc<Mutex<RecordingState>>>();
+            // Commands like stop hold the lock while waiting on uploads; skip a tick
+            // rather than block behind them.
+            let (status, elapsed, can_pause) = match state.try_lock() {
+                Ok(guard) => (guard.phase(), guard.elapsed().as_secs(), guard.can_pause()),
+                Err(_) => continue,
+            };
+
+            if last_shown == Some((status, elapsed, can_pause)) {
+                continue;
+            }
+            last_shown = Some((status, elapsed, can_pause));
+
+            if let Err(e) = update_tray_menu(&app, status, elapsed, can_pause) {
+                warn!("Failed to update tray menu: {}", e);
+            }
+        }
+    });
+}
+
+fn update_tray_menu(app: &AppHandle, status: RecordingPhase, elapsed_secs: u64, can_pause: bool) -> Result<(), tauri::Error> {
+    let tray = app.tray_handle();
+    let is_capturing = matches!(status, RecordingPhase::Recording | RecordingPhase::Paused);
+
+    let status_title = match status {
+        RecordingPhase::Idle => "Idle".to_string(),
+        RecordingPhase::Countdown => "Starting...".to_string(),
+        RecordingPhase::Recording => format!("Recording {}", format_elapsed(elapsed_secs)),
+        RecordingPhase::Paused => format!("Paused {}", format_elapsed(elapsed_secs)),
+        RecordingPhase::Uploading => "Uploading...".to_string(),
+    };
+    tray.get_item(STATUS_ITEM).set_title(status_title)?;
+
+    tray.get_item(START_ITEM).set_enabled(status == RecordingPhase::Idle)?;
+    tray.get_item(STOP_ITEM).set_enabled(is_capturing)?;
+
+    let pause_item = tray.get_item(PAUSE_ITEM);
+    pause_item.set_enabled(is_capturing && can_pause)?;
+    pause_item.set_title(if status == RecordingPhase::Paused { "Resume Recording" } else { "Pause Recording" })?;
+
+    Ok(())
+}
+
+fn format_elapsed(secs: u64) -> String {
+    if secs >= 3600 {
+        format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+    } else {
+        format!("{:02}:{:02}", secs / 60, secs % 60)
+    }
+}
@@ -0,0 +1,283 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tauri::State;
+use tokio::process::Command;
+use tokio::sync::Mutex;
+use tracing::{debug, info};
+
+use crate::manifest::{finalize_upload_status, recording_dir, update_manifest, RecordingManifest, RecordingStatus};
+use crate::progress::parse_timestamp;
+use crate::recording::VideoQuality;
+use crate::session::RecordingState;
+use crate::utils::ffmpeg_path;
+
+/// What a trim does to one segment.
+enum SegmentTrim {
+    Keep,
+    Remove { is_local: bool },
+    Cut { from: f64, to: f64 },
+}
+
+/// The trim of one track, worked out before anything on disk is touched.
+struct TrackTrim {
+    video_type: &'static str,
+    segments: Vec<(String, SegmentTrim)>,
+}
+
+impl TrackTrim {
+    /// Segments whose contents changed or which were removed by the trim.
+    fn affected(&self) -> impl Iterator<Item = &String> {
+        self.segments.iter()
+            .filter(|(_, trim)| !matches!(trim, SegmentTrim::Keep))
+            .map(|(segment, _)| segment)
+    }
+}
+
+/// Cuts everything outside `start_ms..end_ms` from a finished recording's local
+/// segments. Segments entirely inside the range are kept untouched, segments entirely
+/// outside it are deleted and the two boundary segments are re-cut. Re-cut segments
+/// are marked pending so `retry_upload` replaces their uploaded copies; uploaded
+/// copies of deleted segments are left on the server. Segments are removed locally
+/// once uploaded, so those are assumed to have the nominal segment length and can
+/// only be dropped, not re-cut.
+#[tauri::command]
+pub async fn trim_recording(
+    state: State<'_, Arc<Mutex<RecordingState>>>,
+    video_id: String,
+    start_ms: u64,
+    end_ms: u64,
+) -> Result<(), String> {
+    if end_ms <= start_ms {
+        return Err("The trim end must be after the trim start".to_string());
+    }
+
+    let guard = state.lock().await;
+    let data_dir = guard.data_dir.clone()
+        .ok_or("Data directory is not set in the recording state".to_string())?;
+    let recording_dir = recording_dir(&data_dir, &video_id)?;
+    let manifest = RecordingManifest::load(&recording_dir)?;
+
+    if guard.recording_dir.as_ref() == Some(&recording_dir)
+        && matches!(manifest.status, RecordingStatus::Recording | RecordingStatus::Uploading) {
+        return Err("Cannot trim a recording that is still in progress".to_string());
+    }
+    drop(guard);
+
+    let start = start_ms as f64 / 1000.0;
+    let end = end_ms as f64 / 1000.0;
+    info!("Trimming recording {} to {:.3}s..{:.3}s", video_id, start, end);
+
+    let segment_time_secs = manifest.options.segment_time_secs as f64;
+    let ffmpeg_binary = ffmpeg_path()?;
+
+    // Everything that can fail is done before the first file is replaced or deleted,
+    // so a failed trim leaves the recording as it was.
+    let mut tracks = vec![];
+    for video_type in ["screen", "audio"] {
+        let segment_durations = manifest.segment_durations.get(video_type).cloned().unwrap_or_default();
+        tracks.push(plan_trim(&ffmpeg_binary, &recording_dir, video_type, start, end, segment_time_secs, &segment_durations).await?);
+    }
+    let cuts = cut_boundary_segments(&ffmpeg_binary, &recording_dir, &tracks, manifest.options.video_quality.unwrap_or_default()).await?;
+
+    for (temp_path, path) in cuts {
+        tokio::fs::rename(&temp_path, &path).await
+            .map_err(|e| format!("Failed to replace {}: {}", path.display(), e))?;
+    }
+    for track in &tracks {
+        apply_trim(&recording_dir, track).await?;
+
+        update_manifest(&recording_dir, |manifest| {
+            let video_type = track.video_type;
+            if let Some(uploaded) = manifest.uploaded_segments.get_mut(video_type) {
+                for segment in track.affected() {
+                    uploaded.remove(segment);
+                }
+            }
+            if let Some(durations) = manifest.segment_durations.get_mut(video_type) {
+                for segment in track.affected() {
+                    durations.remove(segment);
+                }
+            }
+            if let Some(errors) = manifest.upload_errors.get_mut(video_type) {
+                for segment in track.affected() {
+                    errors.remove(segment);
+                }
+            }
+            if let Some(skipped) = manifest.skipped_segments.get_mut(video_type) {
+                for segment in track.affected() {
+                    skipped.remove(segment);
+                }
+            }
+        })?;
+    }
+
+    update_manifest(&recording_dir, |manifest| {
+        let recorded = manifest.duration_secs.unwrap_or(f64::MAX);
+        manifest.duration_secs = Some(end.min(recorded) - start.min(recorded));
+        manifest.duration_mismatches.clear();
+    })?;
+    finalize_upload_status(&recording_dir);
+
+    Ok(())
+}
+
+/// Works out what happens to each segment of `video_type`, failing if a boundary
+/// segment is no longer available locally to re-cut.
+async fn plan_trim(
+    ffmpeg_binary: &Path,
+    recording_dir: &Path,
+    video_type: &'static str,
+    start: f64,
+    end: f64,
+    segment_time_secs: f64,
+    segment_durations: &BTreeMap<String, f64>,
+) -> Result<TrackTrim, String> {
+    let chunks_dir = recording_dir.join(video_type);
+    let segment_list = tokio::fs::read_to_string(chunks_dir.join("segment_list.txt")).await.unwrap_or_default();
+
+    let mut segments = vec![];
+    let mut offset = 0.0;
+    for segment in segment_list.lines().filter(|line| !line.is_empty()) {
+        let segment_path = chunks_dir.join(segment);
+        let is_local = segment_path.is_file();
+        // Segment length can change mid-recording, so uploaded segments use the
+        // length probed at upload.
+        let duration = if is_local {
+            probe_duration(ffmpeg_binary, &segment_path).await?
+        } else {
+            segment_durations.get(segment).copied().unwrap_or(segment_time_secs)
+        };
+        let (segment_start, segment_end) = (offset, offset + duration);
+        offset = segment_end;
+
+        let trim = if segment_end <= start || segment_start >= end {
+            SegmentTrim::Remove { is_local }
+        } else if segment_start < start || segment_end > end {
+            if !is_local {
+                return Err(format!("{} was already uploaded and is no longer available locally to re-cut", segment));
+            }
+            SegmentTrim::Cut { from: (start - segment_start).max(0.0), to: (end - segment_start).min(duration) }
+        } else {
+            SegmentTrim::Keep
+        };
+        segments.push((segment.to_string(), trim));
+    }
+
+    Ok(TrackTrim { video_type, segments })
+}
+
+/// Cuts every boundary segment into a temporary file next to it, returning the
+/// temporary files with the segments they replace. On failure the temporary files
+/// are removed and the segments are left untouched.
+async fn cut_boundary_segments(
+    ffmpeg_binary: &Path,
+    recording_dir: &Path,
+    tracks: &[TrackTrim],
+    video_quality: VideoQuality,
+) -> Result<Vec<(PathBuf, PathBuf)>, String> {
+    let mut cuts = vec![];
+    for track in tracks {
+        for (segment, trim) in &track.segments {
+            if let SegmentTrim::Cut { from, to } = trim {
+                let path = recording_dir.join(track.video_type).join(segment);
+                match cut_segment(ffmpeg_binary, &path, track.video_type, video_quality, *from, *to).await {
+                    Ok(temp_path) => cuts.push((temp_path, path)),
+                    Err(e) => {
+                        for (temp_path, _) in cuts {
+                            let _ = tokio::fs::remove_file(temp_path).await;
+                        }
+                        return Err(e);
+                    },
+                }
+            }
+        }
+    }
+    Ok(cuts)
+}
+
+/// Deletes the segments a trim removes and rewrites the segment list.
+async fn apply_trim(recording_dir: &Path, track: &TrackTrim) -> Result<(), String> {
+    let chunks_dir = recording_dir.join(track.video_type);
+    let mut kept = vec![];
+    for (segment, trim) in &track.segments {
+        match trim {
+            SegmentTrim::Remove { is_local: true } => {
+                tokio::fs::remove_file(chunks_dir.join(segment)).await
+                    .map_err(|e| format!("Failed to remove {}: {}", segment, e))?;
+            },
+            SegmentTrim::Remove { is_local: false } => {},
+            SegmentTrim::Keep | SegmentTrim::Cut { .. } => kept.push(segment.as_str()),
+        }
+    }
+
+    let mut contents = kept.join("\n");
+    if !contents.is_empty() {
+        contents.push('\n');
+    }
+    tokio::fs::write(chunks_dir.join("segment_list.txt"), contents).await
+        .map_err(|e| format!("Failed to update segment list: {}", e))?;
+
+    debug!("Trimmed {}: {} kept, {} affected", track.video_type, kept.len(), track.affected().count());
+
+    Ok(())
+}
+
+/// Reads the container duration ffmpeg prints when probing an input.
+async fn probe_duration(ffmpeg_binary: &Path, path: &Path) -> Result<f64, String> {
+    let output = Command::new(ffmpeg_binary)
+        .arg("-hide_banner")
+        .arg("-i")
+        .arg(path)
+        .output()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    String::from_utf8_lossy(&output.stderr)
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("Duration: "))
+        .and_then(|rest| rest.split(',').next())
+        .and_then(parse_timestamp)
+        .ok_or(format!("Could not read the duration of {}", path.display()))
+}
+
+/// Writes the part of `path` between `from` and `to` next to it, returning the new
+/// file. Audio is stream copied, and so is video cut only at its end, since every
+/// segment starts on a keyframe. Video cut at its start is re-encoded with the
+/// capture's settings, so the cut doesn't snap to a keyframe.
+async fn cut_segment(
+    ffmpeg_binary: &Path,
+    path: &Path,
+    video_type: &str,
+    video_quality: VideoQuality,
+    from: f64,
+    to: f64,
+) -> Result<PathBuf, String> {
+    let file_name = path.file_name().and_then(|name| name.to_str()).ok_or("Invalid segment path".to_string())?;
+    let temp_path = path.with_file_name(format!("trim_{}", file_name));
+
+    let (preset, crf, pix_fmt) = video_quality.encoder_settings();
+    let codec_args = match video_type {
+        "screen" if from > 0.0 => vec!["-c:v", "libx264", "-preset", preset, "-crf", crf, "-pix_fmt", pix_fmt],
+        "screen" => vec!["-c:v", "copy"],
+        _ => vec!["-c:a", "copy"],
+    };
+
+    let output = Command::new(ffmpeg_binary)
+        .args(["-hide_banner", "-y", "-ss", &format!("{:.3}", from)])
+        .arg("-i")
+        .arg(path)
+        .args(["-t", &format!("{:.3}", to - from)])
+        .args(&codec_args)
+        .arg(&temp_path)
+        .output()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if !output.status.success() {
+        let _ = tokio::fs::remove_file(&temp_path).await;
+        return Err(format!("Failed to cut {}: {}", file_name, String::from_utf8_lossy(&output.stderr).trim()));
+    }
+
+    Ok(temp_path)
+}
@@ -1,11 +1,117 @@
+use serde::{Serialize, Deserialize};
 use serde_json::Value as JsonValue;
-use std::path::{Path};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
 use std::process::Command;
 use std::env;
+use std::time::{Duration, Instant};
+use futures::future::{BoxFuture, FutureExt};
 use reqwest;
+use tokio_util::io::ReaderStream;
+use tracing::{debug, error, info, warn};
 
+use crate::metrics::record_segment_uploaded;
+use crate::multipart;
+use crate::network::{record_upload_throughput, set_offline, wait_until_online};
 use crate::recording::RecordingOptions;
-use crate::utils::ffmpeg_path_as_str;
+use crate::sts::{self, use_sts, PresignedPost};
+use crate::upload_stats;
+use crate::utils::ffmpeg_path;
+
+const STORAGE_CLASSES: &[&str] = &[
+    "STANDARD",
+    "STANDARD_IA",
+    "ONEZONE_IA",
+    "INTELLIGENT_TIERING",
+    "GLACIER_IR",
+    "GLACIER",
+    "DEEP_ARCHIVE",
+    "REDUCED_REDUNDANCY",
+];
+const ENCRYPTION_ALGORITHMS: &[&str] = &["AES256", "aws:kms", "aws:kms:dsse"];
+const MAX_TAGS: usize = 10;
+
+/// S3 object settings sent to the signing handler, which adds them to the presigned
+/// POST policy. Empty values leave the bucket defaults in place.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct StorageOptions {
+    /// e.g. `INTELLIGENT_TIERING`.
+    pub storage_class: String,
+    /// `AES256`, `aws:kms` or `aws:kms:dsse`.
+    pub server_side_encryption: String,
+    /// KMS key for the `aws:kms` algorithms; the bucket's default key when empty.
+    pub sse_kms_key_id: String,
+    /// Object tags, e.g. for lifecycle rules.
+    pub tags: BTreeMap<String, String>,
+    /// Stored as `x-amz-meta-<key>` headers.
+    pub metadata: BTreeMap<String, String>,
+}
+
+impl StorageOptions {
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.storage_class.is_empty() && !STORAGE_CLASSES.contains(&self.storage_class.as_str()) {
+            return Err(format!("Unsupported storage class: {}", self.storage_class));
+        }
+        if !self.server_side_encryption.is_empty() && !ENCRYPTION_ALGORITHMS.contains(&self.server_side_encryption.as_str()) {
+            return Err(format!("Unsupported server-side encryption: {}", self.server_side_encryption));
+        }
+        if !self.sse_kms_key_id.is_empty() && !self.server_side_encryption.starts_with("aws:kms") {
+            return Err("A KMS key requires aws:kms server-side encryption".to_string());
+        }
+        if self.tags.len() > MAX_TAGS {
+            return Err(format!("At most {} object tags are allowed", MAX_TAGS));
+        }
+        for (key, value) in &self.tags {
+            if key.is_empty() || key.chars().count() > 128 || value.chars().count() > 256 {
+                return Err(format!("Invalid object tag: {}={}", key, value));
+            }
+        }
+        for key in self.metadata.keys() {
+            if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+                return Err(format!("Invalid metadata key: {}", key));
+            }
+        }
+        Ok(())
+    }
+
+    /// Fills the settings this upload left empty from `defaults`. Tags and metadata
+    /// are merged, with the upload's own values winning.
+    pub fn fill_from(&mut self, defaults: &StorageOptions) {
+        if self.storage_class.is_empty() {
+            self.storage_class = defaults.storage_class.clone();
+        }
+        if self.server_side_encryption.is_empty() {
+            self.server_side_encryption = defaults.server_side_encryption.clone();
+            self.sse_kms_key_id = defaults.sse_kms_key_id.clone();
+        }
+        for (key, value) in &defaults.tags {
+            self.tags.entry(key.clone()).or_insert_with(|| value.clone());
+        }
+        for (key, value) in &defaults.metadata {
+            self.metadata.entry(key.clone()).or_insert_with(|| value.clone());
+        }
+    }
+
+    fn add_to_request(&self, body: &mut JsonValue) {
+        if !self.storage_class.is_empty() {
+            body["storageClass"] = JsonValue::String(self.storage_class.clone());
+        }
+        if !self.server_side_encryption.is_empty() {
+            body["serverSideEncryption"] = JsonValue::String(self.server_side_encryption.clone());
+        }
+        if !self.sse_kms_key_id.is_empty() {
+            body["sseKmsKeyId"] = JsonValue::String(self.sse_kms_key_id.clone());
+        }
+        if !self.tags.is_empty() {
+            body["tags"] = serde_json::json!(self.tags);
+        }
+        if !self.metadata.is_empty() {
+            body["metadata"] = serde_json::json!(self.metadata);
+        }
+    }
+}
 
 #[tauri::command]
 pub async fn upload_file(
@@ -14,147 +120,503 @@ pub async fn upload_file(
     file_type: String,
 ) -> Result<String, String> {
     if let Some(ref options) = options {
-        println!("Uploading video...");
+        upload_and_remove_file(options, Path::new(&file_path), &file_type).await
+    } else {
+        return Err("No recording options provided".to_string());
+    }
+}
+
+/// `upload_file` for paths from the app itself, which may not be valid Unicode.
+pub async fn upload_and_remove_file(options: &RecordingOptions, file_path: &Path, file_type: &str) -> Result<String, String> {
+    let (file_key, _) = upload_segment(options, file_path, file_type).await?;
+
+    // Clean up the uploaded file
+    info!("Removing file after upload: {}", file_path.display());
+    let remove_result = tokio::fs::remove_file(file_path).await;
+    match &remove_result {
+        Ok(_) => info!("File removed successfully"),
+        Err(e) => warn!("Failed to remove file after upload: {}", e),
+    }
+    remove_result.map_err(|e| format!("Failed to remove file after upload: {}", e))?;
+
+    Ok(file_key)
+}
+
+/// Where the upload loops send finished segments.
+pub trait Uploader: Send + Sync {
+    /// Uploads one segment of an already probed length, returning its storage key.
+    fn upload_segment<'a>(
+        &'a self,
+        options: &'a RecordingOptions,
+        file_path: &'a Path,
+        file_type: &'a str,
+        duration_secs: f64,
+    ) -> BoxFuture<'a, Result<String, String>>;
+
+    /// Which of `file_keys` the destination has no object for.
+    fn missing_uploads<'a>(
+        &'a self,
+        options: &'a RecordingOptions,
+        file_keys: &'a [String],
+    ) -> BoxFuture<'a, Result<Vec<String>, String>>;
+
+    /// Uploads a file that isn't a segment, like the recording's thumbnail, returning
+    /// its storage key. The local file is kept.
+    fn upload_file<'a>(
+        &'a self,
+        options: &'a RecordingOptions,
+        file_path: &'a Path,
+        file_type: &'a str,
+    ) -> BoxFuture<'a, Result<String, String>>;
+
+    /// Where the object uploaded as `file_key` can be fetched from, if known.
+    fn object_url(&self, options: &RecordingOptions, file_key: &str) -> Option<String>;
+}
+
+/// The address uploads to each bucket were posted to, from its presigned POSTs. Cap
+/// can point a bucket at any S3-compatible endpoint, so object URLs are built from
+/// this rather than assumed to be on AWS.
+static BUCKET_URLS: RwLock<BTreeMap<String, String>> = RwLock::new(BTreeMap::new());
+
+fn remember_bucket_url(options: &RecordingOptions, post_url: &str) {
+    if let Ok(mut bucket_urls) = BUCKET_URLS.write() {
+        bucket_urls.insert(options.aws_bucket.clone(), post_url.trim_end_matches('/').to_string());
+    }
+}
+
+/// Uploads to the Cap bucket with a signed POST, or with STS credentials when enabled.
+pub struct S3Uploader;
+
+impl Uploader for S3Uploader {
+    fn upload_segment<'a>(
+        &'a self,
+        options: &'a RecordingOptions,
+        file_path: &'a Path,
+        file_type: &'a str,
+        duration_secs: f64,
+    ) -> BoxFuture<'a, Result<String, String>> {
+        upload_probed_segment(options, file_path, file_type, duration_secs).boxed()
+    }
+
+    fn missing_uploads<'a>(
+        &'a self,
+        options: &'a RecordingOptions,
+        file_keys: &'a [String],
+    ) -> BoxFuture<'a, Result<Vec<String>, String>> {
+        missing_uploads(options, file_keys).boxed()
+    }
+
+    fn upload_file<'a>(
+        &'a self,
+        options: &'a RecordingOptions,
+        file_path: &'a Path,
+        file_type: &'a str,
+    ) -> BoxFuture<'a, Result<String, String>> {
+        upload_sidecar_file(options, file_path, file_type).boxed()
+    }
+
+    /// Known once something was uploaded to the bucket in this session; with STS
+    /// credentials the bucket's address is built locally anyway.
+    fn object_url(&self, options: &RecordingOptions, file_key: &str) -> Option<String> {
+        let bucket_url = BUCKET_URLS.read().ok()
+            .and_then(|bucket_urls| bucket_urls.get(&options.aws_bucket).cloned())
+            .or_else(|| use_sts().then(|| sts::bucket_url(options)))?;
+        Some(format!("{}/{}", bucket_url.trim_end_matches('/'), file_key))
+    }
+}
+
+/// Copies segments into a local directory under their storage keys instead of
+/// uploading them, so the recording pipeline can be run without network access.
+pub struct LocalDirUploader {
+    dir: PathBuf,
+}
+
+impl LocalDirUploader {
+    pub fn new(dir: PathBuf) -> Self {
+        LocalDirUploader { dir }
+    }
+}
+
+impl Uploader for LocalDirUploader {
+    fn upload_segment<'a>(
+        &'a self,
+        options: &'a RecordingOptions,
+        file_path: &'a Path,
+        file_type: &'a str,
+        _duration_secs: f64,
+    ) -> BoxFuture<'a, Result<String, String>> {
+        self.upload_file(options, file_path, file_type)
+    }
+
+    fn missing_uploads<'a>(
+        &'a self,
+        _options: &'a RecordingOptions,
+        file_keys: &'a [String],
+    ) -> BoxFuture<'a, Result<Vec<String>, String>> {
+        async move {
+            Ok(file_keys.iter().filter(|key| !self.dir.join(key).is_file()).cloned().collect())
+        }
+        .boxed()
+    }
+
+    fn upload_file<'a>(
+        &'a self,
+        options: &'a RecordingOptions,
+        file_path: &'a Path,
+        file_type: &'a str,
+    ) -> BoxFuture<'a, Result<String, String>> {
+        async move {
+            let file_name = file_path.file_name()
+                .and_then(|name| name.to_str())
+                .ok_or("Invalid file path")?;
+            let file_key = file_key(options, file_type, file_name);
+
+            let destination = self.dir.join(&file_key);
+            if let Some(parent) = destination.parent() {
+                tokio::fs::create_dir_all(parent).await
+                    .map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+            }
+            tokio::fs::copy(file_path, &destination).await
+                .map_err(|e| format!("Failed to copy {} to {}: {}", file_path.display(), destination.display(), e))?;
+            info!("Copied {} to {}", file_path.display(), destination.display());
+
+            Ok(file_key)
+        }
+        .boxed()
+    }
+
+    fn object_url(&self, _options: &RecordingOptions, file_key: &str) -> Option<String> {
+        reqwest::Url::from_file_path(self.dir.join(file_key)).ok().map(|url| url.to_string())
+    }
+}
 
-        let video_duration = get_video_duration(&file_path).await?;
-        let video_duration_str = format!("{:.1}", video_duration);
+/// Uploads a media file with its probed duration, returning the storage key and that
+/// duration. Unlike `upload_file`, the local file is kept, so it can be uploaded again
+/// if the server turns out not to have it.
+pub async fn upload_segment(options: &RecordingOptions, file_path: &Path, file_type: &str) -> Result<(String, f64), String> {
+    info!("Uploading video...");
 
-        let file_name = Path::new(&file_path)
-            .file_name()
-            .and_then(|name| name.to_str())
-            .ok_or("Invalid file path")?
-            .to_string();
+    let video_duration = get_video_duration(file_path).await?;
+    let file_key = upload_probed_segment(options, file_path, file_type, video_duration).await?;
+    Ok((file_key, video_duration))
+}
 
-        let file_key = format!("{}/{}/{}/{}", options.user_id, options.video_id, file_type, file_name);
+/// `upload_segment` for a file whose duration is already known.
+pub async fn upload_probed_segment(options: &RecordingOptions, file_path: &Path, file_type: &str, duration_secs: f64) -> Result<String, String> {
+    upload_to_storage(options, file_path, file_type, Some(format!("{:.1}", duration_secs))).await
+}
 
-        let server_url_base: &'static str = dotenv_codegen::dotenv!("NEXT_PUBLIC_URL");
-        let server_url = format!("{}/api/upload/signed", server_url_base);
+/// The storage key of a file uploaded for `options`' recording.
+pub fn file_key(options: &RecordingOptions, file_type: &str, file_name: &str) -> String {
+    format!("{}/{}/{}/{}", options.user_id, options.video_id, file_type, file_name)
+}
 
-        // Create the request body for the Next.js handler
-        let body = serde_json::json!({
+/// Asks the Cap API which of `file_keys` it has no object for in the recording's bucket.
+pub async fn missing_uploads(options: &RecordingOptions, file_keys: &[String]) -> Result<Vec<String>, String> {
+    let server_url_base: &'static str = dotenv_codegen::dotenv!("NEXT_PUBLIC_URL");
+    let server_url = format!("{}/api/upload/verify", server_url_base);
+
+    let response = reqwest::Client::new()
+        .post(server_url)
+        .json(&serde_json::json!({
             "userId": options.user_id,
-            "duration": video_duration_str,
-            "fileKey": file_key,
+            "videoId": options.video_id,
+            "fileKeys": file_keys,
             "awsBucket": options.aws_bucket,
-            "awsRegion": options.aws_region,
-        });
-
-        let client = reqwest::Client::new();
-        let server_response = client.post(server_url)
-            .json(&body)
-            .send()
-            .await
-            .map_err(|e| format!("Failed to send request to Next.js handler: {}", e))?
-            .text()
-            .await
-            .map_err(|e| format!("Failed to read response from Next.js handler: {}", e))?;
-
-        println!("Server response: {}", server_response);
-
-
-        // Deserialize the server response
-        let presigned_post_data: JsonValue = serde_json::from_str(&server_response)
-            .map_err(|e| format!("Failed to deserialize server response: {}", e))?;
-
-        // Construct the multipart form for the file upload
-        let fields = presigned_post_data["presignedPostData"]["fields"].as_object()
-            .ok_or("Fields object is missing or not an object")?;
-        
-        let mut form = reqwest::multipart::Form::new();
-        
-        for (key, value) in fields.iter() {
-            let value_str = value.as_str()
-                .ok_or(format!("Value for key '{}' is not a string", key))?;
-            form = form.text(key.to_string(), value_str.to_owned());
-        }
-
-        println!("Uploading file: {}", file_path);
-        
-        let mime_type = if file_path.to_lowercase().ends_with(".aac") {
-            "audio/aac"
-        } else if file_path.to_lowercase().ends_with(".webm") { 
-            "audio/webm" 
-        } else {
-            "video/mp2t"
-        };
+        }))
+        .send()
+        .await
+        .map_err(|e| format!("Failed to send verify request: {}", e))?;
+    if !response.status().is_success() {
+        return Err(format!("Failed to verify uploads. Status: {}", response.status()));
+    }
 
-        let file_bytes = tokio::fs::read(&file_path).await.map_err(|e| format!("Failed to read file: {}", e))?;
-        let file_part = reqwest::multipart::Part::bytes(file_bytes)
-            .file_name(file_name.clone())
-            .mime_str(mime_type)
-            .map_err(|e| format!("Error setting MIME type: {}", e))?;
+    let body: JsonValue = response.json().await.map_err(|e| format!("Failed to read verify response: {}", e))?;
+    let missing = body["missing"].as_array().ok_or("Missing list is missing or not an array")?;
 
-        form = form.part("file", file_part);
+    Ok(missing.iter().filter_map(|key| key.as_str().map(str::to_string)).collect())
+}
 
-        let post_url = presigned_post_data["presignedPostData"]["url"].as_str()
-            .ok_or("URL is missing or not a string")?;
+/// Uploads a non-media file (captions, waveform, timeline data) stored alongside the
+/// video under `file_type`. Unlike `upload_file`, no duration is probed and the local
+/// file is kept.
+pub async fn upload_sidecar_file(options: &RecordingOptions, file_path: &Path, file_type: &str) -> Result<String, String> {
+    upload_to_storage(options, file_path, file_type, None).await
+}
 
-        println!("Uploading file to: {}", post_url);
+/// `file_type` tells audio from video where the extension doesn't, as with WebM.
+fn mime_type(file_path: &Path, file_type: &str) -> &'static str {
+    let extension = file_path.extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "aac" => "audio/aac",
+        "webm" if file_type == "audio" => "audio/webm",
+        "webm" => "video/webm",
+        "jpg" | "jpeg" => "image/jpeg",
+        "vtt" => "text/vtt",
+        "json" => "application/json",
+        _ => "video/mp2t",
+    }
+}
 
-        let response = client.post(post_url)
-            .multipart(form)
-            .send()
-            .await;
+/// Maximum attempts for uploads the server rejected with a temporary error.
+const MAX_UPLOAD_ATTEMPTS: u32 = 5;
 
-        match response {
-            Ok(response) if response.status().is_success() => {
-                println!("File uploaded successfully");
-            }
-            Ok(response) => {
-                // The response was received without a network error, but the status code isn't a success.
-                let status = response.status(); // Get the status before consuming the response
-                let error_body = response.text().await.unwrap_or_else(|_| "<no response body>".to_string());
-                eprintln!("Failed to upload file. Status: {}. Body: {}", status, error_body);
-                return Err(format!("Failed to upload file. Status: {}. Body: {}", status, error_body));
-            }
-            Err(e) => {
-                // The send operation failed before we got any response at all (e.g., a network error).
-                return Err(format!("Failed to send upload file request: {}", e));
+pub enum UploadFailure {
+    /// No response at all; the upload waits until the connection is back.
+    Network(String),
+    /// The server answered with a temporary error (5xx, 429).
+    Retryable(String),
+    Fatal(String),
+}
+
+impl From<String> for UploadFailure {
+    fn from(message: String) -> Self {
+        UploadFailure::Fatal(message)
+    }
+}
+
+impl From<&str> for UploadFailure {
+    fn from(message: &str) -> Self {
+        UploadFailure::Fatal(message.to_string())
+    }
+}
+
+/// Requests a presigned POST for `<user_id>/<video_id>/<file_type>/<file name>` and
+/// uploads the file to it, returning the key. When the connection drops, the upload
+/// waits until Cap is reachable again instead of failing; `start_upload_loop` gives up
+/// on segments still waiting once the recording stops or is cancelled. Temporary
+/// server errors are retried with backoff. Files larger than a part go up in parts
+/// instead when uploads are signed locally; see `multipart`.
+async fn upload_to_storage(
+    options: &RecordingOptions,
+    file_path: &Path,
+    file_type: &str,
+    duration: Option<String>,
+) -> Result<String, String> {
+    let file_size = tokio::fs::metadata(file_path).await.map(|metadata| metadata.len()).unwrap_or(0);
+    let is_chunk = upload_stats::is_chunk(file_type);
+    if is_chunk {
+        upload_stats::record_attempt(&options.video_id);
+    }
+    let mut attempt = 1;
+
+    loop {
+        let started_at = Instant::now();
+        let error = match attempt_upload(options, file_path, file_type, duration.as_deref()).await {
+            Ok(file_key) => {
+                record_upload_throughput(file_size, started_at.elapsed());
+                if is_chunk {
+                    upload_stats::record_success(&options.video_id, file_size, started_at.elapsed());
+                    record_segment_uploaded(file_size);
+                }
+                return Ok(file_key);
+            },
+            Err(UploadFailure::Network(e)) => {
+                warn!("Upload of {} could not reach the server, waiting for the connection: {}", file_path.display(), e);
+                set_offline();
+                wait_until_online().await;
+                None
+            },
+            Err(UploadFailure::Retryable(e)) if attempt < MAX_UPLOAD_ATTEMPTS => {
+                let backoff = Duration::from_secs(2u64.pow(attempt));
+                warn!("Upload of {} failed (attempt {}), retrying in {:?}: {}", file_path.display(), attempt, backoff, e);
+                tokio::time::sleep(backoff).await;
+                attempt += 1;
+                None
+            },
+            Err(UploadFailure::Retryable(e) | UploadFailure::Fatal(e)) => Some(e),
+        };
+
+        if let Some(e) = error {
+            if is_chunk {
+                upload_stats::record_failure(&options.video_id, &e);
             }
+            return Err(e);
         }
-
-        // Clean up the uploaded file
-        println!("Removing file after upload: {}", file_path);
-        let remove_result = tokio::fs::remove_file(&file_path).await;
-        match &remove_result {
-            Ok(_) => println!("File removed successfully"),
-            Err(e) => println!("Failed to remove file after upload: {}", e),
+        if is_chunk {
+            upload_stats::record_retry(&options.video_id);
         }
-        remove_result.map_err(|e| format!("Failed to remove file after upload: {}", e))?;
+    }
+}
+
+/// Streams a file from disk as a request body, with its length so the request still
+/// has a `Content-Length`, which S3 requires for POST uploads.
+pub async fn file_body(path: &Path) -> Result<(reqwest::Body, u64), String> {
+    let file = tokio::fs::File::open(path).await.map_err(|e| format!("Failed to read file: {}", e))?;
+    let len = file.metadata().await.map_err(|e| format!("Failed to read file: {}", e))?.len();
+    Ok((reqwest::Body::wrap_stream(ReaderStream::new(file)), len))
+}
+
+async fn attempt_upload(
+    options: &RecordingOptions,
+    file_path: &Path,
+    file_type: &str,
+    duration: Option<&str>,
+) -> Result<String, UploadFailure> {
+    let file_name = file_path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or("Invalid file path")?
+        .to_string();
+
+    let file_key = file_key(options, file_type, &file_name);
+
+    let file_size = tokio::fs::metadata(file_path).await.map_err(|e| format!("Failed to read file: {}", e))?.len();
+    if multipart::use_multipart(file_size) {
+        multipart::upload(options, file_path, &file_key, mime_type(file_path, file_type), duration).await?;
+        return Ok(file_key);
+    }
 
-        Ok(file_key)
+    let presigned_post = if use_sts() {
+        sts::presigned_post(options, &file_key, duration).await?
     } else {
-        return Err("No recording options provided".to_string());
+        request_presigned_post(options, &file_key, duration).await?
+    };
+
+    let mut form = reqwest::multipart::Form::new();
+    for (key, value) in presigned_post.fields {
+        form = form.text(key, value);
+    }
+
+    info!("Uploading file: {}", file_path.display());
+    
+    let (file_body, file_len) = file_body(file_path).await?;
+    let file_part = reqwest::multipart::Part::stream_with_length(file_body, file_len)
+        .file_name(file_name.clone())
+        .mime_str(mime_type(file_path, file_type))
+        .map_err(|e| format!("Error setting MIME type: {}", e))?;
+
+    form = form.part("file", file_part);
+
+    let post_url = presigned_post.url;
+
+    info!("Uploading file to: {}", post_url);
+
+    let response = reqwest::Client::new().post(&post_url)
+        .multipart(form)
+        .send()
+        .await;
+
+    match response {
+        Ok(response) if response.status().is_success() => {
+            info!("File uploaded successfully");
+            remember_bucket_url(options, &post_url);
+        }
+        Ok(response) => {
+            // The response was received without a network error, but the status code isn't a success.
+            let status = response.status(); // Get the status before consuming the response
+            let error_body = response.text().await.unwrap_or_else(|_| "<no response body>".to_string());
+            error!("Failed to upload file. Status: {}. Body: {}", status, error_body);
+            return Err(rejected_upload(status, &error_body).await);
+        }
+        Err(e) => {
+            // The send operation failed before we got any response at all (e.g., a network error).
+            return Err(UploadFailure::Network(format!("Failed to send upload file request: {}", e)));
+        }
+    }
+
+    Ok(file_key)
+}
+
+/// Sorts an upload S3 rejected into ones worth retrying and ones that aren't. Expired
+/// STS credentials are dropped, so the retry is signed with new ones.
+pub async fn rejected_upload(status: reqwest::StatusCode, error_body: &str) -> UploadFailure {
+    let message = format!("Failed to upload file. Status: {}. Body: {}", status, error_body);
+    if use_sts() && status == reqwest::StatusCode::FORBIDDEN && error_body.contains("ExpiredToken") {
+        sts::invalidate_credentials().await;
+        return UploadFailure::Retryable(message);
+    }
+    if status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+        return UploadFailure::Retryable(message);
+    }
+    UploadFailure::Fatal(message)
+}
+
+/// Asks the Cap API to sign a POST for `file_key`.
+async fn request_presigned_post(
+    options: &RecordingOptions,
+    file_key: &str,
+    duration: Option<&str>,
+) -> Result<PresignedPost, UploadFailure> {
+    let server_url_base: &'static str = dotenv_codegen::dotenv!("NEXT_PUBLIC_URL");
+    let server_url = format!("{}/api/upload/signed", server_url_base);
+
+    // Create the request body for the Next.js handler
+    let mut body = serde_json::json!({
+        "userId": options.user_id,
+        "fileKey": file_key,
+        "awsBucket": options.aws_bucket,
+        "awsRegion": options.aws_region,
+    });
+    if let Some(duration) = duration {
+        body["duration"] = JsonValue::String(duration.to_string());
+    }
+    options.storage.add_to_request(&mut body);
+
+    let client = reqwest::Client::new();
+    let server_response = client.post(server_url)
+        .json(&body)
+        .send()
+        .await
+        .map_err(|e| UploadFailure::Network(format!("Failed to send request to Next.js handler: {}", e)))?
+        .text()
+        .await
+        .map_err(|e| format!("Failed to read response from Next.js handler: {}", e))?;
+
+    debug!("Server response: {}", server_response);
+
+
+    // Deserialize the server response
+    let presigned_post_data: JsonValue = serde_json::from_str(&server_response)
+        .map_err(|e| format!("Failed to deserialize server response: {}", e))?;
+
+    // Collect the form fields the upload has to include
+    let fields = presigned_post_data["presignedPostData"]["fields"].as_object()
+        .ok_or("Fields object is missing or not an object")?;
+    
+    let mut form_fields = vec![];
+    
+    for (key, value) in fields.iter() {
+        let value_str = value.as_str()
+            .ok_or(format!("Value for key '{}' is not a string", key))?;
+        form_fields.push((key.to_string(), value_str.to_owned()));
     }
+
+    let url = presigned_post_data["presignedPostData"]["url"].as_str()
+        .ok_or("URL is missing or not a string")?;
+
+    Ok(PresignedPost { url: url.to_string(), fields: form_fields })
 }
 
-async fn get_video_duration(file_path: &str) -> Result<f64, String> {
-    let ffmpeg_binary_path_str = ffmpeg_path_as_str()?;
 
-    let output = Command::new(&ffmpeg_binary_path_str)
-        .args(&[
-            "-i", file_path,
-            "-f", "null",
-            "-"])
+pub async fn get_video_duration(file_path: &Path) -> Result<f64, String> {
+    let ffmpeg_binary = ffmpeg_path()?;
+
+    let output = Command::new(&ffmpeg_binary)
+        .arg("-i")
+        .arg(file_path)
+        .args(["-f", "null", "-"])
         .output()
         .map_err(|e| format!("Failed to execute FFmpeg for getting duration: {}", e))?;
     
     // Extract the duration from FFmpeg's stderr
     let stderr = String::from_utf8_lossy(&output.stderr);
 
-    println!("Stderr: {}", stderr);
+    debug!("Stderr: {}", stderr);
 
     let duration_line = stderr.split('\n')
         .find(|line| line.contains("Duration"))
         .ok_or("Duration line not found in FFmpeg output")?;
 
-    println!("Duration line: {}", duration_line);
+    debug!("Duration line: {}", duration_line);
 
     let duration_str = duration_line.split("Duration:").nth(1).unwrap()
         .split(',').next().unwrap()
         .trim();
 
-    println!("Duration string: {}", duration_str);
+    debug!("Duration string: {}", duration_str);
 
     let duration_parts: Vec<&str> = duration_str.split(':').collect();
     if duration_parts.len() != 3 {
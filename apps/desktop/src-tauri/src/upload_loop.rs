@@ -0,0 +1,432 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use futures::future::{BoxFuture, FutureExt, Shared};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::task::{JoinError, JoinHandle};
+use tokio::time::Duration;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+use crate::backup::{backup_segment, stage_segment};
+use crate::manifest::{
+    mark_segment_failed, mark_segment_skipped, mark_segment_uploaded, segment_number, sort_segments, RecordingManifest,
+};
+use crate::metrics::ActiveUpload;
+use crate::network::{uploads_allowed, wait_until_offline, PendingUpload};
+use crate::recording::{RecordingOptions, UploadOrder};
+use crate::upload::{get_video_duration, Uploader};
+use crate::waveform::write_segment_peaks;
+
+/// Segments shorter than this are junk ffmpeg writes under load, which break stitching
+/// on the server.
+const MIN_SEGMENT_SECS: f64 = 0.1;
+
+/// The joined upload loops of a recording, awaitable by everyone waiting for them.
+pub type UploadLoops = Shared<BoxFuture<'static, Result<(), String>>>;
+
+/// Uploads new segments of `video_type` from `recording_dir` until `shutdown` is
+/// cancelled, then makes one final pass. Segments the manifest already lists as
+/// uploaded are skipped, which also makes the loop usable for retries.
+pub async fn start_upload_loop(
+    recording_dir: PathBuf,
+    options: RecordingOptions,
+    video_type: String,
+    shutdown: CancellationToken,
+    upload_cancelled: Arc<AtomicBool>,
+    upload_limit: Arc<Semaphore>,
+    uploader: Arc<dyn Uploader>,
+) -> Result<(), String> {
+    let chunks_dir = recording_dir.join(&video_type);
+    let mut watched_segments: HashSet<String> = RecordingManifest::load(&recording_dir)
+        .map(|manifest| {
+            let uploaded = manifest.uploaded_segments.get(&video_type).cloned().unwrap_or_default();
+            let skipped = manifest.skipped_segments.get(&video_type).cloned().unwrap_or_default();
+            uploaded.into_iter().chain(skipped).collect()
+        })
+        .unwrap_or_default();
+    let mut ongoing_tasks: Vec<(String, JoinHandle<Result<(), String>>)> = vec![];
+    let mut held_segments: Vec<(String, PendingUpload)> = vec![];
+    let mut failed_uploads = 0;
+
+    loop {
+        if upload_cancelled.load(Ordering::SeqCst) {
+            for (_, task) in &ongoing_tasks {
+                task.abort();
+            }
+            break;
+        }
+
+        if !uploads_allowed() {
+            // Stopping doesn't wait for uploads to be resumed; what wasn't uploaded
+            // stays pending for `retry_upload`.
+            if shutdown.is_cancelled() {
+                info!("Uploads are paused, leaving the remaining {} segments of {} for later", video_type, options.video_id);
+                break;
+            }
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            continue;
+        }
+
+        // Shutdown is only cancelled once ffmpeg has exited, so the segment list is
+        // complete and a single pass picks up the rest.
+        let is_final_loop = shutdown.is_cancelled();
+
+        let mut listed_segments = load_segment_list(&chunks_dir.join("segment_list.txt"))
+            .map_err(|e| e.to_string())?;
+        if options.early_segment_upload.unwrap_or_default() {
+            listed_segments.extend(closed_segments(&chunks_dir));
+        }
+        let mut current_segments = listed_segments
+            .difference(&watched_segments)
+            .cloned()
+            .collect::<Vec<String>>();
+        sort_segments(&mut current_segments);
+
+        // The trailing segment is what stopping waits on, so it skips the upload queue.
+        let priority_segment = if is_final_loop { current_segments.last().cloned() } else { None };
+
+        for segment_filename in &current_segments {
+            let segment_path = chunks_dir.join(segment_filename);
+            let segment_size = segment_path.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+            if segment_path.is_file() && segment_size == 0 {
+                // ffmpeg can list a segment before its data reaches the disk, so look
+                // again on the next pass; once capture has stopped it stays empty.
+                if !is_final_loop {
+                    continue;
+                }
+                warn!("Skipping empty {} segment {}", video_type, segment_filename);
+                mark_segment_skipped(&recording_dir, &video_type, segment_filename);
+            } else if segment_path.is_file() {
+                if let Some(staged_path) = stage_segment(&recording_dir, &video_type, &segment_path) {
+                    let backup = backup_segment(options.clone(), video_type.clone(), staged_path);
+                    tokio::spawn(async move {
+                        if let Err(e) = backup.await {
+                            warn!("{}", e);
+                        }
+                    });
+                }
+                held_segments.push((segment_filename.clone(), PendingUpload::new(segment_size)));
+            }
+            watched_segments.insert(segment_filename.clone());
+        }
+
+        // Uploads wait for a slot in the order they are started. Ordered uploads all
+        // queue up at once; latest-first ones are held back until a slot is free, so a
+        // newer segment can overtake them on a later pass.
+        if options.upload_order == Some(UploadOrder::LatestFirst) {
+            held_segments.sort_by_key(|(segment, _)| std::cmp::Reverse(segment_number(segment).unwrap_or(0)));
+        }
+        for (segment_filename, pending) in std::mem::take(&mut held_segments) {
+            let is_priority = priority_segment.as_ref() == Some(&segment_filename);
+            let permit = match options.upload_order.unwrap_or_default() {
+                UploadOrder::LatestFirst if !is_final_loop => match upload_limit.clone().try_acquire_owned() {
+                    Ok(permit) => Some(permit),
+                    Err(_) => {
+                        held_segments.push((segment_filename, pending));
+                        continue;
+                    },
+                },
+                _ => None,
+            };
+            let upload_task = spawn_segment_upload(
+                recording_dir.clone(),
+                options.clone(),
+                video_type.clone(),
+                segment_filename.clone(),
+                pending,
+                if is_priority { UploadSlot::Bypass } else { permit.map_or(UploadSlot::Wait(upload_limit.clone()), UploadSlot::Held) },
+                uploader.clone(),
+            );
+            ongoing_tasks.push((segment_filename, upload_task));
+        }
+
+        let (finished, running): (Vec<_>, Vec<_>) = ongoing_tasks.into_iter().partition(|(_, task)| task.is_finished());
+        ongoing_tasks = running;
+        for (segment_filename, task) in finished {
+            if !record_upload_result(&recording_dir, &video_type, &segment_filename, task.await) {
+                failed_uploads += 1;
+            }
+        }
+
+        if is_final_loop {
+            break;
+        }
+        tokio::select! {
+            _ = shutdown.cancelled() => {},
+            _ = tokio::time::sleep(Duration::from_millis(500)) => {},
+        }
+    }
+
+    // Stopping doesn't wait for the connection to come back; uploads still waiting for
+    // it are given up on and their segments left for `retry_upload`. Cancelling the
+    // recording aborts whatever is left.
+    for (segment_filename, mut task) in ongoing_tasks {
+        let result = tokio::select! {
+            result = &mut task => result,
+            _ = wait_until_cancelled(&upload_cancelled) => {
+                task.abort();
+                task.await
+            },
+            _ = wait_until_offline() => {
+                task.abort();
+                Ok(Err("The server was unreachable when the recording stopped".to_string()))
+            },
+        };
+        if !record_upload_result(&recording_dir, &video_type, &segment_filename, result) {
+            failed_uploads += 1;
+        }
+    }
+    if failed_uploads > 0 {
+        warn!("{} {} segments of {} failed to upload", failed_uploads, video_type, options.video_id);
+    }
+
+    Ok(())
+}
+
+async fn wait_until_cancelled(upload_cancelled: &AtomicBool) {
+    while !upload_cancelled.load(Ordering::SeqCst) {
+        tokio::time::sleep(Duration::from_millis(500)).await;
+    }
+}
+
+/// How an upload gets one of the concurrent upload slots.
+enum UploadSlot {
+    /// Waits for a slot like every other upload.
+    Wait(Arc<Semaphore>),
+    /// Already holds one.
+    Held(OwnedSemaphorePermit),
+    /// Goes ahead without one.
+    Bypass,
+}
+
+fn spawn_segment_upload(
+    recording_dir: PathBuf,
+    options: RecordingOptions,
+    video_type: String,
+    segment_filename: String,
+    pending: PendingUpload,
+    slot: UploadSlot,
+    uploader: Arc<dyn Uploader>,
+) -> JoinHandle<Result<(), String>> {
+    let segment_path = recording_dir.join(&video_type).join(&segment_filename);
+    tokio::spawn(async move {
+        let _pending = pending;
+        let _permit = match slot {
+            UploadSlot::Wait(upload_limit) => Some(upload_limit.acquire_owned().await.map_err(|e| e.to_string())?),
+            UploadSlot::Held(permit) => Some(permit),
+            UploadSlot::Bypass => None,
+        };
+        if !options.owns_segment(&segment_filename) {
+            warn!("Skipping {} segment {}, it doesn't belong to {}", video_type, segment_filename, options.video_id);
+            mark_segment_skipped(&recording_dir, &video_type, &segment_filename);
+            return Ok(());
+        }
+        let _active = ActiveUpload::start();
+        let duration_secs = get_video_duration(&segment_path).await?;
+        if duration_secs < MIN_SEGMENT_SECS {
+            warn!("Skipping {} segment {}, it is only {:.3}s long", video_type, segment_filename, duration_secs);
+            mark_segment_skipped(&recording_dir, &video_type, &segment_filename);
+            return Ok(());
+        }
+        if video_type == "audio" {
+            if let Err(e) = write_segment_peaks(&recording_dir, &segment_path).await {
+                warn!("Failed to compute waveform peaks for {}: {}", segment_filename, e);
+            }
+        }
+        info!("Uploading video for {}: {}", video_type, segment_path.display());
+        uploader.upload_segment(&options, &segment_path, &video_type, duration_secs).await?;
+        mark_segment_uploaded(&recording_dir, &video_type, &segment_filename, duration_secs);
+        Ok(())
+    })
+}
+
+/// Records a failed upload task against its segment in the manifest, returning `false`
+/// for it. Tasks aborted by a cancelled recording aren't failures.
+fn record_upload_result(
+    recording_dir: &Path,
+    video_type: &str,
+    segment_filename: &str,
+    result: Result<Result<(), String>, JoinError>,
+) -> bool {
+    let error = match result {
+        Ok(Ok(())) => return true,
+        Err(e) if e.is_cancelled() => return true,
+        Ok(Err(e)) => e,
+        Err(e) => e.to_string(),
+    };
+    warn!("Failed to upload {} segment {}: {}", video_type, segment_filename, error);
+    mark_segment_failed(recording_dir, video_type, segment_filename, &error);
+    false
+}
+
+/// Joins the spawned upload loops into one future that can be awaited from several places.
+pub fn join_upload_loops(screen: JoinHandle<Result<(), String>>, audio: JoinHandle<Result<(), String>>) -> UploadLoops {
+    async move {
+        let (screen, audio) = tokio::join!(screen, audio);
+        screen.map_err(|e| e.to_string())??;
+        audio.map_err(|e| e.to_string())?
+    }
+    .boxed()
+    .shared()
+}
+
+/// Segments ffmpeg has moved on from, i.e. every numbered chunk but the newest, which
+/// may still be open for writing.
+fn closed_segments(chunks_dir: &Path) -> Vec<String> {
+    let mut segments: Vec<String> = match std::fs::read_dir(chunks_dir) {
+        Ok(entries) => entries.flatten()
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .filter(|file_name| segment_number(file_name).is_some())
+            .collect(),
+        Err(_) => return vec![],
+    };
+    sort_segments(&mut segments);
+    segments.pop();
+    segments
+}
+
+pub fn load_segment_list(segment_list_path: &Path) -> io::Result<HashSet<String>> {
+    let file = File::open(segment_list_path)?;
+    let reader = BufReader::new(file);
+
+    let mut segments = HashSet::new();
+    for line_result in reader.lines() {
+        let line = line_result?;
+        if !line.is_empty() {
+            segments.insert(line);
+        }
+    }
+
+    Ok(segments)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::{pending_segments, segment_pattern};
+    use crate::upload::{file_key, LocalDirUploader};
+    use crate::utils::{ensure_ffmpeg, validate_ffmpeg};
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("cap-upload-loop-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn load_segment_list_skips_blank_lines() {
+        let dir = test_dir("segment-list");
+        let segment_list_path = dir.join("segment_list.txt");
+        std::fs::write(&segment_list_path, "recording_chunk_00000.ts\n\nrecording_chunk_00001.ts\nrecording_chunk_00001.ts\n").unwrap();
+
+        let segments = load_segment_list(&segment_list_path).unwrap();
+        let expected: HashSet<String> = ["recording_chunk_00000.ts", "recording_chunk_00001.ts"].iter().map(|s| s.to_string()).collect();
+        assert_eq!(segments, expected);
+    }
+
+    #[test]
+    fn load_segment_list_fails_without_a_list() {
+        let dir = test_dir("missing-list");
+        assert!(load_segment_list(&dir.join("segment_list.txt")).is_err());
+    }
+
+    #[test]
+    fn closed_segments_leave_out_the_newest() {
+        let dir = test_dir("closed");
+        for file_name in ["recording_chunk_00009.ts", "recording_chunk_00010.ts", "recording_chunk_00008.ts", "segment_list.txt"] {
+            std::fs::write(dir.join(file_name), b"").unwrap();
+        }
+
+        assert_eq!(closed_segments(&dir), ["recording_chunk_00008.ts", "recording_chunk_00009.ts"]);
+    }
+
+    #[test]
+    fn closed_segments_are_empty_until_a_second_segment_starts() {
+        let dir = test_dir("single");
+        assert!(closed_segments(&dir.join("missing")).is_empty());
+
+        std::fs::write(dir.join("recording_chunk_00000.ts"), b"").unwrap();
+        assert!(closed_segments(&dir).is_empty());
+    }
+
+    /// An ffmpeg the recording pipeline can use, made the active one; `None` when there
+    /// is none on the `PATH`.
+    fn system_ffmpeg(data_dir: &Path) -> Option<PathBuf> {
+        let path = which::which("ffmpeg").ok()?;
+        validate_ffmpeg(&path).ok()?;
+        ensure_ffmpeg(data_dir, Some(&path)).ok()
+    }
+
+    /// Records a few seconds of ffmpeg's test pattern while the upload loop runs, the
+    /// way a screen recording does, and checks every segment ends up in the local
+    /// upload directory. Skipped without ffmpeg.
+    #[tokio::test]
+    async fn test_pattern_recording_is_uploaded_end_to_end() {
+        let dir = test_dir("end-to-end");
+        let ffmpeg = match system_ffmpeg(&dir) {
+            Some(ffmpeg) => ffmpeg,
+            None => {
+                eprintln!("Skipping the end-to-end upload test, there is no usable ffmpeg on the PATH");
+                return;
+            },
+        };
+
+        let options: RecordingOptions = serde_json::from_value(serde_json::json!({
+            "user_id": "user",
+            "video_id": "video",
+            "aws_region": "us-east-1",
+            "aws_bucket": "bucket",
+            "segment_time_secs": 1,
+            "chunk_naming": "video_id",
+        })).unwrap();
+        let recording_dir = dir.join(&options.video_id);
+        let chunks_dir = recording_dir.join("screen");
+        let segment_list_path = chunks_dir.join("segment_list.txt");
+        std::fs::create_dir_all(&chunks_dir).unwrap();
+        std::fs::write(&segment_list_path, b"").unwrap();
+        RecordingManifest::new(&options).save(&recording_dir).unwrap();
+
+        let upload_dir = dir.join("uploads");
+        let shutdown = CancellationToken::new();
+        let upload_loop = tokio::spawn(start_upload_loop(
+            recording_dir.clone(),
+            options.clone(),
+            "screen".to_string(),
+            shutdown.clone(),
+            Arc::new(AtomicBool::new(false)),
+            Arc::new(Semaphore::new(2)),
+            Arc::new(LocalDirUploader::new(upload_dir.clone())),
+        ));
+
+        let status = tokio::process::Command::new(&ffmpeg)
+            .args(["-hide_banner", "-loglevel", "error"])
+            .args(["-f", "lavfi", "-i", "testsrc=duration=3:size=320x240:rate=30"])
+            .args(["-c:v", "libx264", "-g", "30", "-an"])
+            .args(["-f", "segment", "-segment_time", "1", "-segment_format", "mpegts"])
+            .args(["-segment_list_type", "flat", "-reset_timestamps", "1", "-segment_list"])
+            .arg(&segment_list_path)
+            .arg(chunks_dir.join(segment_pattern(&options.segment_prefix("screen"), "ts")))
+            .status()
+            .await
+            .unwrap();
+        assert!(status.success());
+        shutdown.cancel();
+        upload_loop.await.unwrap().unwrap();
+
+        let segments = load_segment_list(&segment_list_path).unwrap();
+        assert!(!segments.is_empty());
+        let manifest = RecordingManifest::load(&recording_dir).unwrap();
+        let uploaded = manifest.uploaded_segments.get("screen").cloned().unwrap_or_default();
+        for segment in &segments {
+            assert!(segment.starts_with("video_"), "{} isn't named after the video", segment);
+            assert!(uploaded.contains(segment), "{} wasn't uploaded", segment);
+            assert!(upload_dir.join(file_key(&options, "screen", segment)).is_file());
+        }
+        assert!(pending_segments(&recording_dir, "screen").unwrap().is_empty());
+    }
+}
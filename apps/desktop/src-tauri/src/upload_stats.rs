@@ -0,0 +1,121 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+use serde::Serialize;
+use tauri::{AppHandle, Manager};
+
+use crate::manifest::{chunk_failures, ChunkFailure, RecordingStatus};
+
+/// Emitted with an `UploadSummary` when a recording's uploads finish.
+pub const UPLOAD_SUMMARY_EVENT: &str = "uploads://summary";
+/// Emitted with an `UploadFailureReport` when a recording's uploads finish with
+/// segments left behind.
+pub const UPLOAD_FAILURES_EVENT: &str = "uploads://failures";
+
+static STATS: Mutex<Option<HashMap<String, UploadStats>>> = Mutex::new(None);
+
+/// Segment upload counters for one upload session of a recording. A retry starts a
+/// new session. Sidecar files (captions, waveform, ...) are not counted.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct UploadStats {
+    pub chunks_attempted: u32,
+    pub chunks_succeeded: u32,
+    pub chunks_failed: u32,
+    /// Extra attempts after a failed one, including waits for the connection.
+    pub retries: u32,
+    pub bytes_uploaded: u64,
+    /// Time spent sending the successful chunks.
+    pub upload_secs: f64,
+    pub average_throughput_bytes_per_sec: Option<f64>,
+    pub last_error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UploadSummary {
+    pub video_id: String,
+    pub status: RecordingStatus,
+    pub stats: UploadStats,
+}
+
+/// Only segments count; sidecar uploads share the same path with other types.
+pub fn is_chunk(file_type: &str) -> bool {
+    matches!(file_type, "screen" | "audio")
+}
+
+/// Starts a new session for `video_id`, dropping the previous counters.
+pub fn reset(video_id: &str) {
+    if let Ok(mut stats) = STATS.lock() {
+        stats.get_or_insert_with(HashMap::new).insert(video_id.to_string(), UploadStats::default());
+    }
+}
+
+fn update(video_id: &str, update: impl FnOnce(&mut UploadStats)) {
+    if let Ok(mut stats) = STATS.lock() {
+        update(stats.get_or_insert_with(HashMap::new).entry(video_id.to_string()).or_default());
+    }
+}
+
+pub fn record_attempt(video_id: &str) {
+    update(video_id, |stats| stats.chunks_attempted += 1);
+}
+
+pub fn record_retry(video_id: &str) {
+    update(video_id, |stats| stats.retries += 1);
+}
+
+pub fn record_success(video_id: &str, bytes: u64, elapsed: Duration) {
+    update(video_id, |stats| {
+        stats.chunks_succeeded += 1;
+        stats.bytes_uploaded += bytes;
+        stats.upload_secs += elapsed.as_secs_f64();
+        if stats.upload_secs > 0.0 {
+            stats.average_throughput_bytes_per_sec = Some(stats.bytes_uploaded as f64 / stats.upload_secs);
+        }
+    });
+}
+
+pub fn record_failure(video_id: &str, error: &str) {
+    update(video_id, |stats| {
+        stats.chunks_failed += 1;
+        stats.last_error = Some(error.to_string());
+    });
+}
+
+pub fn upload_stats(video_id: &str) -> Option<UploadStats> {
+    STATS.lock().ok()?.as_ref()?.get(video_id).cloned()
+}
+
+/// Emits the final counters of the session once its uploads are done.
+pub fn emit_summary(app: &AppHandle, video_id: &str, status: RecordingStatus) {
+    let summary = UploadSummary {
+        video_id: video_id.to_string(),
+        status,
+        stats: upload_stats(video_id).unwrap_or_default(),
+    };
+    let _ = app.emit_all(UPLOAD_SUMMARY_EVENT, summary);
+}
+
+/// The segments a recording's uploads left behind, per video type.
+#[derive(Debug, Clone, Serialize)]
+pub struct UploadFailureReport {
+    pub video_id: String,
+    pub failures: BTreeMap<String, Vec<ChunkFailure>>,
+}
+
+pub fn emit_failure_report(app: &AppHandle, recording_dir: &Path, video_id: &str) {
+    let failures = chunk_failures(recording_dir);
+    if failures.is_empty() {
+        return;
+    }
+    let report = UploadFailureReport {
+        video_id: video_id.to_string(),
+        failures,
+    };
+    let _ = app.emit_all(UPLOAD_FAILURES_EVENT, report);
+}
+
+#[tauri::command]
+pub fn get_upload_stats(video_id: String) -> Result<UploadStats, String> {
+    upload_stats(&video_id).ok_or(format!("No uploads recorded for {}", video_id))
+}
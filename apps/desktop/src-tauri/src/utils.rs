@@ -1,14 +1,32 @@
 use tokio::process::{ChildStderr};
 use std::process::{Command};
+use std::ffi::OsStr;
 use std::io::Error as IoError;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use tauri::{AppHandle, State};
+use tokio::sync::Mutex;
 use reqwest::Client;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt};
+use tokio::sync::mpsc::UnboundedSender;
+use tracing::{debug, error, info, warn};
+use crate::settings::Settings;
+use crate::ffmpeg_error::{classify_stderr_line, FfmpegError, FfmpegErrorKind};
 use ffmpeg_sidecar::{
+    download::{download_ffmpeg_package, ffmpeg_download_url, unpack_ffmpeg},
     paths::sidecar_dir,
 };
 
+pub fn share_link(video_id: &str) -> String {
+    format!("{}/share/{}", dotenv_codegen::dotenv!("NEXT_PUBLIC_URL"), video_id)
+}
+
 pub async fn send_metadata_api(video_id: &str, start_timestamp: f64, log_type: &str) -> Result<(), String> {
     let client = Client::new();
-    println!("Sending metadata API request for video {}: {}", video_id, start_timestamp);
+    info!("Sending metadata API request for video {}: {}", video_id, start_timestamp);
     
     let params = [
         ("videoId", video_id),
@@ -35,28 +53,129 @@ pub async fn send_metadata_api(video_id: &str, start_timestamp: f64, log_type: &
         }
 }
 
-pub async fn monitor_and_log_recording_start(stderr: ChildStderr, video_id: &str, log_type: &str) -> Result<(), std::io::Error> {
-    use tokio::io::{BufReader, AsyncBufReadExt};
+/// Waits for ffmpeg to report its first timestamp, then keeps draining stderr into
+/// `log_path` in the background so the pipe never fills up and the output is kept for support.
+/// Every line is also forwarded to `line_tx` when given, e.g. for progress parsing.
+///
+/// With `report_start`, the start time is sent to the metadata API; a restarted
+/// capture continues the same video and leaves it alone.
+///
+/// Known failures are classified as they appear; if ffmpeg exits before starting, the
+/// first classified error (or the last line it printed) is returned.
+pub async fn monitor_and_log_recording_start(
+    stderr: ChildStderr,
+    video_id: &str,
+    log_type: &str,
+    log_path: PathBuf,
+    line_tx: Option<UnboundedSender<String>>,
+    report_start: bool,
+) -> Result<(), FfmpegError> {
+    use tokio::io::{BufReader, AsyncWriteExt};
     use chrono::Utc;
+
+    let io_error = |e: IoError| FfmpegError::new(FfmpegErrorKind::Unknown, format!("Failed to read {} output: {}", log_type, e));
+
+    if let Some(parent) = log_path.parent() {
+        tokio::fs::create_dir_all(parent).await.map_err(io_error)?;
+    }
+    let mut log_file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&log_path)
+        .await
+        .map_err(io_error)?;
       
-    let reader = BufReader::new(stderr);
-    let mut lines = reader.lines();
+    let mut reader = BufReader::new(stderr);
+    let mut first_error: Option<FfmpegError> = None;
+    let mut last_line = String::new();
+
+    while let Some(line) = read_stderr_line(&mut reader).await.map_err(io_error)? {
+        log_file.write_all(format!("{}\n", line).as_bytes()).await.map_err(io_error)?;
+
+        if first_error.is_none() {
+            if let Some(kind) = classify_stderr_line(&line) {
+                error!("{} ffmpeg error ({:?}): {}", log_type, kind, line);
+                first_error = Some(FfmpegError::new(kind, line.clone()));
+            }
+        }
 
-    while let Some(line) = lines.next_line().await? {
-        if line.contains("00:00") {
+        if first_error.is_none() && line.contains("00:00") {
             let timestamp = Utc::now().timestamp() as f64;
-            println!("{} recording started at timestamp: {}", log_type, timestamp);
-            if send_metadata_api(video_id, timestamp, log_type).await.is_err() {
-                eprintln!("Failed to send metadata to API.");
+            info!("{} recording started at timestamp: {}", log_type, timestamp);
+            if report_start && send_metadata_api(video_id, timestamp, log_type).await.is_err() {
+                error!("Failed to send metadata to API.");
+            }
+
+            if let Some(ref tx) = line_tx {
+                let _ = tx.send(line);
             }
+
+            let log_type = log_type.to_string();
+            tokio::spawn(async move {
+                while let Ok(Some(line)) = read_stderr_line(&mut reader).await {
+                    if log_file.write_all(format!("{}\n", line).as_bytes()).await.is_err() {
+                        break;
+                    }
+                    if let Some(kind) = classify_stderr_line(&line) {
+                        warn!("{} ffmpeg reported an error while recording ({:?}): {}", log_type, kind, line);
+                    }
+                    if let Some(ref tx) = line_tx {
+                        let _ = tx.send(line);
+                    }
+                }
+            });
+
             return Ok(());
         }
+
+        last_line = line.clone();
+        if let Some(ref tx) = line_tx {
+            let _ = tx.send(line);
+        }
     }
 
-    Err(IoError::new(std::io::ErrorKind::Other, "Screen recording did not start successfully or start timestamp was not found."))
+    Err(first_error.unwrap_or_else(|| {
+        let message = if last_line.is_empty() {
+            format!("{} recording did not start successfully or start timestamp was not found.", log_type)
+        } else {
+            last_line
+        };
+        FfmpegError::new(FfmpegErrorKind::Unknown, message)
+    }))
 }
 
-pub fn run_command(command: &str, args: Vec<&str>) -> Result<(String, String), String> {
+/// Reads the next stderr line, treating `\r` as a line break too since ffmpeg
+/// rewrites its progress line in place with carriage returns.
+pub async fn read_stderr_line<R: AsyncBufRead + Unpin>(reader: &mut R) -> Result<Option<String>, std::io::Error> {
+    let mut line = Vec::new();
+
+    loop {
+        let available = reader.fill_buf().await?;
+        if available.is_empty() {
+            if line.is_empty() {
+                return Ok(None);
+            }
+            return Ok(Some(String::from_utf8_lossy(&line).into_owned()));
+        }
+
+        match available.iter().position(|&b| b == b'\n' || b == b'\r') {
+            Some(index) => {
+                line.extend_from_slice(&available[..index]);
+                reader.consume(index + 1);
+                if !line.is_empty() {
+                    return Ok(Some(String::from_utf8_lossy(&line).into_owned()));
+                }
+            },
+            None => {
+                let len = available.len();
+                line.extend_from_slice(available);
+                reader.consume(len);
+            },
+        }
+    }
+}
+
+pub fn run_command(command: impl AsRef<OsStr>, args: Vec<&str>) -> Result<(String, String), String> {
     let output = Command::new(command)
         .args(args)
         .output()
@@ -65,21 +184,320 @@ pub fn run_command(command: &str, args: Vec<&str>) -> Result<(String, String), S
     let stdout = String::from_utf8(output.stdout).unwrap_or_else(|_| "".to_string());
     let stderr = String::from_utf8(output.stderr).unwrap_or_else(|_| "".to_string());
 
-    println!("Command output: {}", stdout);
-    println!("Command error: {}", stderr);
+    debug!("Command output: {}", stdout);
+    debug!("Command error: {}", stderr);
 
     Ok((stdout, stderr))
 }
 
-pub fn ffmpeg_path_as_str() -> Result<String, String> {
-    let binary_name = if cfg!(target_os = "windows") {
+/// An ffmpeg build we know works with Cap, downloaded when the bundled binary is
+/// missing or unusable. The SHA-256 of the archive is part of the app rather than
+/// fetched from the host that serves it, so a compromised mirror can't swap the
+/// binary. Bumping a build means updating its digest from a verified download; while
+/// a digest is empty the latest build is fetched into the sidecar dir instead, as
+/// before builds were pinned.
+struct PinnedFfmpegBuild {
+    version: &'static str,
+    url: &'static str,
+    sha256: &'static str,
+}
+
+const PINNED_FFMPEG_MACOS: PinnedFfmpegBuild = PinnedFfmpegBuild {
+    version: "6.1.1",
+    url: "https://evermeet.cx/ffmpeg/ffmpeg-6.1.1.zip",
+    sha256: "",
+};
+
+const PINNED_FFMPEG_WINDOWS: PinnedFfmpegBuild = PinnedFfmpegBuild {
+    version: "6.1.1",
+    url: "https://github.com/GyanD/codexffmpeg/releases/download/6.1.1/ffmpeg-6.1.1-essentials_build.zip",
+    sha256: "",
+};
+
+/// Superseded static builds move from `releases/` to `old-releases/`.
+const PINNED_FFMPEG_LINUX: PinnedFfmpegBuild = PinnedFfmpegBuild {
+    version: "6.1",
+    url: "https://johnvansickle.com/ffmpeg/old-releases/ffmpeg-6.1-amd64-static.tar.xz",
+    sha256: "",
+};
+
+/// Encoders and muxers the recording pipeline depends on; a binary without them is treated as incompatible.
+const REQUIRED_FFMPEG_ENCODERS: [&str; 2] = ["libx264", "aac"];
+const REQUIRED_FFMPEG_MUXERS: [&str; 2] = ["segment", "mpegts"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FfmpegSource {
+    Custom,
+    Bundled,
+    Downloaded,
+}
+
+impl FfmpegSource {
+    fn as_str(self) -> &'static str {
+        match self {
+            FfmpegSource::Custom => "custom",
+            FfmpegSource::Bundled => "bundled",
+            FfmpegSource::Downloaded => "downloaded",
+        }
+    }
+}
+
+static FFMPEG_PATH: RwLock<Option<(PathBuf, FfmpegSource)>> = RwLock::new(None);
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FfmpegInfo {
+    pub path: String,
+    pub version: String,
+    pub source: String,
+}
+
+fn ffmpeg_binary_name() -> &'static str {
+    if cfg!(target_os = "windows") {
         "ffmpeg.exe"
     } else {
         "ffmpeg"
+    }
+}
+
+fn pinned_ffmpeg_build() -> Result<&'static PinnedFfmpegBuild, String> {
+    match std::env::consts::OS {
+        "macos" => Ok(&PINNED_FFMPEG_MACOS),
+        "windows" => Ok(&PINNED_FFMPEG_WINDOWS),
+        "linux" => Ok(&PINNED_FFMPEG_LINUX),
+        _ => Err("Unsupported OS".to_string()),
+    }
+}
+
+fn bundled_ffmpeg_path() -> Result<PathBuf, String> {
+    Ok(sidecar_dir().map_err(|e| e.to_string())?.join(ffmpeg_binary_name()))
+}
+
+fn cached_ffmpeg_dir(data_dir: &Path) -> Result<PathBuf, String> {
+    Ok(data_dir.join("ffmpeg").join(pinned_ffmpeg_build()?.version))
+}
+
+/// Returns the `ffmpeg version ...` string of the binary at `path`.
+pub fn probe_ffmpeg_version(path: &Path) -> Result<String, String> {
+    let output = Command::new(path)
+        .arg("-version")
+        .output()
+        .map_err(|e| format!("Failed to run {}: {}", path.display(), e))?;
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    stdout.lines()
+        .next()
+        .and_then(|line| line.strip_prefix("ffmpeg version "))
+        .and_then(|rest| rest.split_whitespace().next())
+        .map(|version| version.to_string())
+        .ok_or_else(|| format!("{} did not report an ffmpeg version", path.display()))
+}
+
+/// Checks that `path` is a runnable ffmpeg with every required encoder and muxer,
+/// returning its version or the reason it can't be used.
+pub fn validate_ffmpeg(path: &Path) -> Result<String, String> {
+    if !path.is_file() {
+        return Err(format!("{} does not exist", path.display()));
+    }
+
+    let version = probe_ffmpeg_version(path)?;
+    ensure_ffmpeg_components(path, "-encoders", &REQUIRED_FFMPEG_ENCODERS)?;
+    ensure_ffmpeg_components(path, "-muxers", &REQUIRED_FFMPEG_MUXERS)?;
+
+    Ok(version)
+}
+
+/// Lists components with e.g. `ffmpeg -encoders`, where the name is the second column.
+fn ensure_ffmpeg_components(path: &Path, list_flag: &str, required: &[&str]) -> Result<(), String> {
+    let output = Command::new(path)
+        .args(["-hide_banner", list_flag])
+        .output()
+        .map_err(|e| format!("Failed to run {}: {}", path.display(), e))?;
+    let listing = String::from_utf8_lossy(&output.stdout);
+
+    let missing: Vec<&str> = required.iter()
+        .filter(|name| !listing.lines().any(|line| line.split_whitespace().nth(1) == Some(**name)))
+        .copied()
+        .collect();
+
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("{} is missing required components: {}", path.display(), missing.join(", ")))
+    }
+}
+
+fn is_compatible_ffmpeg(path: &Path) -> bool {
+    validate_ffmpeg(path).is_ok()
+}
+
+fn sha256_file(path: &Path) -> Result<String, String> {
+    let mut file = File::open(path).map_err(|e| e.to_string())?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher).map_err(|e| e.to_string())?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+fn download_pinned_ffmpeg(destination: &Path) -> Result<PathBuf, String> {
+    let build = pinned_ffmpeg_build()?;
+    if build.sha256.is_empty() {
+        return Err(format!("No checksum is pinned for FFmpeg {}, not downloading it", build.version));
+    }
+    std::fs::create_dir_all(destination).map_err(|e| e.to_string())?;
+
+    info!("Downloading FFmpeg {} from {}", build.version, build.url);
+    let archive_path = download_ffmpeg_package(build.url, destination).map_err(|e| e.to_string())?;
+
+    let actual_checksum = sha256_file(&archive_path)?;
+    if actual_checksum != build.sha256 {
+        let _ = std::fs::remove_file(&archive_path);
+        return Err(format!(
+            "FFmpeg download checksum mismatch: expected {}, got {}",
+            build.sha256, actual_checksum
+        ));
+    }
+
+    info!("Extracting FFmpeg to {}", destination.display());
+    unpack_ffmpeg(&archive_path, destination).map_err(|e| e.to_string())?;
+    let _ = std::fs::remove_file(&archive_path);
+
+    Ok(destination.join(ffmpeg_binary_name()))
+}
+
+/// Unverified fallback for platforms whose pinned build has no digest yet: the latest
+/// release ffmpeg-sidecar knows of, unpacked over the sidecar binary.
+fn download_latest_ffmpeg() -> Result<(), String> {
+    let download_url = ffmpeg_download_url().map_err(|e| e.to_string())?;
+    let destination = sidecar_dir().map_err(|e| e.to_string())?;
+
+    warn!("No checksum is pinned for this platform, downloading FFmpeg unverified from {}", download_url);
+    let archive_path = download_ffmpeg_package(download_url, &destination).map_err(|e| e.to_string())?;
+    info!("Extracting FFmpeg to {}", destination.display());
+    unpack_ffmpeg(&archive_path, &destination).map_err(|e| e.to_string())?;
+
+    Ok(())
+}
+
+/// Picks the ffmpeg binary used for all recordings: the user's custom binary when it
+/// validates, then the bundled sidecar, otherwise the pinned build cached in the data dir,
+/// downloading it if needed. Without a pinned digest the sidecar is downloaded instead.
+pub fn ensure_ffmpeg(data_dir: &Path, custom_path: Option<&Path>) -> Result<PathBuf, String> {
+    if let Some(custom) = custom_path {
+        match validate_ffmpeg(custom) {
+            Ok(version) => {
+                info!("Using custom FFmpeg {} at {}", version, custom.display());
+                set_active_ffmpeg(custom.to_path_buf(), FfmpegSource::Custom)?;
+                return Ok(custom.to_path_buf());
+            },
+            Err(e) => warn!("Custom FFmpeg is unusable, falling back to the bundled one: {}", e),
+        }
+    }
+
+    let bundled = bundled_ffmpeg_path()?;
+    let (path, source) = if is_compatible_ffmpeg(&bundled) {
+        (bundled, FfmpegSource::Bundled)
+    } else {
+        warn!("Bundled FFmpeg at {} is missing or incompatible", bundled.display());
+
+        if pinned_ffmpeg_build()?.sha256.is_empty() {
+            download_latest_ffmpeg()?;
+            if !is_compatible_ffmpeg(&bundled) {
+                return Err(format!("Downloaded FFmpeg at {} is not usable", bundled.display()));
+            }
+            (bundled, FfmpegSource::Downloaded)
+        } else {
+            let cache_dir = cached_ffmpeg_dir(data_dir)?;
+            let cached = cache_dir.join(ffmpeg_binary_name());
+            if !is_compatible_ffmpeg(&cached) {
+                download_pinned_ffmpeg(&cache_dir)?;
+                if !is_compatible_ffmpeg(&cached) {
+                    return Err(format!("Downloaded FFmpeg at {} is not usable", cached.display()));
+                }
+            }
+            (cached, FfmpegSource::Downloaded)
+        }
     };
-    
-    let path = sidecar_dir().map_err(|e| e.to_string())?.join(binary_name);
-    path.to_str()
-        .map(|s| s.to_owned()) // Converts the &str to a String
-        .ok_or_else(|| "Failed to convert FFmpeg binary path to string".to_string())
-}
\ No newline at end of file
+
+    info!("Using FFmpeg at {}", path.display());
+    set_active_ffmpeg(path.clone(), source)?;
+
+    Ok(path)
+}
+
+fn set_active_ffmpeg(path: PathBuf, source: FfmpegSource) -> Result<(), String> {
+    *FFMPEG_PATH.write().map_err(|e| e.to_string())? = Some((path, source));
+    Ok(())
+}
+
+fn active_ffmpeg() -> Result<(PathBuf, FfmpegSource), String> {
+    if let Some(active) = FFMPEG_PATH.read().map_err(|e| e.to_string())?.clone() {
+        return Ok(active);
+    }
+    Ok((bundled_ffmpeg_path()?, FfmpegSource::Bundled))
+}
+
+pub fn ffmpeg_path() -> Result<PathBuf, String> {
+    active_ffmpeg().map(|(path, _)| path)
+}
+
+#[tauri::command]
+pub fn get_ffmpeg_version() -> Result<FfmpegInfo, String> {
+    let (path, source) = active_ffmpeg()?;
+    let version = probe_ffmpeg_version(&path)?;
+
+    Ok(FfmpegInfo {
+        path: path.to_string_lossy().into_owned(),
+        version,
+        source: source.as_str().to_string(),
+    })
+}
+
+/// Looks for an ffmpeg on the `PATH` and reports it if it is usable by Cap.
+#[tauri::command]
+pub fn detect_system_ffmpeg() -> Result<Option<FfmpegInfo>, String> {
+    let path = match which::which("ffmpeg") {
+        Ok(path) => path,
+        Err(_) => return Ok(None),
+    };
+
+    match validate_ffmpeg(&path) {
+        Ok(version) => Ok(Some(FfmpegInfo {
+            path: path.to_string_lossy().into_owned(),
+            version,
+            source: "system".to_string(),
+        })),
+        Err(e) => {
+            info!("System FFmpeg is not usable: {}", e);
+            Ok(None)
+        },
+    }
+}
+
+/// Validates and persists a custom ffmpeg binary, or clears it with `None` to go back
+/// to the bundled one.
+#[tauri::command]
+pub async fn set_ffmpeg_path(
+    app: AppHandle,
+    settings: State<'_, Arc<Mutex<Settings>>>,
+    path: Option<String>,
+) -> Result<FfmpegInfo, String> {
+    let data_dir = app.path_resolver().app_data_dir()
+        .ok_or("Data directory is not available".to_string())?;
+    let config_dir = app.path_resolver().app_config_dir()
+        .ok_or("Config directory is not available".to_string())?;
+
+    if let Some(ref path) = path {
+        validate_ffmpeg(Path::new(path))?;
+    }
+
+    let mut settings_guard = settings.lock().await;
+    settings_guard.ffmpeg_path = path.clone();
+    settings_guard.save(&config_dir)?;
+    drop(settings_guard);
+
+    let custom_path = path.map(PathBuf::from);
+    tokio::task::spawn_blocking(move || ensure_ffmpeg(&data_dir, custom_path.as_deref()))
+        .await
+        .map_err(|e| e.to_string())??;
+
+    get_ffmpeg_version()
+}
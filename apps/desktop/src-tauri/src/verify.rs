@@ -0,0 +1,89 @@
+use std::path::Path;
+use tracing::{info, warn};
+
+use crate::manifest::{
+    mark_segment_failed, mark_segment_uploaded, pending_segments, remove_uploaded_chunks, update_manifest, RecordingManifest,
+};
+use crate::recording::RecordingOptions;
+use crate::settings::ChunkRetention;
+use crate::upload::{file_key, get_video_duration, Uploader};
+
+/// Makes sure the server has every segment before the recording is declared complete.
+/// Segments the server reports missing are marked pending again and re-uploaded from
+/// the local copy, which the upload loops keep until this check. Once a track is
+/// verified, the local copies of its uploaded segments are removed under
+/// `KeepUntilConfirmed`; an unverified track keeps them. When the server can't be
+/// asked, the manifest is trusted for the status, and the tracks that went unverified
+/// are returned as the error.
+pub async fn verify_uploads(
+    recording_dir: &Path,
+    options: &RecordingOptions,
+    uploader: &dyn Uploader,
+    retention: ChunkRetention,
+) -> Result<(), String> {
+    let mut unverified = vec![];
+    for video_type in ["screen", "audio"] {
+        let uploaded: Vec<String> = RecordingManifest::load(recording_dir)?
+            .uploaded_segments
+            .get(video_type)
+            .map(|uploaded| uploaded.iter().cloned().collect())
+            .unwrap_or_default();
+
+        let mut verified = true;
+        if !uploaded.is_empty() {
+            let file_keys: Vec<String> = uploaded.iter().map(|segment| file_key(options, video_type, segment)).collect();
+            match uploader.missing_uploads(options, &file_keys).await {
+                Ok(missing) if !missing.is_empty() => {
+                    warn!("Server is missing {} {} segments of {}", missing.len(), video_type, options.video_id);
+                    update_manifest(recording_dir, |manifest| {
+                        if let Some(uploaded) = manifest.uploaded_segments.get_mut(video_type) {
+                            uploaded.retain(|segment| !missing.contains(&file_key(options, video_type, segment)));
+                        }
+                    })?;
+                },
+                Ok(_) => {},
+                Err(e) => {
+                    warn!("Could not verify the uploads of {}: {}", options.video_id, e);
+                    unverified.push(format!("{} ({})", video_type, e));
+                    verified = false;
+                },
+            }
+        }
+
+        let chunks_dir = recording_dir.join(video_type);
+        for segment in pending_segments(recording_dir, video_type)? {
+            if !options.owns_segment(&segment) {
+                warn!("Segment {} doesn't belong to {} and won't be re-uploaded", segment, options.video_id);
+                continue;
+            }
+            let segment_path = chunks_dir.join(&segment);
+            if !segment_path.is_file() {
+                warn!("Segment {} of {} is missing locally and can't be re-uploaded", segment, options.video_id);
+                continue;
+            }
+            info!("Re-uploading {} segment {}", video_type, segment);
+            let upload = async {
+                let duration_secs = get_video_duration(&segment_path).await?;
+                uploader.upload_segment(options, &segment_path, video_type, duration_secs).await?;
+                Ok::<f64, String>(duration_secs)
+            };
+            match upload.await {
+                Ok(duration_secs) => mark_segment_uploaded(recording_dir, video_type, &segment, duration_secs),
+                Err(e) => {
+                    warn!("Failed to re-upload {}: {}", segment, e);
+                    mark_segment_failed(recording_dir, video_type, &segment, &e);
+                },
+            }
+        }
+
+        if verified && retention == ChunkRetention::KeepUntilConfirmed {
+            remove_uploaded_chunks(recording_dir, video_type);
+        }
+    }
+
+    if unverified.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("Could not verify the uploaded {}", unverified.join(", ")))
+    }
+}
@@ -0,0 +1,145 @@
+use std::path::Path;
+use serde::{Serialize, Deserialize};
+use tokio::process::Command;
+use tracing::{debug, warn};
+
+use crate::recording::RecordingOptions;
+use crate::upload::upload_sidecar_file;
+use crate::utils::ffmpeg_path;
+
+pub const PEAKS_PER_SECOND: u32 = 100;
+/// Audio is decoded at this rate before peaks are taken, which is plenty for drawing.
+const DECODE_SAMPLE_RATE: u32 = 8000;
+const WAVEFORM_FILE: &str = "waveform.json";
+/// Peaks below this, about -40 dBFS, count as silence.
+const SILENCE_THRESHOLD: f32 = 0.01;
+/// Shorter pauses are part of normal speech and aren't worth cutting.
+const MIN_SILENCE_SECS: f64 = 2.0;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Waveform {
+    pub samples_per_second: u32,
+    /// Peak absolute amplitude of each window, from 0 to 1.
+    pub peaks: Vec<f32>,
+    /// Stretches of at least `MIN_SILENCE_SECS` without sound, for the editor to offer
+    /// cutting.
+    #[serde(default)]
+    pub silences: Vec<Silence>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Silence {
+    pub start_secs: f64,
+    pub end_secs: f64,
+}
+
+/// Computes the peaks of one audio segment and keeps them next to the recording.
+/// Runs before the segment is uploaded, since uploaded segments are removed locally.
+pub async fn write_segment_peaks(recording_dir: &Path, segment_path: &Path) -> Result<(), String> {
+    let segment_name = segment_path.file_name()
+        .and_then(|name| name.to_str())
+        .ok_or("Invalid segment path".to_string())?;
+
+    let peaks = compute_peaks(segment_path).await?;
+
+    let waveform_dir = recording_dir.join("waveform");
+    tokio::fs::create_dir_all(&waveform_dir).await.map_err(|e| e.to_string())?;
+    let contents = serde_json::to_string(&peaks).map_err(|e| e.to_string())?;
+    tokio::fs::write(waveform_dir.join(format!("{}.json", segment_name)), contents).await
+        .map_err(|e| format!("Failed to write peaks for {}: {}", segment_name, e))
+}
+
+/// Joins the per-segment peaks in segment list order into `waveform.json`, along with
+/// the silences found in them, and uploads it under the `waveform` type. Segments without peaks are filled with silence so
+/// the waveform stays aligned with the audio.
+pub async fn upload_waveform(recording_dir: &Path, options: &RecordingOptions) -> Result<(), String> {
+    let segment_list = tokio::fs::read_to_string(recording_dir.join("audio").join("segment_list.txt")).await
+        .map_err(|e| format!("Failed to read audio segment list: {}", e))?;
+    let waveform_dir = recording_dir.join("waveform");
+
+    let mut peaks = vec![];
+    // Windows filled in for a segment without peaks are unknown, not silent.
+    let mut known = vec![];
+    for segment in segment_list.lines().filter(|line| !line.is_empty()) {
+        let segment_peaks = tokio::fs::read_to_string(waveform_dir.join(format!("{}.json", segment))).await
+            .ok()
+            .and_then(|contents| serde_json::from_str::<Vec<f32>>(&contents).ok());
+
+        match segment_peaks {
+            Some(segment_peaks) => {
+                known.extend(std::iter::repeat(true).take(segment_peaks.len()));
+                peaks.extend(segment_peaks);
+            },
+            None => {
+                warn!("No waveform peaks for {}, filling with silence", segment);
+                let windows = (options.segment_time_secs * PEAKS_PER_SECOND) as usize;
+                known.extend(std::iter::repeat(false).take(windows));
+                peaks.extend(std::iter::repeat(0.0).take(windows));
+            },
+        }
+    }
+
+    let silences = find_silences(&peaks, &known);
+    let waveform = Waveform { samples_per_second: PEAKS_PER_SECOND, peaks, silences };
+    let waveform_path = waveform_dir.join(WAVEFORM_FILE);
+    tokio::fs::create_dir_all(&waveform_dir).await.map_err(|e| e.to_string())?;
+    tokio::fs::write(&waveform_path, serde_json::to_string(&waveform).map_err(|e| e.to_string())?).await
+        .map_err(|e| format!("Failed to write waveform: {}", e))?;
+    debug!("Wrote {} waveform peaks and {} silences for {}", waveform.peaks.len(), waveform.silences.len(), options.video_id);
+
+    upload_sidecar_file(options, &waveform_path, "waveform").await?;
+
+    Ok(())
+}
+
+/// Runs of known peaks under `SILENCE_THRESHOLD` lasting at least `MIN_SILENCE_SECS`.
+fn find_silences(peaks: &[f32], known: &[bool]) -> Vec<Silence> {
+    let to_secs = |window: usize| window as f64 / PEAKS_PER_SECOND as f64;
+    let mut silences = vec![];
+    let mut start = None;
+    for (window, (peak, known)) in peaks.iter().zip(known).enumerate() {
+        let silent = *known && *peak < SILENCE_THRESHOLD;
+        match (silent, start) {
+            (true, None) => start = Some(window),
+            (false, Some(from)) => {
+                if to_secs(window - from) >= MIN_SILENCE_SECS {
+                    silences.push(Silence { start_secs: to_secs(from), end_secs: to_secs(window) });
+                }
+                start = None;
+            },
+            _ => {},
+        }
+    }
+    if let Some(from) = start {
+        if to_secs(peaks.len() - from) >= MIN_SILENCE_SECS {
+            silences.push(Silence { start_secs: to_secs(from), end_secs: to_secs(peaks.len()) });
+        }
+    }
+    silences
+}
+
+async fn compute_peaks(path: &Path) -> Result<Vec<f32>, String> {
+    let output = Command::new(ffmpeg_path()?)
+        .arg("-i")
+        .arg(path)
+        .args(["-f", "f32le", "-ac", "1", "-ar", &DECODE_SAMPLE_RATE.to_string(), "-"])
+        .output()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if !output.status.success() {
+        return Err(format!("Failed to decode {}: {}", path.display(), String::from_utf8_lossy(&output.stderr).trim()));
+    }
+
+    let window = (DECODE_SAMPLE_RATE / PEAKS_PER_SECOND) as usize;
+    let samples: Vec<f32> = output.stdout.chunks_exact(4)
+        .map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        .collect();
+
+    Ok(samples.chunks(window)
+        .map(|window| {
+            let peak = window.iter().fold(0.0f32, |peak, sample| peak.max(sample.abs())).min(1.0);
+            (peak * 1000.0).round() / 1000.0
+        })
+        .collect())
+}
@@ -0,0 +1,127 @@
+use std::path::Path;
+use std::sync::RwLock;
+use std::time::Duration;
+use reqwest::Url;
+use serde::{Serialize, Deserialize};
+use tracing::{debug, warn};
+
+use crate::manifest::{RecordingManifest, RecordingStatus, StopReason};
+
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+static WEBHOOKS: RwLock<Vec<Webhook>> = RwLock::new(Vec::new());
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEvent {
+    RecordingStarted,
+    RecordingStopped,
+    UploadComplete,
+    UploadFailed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Webhook {
+    pub url: String,
+    /// Events to send; empty sends all of them.
+    #[serde(default)]
+    pub events: Vec<WebhookEvent>,
+}
+
+impl Webhook {
+    fn wants(&self, event: WebhookEvent) -> bool {
+        self.events.is_empty() || self.events.contains(&event)
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct WebhookPayload<'a> {
+    event: WebhookEvent,
+    video_id: &'a str,
+    duration_secs: Option<f64>,
+    /// Sent with `recording_stopped` and the upload events.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stop_reason: Option<StopReason>,
+    timestamp: String,
+}
+
+pub fn validate_webhooks(webhooks: &[Webhook]) -> Result<(), String> {
+    for webhook in webhooks {
+        let url = Url::parse(&webhook.url).map_err(|e| format!("Invalid webhook URL {}: {}", webhook.url, e))?;
+        if url.scheme() != "http" && url.scheme() != "https" {
+            return Err(format!("Webhook URL must use http or https: {}", webhook.url));
+        }
+    }
+    Ok(())
+}
+
+pub fn set_webhooks(webhooks: Vec<Webhook>) {
+    match WEBHOOKS.write() {
+        Ok(mut guard) => *guard = webhooks,
+        Err(e) => warn!("Failed to update webhooks: {}", e),
+    }
+}
+
+/// POSTs the event as JSON to every webhook subscribed to it. Delivery happens in the
+/// background and failures are only logged, so a slow endpoint never holds up a recording.
+pub fn notify(event: WebhookEvent, video_id: &str, duration_secs: Option<f64>, stop_reason: Option<StopReason>) {
+    let webhooks: Vec<Webhook> = match WEBHOOKS.read() {
+        Ok(webhooks) => webhooks.iter().filter(|webhook| webhook.wants(event)).cloned().collect(),
+        Err(_) => return,
+    };
+    if webhooks.is_empty() {
+        return;
+    }
+
+    let body = match serde_json::to_string(&WebhookPayload {
+        event,
+        video_id,
+        duration_secs,
+        stop_reason,
+        timestamp: chrono::Utc::now().to_rfc3339(),
+    }) {
+        Ok(body) => body,
+        Err(e) => {
+            warn!("Failed to serialize webhook payload: {}", e);
+            return;
+        },
+    };
+
+    tauri::async_runtime::spawn(async move {
+        let client = match reqwest::Client::builder().timeout(WEBHOOK_TIMEOUT).build() {
+            Ok(client) => client,
+            Err(e) => {
+                warn!("Failed to create webhook client: {}", e);
+                return;
+            },
+        };
+
+        for webhook in webhooks {
+            let result = client.post(&webhook.url)
+                .header("Content-Type", "application/json")
+                .body(body.clone())
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+
+            match result {
+                Ok(_) => debug!("Sent {:?} webhook to {}", event, webhook.url),
+                Err(e) => warn!("Webhook {} failed: {}", webhook.url, e),
+            }
+        }
+    });
+}
+
+/// Sends `UploadComplete` or `UploadFailed` for a recording whose uploads have finished.
+pub fn notify_upload_result(recording_dir: &Path, status: RecordingStatus) {
+    let event = match status {
+        RecordingStatus::Complete => WebhookEvent::UploadComplete,
+        RecordingStatus::Failed => WebhookEvent::UploadFailed,
+        _ => return,
+    };
+
+    match RecordingManifest::load(recording_dir) {
+        Ok(manifest) => notify(event, &manifest.video_id, manifest.duration_secs, manifest.stop_reason),
+        Err(e) => warn!("Failed to read manifest for webhook: {}", e),
+    }
+}
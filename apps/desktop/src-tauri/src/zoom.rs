@@ -0,0 +1,162 @@
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use tauri::State;
+use tokio::io::AsyncWriteExt;
+use tokio::process::{ChildStdin, Command};
+use tokio::sync::Mutex;
+use tracing::info;
+
+use crate::session::RecordingState;
+use crate::upload_loop::load_segment_list;
+use crate::utils::ffmpeg_path;
+
+/// Crops the captured frame to the zoomed region; a no-op at full view.
+pub const ZOOM_CROP_FILTER: &str = "crop@zoom=w=iw:h=ih:x=0:y=0";
+const ZOOM_CROP_TARGET: &str = "crop@zoom";
+/// Name of the scale filter after the crop, which blows the region back up to the
+/// full frame size.
+pub const ZOOM_SCALE_FILTER: &str = "scale@zoom";
+const ZOOM_STEPS: u32 = 12;
+const ZOOM_STEP_INTERVAL: Duration = Duration::from_millis(33);
+/// Regions are never smaller than this fraction of the frame, so pixels don't turn
+/// into blocks.
+const MAX_ZOOM: u32 = 4;
+
+/// A region of the captured frame, in its pixels.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct ZoomRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// The zoom of the running screen capture.
+#[derive(Debug, Clone, Copy)]
+pub struct Zoom {
+    /// The whole captured frame.
+    pub frame: ZoomRect,
+    /// The region shown, `frame` when zoomed out.
+    pub region: ZoomRect,
+}
+
+/// Zooms the live capture into `rect`, or back out to the full frame when it is
+/// `None`, animating over about 400 ms. The region is widened to the frame's aspect
+/// ratio and kept inside it. The zoom is part of the recorded video.
+#[tauri::command]
+pub async fn set_zoom(state: State<'_, Arc<Mutex<RecordingState>>>, rect: Option<ZoomRect>) -> Result<ZoomRect, String> {
+    let mut guard = state.lock().await;
+    if !guard.is_recording() {
+        return Err("No recording is in progress".to_string());
+    }
+    let recording_dir = guard.recording_dir.clone().ok_or("Recording directory is not set".to_string())?;
+    let previous = guard.zoom;
+    let stdin = guard.screen_process_stdin.as_mut()
+        .ok_or("Zoom needs the ffmpeg screen capture".to_string())?;
+
+    let zoom = match previous {
+        Some(zoom) => zoom,
+        None => {
+            let (width, height) = probe_frame_size(&recording_dir).await?;
+            let frame = ZoomRect { x: 0, y: 0, width, height };
+            // Fix the output size, so it stays the same while the crop changes.
+            send_command(stdin, ZOOM_SCALE_FILTER, "w", width).await?;
+            send_command(stdin, ZOOM_SCALE_FILTER, "h", height).await?;
+            Zoom { frame, region: frame }
+        },
+    };
+    let target = rect.map_or(zoom.frame, |rect| fit_region(rect, zoom.frame));
+
+    for step in 1..=ZOOM_STEPS {
+        let progress = step as f64 / ZOOM_STEPS as f64;
+        send_crop(stdin, interpolate(zoom.region, target, progress)).await?;
+        if step < ZOOM_STEPS {
+            tokio::time::sleep(ZOOM_STEP_INTERVAL).await;
+        }
+    }
+    info!("Zoomed the capture to {:?}", target);
+    guard.zoom = Some(Zoom { frame: zoom.frame, region: target });
+
+    Ok(target)
+}
+
+/// Applies the current zoom to a capture process started again by adaptive quality,
+/// without animating.
+pub async fn restore_zoom(stdin: &mut ChildStdin, zoom: Zoom) -> Result<(), String> {
+    send_command(stdin, ZOOM_SCALE_FILTER, "w", zoom.frame.width).await?;
+    send_command(stdin, ZOOM_SCALE_FILTER, "h", zoom.frame.height).await?;
+    send_crop(stdin, zoom.region).await
+}
+
+async fn send_crop(stdin: &mut ChildStdin, region: ZoomRect) -> Result<(), String> {
+    send_command(stdin, ZOOM_CROP_TARGET, "w", region.width).await?;
+    send_command(stdin, ZOOM_CROP_TARGET, "h", region.height).await?;
+    send_command(stdin, ZOOM_CROP_TARGET, "x", region.x).await?;
+    send_command(stdin, ZOOM_CROP_TARGET, "y", region.y).await
+}
+
+/// Sends a filter command through ffmpeg's interactive `c` key, applied right away.
+async fn send_command(stdin: &mut ChildStdin, target: &str, command: &str, value: u32) -> Result<(), String> {
+    stdin.write_all(format!("c{} -1 {} {}\n", target, command, value).as_bytes()).await
+        .map_err(|e| format!("Failed to send the zoom to ffmpeg: {}", e))
+}
+
+/// Grows `rect` to the aspect ratio of `frame` around its centre and moves it inside
+/// the frame, with even sizes and offsets for chroma subsampling.
+fn fit_region(rect: ZoomRect, frame: ZoomRect) -> ZoomRect {
+    let aspect = frame.width as f64 / frame.height as f64;
+    let min_width = (frame.width / MAX_ZOOM) as f64;
+    let mut width = (rect.width as f64).max(rect.height as f64 * aspect).max(min_width);
+    width = width.min(frame.width as f64);
+    let height = (width / aspect).min(frame.height as f64);
+
+    let center_x = rect.x as f64 + rect.width as f64 / 2.0;
+    let center_y = rect.y as f64 + rect.height as f64 / 2.0;
+    let x = (center_x - width / 2.0).clamp(0.0, frame.width as f64 - width);
+    let y = (center_y - height / 2.0).clamp(0.0, frame.height as f64 - height);
+
+    even_region(x, y, width, height)
+}
+
+/// The region `progress` of the way from `from` to `to`, eased in and out.
+fn interpolate(from: ZoomRect, to: ZoomRect, progress: f64) -> ZoomRect {
+    let eased = progress * progress * (3.0 - 2.0 * progress);
+    let lerp = |a: u32, b: u32| a as f64 + (b as f64 - a as f64) * eased;
+    even_region(lerp(from.x, to.x), lerp(from.y, to.y), lerp(from.width, to.width), lerp(from.height, to.height))
+}
+
+fn even_region(x: f64, y: f64, width: f64, height: f64) -> ZoomRect {
+    let even = |value: f64| (value as u32) & !1;
+    ZoomRect { x: even(x), y: even(y), width: even(width).max(2), height: even(height).max(2) }
+}
+
+/// Reads the frame size from the last finished screen segment.
+async fn probe_frame_size(recording_dir: &Path) -> Result<(u32, u32), String> {
+    let chunks_dir = recording_dir.join("screen");
+    let segment = load_segment_list(&chunks_dir.join("segment_list.txt"))
+        .unwrap_or_default()
+        .pop()
+        .ok_or("Zoom is available once the first segment is recorded".to_string())?;
+
+    let output = Command::new(ffmpeg_path()?)
+        .arg("-hide_banner")
+        .arg("-i")
+        .arg(chunks_dir.join(&segment))
+        .output()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    String::from_utf8_lossy(&output.stderr)
+        .lines()
+        .filter(|line| line.contains("Video:"))
+        .flat_map(|line| line.split([' ', ',']))
+        .find_map(|field| {
+            let (width, height) = field.split_once('x')?;
+            let (width, height): (u32, u32) = (width.parse().ok()?, height.parse().ok()?);
+            // Skips codec tags like "0x31637661".
+            (width > 0 && height > 0).then_some((width, height))
+        })
+        .ok_or(format!("Could not read the frame size of {}", segment))
+}
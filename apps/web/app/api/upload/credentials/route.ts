@@ -0,0 +1,90 @@
+import { STS } from "aws-sdk";
+import { NextRequest } from "next/server";
+
+const sts = new STS({
+  region: process.env.CAP_AWS_REGION || "",
+  credentials: {
+    accessKeyId: process.env.CAP_AWS_ACCESS_KEY || "",
+    secretAccessKey: process.env.CAP_AWS_SECRET_KEY || "",
+  },
+});
+
+const CREDENTIALS_DURATION_SECONDS = 3600;
+
+// Issues short-lived credentials that can only write the user's own objects, for the
+// desktop app to sign its uploads locally.
+export async function POST(request: NextRequest) {
+  try {
+    const { userId } = await request.json();
+    const awsBucket = process.env.CAP_AWS_BUCKET;
+
+    if (!userId || !awsBucket) {
+      console.error("Missing required fields in /api/upload/credentials/route.ts");
+
+      return new Response(
+        JSON.stringify({ error: "Missing required fields" }),
+        {
+          status: 400,
+          headers: {
+            "Content-Type": "application/json",
+          },
+        }
+      );
+    }
+
+    const policy = {
+      Version: "2012-10-17",
+      Statement: [
+        {
+          Effect: "Allow",
+          Action: [
+            "s3:PutObject",
+            "s3:PutObjectTagging",
+            "s3:AbortMultipartUpload",
+            "s3:ListMultipartUploadParts",
+          ],
+          Resource: `arn:aws:s3:::${awsBucket}/${userId}/*`,
+        },
+      ],
+    };
+
+    const { Credentials } = await sts
+      .getFederationToken({
+        Name: `cap-${userId}`.slice(0, 32),
+        Policy: JSON.stringify(policy),
+        DurationSeconds: CREDENTIALS_DURATION_SECONDS,
+      })
+      .promise();
+
+    if (!Credentials) {
+      throw new Error("STS returned no credentials");
+    }
+
+    return new Response(
+      JSON.stringify({
+        credentials: {
+          accessKeyId: Credentials.AccessKeyId,
+          secretAccessKey: Credentials.SecretAccessKey,
+          sessionToken: Credentials.SessionToken,
+          expiration: Credentials.Expiration.toISOString(),
+        },
+      }),
+      {
+        headers: {
+          "Content-Type": "application/json",
+        },
+      }
+    );
+  } catch (error) {
+    console.error("Error issuing upload credentials", error);
+    return new Response(
+      JSON.stringify({ error: "Error issuing upload credentials" }),
+      {
+        status: 500,
+        headers: {
+          "Content-Type": "application/json",
+        },
+      }
+    );
+  }
+}
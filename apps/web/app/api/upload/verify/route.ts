@@ -0,0 +1,84 @@
+import {
+  ListObjectsV2Command,
+  ListObjectsV2CommandOutput,
+  S3Client,
+} from "@aws-sdk/client-s3";
+import { NextRequest } from "next/server";
+
+const s3Client = new S3Client({
+  region: process.env.CAP_AWS_REGION || "",
+  credentials: {
+    accessKeyId: process.env.CAP_AWS_ACCESS_KEY || "",
+    secretAccessKey: process.env.CAP_AWS_SECRET_KEY || "",
+  },
+});
+
+export async function POST(request: NextRequest) {
+  try {
+    const { userId, videoId, fileKeys, awsBucket } = await request.json();
+
+    if (!userId || !videoId || !Array.isArray(fileKeys) || !awsBucket) {
+      console.error("Missing required fields in /api/upload/verify/route.ts");
+
+      return new Response(
+        JSON.stringify({ error: "Missing required fields" }),
+        {
+          status: 400,
+          headers: {
+            "Content-Type": "application/json",
+          },
+        }
+      );
+    }
+
+    // Only the recording's own objects can be checked.
+    const prefix = `${userId}/${videoId}/`;
+    if (fileKeys.some((fileKey) => !String(fileKey).startsWith(prefix))) {
+      return new Response(
+        JSON.stringify({ error: "File keys must belong to the video" }),
+        {
+          status: 400,
+          headers: {
+            "Content-Type": "application/json",
+          },
+        }
+      );
+    }
+
+    const existing = new Set<string>();
+    let continuationToken: string | undefined = undefined;
+    do {
+      const listing: ListObjectsV2CommandOutput = await s3Client.send(
+        new ListObjectsV2Command({
+          Bucket: awsBucket,
+          Prefix: prefix,
+          ContinuationToken: continuationToken,
+        })
+      );
+      for (const object of listing.Contents ?? []) {
+        if (object.Key) {
+          existing.add(object.Key);
+        }
+      }
+      continuationToken = listing.IsTruncated
+        ? listing.NextContinuationToken
+        : undefined;
+    } while (continuationToken);
+
+    const missing = fileKeys.filter((fileKey) => !existing.has(fileKey));
+
+    return new Response(JSON.stringify({ missing }), {
+      headers: {
+        "Content-Type": "application/json",
+      },
+    });
+  } catch (error) {
+    console.error("Error verifying uploads", error);
+    return new Response(JSON.stringify({ error: "Error verifying uploads" }), {
+      status: 500,
+      headers: {
+        "Content-Type": "application/json",
+      },
+    });
+  }
+}